@@ -0,0 +1,55 @@
+//! POSIX ACLs in the binary form the kernel uses for the
+//! system.posix_acl_access and system.posix_acl_default xattrs.
+//!
+//! The value is a little-endian u32 version followed by one
+//! `{ u16 tag, u16 perm, u32 id }` record per entry, ordered by tag and then
+//! by id.
+
+use crate::api_client::AclEntry;
+
+pub const ACCESS_XATTR: &str = "system.posix_acl_access";
+pub const DEFAULT_XATTR: &str = "system.posix_acl_default";
+
+const XATTR_VERSION: u32 = 2;
+const UNDEFINED_ID: u32 = u32::MAX;
+
+const TAG_USER_OBJ: u16 = 0x01;
+const TAG_USER: u16 = 0x02;
+const TAG_GROUP_OBJ: u16 = 0x04;
+const TAG_GROUP: u16 = 0x08;
+const TAG_MASK: u16 = 0x10;
+const TAG_OTHER: u16 = 0x20;
+
+/// Encode entries as an xattr value, or describe why they can't be
+pub fn encode(entries: &[AclEntry]) -> Result<Vec<u8>, String> {
+    let mut records = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let tag = match entry.tag.as_str() {
+            "user_obj" => TAG_USER_OBJ,
+            "user" => TAG_USER,
+            "group_obj" => TAG_GROUP_OBJ,
+            "group" => TAG_GROUP,
+            "mask" => TAG_MASK,
+            "other" => TAG_OTHER,
+            other => return Err(format!("unknown ACL tag {:?}", other)),
+        };
+        let id = if tag == TAG_USER || tag == TAG_GROUP {
+            entry
+                .id
+                .ok_or_else(|| format!("ACL {} entry without an id", entry.tag))?
+        } else {
+            UNDEFINED_ID
+        };
+        records.push((tag, id, entry.perm & 0o7));
+    }
+    records.sort_unstable();
+
+    let mut value = Vec::with_capacity(4 + records.len() * 8);
+    value.extend_from_slice(&XATTR_VERSION.to_le_bytes());
+    for (tag, id, perm) in records {
+        value.extend_from_slice(&tag.to_le_bytes());
+        value.extend_from_slice(&perm.to_le_bytes());
+        value.extend_from_slice(&id.to_le_bytes());
+    }
+    Ok(value)
+}
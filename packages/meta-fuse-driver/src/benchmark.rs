@@ -0,0 +1,340 @@
+use crate::api_client::{
+    ApiClient, BodyFormat, Compression, ReadResponse, ReadResult, RequestTimeouts, TlsSettings,
+};
+use crate::read_source::{self, ReadSource};
+use crate::retry_interrupted;
+use serde::Serialize;
+use std::fs::File;
+use std::io::Read;
+use std::time::{Duration, Instant};
+
+/// Read sizes tried in turn, each capped at the file's size
+const SIZES: [u64; 4] = [4 * 1024, 64 * 1024, 1024 * 1024, 16 * 1024 * 1024];
+const ITERATIONS: usize = 5;
+
+#[derive(Serialize)]
+struct Report {
+    path: String,
+    file_size: u64,
+    /// The source content was read from, as named in FUSE_READ_SOURCE_ORDER,
+    /// or "none" for an empty file
+    source: String,
+    results: Vec<SizeResult>,
+}
+
+#[derive(Serialize)]
+struct SizeResult {
+    size: u64,
+    iterations: usize,
+    p50_ms: f64,
+    p90_ms: f64,
+    p99_ms: f64,
+    bytes_per_sec: f64,
+}
+
+/// Time uncached reads of `path` the way the driver does them, a read call
+/// to core and then the content from the first source it offers in
+/// `read_source_order`, at increasing sizes. Prints a table, or one JSON object with `json`. Returns whether
+/// every read succeeded.
+#[allow(clippy::too_many_arguments)]
+pub fn run(
+    api_url: &str,
+    timeouts: RequestTimeouts,
+    user_agent: Option<String>,
+    root_prefix: Option<String>,
+    body_format: BodyFormat,
+    compression: Compression,
+    tls: TlsSettings,
+    read_source_order: &[ReadSource],
+    path: &str,
+    json: bool,
+) -> bool {
+    let fail = |e: &dyn std::fmt::Display| {
+        if json {
+            println!(
+                "{}",
+                serde_json::json!({ "path": path, "error": e.to_string() })
+            );
+        } else {
+            eprintln!("Error: {}", e);
+        }
+        false
+    };
+
+    let api = match ApiClient::new(
+        api_url.to_string(),
+        timeouts,
+        None,
+        user_agent,
+        None,
+        root_prefix,
+        body_format,
+        compression,
+        tls,
+    ) {
+        Ok(api) => api,
+        Err(e) => return fail(&e),
+    };
+    // The same per-request bound the driver applies to content URLs
+    let http = match api.http_client_builder().timeout(timeouts.global).build() {
+        Ok(http) => http,
+        Err(e) => return fail(&e),
+    };
+
+    let measured = measure(&api, &http, read_source_order, path, |report| {
+        if json {
+            return;
+        }
+        match report.results.last() {
+            None => {
+                println!(
+                    "Benchmarking reads of {} ({} bytes, served {}) on {}",
+                    path, report.file_size, report.source, api_url
+                );
+                println!(
+                    "  {:>10}  {:>9}  {:>9}  {:>9}  {:>12}",
+                    "size", "p50", "p90", "p99", "throughput"
+                );
+            }
+            Some(result) => println!(
+                "  {:>10}  {:>7.1}ms  {:>7.1}ms  {:>7.1}ms  {:>8.1} MB/s",
+                result.size,
+                result.p50_ms,
+                result.p90_ms,
+                result.p99_ms,
+                result.bytes_per_sec / 1_000_000.0
+            ),
+        }
+    });
+    let report = match measured {
+        Ok(report) => report,
+        Err(e) => return fail(&e),
+    };
+
+    if json {
+        match serde_json::to_string(&report) {
+            Ok(text) => println!("{}", text),
+            Err(e) => return fail(&e),
+        }
+    }
+    true
+}
+
+/// Build the report, calling `progress` once the file's size and source
+/// are known and again after each size is timed
+fn measure(
+    api: &ApiClient,
+    http: &reqwest::blocking::Client,
+    read_source_order: &[ReadSource],
+    path: &str,
+    mut progress: impl FnMut(&Report),
+) -> Result<Report, Box<dyn std::error::Error>> {
+    let (first, _) = read_once(api, http, read_source_order, path, 0)?;
+    let mut report = Report {
+        path: path.to_string(),
+        file_size: first.size,
+        source: first_source(read_source_order, &first)
+            .map_or("none".to_string(), |s| s.to_string()),
+        results: Vec::new(),
+    };
+    progress(&report);
+
+    let mut sizes: Vec<u64> = SIZES.iter().map(|&s| s.min(first.size)).collect();
+    sizes.dedup();
+    for size in sizes.into_iter().filter(|&s| s > 0) {
+        let mut latencies = Vec::with_capacity(ITERATIONS);
+        for _ in 0..ITERATIONS {
+            let started = Instant::now();
+            let (_, bytes) = read_once(api, http, read_source_order, path, size)?;
+            if bytes != size {
+                return Err(format!("read {} bytes of {} requested", bytes, size).into());
+            }
+            latencies.push(started.elapsed());
+        }
+        latencies.sort();
+        let total: Duration = latencies.iter().sum();
+        report.results.push(SizeResult {
+            size,
+            iterations: ITERATIONS,
+            p50_ms: millis(percentile(&latencies, 50)),
+            p90_ms: millis(percentile(&latencies, 90)),
+            p99_ms: millis(percentile(&latencies, 99)),
+            bytes_per_sec: (size * ITERATIONS as u64) as f64 / total.as_secs_f64(),
+        });
+        progress(&report);
+    }
+    Ok(report)
+}
+
+/// One read call followed by fetching the first `size` bytes from the
+/// source it returned; returns the result and how many bytes arrived
+fn read_once(
+    api: &ApiClient,
+    http: &reqwest::blocking::Client,
+    read_source_order: &[ReadSource],
+    path: &str,
+    size: u64,
+) -> Result<(ReadResult, u64), Box<dyn std::error::Error>> {
+    let ReadResponse::Fresh(result) = api.read(path, None)? else {
+        return Err("unexpected 304 Not Modified".into());
+    };
+    let size = size.min(result.size);
+    if size == 0 {
+        return Ok((result, 0));
+    }
+
+    let source = first_source(read_source_order, &result)
+        .ok_or_else(|| read_source::no_source_error(read_source_order))?;
+    let url = match source {
+        ReadSource::Webdav => result.webdav_url.as_deref(),
+        ReadSource::FetchUrl => result.fetch_url.as_deref(),
+        _ => None,
+    };
+    let fetched = if let (ReadSource::Inline, Some(content)) = (source, &result.content) {
+        // Inline content arrived decoded with the read result
+        content.data.len().min(size as usize) as u64
+    } else if let Some(url) = url {
+        let response = http
+            .get(url)
+            .header("Range", format!("bytes=0-{}", size - 1))
+            .send()?
+            .error_for_status()?;
+        let mut body = Vec::with_capacity(size as usize);
+        // A server ignoring Range sends everything; count only what's wanted
+        response.take(size).read_to_end(&mut body)?;
+        body.len() as u64
+    } else {
+        let source_path = result.source_path.as_deref().unwrap_or_default();
+        let file = retry_interrupted(|| File::open(source_path))?;
+        let mut body = Vec::with_capacity(size as usize);
+        file.take(size).read_to_end(&mut body)?;
+        body.len() as u64
+    };
+    Ok((result, fetched))
+}
+
+/// The source the driver would try first for a read result
+fn first_source(read_source_order: &[ReadSource], result: &ReadResult) -> Option<ReadSource> {
+    read_source_order
+        .iter()
+        .copied()
+        .find(|source| source.offered_by(result))
+}
+
+/// Nearest-rank percentile of sorted samples
+fn percentile(sorted: &[Duration], pct: usize) -> Duration {
+    let rank = (sorted.len() * pct).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_client::tests::test_client;
+    use crate::test_server::{Response, TestServer};
+
+    /// A core whose read results offer `served` bytes of a `size`-byte
+    /// file from a fetch URL
+    fn fetching_core(size: u64, served: usize) -> TestServer {
+        let url = std::sync::Arc::new(std::sync::OnceLock::<String>::new());
+        let blob_url = std::sync::Arc::clone(&url);
+        let server = TestServer::start(move |request| match request.target.as_str() {
+            "/api/fuse/read" => Response::json(serde_json::json!({
+                "fetchUrl": format!("{}/blob", blob_url.get().unwrap()),
+                "size": size,
+                "mtime": 1.0,
+            })),
+            "/blob" => Response::new(206, vec![b'x'; served]),
+            _ => Response::new(404, ""),
+        });
+        url.set(server.url.clone()).unwrap();
+        server
+    }
+
+    fn measure_on(server: &TestServer, progress: impl FnMut(&Report)) -> Result<Report, String> {
+        let order = read_source::parse_order("inline,fetchurl").unwrap();
+        let http = reqwest::blocking::Client::new();
+        measure(&test_client(&server.url), &http, &order, "/f", progress).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn percentiles_are_nearest_rank() {
+        let samples: Vec<Duration> = (1..=10).map(Duration::from_millis).collect();
+        assert_eq!(percentile(&samples, 50), Duration::from_millis(5));
+        assert_eq!(percentile(&samples, 90), Duration::from_millis(9));
+        assert_eq!(percentile(&samples, 99), Duration::from_millis(10));
+        assert_eq!(percentile(&samples[..1], 50), Duration::from_millis(1));
+        assert_eq!(millis(Duration::from_micros(1500)), 1.5);
+    }
+
+    #[test]
+    fn report_times_each_size_up_to_the_file_size() {
+        let server = fetching_core(10_000, 10_000);
+        let mut shown = Vec::new();
+        let report = measure_on(&server, |report| shown.push(report.results.len())).unwrap();
+
+        // Once before timing, then once per size
+        assert_eq!(shown, [0, 1, 2]);
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["path"], "/f");
+        assert_eq!(json["file_size"], 10_000);
+        assert_eq!(json["source"], "fetchurl");
+        let results = json["results"].as_array().unwrap();
+        let sizes: Vec<u64> = results
+            .iter()
+            .map(|r| r["size"].as_u64().unwrap())
+            .collect();
+        assert_eq!(sizes, [4096, 10_000]);
+        for result in results {
+            assert_eq!(result["iterations"], ITERATIONS);
+            let ms = |field: &str| result[field].as_f64().unwrap();
+            assert!(ms("p50_ms") <= ms("p90_ms") && ms("p90_ms") <= ms("p99_ms"));
+            assert!(ms("bytes_per_sec") > 0.0);
+            let mut fields: Vec<&str> = result
+                .as_object()
+                .unwrap()
+                .keys()
+                .map(String::as_str)
+                .collect();
+            fields.sort();
+            assert_eq!(
+                fields,
+                [
+                    "bytes_per_sec",
+                    "iterations",
+                    "p50_ms",
+                    "p90_ms",
+                    "p99_ms",
+                    "size"
+                ]
+            );
+        }
+        // One untimed read call, then one per iteration of each size
+        assert_eq!(server.count("/api/fuse/read"), 1 + 2 * ITERATIONS);
+        assert_eq!(server.count("/blob"), 2 * ITERATIONS);
+    }
+
+    #[test]
+    fn empty_file_reports_no_source_and_no_sizes() {
+        let server = TestServer::start(|request| match request.target.as_str() {
+            "/api/fuse/read" => Response::json(serde_json::json!({ "size": 0, "mtime": 1.0 })),
+            _ => Response::new(404, ""),
+        });
+        let report = measure_on(&server, |_| {}).unwrap();
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["source"], "none");
+        assert_eq!(json["file_size"], 0);
+        assert_eq!(json["results"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn short_reads_fail_the_benchmark() {
+        let server = fetching_core(10_000, 100);
+        let result = measure_on(&server, |_| {});
+        assert_eq!(result.err().unwrap(), "read 100 bytes of 4096 requested");
+    }
+}
@@ -0,0 +1,142 @@
+//! Multi-range HTTP requests: the `Range` header asking for several byte
+//! ranges at once, and the `multipart/byteranges` body a server answers
+//! one with (RFC 9110 §14.6).
+//!
+//! Each part carries its own `Content-Range`, whose length is trusted over
+//! scanning for the boundary, so part data may contain anything.
+
+/// `Range` header value for inclusive (start, end) ranges
+pub fn header(ranges: &[(u64, u64)]) -> String {
+    let specs: Vec<String> = ranges
+        .iter()
+        .map(|(start, end)| format!("{}-{}", start, end))
+        .collect();
+    format!("bytes={}", specs.join(","))
+}
+
+/// The inclusive (start, end) of a `Content-Range: bytes a-b/total` value
+pub fn parse_content_range(value: &str) -> Option<(u64, u64)> {
+    let range = value.trim().strip_prefix("bytes ")?;
+    let (span, _total) = range.split_once('/')?;
+    let (start, end) = span.split_once('-')?;
+    let (start, end) = (start.trim().parse().ok()?, end.trim().parse().ok()?);
+    (start <= end).then_some((start, end))
+}
+
+/// The boundary parameter of a `multipart/byteranges` content type, or None
+/// for any other type
+pub fn boundary(content_type: &str) -> Option<&str> {
+    let mut params = content_type.split(';');
+    if !params
+        .next()?
+        .trim()
+        .eq_ignore_ascii_case("multipart/byteranges")
+    {
+        return None;
+    }
+    params.find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case("boundary")
+            .then(|| value.trim().trim_matches('"'))
+    })
+}
+
+/// Split a multipart/byteranges body into (offset, data) parts
+pub fn parse(boundary: &str, body: &[u8]) -> Result<Vec<(u64, Vec<u8>)>, String> {
+    let delimiter = format!("--{}", boundary);
+    let mut parts = Vec::new();
+    let mut rest = body;
+    loop {
+        let at = find(rest, delimiter.as_bytes()).ok_or("missing multipart boundary")?;
+        rest = &rest[at + delimiter.len()..];
+        if rest.starts_with(b"--") {
+            return Ok(parts);
+        }
+        rest = rest
+            .strip_prefix(b"\r\n")
+            .ok_or("malformed multipart boundary line")?;
+
+        let headers_end = find(rest, b"\r\n\r\n").ok_or("unterminated part headers")?;
+        let headers = std::str::from_utf8(&rest[..headers_end])
+            .map_err(|_| "part headers are not UTF-8".to_string())?;
+        let (start, end) = headers
+            .split("\r\n")
+            .find_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                name.trim()
+                    .eq_ignore_ascii_case("content-range")
+                    .then_some(value)
+            })
+            .and_then(parse_content_range)
+            .ok_or("part without a valid Content-Range")?;
+
+        rest = &rest[headers_end + 4..];
+        let len = (end - start + 1) as usize;
+        if rest.len() < len {
+            return Err(format!(
+                "part at {} is {} bytes short",
+                start,
+                len - rest.len()
+            ));
+        }
+        parts.push((start, rest[..len].to_vec()));
+        rest = &rest[len..];
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn range_header_lists_every_range() {
+        assert_eq!(header(&[(0, 9)]), "bytes=0-9");
+        assert_eq!(header(&[(0, 9), (100, 199)]), "bytes=0-9,100-199");
+    }
+
+    #[test]
+    fn content_range_values_are_parsed() {
+        assert_eq!(parse_content_range("bytes 0-9/100"), Some((0, 9)));
+        assert_eq!(parse_content_range(" bytes 5-5/*"), Some((5, 5)));
+        assert_eq!(parse_content_range("bytes 9-0/100"), None);
+        assert_eq!(parse_content_range("bytes */100"), None);
+        assert_eq!(parse_content_range("items 0-9/100"), None);
+    }
+
+    #[test]
+    fn boundary_is_taken_only_from_byteranges_types() {
+        assert_eq!(
+            boundary("multipart/byteranges; boundary=\"abc\""),
+            Some("abc")
+        );
+        assert_eq!(
+            boundary("Multipart/ByteRanges;charset=x;Boundary=z"),
+            Some("z")
+        );
+        assert_eq!(boundary("multipart/mixed; boundary=abc"), None);
+        assert_eq!(boundary("multipart/byteranges"), None);
+    }
+
+    #[test]
+    fn parts_are_split_by_their_content_range() {
+        // The second part's data contains the delimiter itself
+        let body = b"preamble\r\n--B\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-2/20\r\n\r\nabc\r\n--B\r\nContent-Range: bytes 10-14/20\r\n\r\n--B--\r\n--B--\r\n";
+        assert_eq!(
+            parse("B", body).unwrap(),
+            [(0, b"abc".to_vec()), (10, b"--B--".to_vec())]
+        );
+
+        let short = b"--B\r\nContent-Range: bytes 0-19/20\r\n\r\nabc\r\n--B--";
+        assert!(parse("B", short).unwrap_err().contains("short"));
+        let unranged = b"--B\r\nContent-Type: text/plain\r\n\r\nabc\r\n--B--";
+        assert!(parse("B", unranged).is_err());
+        assert!(parse("B", b"no parts").is_err());
+    }
+}
@@ -0,0 +1,119 @@
+use crate::api_client::{
+    ApiClient, ApiError, AttrResponse, BodyFormat, Capabilities, Compression, RequestTimeouts,
+    TlsSettings,
+};
+use crate::file_type_from_mode;
+use fuser::FileType;
+use reqwest::StatusCode;
+
+/// Exercise the endpoints the driver depends on and print the outcome of
+/// each step, without touching FUSE. Returns whether every step passed.
+pub fn run(
+    api_url: &str,
+    timeouts: RequestTimeouts,
+    user_agent: Option<String>,
+    root_prefix: Option<String>,
+    body_format: BodyFormat,
+    compression: Compression,
+    tls: TlsSettings,
+) -> bool {
+    println!("Checking meta-fuse API at {}", api_url);
+
+    let api = match ApiClient::new(
+        api_url.to_string(),
+        timeouts,
+        None,
+        user_agent,
+        None,
+        root_prefix,
+        body_format,
+        compression,
+        tls,
+    ) {
+        Ok(api) => api,
+        Err(e) => {
+            println!("  client:     FAILED ({})", e);
+            return false;
+        }
+    };
+
+    let mut ok = true;
+
+    match api.health_check() {
+        Ok(true) => println!("  health:     ok"),
+        Ok(false) => {
+            println!("  health:     FAILED (unhealthy status)");
+            ok = false;
+        }
+        Err(e) => {
+            println!("  health:     FAILED ({})", e);
+            ok = false;
+        }
+    }
+
+    let capabilities = match api.capabilities() {
+        Ok(capabilities) => {
+            println!("  capabilities: {}", capabilities);
+            capabilities
+        }
+        Err(e) => {
+            println!("  capabilities: FAILED ({})", e);
+            ok = false;
+            Capabilities::default()
+        }
+    };
+
+    match api.getattr("/", None) {
+        Ok(AttrResponse::Fresh { attrs, .. }) => {
+            if file_type_from_mode(attrs.mode) == FileType::Directory {
+                println!("  getattr /:  ok (mode {:o})", attrs.mode);
+            } else {
+                println!(
+                    "  getattr /:  FAILED (mode {:o} is not a directory)",
+                    attrs.mode
+                );
+                ok = false;
+            }
+        }
+        Ok(AttrResponse::NotModified) => {
+            println!("  getattr /:  FAILED (unexpected 304 Not Modified)");
+            ok = false;
+        }
+        Err(e) => {
+            println!("  getattr /:  FAILED ({})", e);
+            ok = false;
+        }
+    }
+
+    // Mirror the driver: readdirplus if core offers it, plain readdir if
+    // it doesn't or turns out to lack it after all
+    let plus = if capabilities.readdir_plus {
+        api.readdir_plus_page("/", None)
+    } else {
+        Err(ApiError::Status(StatusCode::NOT_FOUND))
+    };
+    let listing = match plus {
+        Ok(page) => Ok(format!("{} entries via readdirplus", page.entries.len())),
+        Err(ApiError::Status(StatusCode::NOT_FOUND)) => api
+            .readdir_page("/", None)
+            .map(|page| format!("{} entries via readdir", page.entries.len())),
+        Err(e) => Err(e),
+    };
+    match listing {
+        Ok(summary) => println!("  readdir /:  ok ({})", summary),
+        Err(e) => {
+            println!("  readdir /:  FAILED ({})", e);
+            ok = false;
+        }
+    }
+
+    println!(
+        "{}",
+        if ok {
+            "All checks passed"
+        } else {
+            "Check failed"
+        }
+    );
+    ok
+}
@@ -0,0 +1,583 @@
+/// Settings given on the command line; these override env vars and the config file
+#[derive(Debug, Default)]
+pub struct CliArgs {
+    pub mountpoint: String,
+    pub config_path: Option<String>,
+    pub api_url: Option<String>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub file_perm: Option<u16>,
+    pub dir_perm: Option<u16>,
+    /// Fork into the background once mounted
+    pub daemon: bool,
+}
+
+/// What the user asked the binary to do
+#[derive(Debug)]
+pub enum Command {
+    Mount(CliArgs),
+    /// Verify the API contract without mounting; the mountpoint is optional
+    Check(CliArgs),
+    /// Write the namespace to a tar.gz without mounting; the mountpoint is
+    /// optional
+    Export(CliArgs, String),
+    /// Time reads of one path without mounting, optionally reporting JSON;
+    /// the mountpoint is optional
+    Benchmark(CliArgs, String, bool),
+    Help,
+    Version,
+}
+
+/// Parse arguments (excluding the program name).
+///
+/// Named flags accept both `--flag value` and `--flag=value`. The legacy
+/// positional form `<mountpoint> [api-url] [uid] [gid]` keeps working, but a
+/// setting may not be given both positionally and as a flag.
+pub fn parse(args: &[String]) -> Result<Command, String> {
+    let mut cli = CliArgs::default();
+    let mut positional = Vec::new();
+    let mut check = false;
+    let mut export = None;
+    let mut benchmark = None;
+    let mut json = false;
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+        if arg == "-h" || arg == "--help" {
+            return Ok(Command::Help);
+        }
+        if arg == "-V" || arg == "--version" {
+            return Ok(Command::Version);
+        }
+        if arg == "--check" {
+            check = true;
+            continue;
+        }
+        if arg == "--json" {
+            json = true;
+            continue;
+        }
+        if arg == "--daemon" || arg == "--foreground" {
+            cli.daemon = arg == "--daemon";
+            continue;
+        }
+        if arg == "--" {
+            positional.extend(iter.by_ref().cloned());
+            break;
+        }
+        if !arg.starts_with("--") {
+            positional.push(arg.clone());
+            continue;
+        }
+
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) => (flag, Some(value.to_string())),
+            None => (arg.as_str(), None),
+        };
+        let mut value = || -> Result<String, String> {
+            match inline_value.clone() {
+                Some(value) => Ok(value),
+                None => iter
+                    .next()
+                    .cloned()
+                    .ok_or_else(|| format!("{} requires a value", flag)),
+            }
+        };
+
+        match flag {
+            "--config" => cli.config_path = Some(value()?),
+            "--api-url" => cli.api_url = Some(value()?),
+            "--uid" => cli.uid = Some(parse_id(flag, &value()?)?),
+            "--gid" => cli.gid = Some(parse_id(flag, &value()?)?),
+            "--file-perm" => cli.file_perm = Some(parse_perm(flag, &value()?)?),
+            "--dir-perm" => cli.dir_perm = Some(parse_perm(flag, &value()?)?),
+            "--export" => export = Some(value()?),
+            "--benchmark" => benchmark = Some(value()?),
+            _ => return Err(format!("unknown option {}", flag)),
+        }
+    }
+
+    let mut positional = positional.into_iter();
+    match positional.next() {
+        Some(mountpoint) => cli.mountpoint = mountpoint,
+        None if check || export.is_some() || benchmark.is_some() => {}
+        None => return Err("missing <mountpoint>".to_string()),
+    }
+
+    if let Some(api_url) = positional.next() {
+        if cli.api_url.is_some() {
+            return Err("api-url given both positionally and via --api-url".to_string());
+        }
+        cli.api_url = Some(api_url);
+    }
+    if let Some(uid) = positional.next() {
+        if cli.uid.is_some() {
+            return Err("uid given both positionally and via --uid".to_string());
+        }
+        cli.uid = Some(parse_id("uid", &uid)?);
+    }
+    if let Some(gid) = positional.next() {
+        if cli.gid.is_some() {
+            return Err("gid given both positionally and via --gid".to_string());
+        }
+        cli.gid = Some(parse_id("gid", &gid)?);
+    }
+    if let Some(extra) = positional.next() {
+        return Err(format!("unexpected argument {}", extra));
+    }
+
+    if [check, export.is_some(), benchmark.is_some()]
+        .iter()
+        .filter(|&&given| given)
+        .count()
+        > 1
+    {
+        return Err("only one of --check, --export and --benchmark may be given".to_string());
+    }
+    if json && benchmark.is_none() {
+        return Err("--json is only valid with --benchmark".to_string());
+    }
+    if check {
+        return Ok(Command::Check(cli));
+    }
+    if let Some(archive) = export {
+        return Ok(Command::Export(cli, archive));
+    }
+    if let Some(path) = benchmark {
+        return Ok(Command::Benchmark(cli, path, json));
+    }
+    Ok(Command::Mount(cli))
+}
+
+/// Expand a leading `~` and `$VAR` / `${VAR}` references in a path, as a
+/// shell would have had the argument not been quoted
+pub fn expand_path(raw: &str) -> Result<String, String> {
+    expand_path_with(raw, |name| std::env::var(name).ok())
+}
+
+/// expand_path, looking variables up with `lookup` instead of the process
+/// environment
+fn expand_path_with(raw: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String, String> {
+    let var = |name: &str| {
+        lookup(name).ok_or_else(|| format!("{} refers to unset variable {}", raw, name))
+    };
+
+    let mut expanded = String::with_capacity(raw.len());
+    let mut rest = raw;
+    if rest == "~" || rest.starts_with("~/") {
+        expanded.push_str(&var("HOME")?);
+        rest = &rest[1..];
+    }
+
+    while let Some(at) = rest.find('$') {
+        expanded.push_str(&rest[..at]);
+        let after = &rest[at + 1..];
+        let (name, next) = match after.strip_prefix('{') {
+            Some(braced) => match braced.find('}') {
+                Some(end) => (&braced[..end], &braced[end + 1..]),
+                None => return Err(format!("unterminated ${{ in {}", raw)),
+            },
+            None => {
+                let end = after
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(after.len());
+                (&after[..end], &after[end..])
+            }
+        };
+        if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+            // Not a variable reference; keep the $ as written
+            expanded.push('$');
+            rest = after;
+            continue;
+        }
+        expanded.push_str(&var(name)?);
+        rest = next;
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+fn parse_id(name: &str, value: &str) -> Result<u32, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid {} value: {}", name.trim_start_matches('-'), value))
+}
+
+fn parse_perm(name: &str, value: &str) -> Result<u16, String> {
+    u16::from_str_radix(value, 8).map_err(|_| {
+        format!(
+            "invalid octal {} value: {}",
+            name.trim_start_matches('-'),
+            value
+        )
+    })
+}
+
+pub fn usage(program: &str) -> String {
+    format!(
+        "Usage: {program} [OPTIONS] <mountpoint> [api-url] [uid] [gid]
+       {program} --check [OPTIONS]
+       {program} --export <tarfile> [OPTIONS]
+       {program} --benchmark <path> [--json] [OPTIONS]
+
+Options:
+  --config <file>     Path to a TOML config file, which must exist
+  --api-url <url>     API URL (default: http://localhost:3000)
+  --uid <uid>         User ID for file ownership (default: 1000)
+  --gid <gid>         Group ID for file ownership (default: 1000)
+  --file-perm <mode>  File permissions in octal (default: 755)
+  --dir-perm <mode>   Directory permissions in octal (default: 755)
+  --check             Check the API contract (health, getattr and readdir of /)
+                      and exit without mounting
+  --export <tarfile>  Write the whole tree to a gzipped tar without mounting;
+                      unreadable files are skipped with a warning
+  --benchmark <path>  Time reads of <path> from the API at increasing sizes and
+                      report latency percentiles and throughput, without
+                      mounting
+  --json              With --benchmark, print the report as one JSON object
+  --daemon            Run in the background once the mount is up; the shell
+                      still sees mount failures (see FUSE_LOG_FILE)
+  --foreground        Stay in the foreground (the default)
+  -h, --help          Print this help
+  -V, --version       Print the version
+
+Example: {program} /mnt/virtual http://localhost:3000 1000 1000
+         {program} /mnt/virtual --api-url http://localhost:3000 --uid 1000
+         {program} /mnt/virtual (defaults: http://localhost:3000, uid=1000, gid=1000)
+
+Settings are read from the config file, then environment variables,
+then command-line arguments, with later sources taking precedence.
+
+Environment variables:
+  FUSE_CONFIG     - Path to a TOML config file; if it is missing a warning is
+                  logged and the defaults are used
+  FUSE_LOG_FILE   - Append log output to this file instead of stderr (with
+                  --daemon, logs are discarded unless this is set)
+  PUID            - User ID for file ownership (default: 1000)
+  PGID            - Group ID for file ownership (default: 1000)
+  FUSE_FILE_PERM  - File permissions in octal (default: 755)
+  FUSE_DIR_PERM   - Directory permissions in octal (default: 755)
+  FUSE_API_URL    - API URL (default: http://localhost:3000), or comma-separated
+                  /prefix=url pairs to merge several cores into one mount,
+                  e.g. /a=http://core-a:3000,/b=http://core-b:3000; each path
+                  goes to the core with the longest matching prefix. A prefix
+                  under one listed before it is ignored with a warning
+  FUSE_ATTR_TTL_SECS - Kernel attribute cache TTL (default: 1)
+  FUSE_CACHE_TTL_SECS - Driver dir/attr cache TTL (default: 30)
+  FUSE_CACHE_TTL_JITTER_PCT - Randomly vary each entry's TTL by up to this
+                  percentage so expiries spread out (default: 10)
+  FUSE_ADAPTIVE_TTL_MAX_SECS - Double a path's attr cache TTL each time
+                  revalidation finds it unchanged, up to this many seconds,
+                  and drop back on any change (default: off)
+  FUSE_REQUEST_TIMEOUT_SECS - API request timeout (default: 30)
+  FUSE_GETATTR_TIMEOUT_SECS - getattr timeout (default: FUSE_REQUEST_TIMEOUT_SECS)
+  FUSE_READDIR_TIMEOUT_SECS - readdir timeout (default: FUSE_REQUEST_TIMEOUT_SECS)
+  FUSE_READ_TIMEOUT_SECS - read timeout (default: FUSE_REQUEST_TIMEOUT_SECS)
+  FUSE_TIMEOUT_MULTIPLIER - Time each API request out at this multiple of its
+                  operation's average latency, once 10 requests have been
+                  timed; the static timeouts apply until then (default: off)
+  FUSE_TIMEOUT_FLOOR_MS - Shortest latency-derived timeout (default: 500)
+  FUSE_TIMEOUT_CEILING_SECS - Longest latency-derived timeout (default: 120)
+  FUSE_WEBDAV_TIMEOUT_SECS - WebDAV read timeout (default: 60)
+  FUSE_CIRCUIT_COOLDOWN_SECS - Seconds to fail fast after repeated API errors (default: 10)
+  FUSE_ERROR_THRESHOLD - API errors before ERROR.txt appears and the circuit
+                  opens (default: 3)
+  FUSE_ERROR_WINDOW_SECS - Count FUSE_ERROR_THRESHOLD errors within this many
+                  seconds instead of consecutive errors
+  FUSE_VERIFY_CHECKSUMS - Set to 1 to verify full-file reads against API checksums
+  FUSE_USE_API_PERMS - Set to 1 to report and check the API's mode bits
+                  (a displayMode attribute from core is always reported as is)
+  FUSE_USE_API_OWNERSHIP - Set to 1 to report the API's uid/gid
+  FUSE_ALLOW_OTHER - Set to 1 to let other users access the mount; unprivileged
+                  users need user_allow_other in /etc/fuse.conf (default: 1 as
+                  root, 0 otherwise). Also enables auto_unmount, so with it
+                  off a crashed driver's mount needs fusermount -u
+  FUSE_DEFAULT_PERMISSIONS - Set to 1 to have the kernel enforce reported perms
+                  (denies access unexpectedly unless reported owner/mode are accurate)
+  FUSE_PRELOAD_DEPTH - Directory levels to preload into the caches after mounting (default: 0)
+  FUSE_STATS_FILE - Set to 1 to show cache statistics in a file at the root
+  FUSE_STATS_FILE_NAME - Name of the statistics file (default: .meta-fuse-stats)
+  FUSE_INFO_FILE  - Set to 1 to show a .meta-fuse file at the root describing the
+                  mount (hidden once a root listing shows a real file of that name)
+  FUSE_CONTROL_FILE - Set to 1 to show a writable .meta-fuse-control file at the
+                  root: writing \"flush\" empties every cache, reading it shows
+                  the last action and the cache sizes (owner and root only)
+  FUSE_STABLE_INODES - Set to 1 to derive inodes from a path hash so they survive
+                  remounts (a rare hash collision falls back to a sequential inode)
+  FUSE_FORWARD_IDENTITY - Set to 1 to send the caller's uid/gid to the API as
+                  X-User-Id/X-Group-Id and cache results per user
+  FUSE_SLOW_REQUEST_MS - Warn about API requests taking at least this long,
+                  retries included; 0 disables (default: 1000)
+  FUSE_ROOT_PREFIX - Core directory to present as the mount root, e.g.
+                  /projects/foo (default: core's root)
+  FUSE_API_USER_AGENT - User-Agent sent to the API (default: meta-fuse-driver/<version>)
+  FUSE_API_BODY_FORMAT - Encode request bodies as json or form
+                  (application/x-www-form-urlencoded) for gateways that only
+                  accept forms; responses are always JSON (default: json)
+  FUSE_API_MAX_DECODED_SIZE - Most bytes a gzip or deflate API response may
+                  inflate to before it is rejected; keep it above
+                  FUSE_MAX_INLINE_SIZE (default: 67108864)
+  FUSE_API_COMPRESS_MIN_SIZE - Gzip API request bodies of at least this many
+                  bytes, for cores accepting Content-Encoding: gzip (default: 0, off)
+  FUSE_API_CLIENT_CERT - PEM client certificate presented to the API and
+                  content URLs, for cores requiring mutual TLS
+  FUSE_API_CLIENT_KEY - PEM private key for FUSE_API_CLIENT_CERT, in PKCS#8
+                  form (BEGIN PRIVATE KEY); both must be set together
+  FUSE_API_CA_CERT - PEM CA certificate trusted for the API and content URLs
+                  besides the system roots, for cores with a private CA
+  FUSE_API_MAX_CONCURRENCY - Most API requests in flight at once (default: unlimited)
+  FUSE_WAIT_FOR_API_SECS - Keep retrying the startup health check with backoff
+                  for this long before giving up (default: 0, fail at once)
+  FUSE_UNHEALTHY_EXIT_SECS - Unmount and exit nonzero once the API has been
+                  unreachable this long (default: never)
+  FUSE_MAX_READ_KB - Largest read request to negotiate with the kernel, e.g. 1024
+  FUSE_BLKSIZE    - Preferred I/O block size reported to stat (default: 4096)
+  FUSE_LOOKUP_EXISTS_FIRST - Set to 1 to probe exists before getattr in lookup,
+                  caching misses (cheaper misses, one extra round trip on hits)
+  FUSE_DIR_MTIME_VALIDATION - Set to 1 to getattr a directory whose cached
+                  listing has expired and keep the listing if its mtime hasn't
+                  changed; only for cores that bump directory mtimes on change
+  FUSE_CONFINE_SYMLINKS - Set to 1 to refuse symlink targets that resolve outside
+                  the mount (advisory: the kernel resolves links itself)
+  FUSE_MAX_DIR_ENTRIES - List at most this many entries per directory, ending a
+                  cut-short listing with a .TRUNCATED marker file, so a huge
+                  directory can't hang ls; paging on the API side is the real
+                  fix (default: unlimited)
+  FUSE_READDIR_EMPTY_ON_ERROR - Set to 1 to list a directory as empty when
+                  core refuses to list it with 401 or 403, for UIs that choke
+                  on listing errors; any other error still fails
+  FUSE_HIDE_PREFIXES - Comma-separated paths to hide from the mount along with
+                  everything under them, e.g. /secrets,/tmp/private (matched by
+                  whole components, so /secret does not hide /secretsauce)
+  FUSE_READ_SOURCE_ORDER - Comma-separated content sources in the order reads
+                  try them, each failure falling through to the next one core
+                  offers: inline, source, webdav, fetchurl; sources left out
+                  are never used; --export and --benchmark follow it too
+                  (default: inline,webdav,fetchurl,source)
+  FUSE_FLATTEN - Set to 1 to list every file directly under the root, named by
+                  its path with components joined by __ (a literal % is
+                  written %25, and an _ at either end of a component or next
+                  to another _ is written %5F)
+  FUSE_NOTIFY_ATIME - Set to 1 to tell the API when files are opened or read,
+                  at most once a minute per file, so it can track access times
+  FUSE_KERNEL_CACHE - Set to 1 to keep file pages in the kernel page cache across
+                  opens, so re-reads skip the driver. Pages drop on change
+                  events or once the kernel sees a new mtime, so a file changed
+                  without an event may read stale for up to FUSE_ATTR_TTL_SECS
+  FUSE_SMALL_FILE_CACHE_SIZE - Files up to this many bytes are cached whole and
+                  re-read from memory until their mtime changes (default: 1048576)
+  FUSE_MAX_INLINE_SIZE - Largest inline read payload in bytes; bigger ones must
+                  come from source_path or webdav_url (default: 8388608)
+  FUSE_READ_ALIGN - Fetch content from core in whole blocks of this many bytes,
+                  e.g. its own block size, and serve reads out of them; the
+                  last block read is kept per open file (default: off, fetch
+                  exactly what the kernel asks for)
+  FUSE_SNIFF_BYTES - Cache the first this many bytes of each file, read on open or
+                  by the first read there, so repeated content-type sniffing
+                  (e.g. by file managers) is served from memory until the
+                  file's mtime changes. Only reads that fit inside it are
+                  cached, so it should be at least the kernel's read size,
+                  usually 131072 (default: off)
+  FUSE_STALE_WHILE_REVALIDATE - Set to 1 to answer from expired cached attributes
+                  while refreshing them in the background
+  FUSE_MAX_STALE_SECS - How long past expiry stale attributes may be served
+                  before blocking on the API again (default: 300)
+  FUSE_PRETTY_JSON - Set to 1 to serve files with a JSON content type
+                  pretty-printed (up to 1 MiB; larger files are served as stored).
+                  Until a file is first opened its stored size is reported
+  FUSE_CREATE_MOUNTPOINT - Set to 1 to create a missing mountpoint (with
+                  FUSE_DIR_PERM) and require it to be empty
+  FUSE_MOUNT_OPTIONS - Extra comma-separated mount options, e.g. allow_root,noatime
+"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn home(name: &str) -> Option<String> {
+        (name == "HOME").then(|| "/home/fuse".to_string())
+    }
+
+    #[test]
+    fn tilde_expands_to_home() {
+        assert_eq!(expand_path_with("~", home).unwrap(), "/home/fuse");
+        assert_eq!(expand_path_with("~/sub", home).unwrap(), "/home/fuse/sub");
+        // Only a leading ~ naming the current user's home is expanded
+        assert_eq!(expand_path_with("~other/x", home).unwrap(), "~other/x");
+        assert_eq!(expand_path_with("/a/~", home).unwrap(), "/a/~");
+    }
+
+    #[test]
+    fn variables_expand_with_or_without_braces() {
+        assert_eq!(expand_path_with("$HOME/x", home).unwrap(), "/home/fuse/x");
+        assert_eq!(expand_path_with("${HOME}x", home).unwrap(), "/home/fusex");
+        assert_eq!(expand_path_with("/cost/$5", home).unwrap(), "/cost/$5");
+        assert!(expand_path_with("${HOME", home).is_err());
+    }
+
+    fn parse_strs(args: &[&str]) -> Result<Command, String> {
+        parse(&args.iter().map(|a| a.to_string()).collect::<Vec<_>>())
+    }
+
+    fn mount(args: &[&str]) -> CliArgs {
+        match parse_strs(args) {
+            Ok(Command::Mount(cli)) => cli,
+            other => panic!("{:?} parsed as {:?}", args, other),
+        }
+    }
+
+    #[test]
+    fn flags_take_separate_or_inline_values() {
+        let cli = mount(&[
+            "--config",
+            "/etc/fuse.toml",
+            "--api-url=http://core:3000",
+            "--uid",
+            "1001",
+            "--gid=1002",
+            "--file-perm",
+            "640",
+            "--dir-perm=750",
+            "--daemon",
+            "/mnt",
+        ]);
+        assert_eq!(cli.mountpoint, "/mnt");
+        assert_eq!(cli.config_path.as_deref(), Some("/etc/fuse.toml"));
+        assert_eq!(cli.api_url.as_deref(), Some("http://core:3000"));
+        assert_eq!((cli.uid, cli.gid), (Some(1001), Some(1002)));
+        assert_eq!((cli.file_perm, cli.dir_perm), (Some(0o640), Some(0o750)));
+        assert!(cli.daemon);
+        // Only the first = splits, and the last of --daemon/--foreground wins
+        let cli = mount(&[
+            "/mnt",
+            "--api-url=http://core/?a=b",
+            "--daemon",
+            "--foreground",
+        ]);
+        assert_eq!(cli.api_url.as_deref(), Some("http://core/?a=b"));
+        assert!(!cli.daemon);
+    }
+
+    #[test]
+    fn legacy_positionals_fill_what_flags_leave() {
+        let cli = mount(&["/mnt", "http://core:3000", "1001", "1002"]);
+        assert_eq!(cli.api_url.as_deref(), Some("http://core:3000"));
+        assert_eq!((cli.uid, cli.gid), (Some(1001), Some(1002)));
+
+        let cli = mount(&["--uid", "7", "/mnt"]);
+        assert_eq!((cli.uid, cli.gid, cli.api_url), (Some(7), None, None));
+
+        for (args, expected) in [
+            (
+                &["/mnt", "http://a", "--api-url", "http://b"][..],
+                "api-url given both",
+            ),
+            (&["/mnt", "http://a", "1", "--uid=2"], "uid given both"),
+            (&["/mnt", "http://a", "1", "2", "--gid=3"], "gid given both"),
+            (
+                &["/mnt", "http://a", "1", "2", "extra"],
+                "unexpected argument extra",
+            ),
+            (&["/mnt", "http://a", "root"], "invalid uid value: root"),
+        ] {
+            let err = parse_strs(args).unwrap_err();
+            assert!(err.contains(expected), "{:?}: {}", args, err);
+        }
+    }
+
+    #[test]
+    fn double_dash_ends_the_flags() {
+        let cli = mount(&["--uid=5", "--", "--odd-mountpoint", "-h"]);
+        assert_eq!(cli.mountpoint, "--odd-mountpoint");
+        assert_eq!(cli.uid, Some(5));
+        assert!(parse_strs(&["--"])
+            .unwrap_err()
+            .contains("missing <mountpoint>"));
+        // A -- given as a flag's value is that value
+        assert_eq!(
+            mount(&["--config", "--", "/mnt"]).config_path.as_deref(),
+            Some("--")
+        );
+    }
+
+    #[test]
+    fn unknown_and_malformed_flags_are_rejected() {
+        for (args, expected) in [
+            (&["--mountpoint", "/mnt"][..], "unknown option --mountpoint"),
+            (&["/mnt", "--verbose=1"], "unknown option --verbose"),
+            (
+                &["/mnt", "--file-perm", "999"],
+                "invalid octal file-perm value: 999",
+            ),
+            (&["/mnt", "--gid=-1"], "invalid gid value: -1"),
+            (&[], "missing <mountpoint>"),
+        ] {
+            let err = parse_strs(args).unwrap_err();
+            assert!(err.contains(expected), "{:?}: {}", args, err);
+        }
+    }
+
+    #[test]
+    fn flags_missing_their_value_are_rejected() {
+        for flag in [
+            "--config",
+            "--api-url",
+            "--uid",
+            "--gid",
+            "--file-perm",
+            "--dir-perm",
+            "--export",
+            "--benchmark",
+        ] {
+            let err = parse_strs(&["/mnt", flag]).unwrap_err();
+            assert_eq!(err, format!("{} requires a value", flag));
+        }
+        // An empty inline value is a value, and fails as one
+        assert_eq!(mount(&["/mnt", "--api-url="]).api_url.as_deref(), Some(""));
+        assert!(parse_strs(&["/mnt", "--uid="]).is_err());
+    }
+
+    #[test]
+    fn commands_are_exclusive_and_need_no_mountpoint() {
+        assert!(matches!(parse_strs(&["/mnt", "--help"]), Ok(Command::Help)));
+        assert!(matches!(
+            parse_strs(&["-V", "--bogus"]),
+            Ok(Command::Version)
+        ));
+        assert!(matches!(parse_strs(&["--check"]), Ok(Command::Check(_))));
+        match parse_strs(&["--export", "out.tar.gz"]) {
+            Ok(Command::Export(cli, archive)) => {
+                assert_eq!(archive, "out.tar.gz");
+                assert_eq!(cli.mountpoint, "");
+            }
+            other => panic!("{:?}", other),
+        }
+        match parse_strs(&["--benchmark=/big", "--json"]) {
+            Ok(Command::Benchmark(_, path, json)) => {
+                assert_eq!((path.as_str(), json), ("/big", true))
+            }
+            other => panic!("{:?}", other),
+        }
+
+        for (args, expected) in [
+            (&["--check", "--export", "a.tgz"][..], "only one of"),
+            (&["--benchmark", "/f", "--check"], "only one of"),
+            (&["/mnt", "--json"], "--json is only valid with --benchmark"),
+        ] {
+            let err = parse_strs(args).unwrap_err();
+            assert!(err.contains(expected), "{:?}: {}", args, err);
+        }
+    }
+
+    #[test]
+    fn unset_home_is_an_error() {
+        let unset = |_: &str| None;
+        for raw in ["~", "~/sub", "$HOME/x"] {
+            let err = expand_path_with(raw, unset).unwrap_err();
+            assert!(err.contains("unset variable HOME"), "{}", err);
+        }
+        assert_eq!(expand_path_with("/plain", unset).unwrap(), "/plain");
+    }
+}
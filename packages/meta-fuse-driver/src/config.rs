@@ -0,0 +1,355 @@
+use serde::{Deserialize, Deserializer};
+use serde_json::{Map, Value};
+use std::path::Path;
+
+/// Options that can be set from a TOML config file.
+///
+/// Every field is optional; environment variables override values from the
+/// file and command-line arguments override both.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileConfig {
+    pub api_url: Option<String>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    #[serde(default, deserialize_with = "deserialize_perm")]
+    pub file_perm: Option<u16>,
+    #[serde(default, deserialize_with = "deserialize_perm")]
+    pub dir_perm: Option<u16>,
+    /// Kernel attribute/entry TTL in seconds
+    pub attr_ttl_secs: Option<u64>,
+    /// Driver-side dir/attr cache TTL in seconds
+    pub cache_ttl_secs: Option<u64>,
+    /// Random spread applied to each entry's TTL, in percent
+    pub cache_ttl_jitter_pct: Option<u32>,
+    /// Cap in seconds for attribute TTLs grown by unchanged revalidations
+    pub adaptive_ttl_max_secs: Option<u64>,
+    pub request_timeout_secs: Option<u64>,
+    /// Per-operation overrides of request_timeout_secs
+    pub getattr_timeout_secs: Option<u64>,
+    pub readdir_timeout_secs: Option<u64>,
+    pub read_timeout_secs: Option<u64>,
+    /// Derive timeouts from average latency times this factor
+    pub timeout_multiplier: Option<f64>,
+    pub timeout_floor_ms: Option<u64>,
+    pub timeout_ceiling_secs: Option<u64>,
+    pub webdav_timeout_secs: Option<u64>,
+    pub circuit_cooldown_secs: Option<u64>,
+    /// API errors that mark it unhealthy
+    pub error_threshold: Option<usize>,
+    /// Count errors within this many seconds instead of consecutive ones
+    pub error_window_secs: Option<u64>,
+    pub verify_checksums: Option<bool>,
+    pub use_api_perms: Option<bool>,
+    pub use_api_ownership: Option<bool>,
+    pub default_permissions: Option<bool>,
+    /// Let users other than the mounting one access the mount
+    pub allow_other: Option<bool>,
+    /// Directory levels to preload into the caches after mounting
+    pub preload_depth: Option<usize>,
+    /// Show a synthetic cache statistics file in the root
+    pub stats_file: Option<bool>,
+    pub stats_file_name: Option<String>,
+    /// Show a synthetic .meta-fuse file in the root describing the mount
+    pub info_file: Option<bool>,
+    /// Show a writable .meta-fuse-control file in the root for cache commands
+    pub control_file: Option<bool>,
+    pub stable_inodes: Option<bool>,
+    pub forward_identity: Option<bool>,
+    /// Log API requests taking at least this many milliseconds as warnings
+    pub slow_request_ms: Option<u64>,
+    pub api_user_agent: Option<String>,
+    /// "json" (the default) or "form" request bodies
+    pub api_body_format: Option<String>,
+    /// Most bytes a compressed response may inflate to
+    pub api_max_decoded_size: Option<u64>,
+    /// Gzip request bodies of at least this many bytes
+    pub api_compress_min_size: Option<usize>,
+    /// PEM client certificate for cores requiring mutual TLS, paired with
+    /// api_client_key
+    pub api_client_cert: Option<String>,
+    /// PEM PKCS#8 private key for api_client_cert
+    pub api_client_key: Option<String>,
+    /// PEM CA certificate trusted besides the system roots
+    pub api_ca_cert: Option<String>,
+    /// Core directory to present as the mount root
+    pub root_prefix: Option<String>,
+    pub api_max_concurrency: Option<usize>,
+    pub unhealthy_exit_secs: Option<u64>,
+    /// Keep retrying the startup health check for this many seconds
+    pub wait_for_api_secs: Option<u64>,
+    pub max_read_kb: Option<u32>,
+    pub blksize: Option<u32>,
+    pub lookup_exists_first: Option<bool>,
+    /// Check a directory's mtime before re-listing it once its listing expires
+    pub dir_mtime_validation: Option<bool>,
+    /// Serve expired attributes while refreshing them in the background
+    pub stale_while_revalidate: Option<bool>,
+    /// How long past expiry stale attributes may still be served
+    pub max_stale_secs: Option<u64>,
+    /// Refuse symlink targets that point outside the mount
+    pub confine_symlinks: Option<bool>,
+    /// Most entries listed per directory before it is cut short
+    pub max_dir_entries: Option<usize>,
+    /// List directories core refuses to list (401/403) as empty instead of
+    /// failing
+    pub readdir_empty_on_error: Option<bool>,
+    /// Comma-separated paths to hide, with everything under them
+    pub hide_prefixes: Option<String>,
+    /// Comma-separated content sources in the order reads try them
+    pub read_source_order: Option<String>,
+    /// List every file directly under the root by its encoded path
+    pub flatten: Option<bool>,
+    /// Notify core of file accesses so it can track atime
+    pub notify_atime: Option<bool>,
+    /// Keep file pages in the kernel page cache across opens
+    pub kernel_cache: Option<bool>,
+    /// Largest file, in bytes, cached whole in memory
+    pub small_file_cache_size: Option<usize>,
+    /// Largest inline read payload, in decoded bytes
+    pub max_inline_size: Option<u64>,
+    /// Widen content fetches to blocks of this many bytes
+    pub read_align: Option<usize>,
+    /// Cache this many bytes from the start of files for type sniffing
+    pub sniff_bytes: Option<usize>,
+    /// Serve JSON files indented
+    pub pretty_json: Option<bool>,
+    /// Create the mountpoint if it does not exist
+    pub create_mountpoint: Option<bool>,
+    /// Extra comma-separated mount options
+    pub mount_options: Option<String>,
+    /// Append log output to this file instead of stderr
+    pub log_file: Option<String>,
+}
+
+impl FileConfig {
+    /// Load a config file. A missing file is an error when `required`
+    /// (named with --config); otherwise (from FUSE_CONFIG) it is logged and
+    /// the defaults are used.
+    pub fn load(path: &Path, required: bool) -> Result<Self, String> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !required => {
+                // Logging is configured from this file, so is not set up yet
+                eprintln!(
+                    "Warning: config file {} not found, using defaults",
+                    path.display()
+                );
+                return Ok(FileConfig::default());
+            }
+            Err(e) => return Err(format!("failed to read {}: {}", path.display(), e)),
+        };
+        Self::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn parse(text: &str) -> Result<Self, String> {
+        let table = parse_toml(text)?;
+        serde_json::from_value(Value::Object(table)).map_err(|e| e.to_string())
+    }
+}
+
+/// Accept permissions either as an octal string ("755") or a TOML integer (0o755)
+fn deserialize_perm<'de, D>(deserializer: D) -> Result<Option<u16>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Perm {
+        Octal(String),
+        Number(u16),
+    }
+
+    match Option::<Perm>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(Perm::Number(n)) => Ok(Some(n)),
+        Some(Perm::Octal(s)) => u16::from_str_radix(&s, 8)
+            .map(Some)
+            .map_err(|_| serde::de::Error::custom(format!("invalid octal permissions \"{}\"", s))),
+    }
+}
+
+/// Parse the flat subset of TOML used by the config file: `key = value`
+/// pairs of strings, integers, floats and booleans, with `#` comments.
+fn parse_toml(text: &str) -> Result<Map<String, Value>, String> {
+    let mut table = Map::new();
+
+    for (index, raw_line) in text.lines().enumerate() {
+        let line_no = index + 1;
+        let line = raw_line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') {
+            return Err(format!("line {}: tables are not supported", line_no));
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("line {}: expected `key = value`", line_no))?;
+        let key = key.trim().trim_matches('"');
+        if key.is_empty() {
+            return Err(format!("line {}: missing key", line_no));
+        }
+
+        let value = parse_value(value.trim()).map_err(|e| format!("line {}: {}", line_no, e))?;
+        if table.insert(key.to_string(), value).is_some() {
+            return Err(format!("line {}: duplicate key `{}`", line_no, key));
+        }
+    }
+
+    Ok(table)
+}
+
+fn parse_value(raw: &str) -> Result<Value, String> {
+    if let Some(rest) = raw.strip_prefix('"') {
+        let mut out = String::new();
+        let mut chars = rest.chars();
+        loop {
+            match chars.next() {
+                None => return Err("unterminated string".to_string()),
+                Some('"') => break,
+                Some('\\') => match chars.next() {
+                    Some('"') => out.push('"'),
+                    Some('\\') => out.push('\\'),
+                    Some('n') => out.push('\n'),
+                    Some('t') => out.push('\t'),
+                    other => return Err(format!("unsupported escape \\{}", other.unwrap_or(' '))),
+                },
+                Some(c) => out.push(c),
+            }
+        }
+        let trailing = chars.as_str().trim();
+        if !trailing.is_empty() && !trailing.starts_with('#') {
+            return Err(format!("unexpected text after string: {}", trailing));
+        }
+        return Ok(Value::String(out));
+    }
+
+    let value = raw.split('#').next().unwrap_or("").trim();
+    match value {
+        "true" => return Ok(Value::Bool(true)),
+        "false" => return Ok(Value::Bool(false)),
+        "" => return Err("missing value".to_string()),
+        _ => {}
+    }
+
+    let digits = value.replace('_', "");
+    let radix_prefixed = digits.starts_with("0o") || digits.starts_with("0x");
+    if !radix_prefixed && digits.contains(['.', 'e', 'E']) {
+        return digits
+            .parse::<f64>()
+            .ok()
+            .filter(|f| f.is_finite())
+            .map(Value::from)
+            .ok_or_else(|| format!("unsupported value `{}`", value));
+    }
+    let parsed = if let Some(octal) = digits.strip_prefix("0o") {
+        i64::from_str_radix(octal, 8)
+    } else if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16)
+    } else {
+        digits.parse::<i64>()
+    };
+
+    parsed
+        .map(Value::from)
+        .map_err(|_| format!("unsupported value `{}`", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_of_each_type_parse() {
+        let config = FileConfig::parse(
+            r#"
+api_url = "http://core:3000"
+uid = 1_000
+file_perm = 0o644
+dir_perm = "750"
+timeout_multiplier = 1.5
+verify_checksums = true
+use_api_perms = false
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.api_url.as_deref(), Some("http://core:3000"));
+        assert_eq!(config.uid, Some(1000));
+        assert_eq!(config.file_perm, Some(0o644));
+        assert_eq!(config.dir_perm, Some(0o750));
+        assert_eq!(config.timeout_multiplier, Some(1.5));
+        assert_eq!(config.verify_checksums, Some(true));
+        assert_eq!(config.use_api_perms, Some(false));
+
+        assert_eq!(parse_value("2.5e1").unwrap(), Value::from(25.0));
+        assert_eq!(parse_value("-0.25").unwrap(), Value::from(-0.25));
+        assert_eq!(parse_value("0x1e").unwrap(), Value::from(30));
+        assert!(parse_value("1.2.3").is_err());
+        assert!(parse_value("1e999").is_err());
+    }
+
+    #[test]
+    fn strings_take_escapes_and_keep_hashes() {
+        assert_eq!(
+            parse_value(r#""a \"b\" \\ c\td\n""#).unwrap(),
+            Value::from("a \"b\" \\ c\td\n")
+        );
+        assert_eq!(
+            parse_value(r##""#not a comment""##).unwrap(),
+            Value::from("#not a comment")
+        );
+        assert!(parse_value(r#""\q""#).unwrap_err().contains("escape"));
+        assert!(parse_value(r#""open"#)
+            .unwrap_err()
+            .contains("unterminated"));
+        assert!(parse_value(r#""a" b"#)
+            .unwrap_err()
+            .contains("after string"));
+    }
+
+    #[test]
+    fn comments_and_blank_lines_are_skipped() {
+        let config = FileConfig::parse(
+            "# leading comment\n\n  uid = 7 # trailing comment\nroot_prefix = \"/p\"  # note\n",
+        )
+        .unwrap();
+        assert_eq!(config.uid, Some(7));
+        assert_eq!(config.root_prefix.as_deref(), Some("/p"));
+
+        let config = FileConfig::parse("\"gid\" = 9\n").unwrap();
+        assert_eq!(config.gid, Some(9));
+    }
+
+    #[test]
+    fn malformed_and_unknown_entries_are_errors() {
+        let err = FileConfig::parse("uid = 1\nuid_typo = 2\n").unwrap_err();
+        assert!(err.contains("uid_typo"), "{}", err);
+        let err = FileConfig::parse("uid = 1\nuid = 2\n").unwrap_err();
+        assert!(err.contains("line 2: duplicate key"), "{}", err);
+        let err = FileConfig::parse("[section]\n").unwrap_err();
+        assert!(err.contains("tables are not supported"), "{}", err);
+        let err = FileConfig::parse("uid\n").unwrap_err();
+        assert!(err.contains("line 1: expected `key = value`"), "{}", err);
+        let err = FileConfig::parse("uid = \n").unwrap_err();
+        assert!(err.contains("missing value"), "{}", err);
+        let err = FileConfig::parse("uid = \"1\"\n").unwrap_err();
+        assert!(err.contains("invalid type"), "{}", err);
+        let err = FileConfig::parse("file_perm = \"9\"\n").unwrap_err();
+        assert!(err.contains("invalid octal permissions"), "{}", err);
+    }
+
+    #[test]
+    fn missing_file_is_an_error_only_when_required() {
+        let path = std::env::temp_dir().join(format!("fuse-no-config-{}.toml", std::process::id()));
+        assert!(FileConfig::load(&path, false).unwrap().api_url.is_none());
+        let err = FileConfig::load(&path, true).unwrap_err();
+        assert!(err.contains("failed to read"), "{}", err);
+
+        std::fs::write(&path, "uid = oops\n").unwrap();
+        let err = FileConfig::load(&path, false).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert!(err.contains("line 1: unsupported value `oops`"), "{}", err);
+    }
+}
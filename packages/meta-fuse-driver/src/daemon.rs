@@ -0,0 +1,80 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::path::Path;
+
+/// The background half of a `--daemon` fork, holding the pipe the waiting
+/// parent reads the mount outcome from
+pub struct Detached {
+    ready: File,
+}
+
+/// Fork into the background. Only the child returns; the parent stays in
+/// the foreground until the child reports a mount, so failures before that
+/// point still reach the shell, then exits with the child's status.
+///
+/// Must run before any threads are started, since only the calling thread
+/// survives the fork.
+pub fn detach() -> Result<Detached, String> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(format!("pipe: {}", std::io::Error::last_os_error()));
+    }
+    let (mut ready_rx, ready_tx) =
+        unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+
+    match unsafe { libc::fork() } {
+        -1 => Err(format!("fork: {}", std::io::Error::last_os_error())),
+        0 => {
+            drop(ready_rx);
+            // Leave the shell's session so closing the terminal doesn't
+            // take the mount down with it
+            unsafe { libc::setsid() };
+            Ok(Detached { ready: ready_tx })
+        }
+        child => {
+            drop(ready_tx);
+            let mut byte = [0u8; 1];
+            if matches!(ready_rx.read(&mut byte), Ok(1)) {
+                std::process::exit(0);
+            }
+            // The child exited (or crashed) without mounting
+            let mut status = 0;
+            unsafe { libc::waitpid(child, &mut status, 0) };
+            let code = if libc::WIFEXITED(status) {
+                libc::WEXITSTATUS(status)
+            } else {
+                1
+            };
+            std::process::exit(if code == 0 { 1 } else { code });
+        }
+    }
+}
+
+impl Detached {
+    /// Point stdin and stdout at /dev/null and stderr at `log_file` (or
+    /// /dev/null), then release the parent. The parent is released even if
+    /// redirecting fails, since the mount itself is up.
+    pub fn mounted(mut self, log_file: Option<&Path>) -> std::io::Result<()> {
+        let redirected = redirect_stdio(log_file);
+        let _ = self.ready.write_all(b"1");
+        redirected
+    }
+}
+
+fn redirect_stdio(log_file: Option<&Path>) -> std::io::Result<()> {
+    let null = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")?;
+    let stderr = match log_file {
+        Some(path) => OpenOptions::new().create(true).append(true).open(path)?,
+        None => null.try_clone()?,
+    };
+    for (file, fd) in [(&null, 0), (&null, 1), (&stderr, 2)] {
+        if unsafe { libc::dup2(file.as_raw_fd(), fd) } == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
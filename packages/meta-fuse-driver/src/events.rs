@@ -0,0 +1,124 @@
+use crate::api_client::{ApiClient, ApiError};
+use crate::SharedCaches;
+use fuser::Notifier;
+use log::{debug, info, warn};
+use reqwest::StatusCode;
+use std::ffi::OsStr;
+use std::io::{BufRead, BufReader};
+use std::time::Duration;
+
+const INITIAL_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// Spawn a thread that follows core's change events and invalidates the
+/// driver and kernel caches for every path it reports.
+///
+/// The stream is reconnected with backoff when it drops; if core doesn't
+/// offer the events endpoint at all the thread exits quietly.
+pub fn spawn_listener(api: ApiClient, caches: SharedCaches, notifier: Notifier) {
+    let spawned = std::thread::Builder::new()
+        .name("fuse-events".to_string())
+        .spawn(move || {
+            let mut delay = INITIAL_RECONNECT_DELAY;
+            loop {
+                match api.open_event_stream() {
+                    Ok(response) => {
+                        info!("Connected to API change events");
+                        delay = INITIAL_RECONNECT_DELAY;
+                        consume_stream(BufReader::new(response), &api, &caches, &notifier);
+                        warn!("API change event stream closed, reconnecting");
+                    }
+                    Err(ApiError::Status(StatusCode::NOT_FOUND)) => {
+                        info!("API does not provide change events, relying on cache TTLs");
+                        return;
+                    }
+                    Err(e) => {
+                        debug!("Failed to connect to API change events: {}", e);
+                    }
+                }
+
+                std::thread::sleep(delay);
+                delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+            }
+        });
+
+    if let Err(e) = spawned {
+        warn!("Failed to start change event listener: {}", e);
+    }
+}
+
+/// Read Server-Sent Events until the stream ends or errors
+fn consume_stream<R: BufRead>(
+    reader: R,
+    api: &ApiClient,
+    caches: &SharedCaches,
+    notifier: &Notifier,
+) {
+    let mut data = String::new();
+
+    for line in reader.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                debug!("Error reading change event stream: {}", e);
+                return;
+            }
+        };
+
+        if line.is_empty() {
+            if !data.is_empty() {
+                // Changes outside the root prefix are not visible in the mount
+                for path in event_paths(&data).iter().filter_map(|p| api.mount_path(p)) {
+                    invalidate(&path, caches, notifier);
+                }
+                data.clear();
+            }
+        } else if let Some(value) = line.strip_prefix("data:") {
+            if !data.is_empty() {
+                data.push('\n');
+            }
+            data.push_str(value.strip_prefix(' ').unwrap_or(value));
+        }
+        // "event:", "id:", "retry:" and ":" comment lines carry nothing we need
+    }
+}
+
+/// Extract changed paths from an event payload.
+///
+/// Accepts `{"path": "/a"}`, `{"paths": ["/a", "/b"]}` or a bare path.
+fn event_paths(data: &str) -> Vec<String> {
+    match serde_json::from_str::<serde_json::Value>(data) {
+        Ok(value) => {
+            let mut paths = Vec::new();
+            if let Some(path) = value.get("path").and_then(|p| p.as_str()) {
+                paths.push(path.to_string());
+            }
+            if let Some(list) = value.get("paths").and_then(|p| p.as_array()) {
+                paths.extend(list.iter().filter_map(|p| p.as_str()).map(str::to_string));
+            }
+            paths
+        }
+        Err(_) if data.starts_with('/') => vec![data.trim().to_string()],
+        Err(_) => {
+            debug!("Ignoring unrecognised change event: {}", data);
+            Vec::new()
+        }
+    }
+}
+
+fn invalidate(path: &str, caches: &SharedCaches, notifier: &Notifier) {
+    debug!("Change event for {}", path);
+    let (entry, ino) = caches.invalidate(path);
+
+    // ENOENT just means the kernel has nothing cached for it
+    if let Some((parent, name)) = entry {
+        if let Err(e) = notifier.inval_entry(parent, OsStr::new(&name)) {
+            debug!("inval_entry {} failed: {}", path, e);
+        }
+    }
+    if let Some(ino) = ino {
+        if let Err(e) = notifier.inval_inode(ino, 0, 0) {
+            debug!("inval_inode {} failed: {}", path, e);
+        }
+    }
+}
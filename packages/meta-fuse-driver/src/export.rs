@@ -0,0 +1,726 @@
+//! `--export`: crawl the API and write the namespace as a gzipped tar.
+//!
+//! Entries are streamed in the order they are listed, so only the queue of
+//! directories still to visit is held in memory. Headers are POSIX ustar,
+//! with a pax extended header in front of any entry whose path, link target
+//! or size doesn't fit the ustar fields. The archive is compressed in
+//! process as it is written.
+
+use crate::api_client::{
+    ApiClient, ApiError, AttrResponse, BodyFormat, Capabilities, Compression, FileAttributes,
+    ReadResponse, ReadResult, RequestTimeouts, TlsSettings,
+};
+use crate::gzip::GzipWriter;
+use crate::read_source::{self, ReadSource};
+use crate::{file_type_from_mode, retry_interrupted};
+use fuser::FileType;
+use reqwest::StatusCode;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const BLOCK: usize = 512;
+/// Largest size the 12-byte ustar field can hold (11 octal digits)
+const USTAR_MAX_SIZE: u64 = 0o77777777777;
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Export the tree below the API root to `archive`, reading content from
+/// the sources in `read_source_order`. Files that can't be read are skipped
+/// with a warning; returns false only if the archive itself couldn't be
+/// produced.
+#[allow(clippy::too_many_arguments)]
+pub fn run(
+    api_url: &str,
+    timeouts: RequestTimeouts,
+    user_agent: Option<String>,
+    root_prefix: Option<String>,
+    body_format: BodyFormat,
+    compression: Compression,
+    tls: TlsSettings,
+    read_source_order: &[ReadSource],
+    archive: &Path,
+) -> bool {
+    println!(
+        "Exporting meta-fuse API at {} to {}",
+        api_url,
+        archive.display()
+    );
+    let connect_timeout = timeouts.global;
+
+    let api = match ApiClient::new(
+        api_url.to_string(),
+        timeouts,
+        None,
+        user_agent,
+        None,
+        root_prefix,
+        body_format,
+        compression,
+        tls,
+    ) {
+        Ok(api) => api,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return false;
+        }
+    };
+    // Content URLs may serve files far larger than any API response, so
+    // only connecting is bounded
+    let http = match api
+        .http_client_builder()
+        .connect_timeout(connect_timeout)
+        .timeout(None)
+        .build()
+    {
+        Ok(http) => http,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return false;
+        }
+    };
+    let capabilities = api.capabilities().unwrap_or_else(|e| {
+        eprintln!("Warning: capabilities: {}, assuming base endpoints", e);
+        Capabilities::default()
+    });
+
+    let file = match File::create(archive) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Error: {}: {}", archive.display(), e);
+            return false;
+        }
+    };
+
+    let mut exporter = Exporter {
+        api: &api,
+        http: &http,
+        readdir_plus: capabilities.readdir_plus,
+        read_source_order,
+        tar: TarWriter {
+            out: GzipWriter::new(BufWriter::new(file)),
+        },
+        stats: Stats::default(),
+        last_progress: Instant::now(),
+    };
+    let written = exporter.walk().and_then(|()| exporter.tar.finish());
+    let Exporter { tar, stats, .. } = exporter;
+    // Ending the gzip member flushes the file too
+    match written.and_then(|()| tar.out.finish()) {
+        Ok(_) => {
+            println!(
+                "Exported {} files and {} directories ({} bytes), skipped {}",
+                stats.files, stats.dirs, stats.bytes, stats.skipped
+            );
+            true
+        }
+        Err(e) => {
+            eprintln!("Error: writing {}: {}", archive.display(), e);
+            false
+        }
+    }
+}
+
+#[derive(Default, Clone, Copy)]
+struct Stats {
+    files: u64,
+    dirs: u64,
+    bytes: u64,
+    skipped: u64,
+}
+
+struct Exporter<'a, W: Write> {
+    api: &'a ApiClient,
+    http: &'a reqwest::blocking::Client,
+    readdir_plus: bool,
+    read_source_order: &'a [ReadSource],
+    tar: TarWriter<W>,
+    stats: Stats,
+    last_progress: Instant,
+}
+
+impl<W: Write> Exporter<'_, W> {
+    /// Visit every directory depth-first. Listing and per-entry errors are
+    /// warnings; only failing to write the archive stops the walk.
+    fn walk(&mut self) -> io::Result<()> {
+        let mut pending = vec!["/".to_string()];
+        while let Some(dir) = pending.pop() {
+            let entries = match self.list(&dir) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    eprintln!("Warning: skipping directory {}: {}", dir, e);
+                    self.stats.skipped += 1;
+                    continue;
+                }
+            };
+            for (name, attrs) in entries {
+                if name == "." || name == ".." {
+                    continue;
+                }
+                let path = if dir == "/" {
+                    format!("/{}", name)
+                } else {
+                    format!("{}/{}", dir, name)
+                };
+                let attrs = match attrs {
+                    Some(attrs) => attrs,
+                    None => match self.api.getattr(&path, None) {
+                        Ok(AttrResponse::Fresh { attrs, .. }) => attrs,
+                        Ok(AttrResponse::NotModified) => {
+                            self.skip(&path, "unexpected 304 Not Modified");
+                            continue;
+                        }
+                        Err(e) => {
+                            self.skip(&path, e);
+                            continue;
+                        }
+                    },
+                };
+                if self.entry(&path, &attrs)? {
+                    pending.push(path);
+                }
+                self.progress();
+            }
+        }
+        Ok(())
+    }
+
+    /// All names in `dir`, with attributes where readdirplus supplied them
+    fn list(&self, dir: &str) -> Result<Vec<(String, Option<FileAttributes>)>, ApiError> {
+        let mut entries = Vec::new();
+        let mut cursor: Option<String> = None;
+        let mut plus = self.readdir_plus;
+        loop {
+            let next_cursor = if plus {
+                match self.api.readdir_plus_page(dir, cursor.as_deref()) {
+                    Ok(page) => {
+                        entries.extend(page.entries.into_iter().map(|e| (e.name, e.attrs)));
+                        page.next_cursor
+                    }
+                    Err(ApiError::Status(StatusCode::NOT_FOUND)) if cursor.is_none() => {
+                        plus = false;
+                        continue;
+                    }
+                    Err(e) => return Err(e),
+                }
+            } else {
+                let page = self.api.readdir_page(dir, cursor.as_deref())?;
+                entries.extend(page.entries.into_iter().map(|name| (name, None)));
+                page.next_cursor
+            };
+            match next_cursor {
+                Some(next) => cursor = Some(next),
+                None => return Ok(entries),
+            }
+        }
+    }
+
+    /// Write one entry; returns whether it is a directory to descend into
+    fn entry(&mut self, path: &str, attrs: &FileAttributes) -> io::Result<bool> {
+        let name = &path[1..];
+        let mut header = Header::new(attrs);
+        let file_type = file_type_from_mode(attrs.mode);
+        match file_type {
+            FileType::Directory => {
+                header.kind = b'5';
+                self.tar.header(&format!("{}/", name), &header)?;
+                self.stats.dirs += 1;
+                return Ok(true);
+            }
+            FileType::Symlink => {
+                header.kind = b'2';
+                header.link = match self.api.readlink(path) {
+                    Ok(target) => target,
+                    Err(e) => {
+                        self.skip(path, e);
+                        return Ok(false);
+                    }
+                };
+                self.tar.header(name, &header)?;
+            }
+            FileType::CharDevice | FileType::BlockDevice | FileType::NamedPipe => {
+                header.kind = match file_type {
+                    FileType::CharDevice => b'3',
+                    FileType::BlockDevice => b'4',
+                    _ => b'6',
+                };
+                header.device = (
+                    ((attrs.rdev >> 8) & 0xfff) as u64,
+                    ((attrs.rdev & 0xff) | ((attrs.rdev >> 12) & 0xfff00)) as u64,
+                );
+                self.tar.header(name, &header)?;
+            }
+            FileType::Socket => {
+                self.skip(path, "sockets can't be archived");
+                return Ok(false);
+            }
+            FileType::RegularFile => {
+                let (size, content) = match self.open(path) {
+                    Ok(opened) => opened,
+                    Err(e) => {
+                        self.skip(path, e);
+                        return Ok(false);
+                    }
+                };
+                header.size = size;
+                self.tar.header(name, &header)?;
+                if let Err(e) = self.tar.content(content, size)? {
+                    eprintln!("Warning: {} truncated in the archive: {}", path, e);
+                }
+                self.stats.bytes += size;
+            }
+        }
+        self.stats.files += 1;
+        Ok(false)
+    }
+
+    /// Size and a reader for a file's content, taken from the sources core
+    /// offers in FUSE_READ_SOURCE_ORDER, as the mounted driver does; a
+    /// source that can't be opened falls through to the next.
+    fn open(&self, path: &str) -> Result<(u64, Box<dyn Read>), Box<dyn std::error::Error>> {
+        let ReadResponse::Fresh(result) = self.api.read(path, None)? else {
+            return Err("unexpected 304 Not Modified".into());
+        };
+        let mut last_error = None;
+        for &source in self.read_source_order {
+            if !source.offered_by(&result) {
+                continue;
+            }
+            match self.open_source(&result, source) {
+                Ok(opened) => return Ok(opened),
+                Err(e) => {
+                    eprintln!("Warning: reading {} from {} failed: {}", path, source, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        if let Some(e) = last_error {
+            return Err(e);
+        }
+        if result.size == 0 {
+            return Ok((0, Box::new(io::empty())));
+        }
+        Err(read_source::no_source_error(self.read_source_order))
+    }
+
+    fn open_source(
+        &self,
+        result: &ReadResult,
+        source: ReadSource,
+    ) -> Result<(u64, Box<dyn Read>), Box<dyn std::error::Error>> {
+        let url = match source {
+            ReadSource::Inline => {
+                let content = result.content.clone().unwrap_or_default().data;
+                return Ok((content.len() as u64, Box::new(io::Cursor::new(content))));
+            }
+            ReadSource::Source => {
+                let source_path = result.source_path.as_deref().unwrap_or_default();
+                let file = retry_interrupted(|| File::open(source_path))?;
+                return Ok((result.size, Box::new(file)));
+            }
+            ReadSource::Webdav => result.webdav_url.as_deref(),
+            ReadSource::FetchUrl => result.fetch_url.as_deref(),
+        };
+        let response = self
+            .http
+            .get(url.unwrap_or_default())
+            .send()?
+            .error_for_status()?;
+        Ok((result.size, Box::new(response)))
+    }
+
+    fn skip(&mut self, path: &str, reason: impl std::fmt::Display) {
+        eprintln!("Warning: skipping {}: {}", path, reason);
+        self.stats.skipped += 1;
+    }
+
+    fn progress(&mut self) {
+        if self.last_progress.elapsed() >= PROGRESS_INTERVAL {
+            self.last_progress = Instant::now();
+            println!(
+                "  {} files, {} directories, {} bytes so far",
+                self.stats.files, self.stats.dirs, self.stats.bytes
+            );
+        }
+    }
+}
+
+/// Metadata for one tar header; the name is passed separately
+struct Header {
+    kind: u8,
+    mode: u32,
+    owner: (u32, u32),
+    size: u64,
+    mtime: u64,
+    link: String,
+    device: (u64, u64),
+}
+
+impl Header {
+    fn new(attrs: &FileAttributes) -> Self {
+        Header {
+            kind: b'0',
+            mode: attrs.mode & 0o7777,
+            owner: (attrs.uid.unwrap_or(0), attrs.gid.unwrap_or(0)),
+            size: 0,
+            mtime: attrs.mtime.max(0.0) as u64,
+            link: String::new(),
+            device: (0, 0),
+        }
+    }
+}
+
+struct TarWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> TarWriter<W> {
+    fn header(&mut self, name: &str, header: &Header) -> io::Result<()> {
+        let split = split_name(name);
+        let mut pax = Vec::new();
+        if split.is_none() {
+            pax_record(&mut pax, "path", name);
+        }
+        if header.link.len() > 100 {
+            pax_record(&mut pax, "linkpath", &header.link);
+        }
+        if header.size > USTAR_MAX_SIZE {
+            pax_record(&mut pax, "size", &header.size.to_string());
+        }
+        if !pax.is_empty() {
+            let mut block = [0u8; BLOCK];
+            fill_header(
+                &mut block,
+                ("", "PaxHeader"),
+                b'x',
+                0o644,
+                (0, 0),
+                pax.len() as u64,
+                header.mtime,
+                "",
+                (0, 0),
+            );
+            self.out.write_all(&block)?;
+            self.out.write_all(&pax)?;
+            self.pad(pax.len() as u64)?;
+        }
+
+        let mut block = [0u8; BLOCK];
+        fill_header(
+            &mut block,
+            split.unwrap_or(("", truncate(name, 100))),
+            header.kind,
+            header.mode,
+            header.owner,
+            header.size.min(USTAR_MAX_SIZE),
+            header.mtime,
+            truncate(&header.link, 100),
+            header.device,
+        );
+        self.out.write_all(&block)
+    }
+
+    /// Copy exactly `size` bytes of content. A source that fails or ends
+    /// early is padded with zeros so the archive stays well-formed; that
+    /// error is returned in the inner result, while errors writing the
+    /// archive are returned in the outer one.
+    fn content(&mut self, mut source: Box<dyn Read>, size: u64) -> io::Result<io::Result<()>> {
+        let mut buffer = vec![0u8; 64 * 1024];
+        let mut remaining = size;
+        let mut outcome = Ok(());
+        while remaining > 0 {
+            let want = remaining.min(buffer.len() as u64) as usize;
+            match source.read(&mut buffer[..want]) {
+                Ok(0) => {
+                    outcome = Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        format!("{} of {} bytes missing", remaining, size),
+                    ));
+                    break;
+                }
+                Ok(n) => {
+                    self.out.write_all(&buffer[..n])?;
+                    remaining -= n as u64;
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    outcome = Err(e);
+                    break;
+                }
+            }
+        }
+        if remaining > 0 {
+            buffer.fill(0);
+            while remaining > 0 {
+                let n = remaining.min(buffer.len() as u64) as usize;
+                self.out.write_all(&buffer[..n])?;
+                remaining -= n as u64;
+            }
+        }
+        self.pad(size)?;
+        Ok(outcome)
+    }
+
+    fn pad(&mut self, size: u64) -> io::Result<()> {
+        let tail = (size % BLOCK as u64) as usize;
+        if tail != 0 {
+            self.out.write_all(&[0u8; BLOCK][..BLOCK - tail])?;
+        }
+        Ok(())
+    }
+
+    /// Two zero blocks end the archive
+    fn finish(&mut self) -> io::Result<()> {
+        self.out.write_all(&[0u8; 2 * BLOCK])?;
+        self.out.flush()
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn fill_header(
+    block: &mut [u8; BLOCK],
+    (prefix, name): (&str, &str),
+    kind: u8,
+    mode: u32,
+    (uid, gid): (u32, u32),
+    size: u64,
+    mtime: u64,
+    link: &str,
+    (major, minor): (u64, u64),
+) {
+    block[..name.len()].copy_from_slice(name.as_bytes());
+    octal(&mut block[100..108], mode as u64);
+    octal(&mut block[108..116], uid as u64);
+    octal(&mut block[116..124], gid as u64);
+    octal(&mut block[124..136], size);
+    octal(&mut block[136..148], mtime);
+    block[156] = kind;
+    block[157..157 + link.len()].copy_from_slice(link.as_bytes());
+    block[257..263].copy_from_slice(b"ustar\0");
+    block[263..265].copy_from_slice(b"00");
+    octal(&mut block[329..337], major);
+    octal(&mut block[337..345], minor);
+    block[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+
+    // The checksum is computed with its own field read as spaces
+    block[148..156].fill(b' ');
+    let sum: u32 = block.iter().map(|&b| b as u32).sum();
+    octal(&mut block[148..155], sum as u64);
+}
+
+/// Zero-padded octal filling all but the last byte of `field`, which stays
+/// NUL. Values too large for the field are clamped to its maximum.
+fn octal(field: &mut [u8], value: u64) {
+    let digits = field.len() - 1;
+    let max = (1u64 << (3 * digits as u32)) - 1;
+    let text = format!("{:0width$o}", value.min(max), width = digits);
+    field[..digits].copy_from_slice(text.as_bytes());
+    field[digits] = 0;
+}
+
+/// Split a name into the ustar prefix and name fields, or None if it
+/// needs a pax path record
+fn split_name(name: &str) -> Option<(&str, &str)> {
+    if name.len() <= 100 {
+        return Some(("", name));
+    }
+    name.char_indices()
+        .filter(|&(i, c)| c == '/' && i <= 155)
+        .map(|(i, _)| (&name[..i], &name[i + 1..]))
+        .find(|(_, rest)| !rest.is_empty() && rest.len() <= 100)
+}
+
+/// The longest prefix of `s` within `max` bytes that ends on a char boundary
+fn truncate(s: &str, max: usize) -> &str {
+    if s.len() <= max {
+        return s;
+    }
+    let mut end = max;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+/// Append a `<len> <key>=<value>\n` record, where len counts the whole
+/// record including its own digits
+fn pax_record(records: &mut Vec<u8>, key: &str, value: &str) {
+    let body = key.len() + value.len() + 3;
+    let mut len = body + body.to_string().len();
+    if len.to_string().len() + body != len {
+        len = body + len.to_string().len();
+    }
+    records.extend_from_slice(format!("{} {}={}\n", len, key, value).as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_client::tests::test_timeouts;
+    use crate::test_server::Response;
+    use crate::tests::start_core;
+
+    fn attrs(mode: u32) -> FileAttributes {
+        serde_json::from_value(serde_json::json!({
+            "size": 0, "mode": mode, "mtime": 1_700_000_000.0, "uid": 10, "gid": 20,
+        }))
+        .unwrap()
+    }
+
+    fn field(block: &[u8], range: std::ops::Range<usize>) -> &str {
+        let bytes = &block[range];
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        std::str::from_utf8(&bytes[..end]).unwrap()
+    }
+
+    fn octal_field(block: &[u8], range: std::ops::Range<usize>) -> u64 {
+        u64::from_str_radix(field(block, range).trim(), 8).unwrap()
+    }
+
+    /// The stored checksum matches the sum of the block with the checksum
+    /// field read as spaces
+    fn assert_checksum(block: &[u8]) {
+        let mut blank = block.to_vec();
+        blank[148..156].fill(b' ');
+        let sum: u64 = blank.iter().map(|&b| u64::from(b)).sum();
+        assert_eq!(octal_field(block, 148..155), sum);
+        assert_eq!(block[155], b' ');
+    }
+
+    fn writer() -> TarWriter<Vec<u8>> {
+        TarWriter { out: Vec::new() }
+    }
+
+    #[test]
+    fn headers_carry_valid_checksums_and_ustar_fields() {
+        let mut tar = writer();
+        let mut header = Header::new(&attrs(0o100640));
+        header.size = 3;
+        tar.header("dir/file.txt", &header).unwrap();
+        let block = &tar.out[..BLOCK];
+
+        assert_eq!(tar.out.len(), BLOCK);
+        assert_eq!(field(block, 0..100), "dir/file.txt");
+        assert_eq!(octal_field(block, 100..108), 0o640);
+        assert_eq!(
+            (octal_field(block, 108..116), octal_field(block, 116..124)),
+            (10, 20)
+        );
+        assert_eq!(octal_field(block, 124..136), 3);
+        assert_eq!(octal_field(block, 136..148), 1_700_000_000);
+        assert_eq!(block[156], b'0');
+        assert_eq!(&block[257..265], b"ustar\x0000");
+        assert_checksum(block);
+    }
+
+    #[test]
+    fn long_names_use_the_prefix_field_or_a_pax_path_record() {
+        // Splits at a slash into prefix and name
+        let split = format!("{}/{}", "d".repeat(120), "f".repeat(90));
+        let mut tar = writer();
+        tar.header(&split, &Header::new(&attrs(0o100644))).unwrap();
+        assert_eq!(tar.out.len(), BLOCK);
+        assert_eq!(field(&tar.out, 0..100), "f".repeat(90));
+        assert_eq!(field(&tar.out, 345..500), "d".repeat(120));
+
+        // One component too long for either field needs a pax header
+        let long = format!("top/{}", "é".repeat(80));
+        let mut tar = writer();
+        tar.header(&long, &Header::new(&attrs(0o100644))).unwrap();
+        let (pax_header, rest) = tar.out.split_at(BLOCK);
+        assert_eq!(pax_header[156], b'x');
+        assert_checksum(pax_header);
+        let len = octal_field(pax_header, 124..136) as usize;
+        let record = std::str::from_utf8(&rest[..len]).unwrap();
+        assert_eq!(record, format!("{} path={}\n", len, long));
+        assert!(rest[len..BLOCK].iter().all(|&b| b == 0));
+
+        let header = &rest[BLOCK..];
+        assert_eq!(header.len(), BLOCK);
+        assert_eq!(header[156], b'0');
+        // The ustar name is cut on a char boundary
+        assert!(field(header, 0..100).len() <= 100);
+        assert!(long.starts_with(field(header, 0..100)));
+        assert_checksum(header);
+    }
+
+    #[test]
+    fn pax_record_lengths_count_themselves() {
+        for value_len in [0, 1, 85, 86, 87, 88, 94, 95, 96, 990, 991, 992, 1000] {
+            let mut records = Vec::new();
+            pax_record(&mut records, "path", &"v".repeat(value_len));
+            let text = std::str::from_utf8(&records).unwrap();
+            let (len, _) = text.split_once(' ').unwrap();
+            assert_eq!(len.parse::<usize>().unwrap(), records.len(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn content_is_padded_to_whole_blocks() {
+        let mut tar = writer();
+        let outcome = tar.content(Box::new(&b"abc"[..]), 3).unwrap();
+        assert!(outcome.is_ok());
+        assert_eq!(tar.out.len(), BLOCK);
+        assert_eq!(&tar.out[..3], b"abc");
+        assert!(tar.out[3..].iter().all(|&b| b == 0));
+
+        // A whole block needs no padding
+        let mut tar = writer();
+        let data = vec![7u8; BLOCK];
+        tar.content(Box::new(io::Cursor::new(data)), BLOCK as u64)
+            .unwrap()
+            .unwrap();
+        assert_eq!(tar.out.len(), BLOCK);
+
+        // A source ending early is made up with zeros to its stated size
+        let mut tar = writer();
+        let outcome = tar.content(Box::new(&b"ab"[..]), 600).unwrap();
+        assert_eq!(outcome.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(tar.out.len(), 2 * BLOCK);
+        assert!(tar.out[2..].iter().all(|&b| b == 0));
+
+        tar.finish().unwrap();
+        assert_eq!(tar.out.len(), 4 * BLOCK);
+    }
+
+    #[test]
+    fn export_writes_a_gzipped_tar() {
+        use base64::Engine;
+        let server = start_core(|request| match request.target.as_str() {
+            "/api/fuse/readdir" => Response::json(serde_json::json!({ "entries": ["a.txt"] })),
+            "/api/fuse/getattr" => Response::json(serde_json::json!({
+                "size": 5, "mode": 0o100644, "mtime": 1.0,
+            })),
+            "/api/fuse/read" => Response::json(serde_json::json!({
+                "content": base64::prelude::BASE64_STANDARD.encode(b"hello"),
+                "size": 5,
+            })),
+            _ => Response::new(404, ""),
+        });
+        let archive = std::env::temp_dir().join(format!("fuse-export-{}.tgz", std::process::id()));
+        assert!(run(
+            &server.url,
+            test_timeouts(),
+            None,
+            None,
+            BodyFormat::Json,
+            Compression::default(),
+            TlsSettings::default(),
+            &read_source::DEFAULT_ORDER,
+            &archive,
+        ));
+        let compressed = std::fs::read(&archive).unwrap();
+        std::fs::remove_file(&archive).unwrap();
+
+        let mut tar = Vec::new();
+        crate::inflate::decoder("gzip", &compressed[..], u64::MAX)
+            .unwrap()
+            .read_to_end(&mut tar)
+            .unwrap();
+        assert_eq!(tar.len(), 4 * BLOCK);
+        assert_eq!(field(&tar, 0..100), "a.txt");
+        assert_checksum(&tar[..BLOCK]);
+        assert_eq!(&tar[BLOCK..BLOCK + 5], b"hello");
+        assert!(tar[BLOCK + 5..].iter().all(|&b| b == 0));
+    }
+}
@@ -0,0 +1,179 @@
+//! Names for the flat view, where every file in the tree is listed directly
+//! under the root.
+//!
+//! A path's components are joined with `__`. Inside a component `%` is
+//! written `%25`, and any `_` that starts or ends the component or touches
+//! another `_` is written `%5F`, so an encoded component never contains the
+//! separator and never sits against it. Decoding accepts only names in this
+//! canonical form, which keeps the mapping one-to-one.
+
+const SEPARATOR: &str = "__";
+
+/// Flat name for an absolute path such as "/dir/sub/file"
+pub fn encode(path: &str) -> String {
+    path.split('/')
+        .filter(|c| !c.is_empty())
+        .map(encode_component)
+        .collect::<Vec<_>>()
+        .join(SEPARATOR)
+}
+
+/// Absolute path a flat name stands for, or None if the name is not one
+/// that encode() produces
+pub fn decode(name: &str) -> Option<String> {
+    let mut path = String::with_capacity(name.len() + 1);
+    for component in name.split(SEPARATOR) {
+        path.push('/');
+        path.push_str(&decode_component(component)?);
+    }
+    (encode(&path) == name).then_some(path)
+}
+
+fn encode_component(component: &str) -> String {
+    let chars: Vec<char> = component.chars().collect();
+    let mut encoded = String::with_capacity(component.len());
+    for (i, &c) in chars.iter().enumerate() {
+        match c {
+            '%' => encoded.push_str("%25"),
+            '_' if i == 0 || i + 1 == chars.len() || chars[i - 1] == '_' || chars[i + 1] == '_' => {
+                encoded.push_str("%5F")
+            }
+            c => encoded.push(c),
+        }
+    }
+    encoded
+}
+
+fn decode_component(component: &str) -> Option<String> {
+    if component.is_empty() || component == "." || component == ".." {
+        return None;
+    }
+    let mut decoded = String::with_capacity(component.len());
+    let mut rest = component;
+    while let Some(at) = rest.find('%') {
+        decoded.push_str(&rest[..at]);
+        let escape = rest.get(at..at + 3)?;
+        decoded.push(match escape {
+            "%25" => '%',
+            "%5F" => '_',
+            _ => return None,
+        });
+        rest = &rest[at + 3..];
+    }
+    decoded.push_str(rest);
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn underscores_and_percents_are_escaped_where_they_could_be_misread() {
+        assert_eq!(encode("/dir/sub/file.txt"), "dir__sub__file.txt");
+        assert_eq!(encode("/a_b/c"), "a_b__c");
+        assert_eq!(encode("/_a/b_"), "%5Fa__b%5F");
+        assert_eq!(encode("/a__b"), "a%5F%5Fb");
+        assert_eq!(encode("/_"), "%5F");
+        assert_eq!(encode("/100%/%5F"), "100%25__%255F");
+        assert_eq!(encode("//a///b/"), "a__b");
+    }
+
+    #[test]
+    fn edge_names_round_trip() {
+        for path in [
+            "/a",
+            "/a/b/c",
+            "/a_b",
+            "/_a",
+            "/a_",
+            "/_",
+            "/__",
+            "/___",
+            "/a__b",
+            "/a___b",
+            "/%",
+            "/%%",
+            "/%5F",
+            "/%25",
+            "/a%/_b",
+            "/a_/_b",
+            "/_%_/%_%",
+            "/x/__init__.py",
+        ] {
+            assert_eq!(decode(&encode(path)).as_deref(), Some(path), "{}", path);
+        }
+    }
+
+    #[test]
+    fn only_canonical_names_decode() {
+        for name in [
+            "",
+            "__a",
+            "a__",
+            "a____b",
+            "%",
+            "%5",
+            "%5f",
+            "%41",
+            "%5Fa%5F_b",
+            // An underscore next to the separator must be escaped
+            "a___b",
+            "a_%5F",
+            // Escaping what needs no escape is not canonical either
+            "a%5Fb",
+            ".",
+            "a__..",
+        ] {
+            assert_eq!(decode(name), None, "{}", name);
+        }
+        assert_eq!(decode("a%5F%5F%5Fb").as_deref(), Some("/a___b"));
+    }
+
+    /// Every component up to `len` characters long over `alphabet`
+    fn components(alphabet: &[char], len: usize) -> Vec<String> {
+        let mut all = vec![String::new()];
+        let mut last = vec![String::new()];
+        for _ in 0..len {
+            last = last
+                .iter()
+                .flat_map(|prefix| alphabet.iter().map(move |&c| format!("{}{}", prefix, c)))
+                .collect();
+            all.extend(last.iter().cloned());
+        }
+        all.retain(|c| !c.is_empty());
+        all
+    }
+
+    #[test]
+    fn distinct_paths_never_share_a_name() {
+        let alphabet = ['a', '_', '%', '5', 'F'];
+        let mut paths: Vec<String> = Vec::new();
+        let long = components(&alphabet, 3);
+        for a in &long {
+            paths.push(format!("/{}", a));
+            for b in &long {
+                paths.push(format!("/{}/{}", a, b));
+            }
+        }
+        let short = components(&alphabet, 2);
+        for a in &short {
+            for b in &short {
+                for c in &short {
+                    paths.push(format!("/{}/{}/{}", a, b, c));
+                }
+            }
+        }
+
+        let mut seen: HashMap<String, &str> = HashMap::with_capacity(paths.len());
+        for path in &paths {
+            let name = encode(path);
+            assert!(!name.is_empty());
+            if let Some(other) = seen.insert(name.clone(), path) {
+                panic!("{} and {} both flatten to {}", other, path, name);
+            }
+            assert_eq!(decode(&name).as_deref(), Some(path.as_str()), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,299 @@
+//! Gzip encoding (RFC 1952), for compressing request bodies and exported
+//! archives.
+//!
+//! This is a small LZ77 compressor over deflate's fixed Huffman codes: it
+//! finds repeats through a hash of the next three bytes and takes the
+//! first match. JSON requests and tar headers are repetitive enough for
+//! that to pay off, and it keeps the encoder short.
+
+use std::io::{self, Write};
+
+use crate::inflate::{crc32_update, DIST_BASE, DIST_EXTRA, LENGTH_BASE, LENGTH_EXTRA};
+
+/// Back-references reach at most this far behind
+const WINDOW: usize = 32 * 1024;
+/// Input gathered before it is compressed into a block
+const BLOCK: usize = 64 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: u32 = 15;
+
+/// Compress `data` as a single gzip member
+pub fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut writer = GzipWriter::new(Vec::new());
+    writer.write_all(data).expect("writing to a Vec");
+    writer.finish().expect("writing to a Vec")
+}
+
+/// Compresses what is written to it as one gzip member written to `W`.
+/// Call `finish` to end the member; dropping the writer loses the end.
+pub struct GzipWriter<W: Write> {
+    out: BitWriter<W>,
+    /// The last WINDOW bytes already compressed, then input not yet
+    /// compressed, from `start` on
+    data: Vec<u8>,
+    start: usize,
+    /// Position in the whole input of `data[0]`
+    base: usize,
+    /// For each hash of three bytes, one past the last position in the
+    /// whole input they were seen at, or 0
+    head: Vec<usize>,
+    crc: u32,
+    size: u32,
+}
+
+impl<W: Write> GzipWriter<W> {
+    pub fn new(inner: W) -> Self {
+        let mut out = BitWriter::new(inner);
+        // No file name or modification time, unknown operating system
+        out.bytes
+            .extend_from_slice(&[0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff]);
+        GzipWriter {
+            out,
+            data: Vec::new(),
+            start: 0,
+            base: 0,
+            head: vec![0; 1 << HASH_BITS],
+            crc: 0,
+            size: 0,
+        }
+    }
+
+    /// Compress what is left, write the gzip trailer and hand back the
+    /// inner writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.block(true)?;
+        self.out.align();
+        self.out.bytes.extend_from_slice(&self.crc.to_le_bytes());
+        self.out.bytes.extend_from_slice(&self.size.to_le_bytes());
+        self.out.flush_bytes()?;
+        self.out.inner.flush()?;
+        Ok(self.out.inner)
+    }
+
+    fn hash(bytes: &[u8]) -> usize {
+        let word = u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+        (word.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+    }
+
+    fn remember(&mut self, at: usize) {
+        if at + MIN_MATCH <= self.data.len() {
+            let hash = Self::hash(&self.data[at..]);
+            self.head[hash] = self.base + at + 1;
+        }
+    }
+
+    /// Compress everything not yet compressed into one fixed-code block
+    fn block(&mut self, last: bool) -> io::Result<()> {
+        self.out.put(u32::from(last), 1);
+        self.out.put(1, 2);
+        let mut at = self.start;
+        while at < self.data.len() {
+            let (len, distance) = self.longest_match(at);
+            if len >= MIN_MATCH {
+                self.out.length(len, distance);
+                for skipped in at..at + len {
+                    self.remember(skipped);
+                }
+                at += len;
+            } else {
+                self.out.literal(self.data[at]);
+                self.remember(at);
+                at += 1;
+            }
+            self.out.flush_bytes_over(8 * 1024)?;
+        }
+        self.out.literal_code(256);
+        self.start = self.data.len();
+        if self.start > 2 * WINDOW {
+            let drop = self.start - WINDOW;
+            self.data.drain(..drop);
+            self.base += drop;
+            self.start = WINDOW;
+        }
+        Ok(())
+    }
+
+    /// The repeat starting at `at` of the last place its first three bytes
+    /// were seen, as (length, distance)
+    fn longest_match(&self, at: usize) -> (usize, usize) {
+        if at + MIN_MATCH > self.data.len() {
+            return (0, 0);
+        }
+        let seen = self.head[Self::hash(&self.data[at..])];
+        if seen == 0 || seen - 1 < self.base {
+            return (0, 0);
+        }
+        let from = seen - 1 - self.base;
+        let distance = at - from;
+        if distance > WINDOW {
+            return (0, 0);
+        }
+        let limit = std::cmp::min(MAX_MATCH, self.data.len() - at);
+        let len = (0..limit)
+            .take_while(|&i| self.data[from + i] == self.data[at + i])
+            .count();
+        (len, distance)
+    }
+}
+
+impl<W: Write> Write for GzipWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.data.extend_from_slice(buf);
+        self.crc = crc32_update(self.crc, buf);
+        self.size = self.size.wrapping_add(buf.len() as u32);
+        if self.data.len() - self.start >= BLOCK {
+            self.block(false)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush_bytes()?;
+        self.out.inner.flush()
+    }
+}
+
+/// Bits written least significant first, as deflate packs them
+struct BitWriter<W> {
+    inner: W,
+    bytes: Vec<u8>,
+    bits: u64,
+    count: u32,
+}
+
+impl<W: Write> BitWriter<W> {
+    fn new(inner: W) -> Self {
+        BitWriter {
+            inner,
+            bytes: Vec::new(),
+            bits: 0,
+            count: 0,
+        }
+    }
+
+    fn put(&mut self, value: u32, n: u32) {
+        self.bits |= u64::from(value) << self.count;
+        self.count += n;
+        while self.count >= 8 {
+            self.bytes.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Huffman codes are packed most significant bit first
+    fn code(&mut self, code: u32, n: u32) {
+        self.put(code.reverse_bits() >> (32 - n), n);
+    }
+
+    fn literal_code(&mut self, symbol: u32) {
+        match symbol {
+            0..=143 => self.code(0x30 + symbol, 8),
+            144..=255 => self.code(0x190 + symbol - 144, 9),
+            256..=279 => self.code(symbol - 256, 7),
+            _ => self.code(0xc0 + symbol - 280, 8),
+        }
+    }
+
+    fn literal(&mut self, byte: u8) {
+        self.literal_code(u32::from(byte));
+    }
+
+    fn length(&mut self, len: usize, distance: usize) {
+        let code = LENGTH_BASE
+            .iter()
+            .rposition(|&base| base as usize <= len)
+            .unwrap();
+        self.literal_code(257 + code as u32);
+        self.put(
+            (len - LENGTH_BASE[code] as usize) as u32,
+            u32::from(LENGTH_EXTRA[code]),
+        );
+        let code = DIST_BASE
+            .iter()
+            .rposition(|&base| base as usize <= distance)
+            .unwrap();
+        self.code(code as u32, 5);
+        self.put(
+            (distance - DIST_BASE[code] as usize) as u32,
+            u32::from(DIST_EXTRA[code]),
+        );
+    }
+
+    fn align(&mut self) {
+        if self.count > 0 {
+            self.put(0, 8 - self.count);
+        }
+    }
+
+    fn flush_bytes_over(&mut self, n: usize) -> io::Result<()> {
+        if self.bytes.len() >= n {
+            self.flush_bytes()?;
+        }
+        Ok(())
+    }
+
+    fn flush_bytes(&mut self) -> io::Result<()> {
+        self.inner.write_all(&self.bytes)?;
+        self.bytes.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn gunzip(data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        crate::inflate::decoder("gzip", data, u64::MAX)
+            .unwrap()
+            .read_to_end(&mut out)
+            .unwrap();
+        out
+    }
+
+    #[test]
+    fn round_trips_through_the_decoder() {
+        assert_eq!(gunzip(&gzip(b"")), b"");
+        assert_eq!(gunzip(&gzip(b"ab")), b"ab");
+
+        let json = br#"{"path":"/a/b","path2":"/a/b/c","path3":"/a/b/c/d"}"#;
+        let compressed = gzip(json);
+        assert_eq!(gunzip(&compressed), json);
+
+        // Every byte value, and runs far longer than the window and the
+        // longest match
+        let mut mixed: Vec<u8> = (0..=255).collect();
+        mixed.resize(mixed.len() + 100_000, b'x');
+        mixed.extend((0..300_000u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8));
+        assert_eq!(gunzip(&gzip(&mixed)), mixed);
+    }
+
+    #[test]
+    fn repetitive_input_shrinks() {
+        let listing: Vec<u8> = (0..2000)
+            .flat_map(|i| format!(r#"{{"name":"file{}.txt","size":{}}},"#, i, i * 3).into_bytes())
+            .collect();
+        let compressed = gzip(&listing);
+        assert!(
+            compressed.len() * 3 < listing.len(),
+            "{} of {}",
+            compressed.len(),
+            listing.len()
+        );
+    }
+
+    #[test]
+    fn piecewise_writes_round_trip() {
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i % 97) as u8).collect();
+        let mut writer = GzipWriter::new(Vec::new());
+        for chunk in data.chunks(1000) {
+            writer.write_all(chunk).unwrap();
+        }
+        let compressed = writer.finish().unwrap();
+        assert_eq!(gunzip(&compressed), data);
+    }
+}
@@ -0,0 +1,831 @@
+//! Decoding of gzip and deflate `Content-Encoding`s (RFC 1951, 1950 and
+//! 1952), so API responses can be compressed in transit.
+//!
+//! Bodies are inflated as they are read, so a compressed read response
+//! streams through the inline content decoder just as an uncompressed one
+//! does. Every decoder stops with an error once it has produced more than
+//! its limit, so a small body can't inflate without bound.
+
+use std::io::{self, Read};
+
+/// Value of the Accept-Encoding header naming what decoder understands
+pub const ACCEPTED: &str = "gzip, deflate";
+
+/// Back-references reach at most this far into the output
+const WINDOW: usize = 32 * 1024;
+/// Decoded bytes gathered before a read hands them over
+const FILL: usize = 16 * 1024;
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+fn truncated() -> io::Error {
+    invalid("compressed body is truncated")
+}
+
+/// A reader undoing a Content-Encoding header value, which lists the
+/// encodings in the order they were applied. Each decoding step fails once
+/// it has produced more than `limit` bytes.
+pub fn decoder<'a>(
+    content_encoding: &str,
+    body: impl Read + 'a,
+    limit: u64,
+) -> io::Result<Box<dyn Read + 'a>> {
+    let mut reader: Box<dyn Read + 'a> = Box::new(body);
+    for encoding in content_encoding.rsplit(',').map(str::trim) {
+        reader = match encoding.to_ascii_lowercase().as_str() {
+            "" | "identity" => reader,
+            "gzip" | "x-gzip" => Box::new(Limited::new(Gunzip::new(reader), limit)),
+            "deflate" => Box::new(Limited::new(Deflate::new(reader), limit)),
+            other => return Err(invalid(format!("unsupported content encoding {}", other))),
+        };
+    }
+    Ok(reader)
+}
+
+/// Fails a read that would take the total past `left`
+struct Limited<R> {
+    inner: R,
+    limit: u64,
+    left: u64,
+}
+
+impl<R> Limited<R> {
+    fn new(inner: R, limit: u64) -> Self {
+        Limited {
+            inner,
+            limit,
+            left: limit,
+        }
+    }
+}
+
+impl<R: Read> Read for Limited<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.left = self.left.checked_sub(n as u64).ok_or_else(|| {
+            invalid(format!(
+                "compressed body inflates past {} bytes",
+                self.limit
+            ))
+        })?;
+        Ok(n)
+    }
+}
+
+/// Compressed input, read a byte or a few bits at a time, least
+/// significant bit first as deflate packs them
+struct Input<R> {
+    inner: R,
+    buf: Box<[u8]>,
+    pos: usize,
+    len: usize,
+    bits: u32,
+    count: u32,
+}
+
+impl<R: Read> Input<R> {
+    fn new(inner: R) -> Self {
+        Input {
+            inner,
+            buf: vec![0; 8 * 1024].into_boxed_slice(),
+            pos: 0,
+            len: 0,
+            bits: 0,
+            count: 0,
+        }
+    }
+
+    /// Up to `n` unread bytes without consuming them; fewer only at the
+    /// end of the input
+    fn peek(&mut self, n: usize) -> io::Result<&[u8]> {
+        if self.len - self.pos < n {
+            self.buf.copy_within(self.pos..self.len, 0);
+            self.len -= self.pos;
+            self.pos = 0;
+            while self.len < n {
+                match self.inner.read(&mut self.buf[self.len..]) {
+                    Ok(0) => break,
+                    Ok(read) => self.len += read,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        Ok(&self.buf[self.pos..self.len.min(self.pos + n)])
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        let byte = *self.peek(1)?.first().ok_or_else(truncated)?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn u16_le(&mut self) -> io::Result<u16> {
+        Ok(u16::from_le_bytes([self.byte()?, self.byte()?]))
+    }
+
+    fn u32_bytes(&mut self) -> io::Result<[u8; 4]> {
+        Ok([self.byte()?, self.byte()?, self.byte()?, self.byte()?])
+    }
+
+    fn take(&mut self, n: u32) -> io::Result<u32> {
+        while self.count < n {
+            self.bits |= u32::from(self.byte()?) << self.count;
+            self.count += 8;
+        }
+        let value = self.bits & ((1u32 << n) - 1);
+        self.bits = self.bits.checked_shr(n).unwrap_or(0);
+        self.count -= n;
+        Ok(value)
+    }
+
+    /// Drop the rest of the current byte. Bits are only taken from the
+    /// input as needed, so no whole byte is ever left buffered.
+    fn align(&mut self) {
+        self.bits = 0;
+        self.count = 0;
+    }
+}
+
+/// Where a raw deflate stream is between reads
+enum Block {
+    /// Before a block header, or done after the last block
+    Start,
+    /// Inside a stored block with this many bytes left
+    Stored(usize),
+    /// Inside a compressed block coded with these literal/length and
+    /// distance codes
+    Codes(Box<(Huffman, Huffman)>),
+}
+
+/// State of one raw deflate stream (RFC 1951)
+struct Inflater {
+    /// The last WINDOW bytes handed over, for back-references, followed by
+    /// decoded bytes not yet handed over, from `delivered` on
+    window: Vec<u8>,
+    delivered: usize,
+    block: Block,
+    last: bool,
+    done: bool,
+}
+
+impl Inflater {
+    fn new() -> Self {
+        Inflater {
+            window: Vec::new(),
+            delivered: 0,
+            block: Block::Start,
+            last: false,
+            done: false,
+        }
+    }
+
+    fn pending(&self) -> &[u8] {
+        &self.window[self.delivered..]
+    }
+
+    /// Hand over up to `buf.len()` decoded bytes
+    fn deliver(&mut self, buf: &mut [u8]) -> usize {
+        let n = std::cmp::min(buf.len(), self.window.len() - self.delivered);
+        buf[..n].copy_from_slice(&self.window[self.delivered..self.delivered + n]);
+        self.delivered += n;
+        n
+    }
+
+    /// Decode until FILL bytes are waiting or the stream has ended
+    fn fill<R: Read>(&mut self, input: &mut Input<R>) -> io::Result<()> {
+        if self.delivered > 2 * WINDOW {
+            self.window.drain(..self.delivered - WINDOW);
+            self.delivered = WINDOW;
+        }
+        while !self.done && self.window.len() - self.delivered < FILL {
+            match &mut self.block {
+                Block::Start if self.last => {
+                    input.align();
+                    self.done = true;
+                }
+                Block::Start => {
+                    self.last = input.take(1)? == 1;
+                    self.block = match input.take(2)? {
+                        0 => {
+                            input.align();
+                            let len = input.u16_le()?;
+                            if len != !input.u16_le()? {
+                                return Err(invalid("stored block length mismatch"));
+                            }
+                            Block::Stored(len as usize)
+                        }
+                        1 => Block::Codes(Box::new(fixed_codes()?)),
+                        2 => Block::Codes(Box::new(dynamic_codes(input)?)),
+                        _ => return Err(invalid("invalid deflate block type")),
+                    };
+                }
+                Block::Stored(0) => self.block = Block::Start,
+                Block::Stored(left) => {
+                    let chunk = input.peek(std::cmp::min(*left, FILL))?;
+                    if chunk.is_empty() {
+                        return Err(truncated());
+                    }
+                    let n = chunk.len();
+                    self.window.extend_from_slice(chunk);
+                    input.pos += n;
+                    *left -= n;
+                }
+                Block::Codes(codes) => {
+                    let (literals, distances) = &**codes;
+                    if !copy_symbol(input, &mut self.window, literals, distances)? {
+                        self.block = Block::Start;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Decode one literal or back-reference onto `out`, returning false at the
+/// end of the block
+fn copy_symbol<R: Read>(
+    input: &mut Input<R>,
+    out: &mut Vec<u8>,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> io::Result<bool> {
+    let symbol = literals.decode(input)? as usize;
+    match symbol {
+        0..=255 => out.push(symbol as u8),
+        256 => return Ok(false),
+        _ => {
+            let code = symbol - 257;
+            if code >= LENGTH_BASE.len() {
+                return Err(invalid("invalid length code"));
+            }
+            let len =
+                LENGTH_BASE[code] as usize + input.take(u32::from(LENGTH_EXTRA[code]))? as usize;
+            let code = distances.decode(input)? as usize;
+            if code >= DIST_BASE.len() {
+                return Err(invalid("invalid distance code"));
+            }
+            let distance =
+                DIST_BASE[code] as usize + input.take(u32::from(DIST_EXTRA[code]))? as usize;
+            if distance > out.len() {
+                return Err(invalid("distance reaches before the start of the stream"));
+            }
+            // The copy may overlap what it produces, so go byte by byte
+            let from = out.len() - distance;
+            for i in 0..len {
+                out.push(out[from + i]);
+            }
+        }
+    }
+    Ok(true)
+}
+
+/// A gzip body (RFC 1952) of one or more members, each checked against its
+/// CRC and length
+struct Gunzip<R> {
+    input: Input<R>,
+    member: Member,
+    first: bool,
+    crc: u32,
+    size: u32,
+}
+
+enum Member {
+    Header,
+    Body(Inflater),
+    End,
+}
+
+impl<R: Read> Gunzip<R> {
+    fn new(inner: R) -> Self {
+        Gunzip {
+            input: Input::new(inner),
+            member: Member::Header,
+            first: true,
+            crc: 0,
+            size: 0,
+        }
+    }
+
+    /// Parse the header of the next member, if one follows; anything but
+    /// another member after the first is padding
+    fn header(&mut self) -> io::Result<bool> {
+        const FHCRC: u8 = 2;
+        const FEXTRA: u8 = 4;
+        const FNAME: u8 = 8;
+        const FCOMMENT: u8 = 16;
+
+        if self.input.peek(2)? != [0x1f, 0x8b] {
+            return if self.first {
+                Err(invalid("not a gzip stream"))
+            } else {
+                Ok(false)
+            };
+        }
+        let mut header = [0u8; 10];
+        for byte in &mut header {
+            *byte = self.input.byte()?;
+        }
+        if header[2] != 8 {
+            return Err(invalid(format!(
+                "unsupported gzip compression method {}",
+                header[2]
+            )));
+        }
+        let flags = header[3];
+        if flags & FEXTRA != 0 {
+            for _ in 0..self.input.u16_le()? {
+                self.input.byte()?;
+            }
+        }
+        for flag in [FNAME, FCOMMENT] {
+            if flags & flag != 0 {
+                while self.input.byte()? != 0 {}
+            }
+        }
+        if flags & FHCRC != 0 {
+            self.input.u16_le()?;
+        }
+        self.first = false;
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for Gunzip<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match &mut self.member {
+                Member::Header => {
+                    if !self.header()? {
+                        self.member = Member::End;
+                        return Ok(0);
+                    }
+                    self.member = Member::Body(Inflater::new());
+                    self.crc = 0;
+                    self.size = 0;
+                }
+                Member::Body(inflater) if !inflater.pending().is_empty() => {
+                    let n = inflater.deliver(buf);
+                    self.crc = crc32_update(self.crc, &buf[..n]);
+                    self.size = self.size.wrapping_add(n as u32);
+                    return Ok(n);
+                }
+                Member::Body(inflater) if !inflater.done => inflater.fill(&mut self.input)?,
+                Member::Body(_) => {
+                    if u32::from_le_bytes(self.input.u32_bytes()?) != self.crc {
+                        return Err(invalid("gzip CRC mismatch"));
+                    }
+                    if u32::from_le_bytes(self.input.u32_bytes()?) != self.size {
+                        return Err(invalid("gzip length mismatch"));
+                    }
+                    self.member = Member::Header;
+                }
+                Member::End => return Ok(0),
+            }
+        }
+    }
+}
+
+/// A deflate body. The encoding is meant to be zlib-wrapped (RFC 1950),
+/// but some servers send a raw deflate stream; accept both.
+struct Deflate<R> {
+    input: Input<R>,
+    inflater: Inflater,
+    /// Whether the stream is zlib-wrapped, once its first bytes are seen
+    zlib: Option<bool>,
+    adler: u32,
+    checked: bool,
+}
+
+impl<R: Read> Deflate<R> {
+    fn new(inner: R) -> Self {
+        Deflate {
+            input: Input::new(inner),
+            inflater: Inflater::new(),
+            zlib: None,
+            adler: 1,
+            checked: false,
+        }
+    }
+
+    fn header(&mut self) -> io::Result<bool> {
+        let zlib = match *self.input.peek(2)? {
+            [cmf, flg] => {
+                cmf & 0x0f == 8 && cmf >> 4 <= 7 && (u16::from(cmf) << 8 | u16::from(flg)) % 31 == 0
+            }
+            _ => false,
+        };
+        if zlib {
+            if self.input.peek(2)?[1] & 0x20 != 0 {
+                return Err(invalid("zlib preset dictionaries are not supported"));
+            }
+            self.input.pos += 2;
+        }
+        Ok(zlib)
+    }
+}
+
+impl<R: Read> Read for Deflate<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let zlib = match self.zlib {
+            Some(zlib) => zlib,
+            None => {
+                let zlib = self.header()?;
+                self.zlib = Some(zlib);
+                zlib
+            }
+        };
+        while self.inflater.pending().is_empty() && !self.inflater.done {
+            self.inflater.fill(&mut self.input)?;
+        }
+        let n = self.inflater.deliver(buf);
+        if zlib {
+            self.adler = adler32_update(self.adler, &buf[..n]);
+            if self.inflater.done && self.inflater.pending().is_empty() && !self.checked {
+                if u32::from_be_bytes(self.input.u32_bytes()?) != self.adler {
+                    return Err(invalid("zlib checksum mismatch"));
+                }
+                self.checked = true;
+            }
+        }
+        Ok(n)
+    }
+}
+
+fn fixed_codes() -> io::Result<(Huffman, Huffman)> {
+    let mut lengths = [0u8; 288 + 30];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..288].fill(8);
+    lengths[288..].fill(5);
+    Ok((
+        Huffman::new(&lengths[..288])?,
+        Huffman::new(&lengths[288..])?,
+    ))
+}
+
+fn dynamic_codes<R: Read>(input: &mut Input<R>) -> io::Result<(Huffman, Huffman)> {
+    let literal_count = input.take(5)? as usize + 257;
+    let distance_count = input.take(5)? as usize + 1;
+    let code_length_count = input.take(4)? as usize + 4;
+    if literal_count > 286 || distance_count > 30 {
+        return Err(invalid("too many codes in dynamic block"));
+    }
+
+    let mut code_lengths = [0u8; 19];
+    for &symbol in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_lengths[symbol] = input.take(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&code_lengths)?;
+
+    let mut lengths = vec![0u8; literal_count + distance_count];
+    let mut at = 0;
+    while at < lengths.len() {
+        let symbol = code_lengths.decode(input)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => match at.checked_sub(1) {
+                Some(previous) => (lengths[previous], 3 + input.take(2)? as usize),
+                None => return Err(invalid("repeat with no previous code length")),
+            },
+            17 => (0, 3 + input.take(3)? as usize),
+            _ => (0, 11 + input.take(7)? as usize),
+        };
+        if at + repeat > lengths.len() {
+            return Err(invalid("code lengths overrun dynamic block"));
+        }
+        lengths[at..at + repeat].fill(value);
+        at += repeat;
+    }
+    if lengths[256] == 0 {
+        return Err(invalid("dynamic block has no end-of-block code"));
+    }
+    Ok((
+        Huffman::new(&lengths[..literal_count])?,
+        Huffman::new(&lengths[literal_count..])?,
+    ))
+}
+
+/// Base length and extra bits for length codes 257..=285
+pub(crate) const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+pub(crate) const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+/// Base distance and extra bits for distance codes 0..=29
+pub(crate) const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+pub(crate) const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// Order the code length code lengths of a dynamic block are sent in
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// A canonical Huffman code as the number of codes of each length and the
+/// symbols in code order
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> io::Result<Self> {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        // More codes of a length than the shorter ones leave room for is
+        // not a prefix code; fewer is allowed, e.g. a single distance code
+        let mut left: i32 = 1;
+        for &count in &counts[1..] {
+            left = (left << 1) - i32::from(count);
+            if left < 0 {
+                return Err(invalid("invalid Huffman code lengths"));
+            }
+        }
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Ok(Huffman { counts, symbols })
+    }
+
+    fn decode<R: Read>(&self, input: &mut Input<R>) -> io::Result<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for &count in &self.counts[1..] {
+            code |= input.take(1)? as i32;
+            let count = i32::from(count);
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid("invalid Huffman code"))
+    }
+}
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xedb8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+/// Extend a CRC-32 (starting from 0) over `data`
+pub(crate) fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    !data.iter().fold(!crc, |crc, &byte| {
+        CRC_TABLE[((crc ^ u32::from(byte)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Extend an Adler-32 (starting from 1) over `data`
+fn adler32_update(adler: u32, data: &[u8]) -> u32 {
+    let (mut a, mut b) = (adler & 0xffff, adler >> 16);
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += u32::from(byte);
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    b << 16 | a
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn hex(text: &str) -> Vec<u8> {
+        (0..text.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn raw(data: &[u8]) -> io::Result<Vec<u8>> {
+        let (mut input, mut inflater) = (Input::new(data), Inflater::new());
+        let mut out = Vec::new();
+        while !inflater.done {
+            inflater.fill(&mut input)?;
+            out.extend_from_slice(inflater.pending());
+            inflater.delivered = inflater.window.len();
+        }
+        Ok(out)
+    }
+
+    fn decode(content_encoding: &str, body: Vec<u8>) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        decoder(content_encoding, &body[..], u64::MAX)?.read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    /// Hands over its data a byte per read, as a slow connection might
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match (self.0.split_first(), buf.first_mut()) {
+                (Some((&byte, rest)), Some(slot)) => {
+                    *slot = byte;
+                    self.0 = rest;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
+        }
+    }
+
+    #[test]
+    fn stored_fixed_and_dynamic_blocks_inflate() {
+        assert_eq!(raw(&hex("010600f9ff73746f726564")).unwrap(), b"stored");
+        assert_eq!(
+            raw(&hex("cb48cdc9c957c8409000")).unwrap(),
+            b"hello hello hello"
+        );
+
+        let entries: Vec<String> = (0..8)
+            .map(|i| {
+                format!(
+                    r#"{{"name":"file{:02}.txt","attrs":{{"size":{}}}}}"#,
+                    i,
+                    i * 7
+                )
+            })
+            .collect();
+        let listing = format!(r#"{{"entries":[{}]}}"#, entries.join(","));
+        let dynamic = hex(concat!(
+            "75ca310e80200c00c0bf7426061044fd8a7170c0844419b08391f4efba9a96f9ae42",
+            "cc5852bc605e2ae4ed8c30c39e8ea875873782820db17c5ae14acf879a48fda39163",
+            "60d1cad138367b795ac3a66bcc914d2fcfdeb339c8d3593643634e442bbd"
+        ));
+        assert_eq!(dynamic[0] >> 1 & 3, 2, "vector uses a dynamic block");
+        assert_eq!(raw(&dynamic).unwrap(), listing.as_bytes());
+    }
+
+    #[test]
+    fn gzip_members_with_headers_are_decoded_and_joined() {
+        // The first member carries a file name, the second none
+        let named = hex("1f8b08080000000002ff612e6a736f6e00ab564a54b232ac0500afac1b5607000000");
+        let plain = hex("1f8b0800000000000203ab564a52b232aa0500bc85963a07000000");
+        assert_eq!(decode("gzip", named.clone()).unwrap(), br#"{"a":1}"#);
+
+        let mut both = named;
+        both.extend_from_slice(&plain);
+        assert_eq!(decode("x-gzip", both).unwrap(), br#"{"a":1}{"b":2}"#);
+    }
+
+    #[test]
+    fn deflate_accepts_zlib_wrapped_and_raw_streams() {
+        let expected = b"hello hello hello";
+        assert_eq!(
+            decode("deflate", hex("789ccb48cdc9c957c84090003a2e067d")).unwrap(),
+            expected
+        );
+        assert_eq!(
+            decode("deflate", hex("cb48cdc9c957c8409000")).unwrap(),
+            expected
+        );
+        assert_eq!(decode("identity", expected.to_vec()).unwrap(), expected);
+        // Encodings are undone last applied first
+        assert_eq!(
+            decode(
+                "identity, gzip",
+                hex("1f8b0800000000000203ab564a52b232aa0500bc85963a07000000")
+            )
+            .unwrap(),
+            br#"{"b":2}"#
+        );
+    }
+
+    #[test]
+    fn corrupt_truncated_and_unknown_bodies_are_errors() {
+        let mut gzip = hex("1f8b0800000000000203ab564a52b232aa0500bc85963a07000000");
+        let len = gzip.len();
+        gzip[len - 8] ^= 1;
+        assert!(decode("gzip", gzip.clone())
+            .unwrap_err()
+            .to_string()
+            .contains("CRC"));
+        assert!(decode("gzip", gzip[..len - 10].to_vec()).is_err());
+
+        let mut zlib = hex("789ccb48cdc9c957c84090003a2e067d");
+        let len = zlib.len();
+        zlib[len - 1] ^= 1;
+        assert!(decode("deflate", zlib)
+            .unwrap_err()
+            .to_string()
+            .contains("checksum"));
+
+        assert!(decode("br", b"{}".to_vec())
+            .unwrap_err()
+            .to_string()
+            .contains("br"));
+        assert!(decode("gzip", b"{}".to_vec()).is_err());
+    }
+
+    #[test]
+    fn stored_blocks_longer_than_a_fill_stream_through() {
+        let data: Vec<u8> = (0..40_000u32).map(|i| (i % 251) as u8).collect();
+        let len = data.len() as u16;
+        let mut stream = vec![1];
+        stream.extend_from_slice(&len.to_le_bytes());
+        stream.extend_from_slice(&(!len).to_le_bytes());
+        stream.extend_from_slice(&data);
+        assert_eq!(raw(&stream).unwrap(), data);
+        assert!(raw(&stream[..stream.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn bodies_inflate_from_input_arriving_a_byte_at_a_time() {
+        // Longer than the window, so back-references span compactions
+        let text: Vec<u8> = (0..20_000)
+            .flat_map(|i| format!("line {} of the listing\n", i % 700).into_bytes())
+            .collect();
+        let gzip = crate::gzip::gzip(&text);
+        let mut out = Vec::new();
+        decoder("gzip", Trickle(&gzip), u64::MAX)
+            .unwrap()
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(out, text);
+
+        let mut zlib = vec![0x78, 0x9c];
+        let deflated = &gzip[10..gzip.len() - 8];
+        zlib.extend_from_slice(deflated);
+        zlib.extend_from_slice(&adler32_update(1, &text).to_be_bytes());
+        let mut out = Vec::new();
+        decoder("deflate", Trickle(&zlib), u64::MAX)
+            .unwrap()
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(out, text);
+    }
+
+    #[test]
+    fn decoding_stops_at_the_limit() {
+        // A megabyte of zeros compresses to about a kilobyte
+        let zeros = vec![0u8; 1 << 20];
+        let gzip = crate::gzip::gzip(&zeros);
+        assert!(gzip.len() < 8 * 1024);
+
+        let mut out = Vec::new();
+        let err = decoder("gzip", &gzip[..], 64 * 1024)
+            .unwrap()
+            .read_to_end(&mut out)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("65536"), "{}", err);
+        assert!(out.len() <= 64 * 1024 + FILL);
+
+        let mut out = Vec::new();
+        decoder("gzip", &gzip[..], 1 << 20)
+            .unwrap()
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(out.len(), 1 << 20);
+    }
+
+    #[test]
+    fn checksums_match_known_values() {
+        assert_eq!(crc32_update(0, b"123456789"), 0xcbf4_3926);
+        assert_eq!(adler32_update(1, b"Wikipedia"), 0x11e6_0398);
+        let (head, tail) = b"123456789".split_at(4);
+        assert_eq!(crc32_update(crc32_update(0, head), tail), 0xcbf4_3926);
+    }
+}
@@ -0,0 +1,396 @@
+//! Base64 `content` of a read response, decoded while the body is still
+//! arriving.
+//!
+//! serde_json buffers a whole string before handing it over, so parsing a
+//! read response normally holds the full base64 text and then its decoded
+//! copy. Here the body is scanned once: the content string is decoded a
+//! chunk at a time, keeping only the bytes the caller asked for, and the
+//! remaining fields are passed on to serde as a small JSON object.
+
+use base64::Engine;
+use serde::{Deserialize, Deserializer};
+use std::io::{self, BufRead};
+
+/// Characters of base64 decoded at a time; a multiple of 4
+const CHUNK: usize = 16 * 1024;
+
+/// Which decoded bytes of inline content to keep
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InlineWindow {
+    pub offset: u64,
+    pub len: u64,
+    /// Content no longer than this is kept whole, for caching and checksum
+    /// verification, wherever the window lies
+    pub keep_whole: u64,
+}
+
+impl InlineWindow {
+    /// Keep everything, as a caller wanting the whole file does
+    pub const WHOLE: InlineWindow = InlineWindow {
+        offset: 0,
+        len: u64::MAX,
+        keep_whole: u64::MAX,
+    };
+
+    /// The part of `pos..pos + n` inside the window, relative to `pos`
+    fn part(&self, pos: u64, n: usize) -> std::ops::Range<usize> {
+        let end = self.offset.saturating_add(self.len);
+        let from = self.offset.saturating_sub(pos).min(n as u64) as usize;
+        let to = end.saturating_sub(pos).min(n as u64) as usize;
+        from..to.max(from)
+    }
+}
+
+/// Decoded inline content: all of it, or the window of it that was kept
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InlineContent {
+    /// Decoded length of the whole content
+    pub len: u64,
+    /// Offset of `data` within the content
+    pub start: u64,
+    pub data: Vec<u8>,
+}
+
+impl InlineContent {
+    pub fn is_whole(&self) -> bool {
+        self.start == 0 && self.data.len() as u64 == self.len
+    }
+
+    /// Bytes `offset..offset + size`, clipped to the content, or None if
+    /// they weren't kept
+    pub fn range(&self, offset: u64, size: u64) -> Option<Vec<u8>> {
+        if offset >= self.len {
+            return Some(Vec::new());
+        }
+        let end = offset.saturating_add(size).min(self.len);
+        let kept_end = self.start + self.data.len() as u64;
+        if offset < self.start || end > kept_end {
+            return None;
+        }
+        Some(self.data[(offset - self.start) as usize..(end - self.start) as usize].to_vec())
+    }
+}
+
+/// Deserialize a base64 string in full, for read results not parsed with
+/// split_read_body
+pub fn deserialize_whole<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Box<InlineContent>>, D::Error> {
+    let Some(encoded) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    let data = base64::prelude::BASE64_STANDARD
+        .decode(encoded.as_bytes())
+        .map_err(serde::de::Error::custom)?;
+    Ok(Some(Box::new(InlineContent {
+        len: data.len() as u64,
+        start: 0,
+        data,
+    })))
+}
+
+/// Base64 decoded a chunk at a time, keeping what the window asks for
+struct Decoder {
+    window: InlineWindow,
+    pending: Vec<u8>,
+    content: InlineContent,
+    /// Whether every byte so far has been kept
+    whole: bool,
+}
+
+impl Decoder {
+    fn new(window: InlineWindow) -> Self {
+        Decoder {
+            window,
+            pending: Vec::with_capacity(CHUNK),
+            content: InlineContent::default(),
+            whole: true,
+        }
+    }
+
+    fn push_char(&mut self, c: u8) -> io::Result<()> {
+        // Line breaks, as in MIME-wrapped base64, carry nothing
+        if !c.is_ascii_whitespace() {
+            self.pending.push(c);
+            if self.pending.len() == CHUNK {
+                self.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let decoded = base64::prelude::BASE64_STANDARD
+            .decode(&self.pending)
+            .map_err(|e| invalid(format!("invalid base64 content: {}", e)))?;
+        self.pending.clear();
+
+        let pos = self.content.len;
+        self.content.len += decoded.len() as u64;
+        if self.whole {
+            if self.content.len <= self.window.keep_whole {
+                self.content.data.extend_from_slice(&decoded);
+                return Ok(());
+            }
+            // Too long to keep whole: from here on only the window is held
+            self.whole = false;
+            let kept = self.window.part(0, self.content.data.len());
+            self.content.data.truncate(kept.end);
+            self.content.data.drain(..kept.start);
+            self.content.start = self.window.offset;
+        }
+        let part = self.window.part(pos, decoded.len());
+        self.content.data.extend_from_slice(&decoded[part]);
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<InlineContent> {
+        if !self.pending.is_empty() {
+            self.flush()?;
+        }
+        if !self.whole {
+            self.content.start = self.content.start.min(self.content.len);
+        }
+        Ok(self.content)
+    }
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+fn next(reader: &mut impl BufRead) -> io::Result<u8> {
+    let c = peek(reader)?.ok_or_else(|| invalid("read response ends early"))?;
+    reader.consume(1);
+    Ok(c)
+}
+
+fn peek(reader: &mut impl BufRead) -> io::Result<Option<u8>> {
+    Ok(reader.fill_buf()?.first().copied())
+}
+
+fn skip_whitespace(reader: &mut impl BufRead) -> io::Result<()> {
+    while peek(reader)?.is_some_and(|c| c.is_ascii_whitespace()) {
+        reader.consume(1);
+    }
+    Ok(())
+}
+
+/// Copy one JSON value to `out` unchanged, stopping on the byte after it
+fn copy_value(reader: &mut impl BufRead, out: &mut Vec<u8>) -> io::Result<()> {
+    match peek(reader)? {
+        Some(b'"') => {
+            out.push(next(reader)?);
+            copy_string_rest(reader, out)
+        }
+        Some(b'{' | b'[') => {
+            let mut depth = 0usize;
+            loop {
+                let c = next(reader)?;
+                out.push(c);
+                match c {
+                    b'"' => copy_string_rest(reader, out)?,
+                    b'{' | b'[' => depth += 1,
+                    b'}' | b']' => depth -= 1,
+                    _ => {}
+                }
+                if depth == 0 {
+                    return Ok(());
+                }
+            }
+        }
+        _ => {
+            // A number or literal runs up to the next delimiter
+            let start = out.len();
+            while let Some(c) = peek(reader)? {
+                if matches!(c, b',' | b'}' | b']') || c.is_ascii_whitespace() {
+                    break;
+                }
+                out.push(c);
+                reader.consume(1);
+            }
+            if out.len() == start {
+                return Err(invalid("expected a value in read response"));
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Copy the rest of a string whose opening quote was already copied
+fn copy_string_rest(reader: &mut impl BufRead, out: &mut Vec<u8>) -> io::Result<()> {
+    loop {
+        let c = next(reader)?;
+        out.push(c);
+        match c {
+            b'"' => return Ok(()),
+            b'\\' => out.push(next(reader)?),
+            _ => {}
+        }
+    }
+}
+
+/// Decode a JSON string's base64 text, its opening quote already read
+fn decode_string(reader: &mut impl BufRead, decoder: &mut Decoder) -> io::Result<()> {
+    loop {
+        match next(reader)? {
+            b'"' => return Ok(()),
+            b'\\' => match next(reader)? {
+                b'/' => decoder.push_char(b'/')?,
+                b'n' | b'r' | b't' => {}
+                c => {
+                    return Err(invalid(format!(
+                        "unexpected \\{} in base64 content",
+                        c as char
+                    )))
+                }
+            },
+            c => decoder.push_char(c)?,
+        }
+    }
+}
+
+/// Split a read response body into its decoded `content` and the JSON of
+/// every other field
+pub fn split_read_body(
+    reader: &mut impl BufRead,
+    window: InlineWindow,
+) -> io::Result<(Vec<u8>, Option<Box<InlineContent>>)> {
+    let mut rest = vec![b'{'];
+    let mut content = None;
+    skip_whitespace(reader)?;
+    if next(reader)? != b'{' {
+        return Err(invalid("read response is not a JSON object"));
+    }
+    loop {
+        skip_whitespace(reader)?;
+        let mut key = Vec::new();
+        match next(reader)? {
+            b'}' if rest.len() == 1 && content.is_none() => break,
+            b'"' => {
+                key.push(b'"');
+                copy_string_rest(reader, &mut key)?;
+            }
+            _ => return Err(invalid("expected a field name in read response")),
+        }
+        skip_whitespace(reader)?;
+        if next(reader)? != b':' {
+            return Err(invalid("expected : in read response"));
+        }
+        skip_whitespace(reader)?;
+        if key == b"\"content\"" && peek(reader)? == Some(b'"') {
+            reader.consume(1);
+            let mut decoder = Decoder::new(window);
+            decode_string(reader, &mut decoder)?;
+            content = Some(Box::new(decoder.finish()?));
+        } else {
+            if rest.len() > 1 {
+                rest.push(b',');
+            }
+            rest.extend_from_slice(&key);
+            rest.push(b':');
+            copy_value(reader, &mut rest)?;
+        }
+        skip_whitespace(reader)?;
+        match next(reader)? {
+            b',' => continue,
+            b'}' => break,
+            _ => return Err(invalid("expected , or } in read response")),
+        }
+    }
+    rest.push(b'}');
+    Ok((rest, content))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(body: &str, window: InlineWindow) -> (serde_json::Value, Option<Box<InlineContent>>) {
+        let (fields, content) = split_read_body(&mut body.as_bytes(), window).unwrap();
+        (serde_json::from_slice(&fields).unwrap(), content)
+    }
+
+    fn window(offset: u64, len: u64, keep_whole: u64) -> InlineWindow {
+        InlineWindow {
+            offset,
+            len,
+            keep_whole,
+        }
+    }
+
+    #[test]
+    fn other_fields_pass_through_around_the_content() {
+        let body = r#" { "size": 4, "content" : "aGn\/\/g==\n", "mtime":1.5,
+            "extra": {"a": ["x", "}\"", null]}, "checksum": null } "#;
+        let (fields, content) = split(body, InlineWindow::WHOLE);
+        assert_eq!(
+            fields,
+            serde_json::json!({
+                "size": 4,
+                "mtime": 1.5,
+                "extra": {"a": ["x", "}\"", null]},
+                "checksum": null,
+            })
+        );
+        // "\/" is an escaped slash, which base64 uses; line breaks are skipped
+        let content = content.unwrap();
+        assert_eq!(content.data, b"hi\xff\xfe");
+        assert!(content.is_whole());
+    }
+
+    #[test]
+    fn null_or_missing_content_is_left_to_serde() {
+        let (fields, content) = split(r#"{"content":null,"size":0}"#, InlineWindow::WHOLE);
+        assert_eq!(fields, serde_json::json!({"content": null, "size": 0}));
+        assert!(content.is_none());
+        let (fields, content) = split("{}", InlineWindow::WHOLE);
+        assert_eq!(fields, serde_json::json!({}));
+        assert!(content.is_none());
+    }
+
+    #[test]
+    fn only_the_window_of_long_content_is_kept() {
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        let body = format!(
+            r#"{{"content":"{}","size":100000}}"#,
+            base64::prelude::BASE64_STANDARD.encode(&data)
+        );
+
+        let (_, content) = split(&body, window(50_000, 1000, 4096));
+        let content = content.unwrap();
+        assert_eq!((content.len, content.start), (100_000, 50_000));
+        assert_eq!(content.data, &data[50_000..51_000]);
+        assert!(!content.is_whole());
+        assert_eq!(content.range(50_100, 10).unwrap(), &data[50_100..50_110]);
+        assert_eq!(content.range(40_000, 10), None);
+        assert_eq!(content.range(200_000, 10).unwrap(), b"");
+
+        // Short enough to keep whole, wherever the window is
+        let (_, content) = split(&body, window(50_000, 1000, 100_000));
+        assert!(content.unwrap().is_whole());
+
+        // A window past the end keeps nothing
+        let (_, content) = split(&body, window(150_000, 1000, 0));
+        let content = content.unwrap();
+        assert_eq!((content.len, content.start), (100_000, 100_000));
+        assert!(content.data.is_empty());
+    }
+
+    #[test]
+    fn malformed_bodies_are_errors() {
+        for body in [
+            r#"{"content":"not base64!"}"#,
+            r#"{"content":"aGVsbG8=","size":"#,
+            r#"{"size" 1}"#,
+            r#"["content"]"#,
+            r#"{"content":"aGVsb"}"#,
+        ] {
+            assert!(
+                split_read_body(&mut body.as_bytes(), InlineWindow::WHOLE).is_err(),
+                "{}",
+                body
+            );
+        }
+    }
+}
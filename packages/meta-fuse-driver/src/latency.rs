@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Weight of each new sample in the moving average
+const EMA_ALPHA: f64 = 0.2;
+/// Samples an endpoint needs before its average sets the timeout
+const MIN_SAMPLES: u32 = 10;
+
+/// How request timeouts follow observed latency
+#[derive(Debug, Clone, Copy)]
+pub struct AdaptiveTimeout {
+    /// Timeout as a multiple of the average latency
+    pub multiplier: f64,
+    pub floor: Duration,
+    pub ceiling: Duration,
+}
+
+/// Exponential moving average of request latency, per endpoint
+#[derive(Debug, Default)]
+pub struct LatencyTracker {
+    averages: Mutex<HashMap<&'static str, Average>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Average {
+    secs: f64,
+    samples: u32,
+}
+
+/// An endpoint's current average, for reporting
+pub struct LatencyStat {
+    pub endpoint: &'static str,
+    pub average: Duration,
+    pub samples: u32,
+}
+
+impl LatencyTracker {
+    /// Fold in the duration of one completed request
+    pub fn record(&self, endpoint: &'static str, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        let mut averages = self.averages.lock().unwrap();
+        averages
+            .entry(endpoint)
+            .and_modify(|average| {
+                average.secs += EMA_ALPHA * (secs - average.secs);
+                average.samples = average.samples.saturating_add(1);
+            })
+            .or_insert(Average { secs, samples: 1 });
+    }
+
+    /// Timeout for the next request to `endpoint`: `fallback` until enough
+    /// samples have accrued, then the multiple of the average, clamped
+    pub fn timeout(
+        &self,
+        endpoint: &'static str,
+        adaptive: &AdaptiveTimeout,
+        fallback: Duration,
+    ) -> Duration {
+        match self.averages.lock().unwrap().get(endpoint) {
+            Some(average) if average.samples >= MIN_SAMPLES => {
+                // A ceiling configured below the floor gives way to it
+                let ceiling = adaptive.ceiling.max(adaptive.floor);
+                Duration::try_from_secs_f64(average.secs * adaptive.multiplier)
+                    .unwrap_or(ceiling)
+                    .clamp(adaptive.floor, ceiling)
+            }
+            _ => fallback,
+        }
+    }
+
+    /// Every endpoint's average, sorted by name
+    pub fn snapshot(&self) -> Vec<LatencyStat> {
+        let mut stats: Vec<LatencyStat> = self
+            .averages
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(&endpoint, average)| LatencyStat {
+                endpoint,
+                average: Duration::from_secs_f64(average.secs),
+                samples: average.samples,
+            })
+            .collect();
+        stats.sort_by_key(|stat| stat.endpoint);
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADAPTIVE: AdaptiveTimeout = AdaptiveTimeout {
+        multiplier: 3.0,
+        floor: Duration::from_millis(50),
+        ceiling: Duration::from_secs(10),
+    };
+    const FALLBACK: Duration = Duration::from_secs(30);
+
+    fn warmed_up(latency: Duration) -> LatencyTracker {
+        let tracker = LatencyTracker::default();
+        for _ in 0..MIN_SAMPLES {
+            tracker.record("getattr", latency);
+        }
+        tracker
+    }
+
+    #[test]
+    fn falls_back_until_enough_samples() {
+        let tracker = LatencyTracker::default();
+        for _ in 1..MIN_SAMPLES {
+            tracker.record("getattr", Duration::from_millis(100));
+        }
+        assert_eq!(tracker.timeout("getattr", &ADAPTIVE, FALLBACK), FALLBACK);
+        tracker.record("getattr", Duration::from_millis(100));
+        assert_eq!(
+            tracker.timeout("getattr", &ADAPTIVE, FALLBACK),
+            Duration::from_millis(300)
+        );
+    }
+
+    #[test]
+    fn timeout_is_clamped() {
+        let fast = warmed_up(Duration::from_millis(1));
+        assert_eq!(fast.timeout("getattr", &ADAPTIVE, FALLBACK), ADAPTIVE.floor);
+        let slow = warmed_up(Duration::from_secs(60));
+        assert_eq!(
+            slow.timeout("getattr", &ADAPTIVE, FALLBACK),
+            ADAPTIVE.ceiling
+        );
+    }
+
+    #[test]
+    fn timeout_grows_back_after_timeouts() {
+        // Fast history has shrunk the timeout to the floor
+        let tracker = warmed_up(Duration::from_millis(5));
+        let mut timeout = tracker.timeout("getattr", &ADAPTIVE, FALLBACK);
+        assert_eq!(timeout, ADAPTIVE.floor);
+
+        // Each timed-out request is recorded as taking the whole timeout
+        let mut grown = Vec::new();
+        for _ in 0..20 {
+            tracker.record("getattr", timeout);
+            timeout = tracker.timeout("getattr", &ADAPTIVE, FALLBACK);
+            grown.push(timeout);
+        }
+        assert!(grown.windows(2).all(|pair| pair[1] >= pair[0]));
+        assert!(
+            timeout > ADAPTIVE.floor * 10,
+            "timeout stuck at {:?}",
+            timeout
+        );
+    }
+
+    #[test]
+    fn endpoints_are_tracked_separately() {
+        let tracker = warmed_up(Duration::from_millis(100));
+        assert_eq!(tracker.timeout("read", &ADAPTIVE, FALLBACK), FALLBACK);
+        let stats = tracker.snapshot();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].endpoint, "getattr");
+        assert_eq!(stats[0].samples, MIN_SAMPLES);
+    }
+}
@@ -0,0 +1,99 @@
+use std::sync::{Condvar, Mutex};
+
+/// Blocking counting semaphore capping concurrent backend requests.
+///
+/// With no limit it only counts, so the in-flight number can still be
+/// reported.
+#[derive(Debug)]
+pub struct RequestLimiter {
+    limit: Option<usize>,
+    in_flight: Mutex<usize>,
+    released: Condvar,
+}
+
+/// Held for the duration of one request; frees its slot when dropped
+pub struct Permit<'a> {
+    limiter: &'a RequestLimiter,
+}
+
+impl RequestLimiter {
+    pub fn new(limit: Option<usize>) -> Self {
+        RequestLimiter {
+            limit,
+            in_flight: Mutex::new(0),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Wait for a free slot
+    pub fn acquire(&self) -> Permit<'_> {
+        self.take_slot();
+        Permit { limiter: self }
+    }
+
+    /// Give up the slot the calling thread holds while `f` runs, then wait
+    /// for one again, so a request sleeping between retries doesn't hold
+    /// up others. Only for callers holding a permit.
+    pub fn released<T>(&self, f: impl FnOnce() -> T) -> T {
+        self.free_slot();
+        let result = f();
+        self.take_slot();
+        result
+    }
+
+    fn take_slot(&self) {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if let Some(limit) = self.limit {
+            while *in_flight >= limit {
+                in_flight = self.released.wait(in_flight).unwrap();
+            }
+        }
+        *in_flight += 1;
+    }
+
+    fn free_slot(&self) {
+        *self.in_flight.lock().unwrap() -= 1;
+        self.released.notify_one();
+    }
+
+    pub fn in_flight(&self) -> usize {
+        *self.in_flight.lock().unwrap()
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.limiter.free_slot();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    #[test]
+    fn released_slot_is_free_for_others_until_taken_back() {
+        let limiter = RequestLimiter::new(Some(1));
+        let _permit = limiter.acquire();
+        let (done, finished) = mpsc::channel();
+        std::thread::scope(|scope| {
+            limiter.released(|| {
+                assert_eq!(limiter.in_flight(), 0);
+                scope.spawn(|| {
+                    let _other = limiter.acquire();
+                    std::thread::sleep(Duration::from_millis(50));
+                    done.send(()).unwrap();
+                });
+                finished.recv().unwrap();
+            });
+            // Taking the slot back waited for the other permit to drop
+            assert_eq!(limiter.in_flight(), 1);
+        });
+    }
+}
@@ -1,75 +1,699 @@
+mod acl;
 mod api_client;
+mod benchmark;
+mod byteranges;
+mod check;
+mod cli;
+mod config;
+mod daemon;
+mod events;
+mod export;
+mod flatten;
+mod gzip;
+mod inflate;
+mod inline_content;
+mod latency;
+mod limiter;
+mod preload;
+mod read_source;
+mod revalidate;
+mod single_flight;
+#[cfg(test)]
+mod test_server;
+mod watchdog;
 
-use api_client::ApiClient;
-use base64::Engine;
+use api_client::{
+    ApiClient, ApiError, AttrResponse, BodyFormat, CacheControl, Capabilities, Compression,
+    ReadResponse, RequestTimeouts, TlsSettings,
+};
 use fuser::{
-    FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry,
-    Request,
+    FileAttr, FileType, Filesystem, KernelConfig, MountOption, ReplyAttr, ReplyCreate, ReplyData,
+    ReplyDirectory, ReplyEmpty, ReplyEntry, ReplyLseek, ReplyOpen, ReplyWrite, ReplyXattr, Request,
+    TimeOrNow,
 };
-use libc::ENOENT;
-use log::{debug, error, info};
-use std::collections::HashMap;
+use inline_content::InlineWindow;
+use libc::{ENOENT, EROFS};
+use log::{debug, error, info, warn};
+use read_source::ReadSource;
+use reqwest::StatusCode;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::ffi::OsStr;
 use std::fs::File;
 use std::io::Read;
-use std::sync::{Arc, Mutex};
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 const TTL: Duration = Duration::from_secs(1);
-const ROOT_INO: u64 = 1;
 const CACHE_TTL: Duration = Duration::from_secs(30);
+const DEFAULT_CACHE_TTL_JITTER_PCT: u32 = 10;
+
+// Reserved inodes. Paths from core never map to these, whether allocated
+// sequentially or hashed; 0 is not a valid inode at all.
+const ROOT_INO: u64 = 1;
 const ERROR_FILE_INO: u64 = 2;
-const API_ERROR_THRESHOLD: usize = 3;
+const STATS_FILE_INO: u64 = 3;
+/// Marker listed at the end of directories cut short by FUSE_MAX_DIR_ENTRIES
+const TRUNCATED_FILE_INO: u64 = 4;
+/// Description of the mount served at the root with FUSE_INFO_FILE
+const INFO_FILE_INO: u64 = 5;
+/// Writable file at the root taking cache commands, with FUSE_CONTROL_FILE
+const CONTROL_FILE_INO: u64 = 6;
+/// Inodes up to this one are reserved for the root and synthetic files
+const LAST_SYNTHETIC_INO: u64 = CONTROL_FILE_INO;
+
+const TRUNCATED_FILE_NAME: &str = ".TRUNCATED";
+const INFO_FILE_NAME: &str = ".meta-fuse";
+const CONTROL_FILE_NAME: &str = ".meta-fuse-control";
+/// Block size for multi-range reads when FUSE_READ_ALIGN isn't set
+const FETCH_BLOCK_SIZE: usize = 64 * 1024;
+/// Blocks past the end of a read fetched along with it
+const FETCH_READAHEAD_BLOCKS: u64 = 4;
+/// Cached blocks separated by at most this many are fetched again rather
+/// than splitting a range around them
+const FETCH_MERGE_GAP_BLOCKS: u64 = 1;
+/// Blocks kept per open file
+const FETCHED_BLOCKS_MAX: usize = 64;
+const DEFAULT_STATS_FILE_NAME: &str = ".meta-fuse-stats";
+const DEFAULT_ERROR_THRESHOLD: usize = 3;
+/// Listings larger than this are not kept in dir_cache
+pub const DIR_CACHE_MAX_ENTRIES: usize = 10_000;
+/// First and largest pause between startup health checks
+const WAIT_FOR_API_INITIAL_DELAY: Duration = Duration::from_millis(500);
+const WAIT_FOR_API_MAX_DELAY: Duration = Duration::from_secs(10);
+const TYPE_CACHE_TTL: Duration = Duration::from_secs(600);
+const TYPE_CACHE_MAX_ENTRIES: usize = 100_000;
+/// Paths remembered as missing before expired ones are swept out
+const NEGATIVE_CACHE_MAX_ENTRIES: usize = 100_000;
+/// Lifetime of cache entries core marks immutable
+const IMMUTABLE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+/// Longest name the kernel accepts; longer flat names are left out
+const FLAT_NAME_MAX: usize = 255;
+/// Default size limit for files kept in content_cache, and the largest file
+/// pretty-printed or decoded in full
+const CONTENT_CACHE_MAX_FILE_SIZE: usize = 1024 * 1024;
+/// Largest inline payload decoded by default
+const DEFAULT_MAX_INLINE_SIZE: u64 = 8 * 1024 * 1024;
+const CONTENT_CACHE_MAX_ENTRIES: usize = 256;
+/// File heads kept for content sniffing with FUSE_SNIFF_BYTES
+const SNIFF_CACHE_MAX_ENTRIES: usize = 4096;
+const DEFAULT_BLKSIZE: u32 = 4096;
+/// Shortest gap between access notifications for the same file
+const ATIME_NOTIFY_INTERVAL: Duration = Duration::from_secs(60);
+/// Files remembered for access-notification debouncing before pruning
+const ATIME_NOTIFY_MAX_TRACKED: usize = 10_000;
+/// Access notifications waiting to be sent before new ones are dropped
+const ATIME_NOTIFY_QUEUE: usize = 256;
+/// Most stale attribute entries waiting for a background refresh
+const ATTR_REFRESH_QUEUE: usize = 256;
+const DEFAULT_MAX_STALE: Duration = Duration::from_secs(300);
+const DEFAULT_CIRCUIT_COOLDOWN: Duration = Duration::from_secs(10);
+const DEFAULT_SLOW_REQUEST: Duration = Duration::from_secs(1);
+/// Bounds on latency-derived timeouts with FUSE_TIMEOUT_MULTIPLIER
+const DEFAULT_TIMEOUT_FLOOR: Duration = Duration::from_millis(500);
+const DEFAULT_TIMEOUT_CEILING: Duration = Duration::from_secs(120);
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_WEBDAV_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Resolved directory entries as (inode, type, name)
+type DirListing = Vec<(u64, FileType, String)>;
+
+/// A file to notify core about, with the identity that read it
+type AtimeNotice = (Option<(u32, u32)>, String);
+
+/// An open directory whose listing is assembled page by page
+struct DirHandle {
+    path: String,
+    listing: DirListing,
+    /// Cursor for the next page, or None once the listing is complete
+    next_cursor: Option<String>,
+    /// Names gathered so far for dir_cache, dropped if the listing grows too large
+    names: Option<Vec<String>>,
+    /// Child names taken into the listing so far, for FUSE_MAX_DIR_ENTRIES
+    seen: usize,
+}
+
+/// A WebDAV response body kept open between reads, positioned at `offset`
+struct WebdavStream {
+    url: String,
+    offset: u64,
+    body: reqwest::blocking::Response,
+}
+
+/// State for an open file
+#[derive(Default)]
+struct FileHandle {
+    /// Left open after a sequential WebDAV read so the next one continues it
+    stream: Option<WebdavStream>,
+    /// Blocks fetched through the fetch URL, including readahead
+    blocks: FetchedBlocks,
+    /// The last FUSE_READ_ALIGN block read through this handle, so reads
+    /// inside it don't fetch it again
+    aligned: Option<AlignedBlock>,
+}
+
+/// A block of content starting at an aligned offset, with the file size and
+/// mtime it was read at
+struct AlignedBlock {
+    start: usize,
+    data: Vec<u8>,
+    file_size: u64,
+    mtime: Option<f64>,
+}
+
+impl AlignedBlock {
+    /// The bytes of `size` at `offset`, if the block was read from the same
+    /// version of the file and covers them, or reaches its end
+    fn slice(
+        &self,
+        read_result: &api_client::ReadResult,
+        offset: usize,
+        size: usize,
+    ) -> Option<Vec<u8>> {
+        if self.file_size != read_result.size || self.mtime != read_result.mtime {
+            return None;
+        }
+        let end = self.start + self.data.len();
+        let reaches_eof = end as u64 >= self.file_size;
+        if offset < self.start || (offset + size > end && !reaches_eof) {
+            return None;
+        }
+        let skip = std::cmp::min(offset - self.start, self.data.len());
+        let until = std::cmp::min(skip + size, self.data.len());
+        Some(self.data[skip..until].to_vec())
+    }
+}
+
+/// Data fetched for a range, with the offset it starts at
+type RangePart = (u64, Vec<u8>);
+
+/// Block-aligned pieces of one open file, keyed by block index and evicted
+/// oldest first
+#[derive(Default)]
+struct FetchedBlocks {
+    /// File size the blocks were fetched at; a change discards them
+    file_size: u64,
+    blocks: HashMap<u64, Vec<u8>>,
+    order: VecDeque<u64>,
+}
+
+impl FetchedBlocks {
+    /// Keep the whole blocks within a fetched range starting at `start`;
+    /// the final block of the file may be short
+    fn store(&mut self, block_size: u64, start: u64, data: &[u8]) {
+        let mut index = start.div_ceil(block_size);
+        loop {
+            let from = (index * block_size - start) as usize;
+            if from >= data.len() {
+                return;
+            }
+            let to = std::cmp::min(from + block_size as usize, data.len());
+            let complete = to - from == block_size as usize
+                || index * block_size + (to - from) as u64 == self.file_size;
+            if !complete {
+                return;
+            }
+            if self.blocks.insert(index, data[from..to].to_vec()).is_none() {
+                self.order.push_back(index);
+            }
+            while self.order.len() > FETCHED_BLOCKS_MAX {
+                if let Some(oldest) = self.order.pop_front() {
+                    self.blocks.remove(&oldest);
+                }
+            }
+            index += 1;
+        }
+    }
+}
+
+/// Blocks a read has claimed in blocks_in_flight, released when it is done
+/// with them, whether it stored them, failed or unwound
+struct InFlightBlocks<'a> {
+    fs: &'a ApiFS,
+    fh: u64,
+    indices: Vec<u64>,
+}
+
+impl Drop for InFlightBlocks<'_> {
+    fn drop(&mut self) {
+        if self.indices.is_empty() {
+            return;
+        }
+        if let Ok(mut in_flight) = self.fs.blocks_in_flight.lock() {
+            for index in &self.indices {
+                in_flight.remove(&(self.fh, *index));
+            }
+        }
+        self.fs.blocks_landed.notify_all();
+    }
+}
+
+/// Cache key: the caller's uid when identity is forwarded, so one user's
+/// results are never served to another, plus the path
+type CacheKey = (Option<u32>, String);
+
+fn cache_key(path: &str) -> CacheKey {
+    (api_client::current_uid(), normalize_path(path))
+}
 
 /// Cached directory entry
 #[derive(Clone)]
 struct CachedDirEntry {
     entries: Vec<String>,
     timestamp: SystemTime,
+    /// This entry's lifetime, jittered so entries don't expire together
+    ttl: Duration,
+    /// The directory's own mtime when listed, if its attributes were cached
+    mtime: Option<f64>,
+}
+
+/// mtime of a path's cached attributes, expired or not
+fn cached_mtime(attr_cache: &Mutex<HashMap<CacheKey, CachedAttrs>>, path: &str) -> Option<f64> {
+    attr_cache
+        .lock()
+        .unwrap()
+        .get(&cache_key(path))
+        .map(|cached| cached.attrs.mtime)
 }
 
 /// Cached file attributes
 #[derive(Clone)]
 struct CachedAttrs {
     attrs: api_client::FileAttributes,
+    /// ETag from the response, used to revalidate once expired
+    etag: Option<String>,
     timestamp: SystemTime,
+    /// This entry's lifetime, jittered so entries don't expire together
+    /// and lengthened while revalidation keeps finding no change
+    ttl: Duration,
+    /// Consecutive revalidations that found the attributes unchanged
+    unchanged_streak: u32,
+}
+
+impl CachedAttrs {
+    /// Restart the entry's lifetime after core confirmed it unchanged
+    fn renew(&mut self, cache_ttl: &CacheTtl) {
+        self.unchanged_streak = self.unchanged_streak.saturating_add(1);
+        if cache_ttl.adaptive_max.is_some() {
+            self.ttl = cache_ttl.extended(cache_ttl.sample(), self.unchanged_streak);
+        }
+        self.timestamp = SystemTime::now();
+    }
+}
+
+/// Cache fresh attributes for a path under `cache_ttl`, or drop its entry if
+/// core said not to store them. Attributes whose mtime and size match the
+/// entry they replace count as an unchanged revalidation, unless core set
+/// the lifetime itself.
+fn insert_attrs(
+    cache: &mut HashMap<CacheKey, CachedAttrs>,
+    cache_ttl: &CacheTtl,
+    path: &str,
+    attrs: api_client::FileAttributes,
+    etag: Option<String>,
+    cache_control: CacheControl,
+) {
+    let key = cache_key(path);
+    let Some(ttl) = cache_ttl.for_response(&cache_control) else {
+        debug!("Not caching attrs for {}: no-store", path);
+        cache.remove(&key);
+        return;
+    };
+    let unchanged_streak = match cache.get(&key) {
+        Some(previous)
+            if previous.attrs.mtime == attrs.mtime
+                && previous.attrs.size == attrs.size
+                && cache_control.max_age.is_none()
+                && !cache_control.immutable =>
+        {
+            previous.unchanged_streak.saturating_add(1)
+        }
+        _ => 0,
+    };
+    cache.insert(
+        key,
+        CachedAttrs {
+            attrs,
+            etag,
+            timestamp: SystemTime::now(),
+            ttl: cache_ttl.extended(ttl, unchanged_streak),
+            unchanged_streak,
+        },
+    );
+}
+
+/// File types by path, shared by every user. Kept apart from the attribute
+/// cache and for longer, since readdir needs only the type and types rarely
+/// change; they also outlive attributes dropped when the kernel forgets an
+/// inode.
+type TypeCache = Arc<Mutex<HashMap<CacheKey, (FileType, SystemTime)>>>;
+
+fn cache_type(types: &TypeCache, path: &str, mode: u32) {
+    let mut types = types.lock().unwrap();
+    if types.len() >= TYPE_CACHE_MAX_ENTRIES {
+        types.retain(|_, (_, timestamp)| is_fresh(*timestamp, TYPE_CACHE_TTL));
+    }
+    types.insert(
+        cache_key(path),
+        (file_type_from_mode(mode), SystemTime::now()),
+    );
+}
+
+/// Remember that a path does not exist.
+///
+/// A full cache first drops whatever has expired, then the older half of
+/// what is left, so a scan of missing names cannot grow it without bound.
+fn cache_negative(cache: &Mutex<HashMap<CacheKey, SystemTime>>, ttl: Duration, path: &str) {
+    let mut cache = cache.lock().unwrap();
+    if cache.len() >= NEGATIVE_CACHE_MAX_ENTRIES {
+        cache.retain(|_, timestamp| is_fresh(*timestamp, ttl));
+    }
+    if cache.len() >= NEGATIVE_CACHE_MAX_ENTRIES {
+        let mut learned: Vec<SystemTime> = cache.values().copied().collect();
+        let (_, &mut median, _) = learned.select_nth_unstable(cache.len() / 2);
+        cache.retain(|_, timestamp| *timestamp > median);
+    }
+    cache.insert(cache_key(path), SystemTime::now());
+}
+
+/// Driver cache lifetime, spread randomly by up to ±jitter_pct per entry so
+/// that entries stored together don't all expire in the same instant
+#[derive(Debug, Clone, Copy)]
+struct CacheTtl {
+    base: Duration,
+    jitter_pct: u32,
+    /// Longest an attribute entry's lifetime may grow to by doubling with
+    /// each unchanged revalidation; None keeps lifetimes fixed
+    adaptive_max: Option<Duration>,
+}
+
+impl CacheTtl {
+    /// A lifetime for a newly stored entry
+    fn sample(&self) -> Duration {
+        use ring::rand::SecureRandom;
+
+        let mut bytes = [0u8; 4];
+        if self.jitter_pct == 0 || ring::rand::SystemRandom::new().fill(&mut bytes).is_err() {
+            return self.base;
+        }
+        // Uniform in [-1, 1]
+        let unit = u32::from_le_bytes(bytes) as f64 / u32::MAX as f64 * 2.0 - 1.0;
+        self.base
+            .mul_f64(1.0 + unit * self.jitter_pct as f64 / 100.0)
+    }
+
+    /// Lifetime for an entry from a response carrying `cache_control`, or
+    /// None if it must not be stored
+    fn for_response(&self, cache_control: &CacheControl) -> Option<Duration> {
+        if cache_control.no_store {
+            None
+        } else if cache_control.immutable {
+            Some(IMMUTABLE_TTL)
+        } else {
+            Some(cache_control.max_age.unwrap_or_else(|| self.sample()))
+        }
+    }
+
+    /// `ttl` doubled once per unchanged revalidation, up to adaptive_max
+    fn extended(&self, ttl: Duration, unchanged_streak: u32) -> Duration {
+        match self.adaptive_max {
+            Some(max) if unchanged_streak > 0 => ttl
+                .saturating_mul(2u32.saturating_pow(unchanged_streak))
+                .min(max)
+                .max(ttl),
+            _ => ttl,
+        }
+    }
+}
+
+/// Whether an entry stored at `timestamp` is still within `ttl`
+fn is_fresh(timestamp: SystemTime, ttl: Duration) -> bool {
+    SystemTime::now()
+        .duration_since(timestamp)
+        .map(|d| d < ttl)
+        .unwrap_or(false)
+}
+
+/// Whole-file content, revalidated against core by mtime on each read
+struct CachedContent {
+    data: Vec<u8>,
+    mtime: f64,
+    stored_at: Instant,
+    /// Set when core gave the content a max-age or marked it immutable:
+    /// it may be served without checking the attributes until then
+    fresh_until: Option<Instant>,
+}
+
+type ContentCache = Arc<Mutex<HashMap<CacheKey, CachedContent>>>;
+
+/// The first FUSE_SNIFF_BYTES of a file, valid while its mtime is unchanged
+struct CachedHead {
+    data: Vec<u8>,
+    mtime: f64,
+    stored_at: Instant,
+}
+
+/// Drop a path's cached content (raw and pretty-printed) when fresh
+/// attributes show it changed: a different mtime or size than the attributes
+/// cached before, or than the content was stored under
+fn purge_changed_content(
+    content: &ContentCache,
+    pretty: &ContentCache,
+    path: &str,
+    previous: Option<&api_client::FileAttributes>,
+    attrs: &api_client::FileAttributes,
+) {
+    let key = cache_key(path);
+    let changed = previous.is_some_and(|prev| prev.mtime != attrs.mtime || prev.size != attrs.size);
+    for cache in [content, pretty] {
+        let mut cache = cache.lock().unwrap();
+        if cache
+            .get(&key)
+            .is_some_and(|cached| changed || cached.mtime != attrs.mtime)
+        {
+            debug!("{} changed, dropping cached content", path);
+            cache.remove(&key);
+        }
+    }
+}
+
+/// Circuit breaker state for API calls
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CircuitState {
+    /// Requests flow normally
+    Closed,
+    /// Requests fail fast until the cooldown has elapsed
+    Open { opened_at: Instant },
+    /// A single probe request is in flight; everything else fails fast.
+    /// A probe whose outcome is never recorded stops holding the circuit
+    /// once a cooldown has passed since it was let through.
+    HalfOpen { probe_at: Instant },
 }
 
 /// API health tracker
 struct ApiHealth {
     consecutive_errors: usize,
+    /// Errors that mark the API unhealthy
+    error_threshold: usize,
+    /// When set, the threshold counts errors within this window rather than
+    /// consecutive ones, so a slow drip of failures never trips it
+    error_window: Option<Duration>,
+    /// Times of the most recent errors, at most error_threshold of them
+    recent_errors: VecDeque<Instant>,
     last_error_message: String,
     last_error_time: Option<SystemTime>,
+    circuit: CircuitState,
+    circuit_cooldown: Duration,
+    /// When the current unhealthy stretch began
+    unhealthy_since: Option<Instant>,
+    /// Published for lock-free reads whenever the API turns (un)healthy
+    status: Arc<HealthStatus>,
+}
+
+/// What the hot paths need to know about API health, readable without
+/// taking the ApiHealth lock. Only rewritten when the API turns unhealthy
+/// or recovers.
+#[derive(Debug, Default)]
+struct HealthStatus {
+    unhealthy: AtomicBool,
+    /// ERROR.txt content frozen when the unhealthy stretch began, so its
+    /// reported size and read data always agree
+    error_content: RwLock<Option<Arc<str>>>,
+}
+
+impl HealthStatus {
+    fn is_unhealthy(&self) -> bool {
+        self.unhealthy.load(Ordering::Acquire)
+    }
+
+    fn error_content(&self) -> Option<Arc<str>> {
+        self.error_content.read().unwrap().clone()
+    }
+
+    fn publish(&self, error_content: Option<String>) {
+        let unhealthy = error_content.is_some();
+        *self.error_content.write().unwrap() = error_content.map(Arc::from);
+        self.unhealthy.store(unhealthy, Ordering::Release);
+    }
 }
 
 impl ApiHealth {
-    fn new() -> Self {
+    fn new(
+        circuit_cooldown: Duration,
+        error_threshold: usize,
+        error_window: Option<Duration>,
+    ) -> Self {
         ApiHealth {
             consecutive_errors: 0,
+            error_threshold,
+            error_window,
+            recent_errors: VecDeque::with_capacity(error_threshold),
             last_error_message: String::new(),
             last_error_time: None,
+            circuit: CircuitState::Closed,
+            circuit_cooldown,
+            unhealthy_since: None,
+            status: Arc::new(HealthStatus::default()),
         }
     }
 
+    fn status(&self) -> Arc<HealthStatus> {
+        Arc::clone(&self.status)
+    }
+
     fn record_success(&mut self) {
+        if self.circuit != CircuitState::Closed {
+            info!("API probe succeeded, closing circuit");
+        }
+        // recent_errors is left alone: with a window set, errors count
+        // towards the threshold until they age out, successes or not
         self.consecutive_errors = 0;
+        self.circuit = CircuitState::Closed;
+        if self.unhealthy_since.take().is_some() {
+            self.status.publish(None);
+        }
+    }
+
+    /// Record a failed API call.
+    ///
+    /// Only failures that mean the backend is unavailable count towards the
+    /// circuit breaker; an answer like "not found" proves the API is alive.
+    fn record_api_error(&mut self, e: &ApiError, message: String) {
+        if e.is_backend_failure() {
+            self.record_error(message);
+        } else {
+            self.record_success();
+        }
     }
 
     fn record_error(&mut self, message: String) {
         self.consecutive_errors += 1;
         self.last_error_message = message;
         self.last_error_time = Some(SystemTime::now());
-        if self.consecutive_errors >= API_ERROR_THRESHOLD {
-            error!("API has failed {} consecutive times. ERROR.txt will be displayed.", self.consecutive_errors);
+
+        let now = Instant::now();
+        if let Some(window) = self.error_window {
+            while self
+                .recent_errors
+                .front()
+                .is_some_and(|&at| now.duration_since(at) > window)
+            {
+                self.recent_errors.pop_front();
+            }
+        }
+        if self.recent_errors.len() >= self.error_threshold {
+            self.recent_errors.pop_front();
+        }
+        self.recent_errors.push_back(now);
+
+        let tripped = self.threshold_reached(now);
+        if tripped {
+            if self.unhealthy_since.is_none() {
+                self.unhealthy_since = Some(now);
+                self.status.publish(Some(self.render_error_content()));
+            }
+            match self.error_window {
+                Some(window) => error!(
+                    "API has failed {} times in {}s. ERROR.txt will be displayed.",
+                    self.recent_errors.len(),
+                    window.as_secs()
+                ),
+                None => error!(
+                    "API has failed {} consecutive times. ERROR.txt will be displayed.",
+                    self.consecutive_errors
+                ),
+            }
+        }
+        if matches!(self.circuit, CircuitState::HalfOpen { .. }) || tripped {
+            if self.circuit == CircuitState::Closed {
+                error!(
+                    "Opening circuit for {}s, API calls will fail fast",
+                    self.circuit_cooldown.as_secs()
+                );
+            }
+            self.circuit = CircuitState::Open {
+                opened_at: Instant::now(),
+            };
         }
     }
 
-    fn is_unhealthy(&self) -> bool {
-        self.consecutive_errors >= API_ERROR_THRESHOLD
+    /// Returns whether an API request may be attempted right now.
+    ///
+    /// While the circuit is open every call is refused until the cooldown
+    /// elapses, at which point exactly one probe is let through (half-open).
+    fn allow_request(&mut self) -> bool {
+        match self.circuit {
+            CircuitState::Closed => true,
+            CircuitState::Open { opened_at } => {
+                if opened_at.elapsed() >= self.circuit_cooldown {
+                    debug!("Circuit cooldown elapsed, allowing probe request");
+                    self.circuit = CircuitState::HalfOpen {
+                        probe_at: Instant::now(),
+                    };
+                    true
+                } else {
+                    false
+                }
+            }
+            CircuitState::HalfOpen { probe_at } => {
+                if probe_at.elapsed() >= self.circuit_cooldown {
+                    debug!("Probe request never reported back, allowing another");
+                    self.circuit = CircuitState::HalfOpen {
+                        probe_at: Instant::now(),
+                    };
+                    true
+                } else {
+                    false
+                }
+            }
+        }
+    }
+
+    /// Whether the errors so far mark the API unhealthy: error_threshold
+    /// of them within the window if one is set, else that many in a row
+    fn threshold_reached(&self, now: Instant) -> bool {
+        match self.error_window {
+            Some(window) => {
+                self.recent_errors.len() >= self.error_threshold
+                    && self
+                        .recent_errors
+                        .front()
+                        .is_some_and(|&oldest| now.duration_since(oldest) <= window)
+            }
+            None => self.consecutive_errors >= self.error_threshold,
+        }
+    }
+
+    /// How long the API has been continuously unhealthy, if it is
+    fn unhealthy_for(&self) -> Option<Duration> {
+        self.unhealthy_since.map(|since| since.elapsed())
     }
 
-    fn get_error_content(&self) -> String {
-        let timestamp = self.last_error_time
+    fn render_error_content(&self) -> String {
+        let timestamp = self
+            .last_error_time
             .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
             .map(|d| d.as_secs())
             .unwrap_or(0);
@@ -91,26 +715,90 @@ impl ApiHealth {
             2. Check API health: curl http://localhost:3000/api/fuse/health\n\
             3. Restart the container: docker restart meta-fuse\n\n\
             This ERROR.txt file will disappear once the API is responding again.\n",
-            self.consecutive_errors,
-            self.last_error_message,
-            timestamp
+            self.consecutive_errors, self.last_error_message, timestamp
         )
     }
 }
 
+/// FNV-1a, chosen because it is fixed across builds and Rust versions
+fn fnv1a_64(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Hashed inode for a path, rehashed until it leaves the reserved range so
+/// no path can become the root or a synthetic file. Depends only on the
+/// path, so it stays stable across remounts.
+fn path_ino_hash(path: &str) -> u64 {
+    let mut ino = fnv1a_64(path.as_bytes());
+    while ino <= LAST_SYNTHETIC_INO {
+        ino = fnv1a_64(&ino.to_le_bytes());
+    }
+    ino
+}
+
+/// Hit/miss counters reported through the stats file
+#[derive(Default)]
+struct CacheStats {
+    dir_hits: AtomicU64,
+    dir_misses: AtomicU64,
+    attr_hits: AtomicU64,
+    attr_misses: AtomicU64,
+    content_hits: AtomicU64,
+    /// Content hits served without asking core at all
+    small_file_hits: AtomicU64,
+    content_misses: AtomicU64,
+    sniff_hits: AtomicU64,
+    sniff_misses: AtomicU64,
+}
+
 /// Maps virtual paths to inode numbers
 struct InodeMapper {
     path_to_ino: HashMap<String, u64>,
     ino_to_path: HashMap<u64, String>,
     next_ino: u64,
+    /// Derive inodes from a hash of the path so they survive remounts
+    stable: bool,
+    /// Outstanding kernel lookups per inode, released by forget
+    lookups: HashMap<u64, u64>,
+    /// Generation of live inodes that have been recycled; absent means 0
+    generations: HashMap<u64, u64>,
+    /// Evicted hashed inodes, remembered so reusing one bumps the
+    /// generation and a late request for one can be served
+    retired: HashMap<u64, RetiredIno>,
+    /// Evictions so far, ordering the retired entries for pruning
+    retired_count: u64,
+}
+
+struct RetiredIno {
+    /// Hash of the inode's last path
+    path_hash: u64,
+    generation: u64,
+    /// Parent inode and name at eviction, if the parent was still mapped
+    link: Option<(u64, String)>,
+    /// Value of retired_count when this inode was evicted
+    retired_at: u64,
 }
 
+/// Deepest parent chain get_path will walk to rebuild an evicted path
+const PATH_RECOVERY_MAX_DEPTH: usize = 256;
+/// Evicted inodes remembered before the older half is pruned. A pruned
+/// inode that is reused starts again at generation 0, by which time the
+/// kernel has long dropped any handle to the old one.
+const RETIRED_INODES_MAX: usize = 100_000;
+
 impl InodeMapper {
-    fn new() -> Self {
+    fn new(stable: bool) -> Self {
         let mut mapper = InodeMapper {
             path_to_ino: HashMap::new(),
             ino_to_path: HashMap::new(),
-            next_ino: ROOT_INO + 1,
+            next_ino: LAST_SYNTHETIC_INO + 1,
+            stable,
+            lookups: HashMap::new(),
+            generations: HashMap::new(),
+            retired: HashMap::new(),
+            retired_count: 0,
         };
         mapper.path_to_ino.insert("/".to_string(), ROOT_INO);
         mapper.ino_to_path.insert(ROOT_INO, "/".to_string());
@@ -118,607 +806,6910 @@ impl InodeMapper {
     }
 
     fn get_or_create_ino(&mut self, path: &str) -> u64 {
-        if let Some(&ino) = self.path_to_ino.get(path) {
+        let path = normalize_path(path);
+        if let Some(&ino) = self.path_to_ino.get(&path) {
             return ino;
         }
 
+        let ino = match self.stable_ino(&path) {
+            Some(ino) => ino,
+            None => self.next_sequential_ino(),
+        };
+        // The kernel forgot the evicted inode, so what a new lookup maps to
+        // it, even under the same path, may be another file (removed and
+        // recreated): a new generation keeps old NFS handles from reaching it
+        if let Some(retired) = self.retired.remove(&ino) {
+            self.generations.insert(ino, retired.generation + 1);
+        }
+        self.path_to_ino.insert(path.clone(), ino);
+        self.ino_to_path.insert(ino, path);
+        ino
+    }
+
+    /// The hashed inode for a path, or None if stable inodes are off or the
+    /// hash lands on an already-taken inode.
+    ///
+    /// A 64-bit hash makes collisions vanishingly rare for realistic trees;
+    /// when one does happen the later path falls back to a sequential
+    /// inode, which is only stable if paths are looked up in the same order.
+    fn stable_ino(&self, path: &str) -> Option<u64> {
+        if !self.stable {
+            return None;
+        }
+        let ino = path_ino_hash(path);
+        match self.ino_to_path.get(&ino) {
+            Some(existing) => {
+                warn!(
+                    "Inode hash collision between {} and {}, allocating sequentially",
+                    existing, path
+                );
+                None
+            }
+            None => Some(ino),
+        }
+    }
+
+    fn next_sequential_ino(&mut self) -> u64 {
+        // Hashed inodes may already occupy part of the sequential range
+        while self.ino_to_path.contains_key(&self.next_ino) {
+            self.next_ino += 1;
+        }
         let ino = self.next_ino;
         self.next_ino += 1;
-        self.path_to_ino.insert(path.to_string(), ino);
-        self.ino_to_path.insert(ino, path.to_string());
         ino
     }
 
-    fn get_path(&self, ino: u64) -> Option<&String> {
-        self.ino_to_path.get(&ino)
+    /// Count a lookup handed to the kernel; reserved inodes are never counted
+    fn add_lookup(&mut self, ino: u64) {
+        if ino > LAST_SYNTHETIC_INO && self.ino_to_path.contains_key(&ino) {
+            *self.lookups.entry(ino).or_insert(0) += 1;
+        }
     }
-}
 
-struct ApiFS {
-    api: ApiClient,
-    inode_mapper: Arc<Mutex<InodeMapper>>,
-    dir_cache: Arc<Mutex<HashMap<String, CachedDirEntry>>>,
-    attr_cache: Arc<Mutex<HashMap<String, CachedAttrs>>>,
-    api_health: Arc<Mutex<ApiHealth>>,
-    default_uid: u32,
-    default_gid: u32,
-    file_perm: u16,
-    dir_perm: u16,
-}
+    /// Release `nlookup` kernel references to an inode, dropping its
+    /// mapping once none remain. Returns the path of an evicted inode.
+    fn forget(&mut self, ino: u64, nlookup: u64) -> Option<String> {
+        let Entry::Occupied(mut count) = self.lookups.entry(ino) else {
+            return None;
+        };
+        *count.get_mut() = count.get().saturating_sub(nlookup);
+        if *count.get() > 0 {
+            return None;
+        }
+        count.remove();
+        let path = self.ino_to_path.remove(&ino)?;
+        self.path_to_ino.remove(&path);
+        // Sequential inodes are never handed out twice; hashed ones come
+        // back whenever a path hashes to them again
+        let generation = self.generations.remove(&ino).unwrap_or(0);
+        if self.stable {
+            let (parent, name) = split_parent(&path);
+            let link = self
+                .path_to_ino
+                .get(parent)
+                .map(|&parent_ino| (parent_ino, name.to_string()));
+            if self.retired.len() >= RETIRED_INODES_MAX {
+                let cutoff = self.retired_count - RETIRED_INODES_MAX as u64 / 2;
+                self.retired
+                    .retain(|_, retired| retired.retired_at >= cutoff);
+            }
+            self.retired_count += 1;
+            self.retired.insert(
+                ino,
+                RetiredIno {
+                    path_hash: fnv1a_64(path.as_bytes()),
+                    generation,
+                    link,
+                    retired_at: self.retired_count,
+                },
+            );
+        }
+        Some(path)
+    }
 
-impl ApiFS {
-    fn new(api_url: String, uid: u32, gid: u32, file_perm: u16, dir_perm: u16) -> Result<Self, Box<dyn std::error::Error>> {
-        let api = ApiClient::new(api_url)?;
+    /// Generation to report with an inode, bumped each time it is recycled
+    fn generation(&self, ino: u64) -> u64 {
+        self.generations.get(&ino).copied().unwrap_or(0)
+    }
 
-        if !api.health_check()? {
-            return Err("API health check failed".into());
+    /// Path of an inode, rebuilding the mapping of an evicted one from its
+    /// parent chain if need be
+    fn get_path(&mut self, ino: u64) -> Option<&String> {
+        if !self.ino_to_path.contains_key(&ino) {
+            self.recover_path(ino, PATH_RECOVERY_MAX_DEPTH)?;
         }
+        self.ino_to_path.get(&ino)
+    }
 
-        Ok(ApiFS {
-            api,
-            inode_mapper: Arc::new(Mutex::new(InodeMapper::new())),
-            dir_cache: Arc::new(Mutex::new(HashMap::new())),
-            attr_cache: Arc::new(Mutex::new(HashMap::new())),
-            api_health: Arc::new(Mutex::new(ApiHealth::new())),
-            default_uid: uid,
-            default_gid: gid,
-            file_perm,
-            dir_perm,
-        })
+    /// Re-map an evicted hashed inode under the path its parent link
+    /// describes, provided that path still hashes to it
+    fn recover_path(&mut self, ino: u64, depth: usize) -> Option<String> {
+        if let Some(path) = self.ino_to_path.get(&ino) {
+            return Some(path.clone());
+        }
+        let retired = self.retired.get(&ino)?;
+        let (parent, name) = retired.link.clone()?;
+        let path_hash = retired.path_hash;
+        let parent_path = self.recover_path(parent, depth.checked_sub(1)?)?;
+        let path = if parent_path == "/" {
+            format!("/{}", name)
+        } else {
+            format!("{}/{}", parent_path, name)
+        };
+        if fnv1a_64(path.as_bytes()) != path_hash
+            || self.path_to_ino.contains_key(&path)
+            || path_ino_hash(&path) != ino
+        {
+            return None;
+        }
+        // A late request names the inode the kernel knew, so it keeps its
+        // generation rather than being treated as a new lookup
+        let generation = self
+            .retired
+            .remove(&ino)
+            .map_or(0, |retired| retired.generation);
+        if generation > 0 {
+            self.generations.insert(ino, generation);
+        }
+        self.path_to_ino.insert(path.clone(), ino);
+        self.ino_to_path.insert(ino, path.clone());
+        debug!("Recovered evicted inode {} as {}", ino, path);
+        Some(path)
     }
 
-    fn is_cache_valid(timestamp: SystemTime) -> bool {
-        SystemTime::now()
-            .duration_since(timestamp)
-            .map(|d| d < CACHE_TTL)
-            .unwrap_or(false)
+    fn get_ino(&self, path: &str) -> Option<u64> {
+        self.path_to_ino.get(&normalize_path(path)).copied()
     }
+}
 
-    fn get_cached_readdir(&self, path: &str) -> Option<Vec<String>> {
-        let cache = self.dir_cache.lock().unwrap();
-        if let Some(cached) = cache.get(path) {
-            if Self::is_cache_valid(cached.timestamp) {
-                debug!("Cache hit for readdir: {}", path);
-                return Some(cached.entries.clone());
-            }
+/// Handles to the driver caches for use from background threads
+#[derive(Clone)]
+pub struct SharedCaches {
+    inode_mapper: Arc<Mutex<InodeMapper>>,
+    dir_cache: Arc<Mutex<HashMap<CacheKey, CachedDirEntry>>>,
+    attr_cache: Arc<Mutex<HashMap<CacheKey, CachedAttrs>>>,
+    /// Paths known not to exist, with when that was learned
+    negative_cache: Arc<Mutex<HashMap<CacheKey, SystemTime>>>,
+    type_cache: TypeCache,
+    content_cache: ContentCache,
+    pretty_cache: ContentCache,
+    cache_ttl: CacheTtl,
+}
+
+impl SharedCaches {
+    /// Forget the path and its parent listing.
+    ///
+    /// Returns the (parent inode, name) entry and the path's own inode, for
+    /// whichever of them the kernel may have been told about.
+    pub fn invalidate(&self, path: &str) -> (Option<(u64, String)>, Option<u64>) {
+        let path = normalize_path(path);
+        let path = path.as_str();
+        let (parent, name) = split_parent(path);
+
+        // Drop the entries for every user's view of the path
+        self.attr_cache
+            .lock()
+            .unwrap()
+            .retain(|(_, cached), _| cached != path);
+        self.negative_cache
+            .lock()
+            .unwrap()
+            .retain(|(_, cached), _| cached != path);
+        self.type_cache
+            .lock()
+            .unwrap()
+            .retain(|(_, cached), _| cached != path);
+        for content in [&self.content_cache, &self.pretty_cache] {
+            content
+                .lock()
+                .unwrap()
+                .retain(|(_, cached), _| cached != path);
         }
-        None
+        self.dir_cache
+            .lock()
+            .unwrap()
+            .retain(|(_, cached), _| cached != path && cached != parent);
+
+        let mapper = self.inode_mapper.lock().unwrap();
+        let entry = if path == "/" || name.is_empty() {
+            None
+        } else {
+            mapper.get_ino(parent).map(|ino| (ino, name.to_string()))
+        };
+        (entry, mapper.get_ino(path))
     }
 
-    fn cache_readdir(&self, path: &str, entries: Vec<String>) {
-        let mut cache = self.dir_cache.lock().unwrap();
-        cache.insert(
-            path.to_string(),
+    pub fn store_listing(&self, path: &str, entries: Vec<String>) {
+        let mtime = cached_mtime(&self.attr_cache, path);
+        self.dir_cache.lock().unwrap().insert(
+            cache_key(path),
             CachedDirEntry {
                 entries,
                 timestamp: SystemTime::now(),
+                ttl: self.cache_ttl.sample(),
+                mtime,
             },
         );
     }
 
-    fn get_cached_attrs(&self, path: &str) -> Option<api_client::FileAttributes> {
-        let cache = self.attr_cache.lock().unwrap();
-        if let Some(cached) = cache.get(path) {
-            if Self::is_cache_valid(cached.timestamp) {
-                debug!("Cache hit for getattr: {}", path);
-                return Some(cached.attrs.clone());
-            }
-        }
-        None
-    }
-
-    fn cache_attrs(&self, path: &str, attrs: api_client::FileAttributes) {
+    pub fn store_attrs(
+        &self,
+        path: &str,
+        attrs: api_client::FileAttributes,
+        etag: Option<String>,
+        cache_control: CacheControl,
+    ) {
+        cache_type(&self.type_cache, path, attrs.mode);
         let mut cache = self.attr_cache.lock().unwrap();
-        cache.insert(
-            path.to_string(),
-            CachedAttrs {
-                attrs,
-                timestamp: SystemTime::now(),
-            },
+        purge_changed_content(
+            &self.content_cache,
+            &self.pretty_cache,
+            path,
+            cache.get(&cache_key(path)).map(|cached| &cached.attrs),
+            &attrs,
+        );
+        insert_attrs(
+            &mut cache,
+            &self.cache_ttl,
+            path,
+            attrs,
+            etag,
+            cache_control,
         );
     }
 
-    fn get_error_file_attrs(&self) -> FileAttr {
-        let content = self.api_health.lock().unwrap().get_error_content();
-        let size = content.len() as u64;
+    /// ETag of the cached attributes for a path, expired or not
+    pub fn attr_etag(&self, path: &str) -> Option<String> {
+        self.attr_cache
+            .lock()
+            .unwrap()
+            .get(&cache_key(path))
+            .and_then(|cached| cached.etag.clone())
+    }
 
-        FileAttr {
-            ino: ERROR_FILE_INO,
-            size,
-            blocks: (size + 511) / 512,
-            atime: UNIX_EPOCH + Duration::from_secs(0),
-            mtime: UNIX_EPOCH + Duration::from_secs(0),
-            ctime: UNIX_EPOCH + Duration::from_secs(0),
-            crtime: UNIX_EPOCH,
-            kind: FileType::RegularFile,
-            perm: 0o444,
-            nlink: 1,
-            uid: self.default_uid,
-            gid: self.default_gid,
-            rdev: 0,
-            blksize: 512,
-            flags: 0,
+    /// Forget a path the API no longer has and remember that it is missing
+    pub fn store_missing(&self, path: &str) {
+        self.invalidate(path);
+        cache_negative(&self.negative_cache, self.cache_ttl.base, path);
+    }
+
+    /// Mark cached attributes as freshly revalidated
+    pub fn touch_attrs(&self, path: &str) {
+        if let Some(cached) = self.attr_cache.lock().unwrap().get_mut(&cache_key(path)) {
+            cached.renew(&self.cache_ttl);
         }
     }
+}
 
-    fn convert_attrs(&self, path: &str, api_attrs: api_client::FileAttributes) -> FileAttr {
-        let ino = {
-            let mut mapper = self.inode_mapper.lock().unwrap();
-            mapper.get_or_create_ino(path)
-        };
+/// Runtime options for the filesystem
+struct FsConfig {
+    uid: u32,
+    gid: u32,
+    file_perm: u16,
+    dir_perm: u16,
+    /// How long the kernel may cache entries and attributes
+    attr_ttl: Duration,
+    /// How long the driver caches directory listings and attributes
+    cache_ttl: CacheTtl,
+    request_timeouts: RequestTimeouts,
+    webdav_timeout: Duration,
+    circuit_cooldown: Duration,
+    /// API errors that mark it unhealthy
+    error_threshold: usize,
+    /// Count errors within this window instead of consecutive ones
+    error_window: Option<Duration>,
+    verify_checksums: bool,
+    /// Report and enforce the API's mode bits instead of file_perm/dir_perm
+    use_api_perms: bool,
+    /// Report the API's uid/gid instead of the configured owner
+    use_api_ownership: bool,
+    /// How many directory levels to walk into the caches after mounting
+    preload_depth: usize,
+    /// Name of the synthetic stats file in the root, if enabled
+    stats_file: Option<String>,
+    /// Serve a description of the mount as /.meta-fuse
+    info_file: bool,
+    /// Accept cache commands written to /.meta-fuse-control
+    control_file: bool,
+    /// Hash paths into inode numbers that stay the same across remounts
+    stable_inodes: bool,
+    /// Send the caller's uid/gid to the API and cache results per user
+    forward_identity: bool,
+    /// API requests at least this slow are logged as warnings
+    slow_request: Option<Duration>,
+    /// User-Agent for API requests, overriding meta-fuse-driver/<version>
+    api_user_agent: Option<String>,
+    /// Core directory to present as the mount root
+    root_prefix: Option<String>,
+    /// Encoding of API request bodies
+    api_body_format: BodyFormat,
+    /// Response inflation limit and request gzip threshold
+    api_compression: Compression,
+    /// Client certificate and extra CA for talking to cores over TLS
+    api_tls: TlsSettings,
+    /// Most API requests allowed in flight at once; None is unlimited
+    api_max_concurrency: Option<usize>,
+    /// Keep retrying the startup health check for this long; None fails
+    /// on the first attempt
+    wait_for_api: Option<Duration>,
+    /// Unmount and exit once the API has been unhealthy this long
+    unhealthy_exit: Option<Duration>,
+    /// Largest read request, in bytes, to negotiate with the kernel
+    max_read: Option<u32>,
+    /// Preferred I/O size reported to stat
+    blksize: u32,
+    /// Ask the exists endpoint before fetching attributes in lookup
+    lookup_exists_first: bool,
+    /// Renew an expired listing whose directory mtime has not changed
+    dir_mtime_validation: bool,
+    /// Serve JSON files pretty-printed, reporting the reformatted size
+    pretty_json: bool,
+    /// Reject symlink targets that leave the mount, which lives at this
+    /// absolute path; None serves targets unchecked
+    confine_symlinks: Option<std::path::PathBuf>,
+    /// POST an access notification to core when files are opened or read
+    notify_atime: bool,
+    /// Keep file pages in the kernel cache across opens
+    kernel_cache: bool,
+    /// Files up to this size are cached whole and served from memory
+    small_file_cache_size: usize,
+    /// Largest decoded inline payload accepted from a read response
+    max_inline_size: u64,
+    /// Fetch content in whole blocks of this size, serving the requested
+    /// bytes out of them
+    read_align: Option<usize>,
+    /// Cache this many bytes from the start of each file read there
+    sniff_bytes: Option<usize>,
+    /// When set, expired attributes up to this far past their TTL are served
+    /// while a background refresh runs
+    max_stale: Option<Duration>,
+    /// List every file directly under the root by its encoded path
+    flatten: bool,
+    /// Cut directory listings off after this many entries
+    max_dir_entries: Option<usize>,
+    /// List directories core refuses to list (401/403) as empty instead of
+    /// failing
+    readdir_empty_on_error: bool,
+    /// Normalized paths hidden, with everything under them, from the mount
+    hide_prefixes: Vec<String>,
+    /// Content sources in the order reads try them; others are never used
+    read_source_order: Vec<ReadSource>,
+}
 
-        let kind = if api_attrs.mode & 0o040000 != 0 {
-            FileType::Directory
-        } else {
-            FileType::RegularFile
-        };
+struct ApiFS {
+    api: ApiClient,
+    inode_mapper: Arc<Mutex<InodeMapper>>,
+    dir_cache: Arc<Mutex<HashMap<CacheKey, CachedDirEntry>>>,
+    attr_cache: Arc<Mutex<HashMap<CacheKey, CachedAttrs>>>,
+    /// Paths known not to exist, with when that was learned
+    negative_cache: Arc<Mutex<HashMap<CacheKey, SystemTime>>>,
+    type_cache: TypeCache,
+    content_cache: ContentCache,
+    /// Pretty-printed renderings of JSON files, keyed like content_cache
+    pretty_cache: ContentCache,
+    /// File heads for tools sniffing content types, with FUSE_SNIFF_BYTES
+    sniff_cache: Mutex<HashMap<CacheKey, CachedHead>>,
+    api_health: Arc<Mutex<ApiHealth>>,
+    health_status: Arc<HealthStatus>,
+    /// Directory listings snapshotted at opendir, keyed by file handle
+    dir_handles: Arc<Mutex<HashMap<u64, DirHandle>>>,
+    file_handles: Mutex<HashMap<u64, FileHandle>>,
+    /// (file handle, block index) pairs some thread is fetching, so that
+    /// overlapping reads wait for a block rather than fetch it again
+    blocks_in_flight: Mutex<HashSet<(u64, u64)>>,
+    /// Signalled whenever blocks leave blocks_in_flight
+    blocks_landed: Condvar,
+    next_fh: AtomicU64,
+    /// Client for WebDAV and fetch URL reads, separate from the API client
+    webdav_client: reqwest::blocking::Client,
+    stats: CacheStats,
+    started_at: Instant,
+    /// Text of the info file, rendered at mount time; None if it is off
+    info_content: Option<String>,
+    /// Outcome of the last command written to the control file
+    last_control_action: Mutex<String>,
+    /// Set once the session exists, so a flush can drop kernel-cached pages
+    notifier: Arc<Mutex<Option<Arc<fuser::Notifier>>>>,
+    readdir_plus_supported: AtomicBool,
+    path_chain_supported: AtomicBool,
+    seek_supported: AtomicBool,
+    acl_supported: AtomicBool,
+    multi_range_supported: AtomicBool,
+    /// Never switched off: a core without ranged reads ignores the range
+    ranged_read_supported: bool,
+    /// Set once oversized inline content has been warned about
+    inline_size_warned: AtomicBool,
+    /// When each file last had an access notification sent
+    atime_notified: Mutex<HashMap<CacheKey, Instant>>,
+    /// Feeds the thread sending access notifications, if they are enabled
+    atime_queue: Option<SyncSender<AtimeNotice>>,
+    /// Directories whose last listing was cut short by FUSE_MAX_DIR_ENTRIES,
+    /// where the truncation marker resolves
+    truncated_dirs: Mutex<HashSet<String>>,
+    /// Attribute entries with a background refresh in progress
+    attr_refresher: Option<revalidate::AttrRefresher>,
+    /// Set on unmount to stop background work
+    shutdown: Arc<AtomicBool>,
+    config: FsConfig,
+}
 
-        let perm = if kind == FileType::Directory {
-            self.dir_perm
-        } else {
-            self.file_perm
-        };
+impl ApiFS {
+    fn new(api_url: String, config: FsConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let info_content = config
+            .info_file
+            .then(|| render_info(&api_url, &config, SystemTime::now()));
+        let api = ApiClient::new(
+            api_url,
+            config.request_timeouts,
+            config.api_max_concurrency,
+            config.api_user_agent.clone(),
+            config.slow_request,
+            config.root_prefix.clone(),
+            config.api_body_format,
+            config.api_compression,
+            config.api_tls.clone(),
+        )?;
 
-        FileAttr {
-            ino,
-            size: api_attrs.size,
-            blocks: (api_attrs.size + 511) / 512,
-            atime: UNIX_EPOCH + Duration::from_secs_f64(api_attrs.atime),
-            mtime: UNIX_EPOCH + Duration::from_secs_f64(api_attrs.mtime),
-            ctime: UNIX_EPOCH + Duration::from_secs_f64(api_attrs.ctime),
-            crtime: UNIX_EPOCH,
-            kind,
-            perm,
-            nlink: api_attrs.nlink,
-            uid: self.default_uid,
-            gid: self.default_gid,
-            rdev: 0,
-            blksize: 512,
-            flags: 0,
+        wait_for_api(&api, config.wait_for_api)?;
+
+        // Endpoints core claims but lacks are still switched off by the
+        // 404 fallbacks on first use
+        let capabilities = api.capabilities().unwrap_or_else(|e| {
+            warn!(
+                "Failed to fetch API capabilities, using base endpoints only: {}",
+                e
+            );
+            Capabilities::default()
+        });
+        info!("API capabilities: {}", capabilities);
+
+        let api_health = ApiHealth::new(
+            config.circuit_cooldown,
+            config.error_threshold,
+            config.error_window,
+        );
+
+        let webdav_client = api
+            .http_client_builder()
+            .timeout(config.webdav_timeout)
+            .build()?;
+
+        let atime_queue = if config.notify_atime {
+            spawn_atime_worker(api.clone())
+        } else {
+            None
+        };
+
+        let mut fs = ApiFS {
+            api,
+            inode_mapper: Arc::new(Mutex::new(InodeMapper::new(config.stable_inodes))),
+            dir_cache: Arc::new(Mutex::new(HashMap::new())),
+            attr_cache: Arc::new(Mutex::new(HashMap::new())),
+            negative_cache: Arc::new(Mutex::new(HashMap::new())),
+            type_cache: Arc::new(Mutex::new(HashMap::new())),
+            content_cache: Arc::new(Mutex::new(HashMap::new())),
+            pretty_cache: Arc::new(Mutex::new(HashMap::new())),
+            sniff_cache: Mutex::new(HashMap::new()),
+            health_status: api_health.status(),
+            api_health: Arc::new(Mutex::new(api_health)),
+            dir_handles: Arc::new(Mutex::new(HashMap::new())),
+            file_handles: Mutex::new(HashMap::new()),
+            blocks_in_flight: Mutex::new(HashSet::new()),
+            blocks_landed: Condvar::new(),
+            next_fh: AtomicU64::new(1),
+            webdav_client,
+            stats: CacheStats::default(),
+            started_at: Instant::now(),
+            info_content,
+            last_control_action: Mutex::new("none".to_string()),
+            notifier: Arc::new(Mutex::new(None)),
+            readdir_plus_supported: AtomicBool::new(capabilities.readdir_plus),
+            path_chain_supported: AtomicBool::new(capabilities.path_chain),
+            seek_supported: AtomicBool::new(capabilities.seek),
+            acl_supported: AtomicBool::new(capabilities.acl),
+            multi_range_supported: AtomicBool::new(capabilities.multi_range),
+            ranged_read_supported: capabilities.ranged_read,
+            inline_size_warned: AtomicBool::new(false),
+            atime_notified: Mutex::new(HashMap::new()),
+            atime_queue,
+            truncated_dirs: Mutex::new(HashSet::new()),
+            attr_refresher: None,
+            shutdown: Arc::new(AtomicBool::new(false)),
+            config,
+        };
+        if fs.config.max_stale.is_some() {
+            fs.attr_refresher = revalidate::AttrRefresher::spawn(
+                fs.api.clone(),
+                fs.shared_caches(),
+                Arc::clone(&fs.api_health),
+                ATTR_REFRESH_QUEUE,
+            );
         }
+        Ok(fs)
     }
 
-    fn read_file_content(
-        &self,
-        read_result: &api_client::ReadResult,
-        offset: usize,
-        size: usize,
-    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-        // Priority 1: Inline base64 content (for virtual files)
-        if let Some(ref content_b64) = read_result.content {
-            let content = base64::prelude::BASE64_STANDARD.decode(content_b64)?;
-            let end = std::cmp::min(offset + size, content.len());
-            if offset >= content.len() {
-                return Ok(vec![]);
-            }
-            return Ok(content[offset..end].to_vec());
+    /// Answer a lookup, counting the reference the kernel now holds
+    fn reply_entry(&self, attr: &FileAttr, reply: ReplyEntry) {
+        let generation = {
+            let mut mapper = self.inode_mapper.lock().unwrap();
+            mapper.add_lookup(attr.ino);
+            mapper.generation(attr.ino)
+        };
+        reply.entry(&self.config.attr_ttl, attr, generation);
+    }
+
+    /// Release kernel references and evict the mappings and cached
+    /// attributes of inodes the kernel no longer holds
+    fn forget_inodes(&self, nodes: &[(u64, u64)]) {
+        let evicted: HashSet<String> = {
+            let mut mapper = self.inode_mapper.lock().unwrap();
+            nodes
+                .iter()
+                .filter_map(|&(ino, nlookup)| mapper.forget(ino, nlookup))
+                .collect()
+        };
+        if evicted.is_empty() {
+            return;
         }
+        debug!("forget: evicted {} inodes", evicted.len());
+        self.attr_cache
+            .lock()
+            .unwrap()
+            .retain(|(_, path), _| !evicted.contains(path));
+    }
 
-        // Priority 2: WebDAV URL (remote file access via HTTP Range requests)
-        if let Some(ref webdav_url) = read_result.webdav_url {
-            return self.read_from_webdav(webdav_url, offset, size, read_result.size);
+    fn shared_caches(&self) -> SharedCaches {
+        SharedCaches {
+            inode_mapper: Arc::clone(&self.inode_mapper),
+            dir_cache: Arc::clone(&self.dir_cache),
+            attr_cache: Arc::clone(&self.attr_cache),
+            negative_cache: Arc::clone(&self.negative_cache),
+            type_cache: Arc::clone(&self.type_cache),
+            content_cache: Arc::clone(&self.content_cache),
+            pretty_cache: Arc::clone(&self.pretty_cache),
+            cache_ttl: self.config.cache_ttl,
         }
+    }
 
-        // Priority 3: Local filesystem (fallback)
-        if let Some(ref source_path) = read_result.source_path {
-            let mut file = File::open(source_path)?;
+    /// The caller's (uid, gid) to forward to the API, if enabled
+    fn identity(&self, req: &Request) -> Option<(u32, u32)> {
+        self.config.forward_identity.then(|| (req.uid(), req.gid()))
+    }
 
-            if offset > 0 {
-                use std::io::Seek;
-                file.seek(std::io::SeekFrom::Start(offset as u64))?;
+    fn get_cached_readdir(&self, path: &str) -> Option<Vec<String>> {
+        let listed_mtime = {
+            let cache = self.dir_cache.lock().unwrap();
+            match cache.get(&cache_key(path)) {
+                Some(cached) if is_fresh(cached.timestamp, cached.ttl) => {
+                    debug!("Cache hit for readdir: {}", path);
+                    self.stats.dir_hits.fetch_add(1, Ordering::Relaxed);
+                    return Some(cached.entries.clone());
+                }
+                Some(cached) => cached.mtime,
+                None => None,
             }
+        };
 
-            let mut buffer = vec![0u8; size];
-            let bytes_read = file.read(&mut buffer)?;
-            buffer.truncate(bytes_read);
+        if let Some(listed_mtime) = listed_mtime.filter(|_| self.config.dir_mtime_validation) {
+            if let Some(entries) = self.revalidate_listing(path, listed_mtime) {
+                self.stats.dir_hits.fetch_add(1, Ordering::Relaxed);
+                return Some(entries);
+            }
+        }
+        self.stats.dir_misses.fetch_add(1, Ordering::Relaxed);
+        None
+    }
 
-            return Ok(buffer);
+    /// Keep an expired listing if a fresh getattr shows the directory's
+    /// mtime is still the one it was listed at, restarting its lifetime
+    fn revalidate_listing(&self, path: &str, listed_mtime: f64) -> Option<Vec<String>> {
+        if !self.api_health.lock().unwrap().allow_request() {
+            return None;
+        }
+        let attrs = match self.fetch_attrs(path) {
+            Ok(attrs) => {
+                self.api_health.lock().unwrap().record_success();
+                attrs
+            }
+            Err(e) => {
+                self.record_api_error(&e, format!("getattr failed for {}: {}", path, e));
+                debug!("Revalidating listing of {} failed: {}", path, e);
+                return None;
+            }
+        };
+        if attrs.mtime != listed_mtime {
+            debug!("mtime of {} changed, listing it again", path);
+            return None;
         }
 
-        Err("No content, WebDAV URL, or source path available".into())
+        let mut cache = self.dir_cache.lock().unwrap();
+        let cached = cache.get_mut(&cache_key(path))?;
+        debug!("Directory {} unchanged, keeping its cached listing", path);
+        cached.timestamp = SystemTime::now();
+        cached.ttl = self.config.cache_ttl.sample();
+        Some(cached.entries.clone())
     }
 
-    /// Read file content from WebDAV URL using HTTP Range requests
-    fn read_from_webdav(
-        &self,
-        webdav_url: &str,
-        offset: usize,
-        size: usize,
-        file_size: u64,
-    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-        // Don't request beyond the end of the file
-        if offset as u64 >= file_size {
-            return Ok(vec![]);
+    fn cache_readdir(&self, path: &str, entries: Vec<String>) {
+        let mtime = cached_mtime(&self.attr_cache, path);
+        let mut cache = self.dir_cache.lock().unwrap();
+        cache.insert(
+            cache_key(path),
+            CachedDirEntry {
+                entries,
+                timestamp: SystemTime::now(),
+                ttl: self.config.cache_ttl.sample(),
+                mtime,
+            },
+        );
+    }
+
+    /// Cached attributes for a path.
+    ///
+    /// With stale-while-revalidate on, an expired entry still within the
+    /// max-stale window is returned too, and a background refresh started.
+    fn get_cached_attrs(&self, path: &str) -> Option<api_client::FileAttributes> {
+        let cache = self.attr_cache.lock().unwrap();
+        if let Some(cached) = cache.get(&cache_key(path)) {
+            if is_fresh(cached.timestamp, cached.ttl) {
+                debug!("Cache hit for getattr: {}", path);
+                self.stats.attr_hits.fetch_add(1, Ordering::Relaxed);
+                return Some(cached.attrs.clone());
+            }
+
+            let within_stale = self.config.max_stale.is_some_and(|max_stale| {
+                SystemTime::now()
+                    .duration_since(cached.timestamp)
+                    .is_ok_and(|age| age < cached.ttl + max_stale)
+            });
+            if within_stale {
+                debug!("Serving stale attrs for {} while revalidating", path);
+                self.stats.attr_hits.fetch_add(1, Ordering::Relaxed);
+                let attrs = cached.attrs.clone();
+                drop(cache);
+                if let Some(refresher) = &self.attr_refresher {
+                    refresher.request(path);
+                }
+                return Some(attrs);
+            }
         }
+        self.stats.attr_misses.fetch_add(1, Ordering::Relaxed);
+        None
+    }
 
-        // Calculate the actual range to request
-        let end = std::cmp::min(offset as u64 + size as u64 - 1, file_size - 1);
-        let range_header = format!("bytes={}-{}", offset, end);
+    /// Whether unexpired attributes are cached, without counting a hit or miss
+    fn has_fresh_attrs(&self, path: &str) -> bool {
+        self.attr_cache
+            .lock()
+            .unwrap()
+            .get(&cache_key(path))
+            .is_some_and(|cached| is_fresh(cached.timestamp, cached.ttl))
+    }
 
-        debug!("WebDAV read: {} Range: {}", webdav_url, range_header);
+    fn get_cached_type(&self, path: &str) -> Option<FileType> {
+        self.type_cache
+            .lock()
+            .unwrap()
+            .get(&cache_key(path))
+            .filter(|(_, timestamp)| is_fresh(*timestamp, TYPE_CACHE_TTL))
+            .map(|&(file_type, _)| file_type)
+    }
 
-        // Create a new HTTP client for WebDAV requests (separate from API client)
-        let client = reqwest::blocking::Client::builder()
-            .timeout(std::time::Duration::from_secs(60))
-            .build()?;
+    fn is_known_missing(&self, path: &str) -> bool {
+        let cache = self.negative_cache.lock().unwrap();
+        cache
+            .get(&cache_key(path))
+            .is_some_and(|&timestamp| is_fresh(timestamp, self.config.cache_ttl.base))
+    }
 
-        let response = client
-            .get(webdav_url)
-            .header("Range", range_header)
-            .send()?;
+    fn cache_missing(&self, path: &str) {
+        cache_negative(&self.negative_cache, self.config.cache_ttl.base, path);
+    }
 
-        if response.status().is_success() || response.status() == reqwest::StatusCode::PARTIAL_CONTENT {
-            let bytes = response.bytes()?;
-            Ok(bytes.to_vec())
-        } else {
-            Err(format!("WebDAV request failed: {} - {}", response.status(), webdav_url).into())
-        }
+    fn cache_attrs(
+        &self,
+        path: &str,
+        attrs: api_client::FileAttributes,
+        etag: Option<String>,
+        cache_control: CacheControl,
+    ) {
+        cache_type(&self.type_cache, path, attrs.mode);
+        let mut cache = self.attr_cache.lock().unwrap();
+        purge_changed_content(
+            &self.content_cache,
+            &self.pretty_cache,
+            path,
+            cache.get(&cache_key(path)).map(|cached| &cached.attrs),
+            &attrs,
+        );
+        insert_attrs(
+            &mut cache,
+            &self.config.cache_ttl,
+            path,
+            attrs,
+            etag,
+            cache_control,
+        );
     }
-}
 
-impl Filesystem for ApiFS {
-    fn lookup(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
-        let name_str = match name.to_str() {
-            Some(s) => s,
-            None => {
-                error!("Invalid UTF-8 in filename");
-                reply.error(ENOENT);
-                return;
+    /// Fetch attributes from the API and cache them.
+    ///
+    /// If a (possibly expired) cache entry carries an ETag it is sent as
+    /// If-None-Match, and a 304 just revalidates the stored attributes.
+    fn fetch_attrs(&self, path: &str) -> Result<api_client::FileAttributes, ApiError> {
+        let etag = self
+            .attr_cache
+            .lock()
+            .unwrap()
+            .get(&cache_key(path))
+            .and_then(|cached| cached.etag.clone());
+
+        if etag.is_some() {
+            if let AttrResponse::Fresh {
+                attrs,
+                etag,
+                cache_control,
+            } = self.api.getattr(path, etag.as_deref())?
+            {
+                self.cache_attrs(path, attrs.clone(), etag, cache_control);
+                return Ok(attrs);
             }
-        };
 
-        if parent == ROOT_INO && name_str == "ERROR.txt" {
-            let is_unhealthy = self.api_health.lock().unwrap().is_unhealthy();
-            if is_unhealthy {
-                let attr = self.get_error_file_attrs();
-                reply.entry(&TTL, &attr, 0);
-                return;
+            let mut cache = self.attr_cache.lock().unwrap();
+            if let Some(cached) = cache.get_mut(&cache_key(path)) {
+                debug!("getattr not modified: {}", path);
+                cached.renew(&self.config.cache_ttl);
+                return Ok(cached.attrs.clone());
             }
+            // The entry was evicted while we were revalidating; fetch in full
         }
 
-        let parent_path = {
-            let mapper = self.inode_mapper.lock().unwrap();
-            match mapper.get_path(parent) {
-                Some(p) => p.clone(),
-                None => {
-                    error!("Parent inode {} not found", parent);
-                    reply.error(ENOENT);
-                    return;
-                }
+        match self.api.getattr(path, None)? {
+            AttrResponse::Fresh {
+                attrs,
+                etag,
+                cache_control,
+            } => {
+                self.cache_attrs(path, attrs.clone(), etag, cache_control);
+                Ok(attrs)
             }
-        };
-
-        let child_path = if parent_path == "/" {
-            format!("/{}", name_str)
-        } else {
-            format!("{}/{}", parent_path, name_str)
-        };
-
-        debug!("lookup: parent={} name={} -> {}", parent, name_str, child_path);
-
-        if let Some(cached_attrs) = self.get_cached_attrs(&child_path) {
-            let attr = self.convert_attrs(&child_path, cached_attrs);
-            reply.entry(&TTL, &attr, 0);
-            return;
+            AttrResponse::NotModified => Err(ApiError::Status(StatusCode::NOT_MODIFIED)),
         }
+    }
 
-        match self.api.getattr(&child_path) {
-            Ok(api_attrs) => {
+    /// Resolve a path and all its ancestors in one call, caching each
+    /// component's attributes so the kernel's walk down the rest of the
+    /// chain is served from cache.
+    ///
+    /// Returns None if core has no chain endpoint, in which case the caller
+    /// resolves the path on its own.
+    fn prime_path_chain(
+        &self,
+        path: &str,
+    ) -> Option<Result<api_client::FileAttributes, libc::c_int>> {
+        match self.api.getattr_path_components(path) {
+            Ok(components) => {
                 self.api_health.lock().unwrap().record_success();
-                self.cache_attrs(&child_path, api_attrs.clone());
-                let attr = self.convert_attrs(&child_path, api_attrs);
-                reply.entry(&TTL, &attr, 0);
+                let mut found = None;
+                for component in components {
+                    let component_path = normalize_path(&component.path);
+                    match component.attrs {
+                        Some(attrs) => {
+                            if component_path == path {
+                                found = Some(attrs.clone());
+                            }
+                            self.cache_attrs(&component_path, attrs, None, CacheControl::default());
+                        }
+                        None => self.cache_missing(&component_path),
+                    }
+                }
+                if found.is_none() {
+                    self.cache_missing(path);
+                }
+                Some(found.ok_or(ENOENT))
+            }
+            Err(ApiError::Status(StatusCode::NOT_FOUND)) => {
+                if !self.endpoint_missing(path) {
+                    return Some(Err(ENOENT));
+                }
+                info!("API does not support getattrchain, resolving paths per component");
+                self.path_chain_supported.store(false, Ordering::Relaxed);
+                None
             }
             Err(e) => {
-                self.api_health.lock().unwrap().record_error(format!("lookup failed for {}: {}", child_path, e));
-                debug!("lookup failed for {}: {}", child_path, e);
-                reply.error(ENOENT);
+                self.record_api_error(&e, format!("getattrchain failed for {}: {}", path, e));
+                debug!("getattrchain failed for {}: {}", path, e);
+                Some(Err(e.errno()))
             }
         }
     }
 
-    fn getattr(&mut self, _req: &Request, ino: u64, reply: ReplyAttr) {
-        if ino == ERROR_FILE_INO {
-            let is_unhealthy = self.api_health.lock().unwrap().is_unhealthy();
-            if is_unhealthy {
-                let attr = self.get_error_file_attrs();
-                reply.attr(&TTL, &attr);
-                return;
-            } else {
-                reply.error(ENOENT);
-                return;
-            }
+    /// Get attributes for a path from the cache, falling back to the API.
+    ///
+    /// Errors are returned as errno values ready to hand to a reply.
+    fn resolve_attrs(&self, path: &str) -> Result<api_client::FileAttributes, libc::c_int> {
+        if let Some(cached_attrs) = self.get_cached_attrs(path) {
+            return Ok(cached_attrs);
         }
 
-        let path = {
-            let mapper = self.inode_mapper.lock().unwrap();
-            match mapper.get_path(ino) {
-                Some(p) => p.clone(),
-                None => {
-                    error!("Inode {} not found", ino);
-                    reply.error(ENOENT);
-                    return;
-                }
-            }
-        };
-
-        debug!("getattr: ino={} path={}", ino, path);
-
-        if let Some(cached_attrs) = self.get_cached_attrs(&path) {
-            let attr = self.convert_attrs(&path, cached_attrs);
-            reply.attr(&TTL, &attr);
-            return;
+        if !self.api_health.lock().unwrap().allow_request() {
+            debug!("Circuit open, failing attribute fetch for {} fast", path);
+            return Err(libc::EIO);
         }
 
-        match self.api.getattr(&path) {
+        match self.fetch_attrs(path) {
             Ok(api_attrs) => {
                 self.api_health.lock().unwrap().record_success();
-                self.cache_attrs(&path, api_attrs.clone());
-                let attr = self.convert_attrs(&path, api_attrs);
-                reply.attr(&TTL, &attr);
+                Ok(api_attrs)
             }
             Err(e) => {
-                self.api_health.lock().unwrap().record_error(format!("getattr failed for {}: {}", path, e));
-                error!("getattr failed for {}: {}", path, e);
-                reply.error(ENOENT);
+                self.record_api_error(&e, format!("getattr failed for {}: {}", path, e));
+                debug!("getattr failed for {}: {}", path, e);
+                Err(e.errno())
             }
         }
     }
 
-    fn read(
-        &mut self,
-        _req: &Request,
-        ino: u64,
-        _fh: u64,
-        offset: i64,
-        size: u32,
-        _flags: i32,
-        _lock: Option<u64>,
-        reply: ReplyData,
-    ) {
-        if ino == ERROR_FILE_INO {
-            let content = self.api_health.lock().unwrap().get_error_content();
-            let content_bytes = content.as_bytes();
-            let offset = offset as usize;
-            let size = size as usize;
-
-            if offset >= content_bytes.len() {
-                reply.data(&[]);
-                return;
-            }
-
-            let end = std::cmp::min(offset + size, content_bytes.len());
-            reply.data(&content_bytes[offset..end]);
-            return;
+    /// Fetch one page of a directory's names from the API.
+    ///
+    /// Prefers readdirplus so children's attributes are cached in the same
+    /// round trip, and falls back to plain readdir when core lacks it.
+    fn fetch_dir_page(
+        &self,
+        path: &str,
+        cursor: Option<&str>,
+    ) -> Result<(Vec<String>, Option<String>), libc::c_int> {
+        if !self.api_health.lock().unwrap().allow_request() {
+            debug!("Circuit open, failing readdir for {} fast", path);
+            return Err(libc::EIO);
         }
 
-        let path = {
-            let mapper = self.inode_mapper.lock().unwrap();
-            match mapper.get_path(ino) {
-                Some(p) => p.clone(),
-                None => {
-                    error!("Inode {} not found", ino);
-                    reply.error(ENOENT);
-                    return;
+        if self.readdir_plus_supported.load(Ordering::Relaxed) {
+            match self.api.readdir_plus_page(path, cursor) {
+                Ok(page) => {
+                    self.api_health.lock().unwrap().record_success();
+                    let mut names = Vec::with_capacity(page.entries.len());
+                    for entry in page.entries {
+                        if let Some(err) = entry.error {
+                            warn!("Skipping {} in {}: {}", entry.name, path, err);
+                            continue;
+                        }
+                        let Some(name) = entry_name(&entry.name) else {
+                            warn!("Skipping invalid entry name {:?} in {}", entry.name, path);
+                            continue;
+                        };
+                        if let Some(attrs) = entry.attrs {
+                            self.cache_attrs(
+                                &join_path(path, name),
+                                attrs,
+                                None,
+                                CacheControl::default(),
+                            );
+                        }
+                        names.push(name.to_string());
+                    }
+                    return Ok((names, page.next_cursor));
+                }
+                // A 404 may mean the endpoint is missing rather than the path;
+                // plain readdir below tells the two apart
+                Err(ApiError::Status(StatusCode::NOT_FOUND)) => {}
+                Err(e) => {
+                    self.record_api_error(&e, format!("readdirplus failed for {}: {}", path, e));
+                    error!("readdirplus failed for {}: {}", path, e);
+                    return Err(e.errno());
                 }
             }
-        };
-
-        debug!("read: ino={} path={} offset={} size={}", ino, path, offset, size);
+        }
 
-        match self.api.read(&path) {
-            Ok(read_result) => {
+        match self.api.readdir_page(path, cursor) {
+            Ok(page) => {
                 self.api_health.lock().unwrap().record_success();
-                match self.read_file_content(&read_result, offset as usize, size as usize) {
-                    Ok(data) => reply.data(&data),
-                    Err(e) => {
-                        error!("Failed to read file content for {}: {}", path, e);
-                        reply.error(libc::EIO);
-                    }
+                if self.readdir_plus_supported.swap(false, Ordering::Relaxed) {
+                    info!("API does not support readdirplus, falling back to readdir");
                 }
+                let names = page
+                    .entries
+                    .iter()
+                    .filter_map(|raw| {
+                        let name = entry_name(raw);
+                        if name.is_none() {
+                            warn!("Skipping invalid entry name {:?} in {}", raw, path);
+                        }
+                        name.map(str::to_string)
+                    })
+                    .collect();
+                Ok((names, page.next_cursor))
             }
             Err(e) => {
-                self.api_health.lock().unwrap().record_error(format!("read API call failed for {}: {}", path, e));
-                error!("read API call failed for {}: {}", path, e);
-                reply.error(ENOENT);
+                self.record_api_error(&e, format!("readdir failed for {}: {}", path, e));
+                error!("readdir failed for {}: {}", path, e);
+                Err(e.errno())
             }
         }
     }
 
-    fn readdir(
-        &mut self,
-        _req: &Request,
-        ino: u64,
-        _fh: u64,
-        offset: i64,
-        mut reply: ReplyDirectory,
-    ) {
-        let path = {
-            let mapper = self.inode_mapper.lock().unwrap();
-            match mapper.get_path(ino) {
-                Some(p) => p.clone(),
-                None => {
-                    error!("Inode {} not found", ino);
-                    reply.error(ENOENT);
-                    return;
-                }
-            }
-        };
-
-        debug!("readdir: ino={} path={} offset={}", ino, path, offset);
-
-        let entries = if let Some(cached_entries) = self.get_cached_readdir(&path) {
-            cached_entries
+    /// The "." and ".." entries plus any synthetic files for a directory
+    fn dir_listing_prefix(&self, ino: u64, path: &str) -> DirListing {
+        let parent_ino = if ino == ROOT_INO {
+            ROOT_INO
         } else {
-            match self.api.readdir(&path) {
-                Ok(entries) => {
-                    self.api_health.lock().unwrap().record_success();
-                    self.cache_readdir(&path, entries.clone());
-                    entries
-                }
-                Err(e) => {
-                    self.api_health.lock().unwrap().record_error(format!("readdir failed for {}: {}", path, e));
-                    error!("readdir failed for {}: {}", path, e);
-                    reply.error(ENOENT);
-                    return;
-                }
-            }
+            let (parent, _) = split_parent(path);
+            self.inode_mapper.lock().unwrap().get_or_create_ino(parent)
         };
-
         let mut full_entries = vec![
             (ino, FileType::Directory, ".".to_string()),
-            (ino, FileType::Directory, "..".to_string()),
+            (parent_ino, FileType::Directory, "..".to_string()),
         ];
 
         if ino == ROOT_INO {
-            let is_unhealthy = self.api_health.lock().unwrap().is_unhealthy();
+            let is_unhealthy = self.health_status.is_unhealthy();
             if is_unhealthy {
-                full_entries.push((ERROR_FILE_INO, FileType::RegularFile, "ERROR.txt".to_string()));
+                full_entries.push((
+                    ERROR_FILE_INO,
+                    FileType::RegularFile,
+                    "ERROR.txt".to_string(),
+                ));
+            }
+            if let Some(ref name) = self.config.stats_file {
+                full_entries.push((STATS_FILE_INO, FileType::RegularFile, name.clone()));
+            }
+            if self.info_file_visible() {
+                full_entries.push((
+                    INFO_FILE_INO,
+                    FileType::RegularFile,
+                    INFO_FILE_NAME.to_string(),
+                ));
+            }
+            if self.control_file_visible() {
+                full_entries.push((
+                    CONTROL_FILE_INO,
+                    FileType::RegularFile,
+                    CONTROL_FILE_NAME.to_string(),
+                ));
             }
         }
 
+        full_entries
+    }
+
+    /// Resolve child names into (inode, type, name) tuples
+    fn resolve_dir_entries(&self, path: &str, entries: Vec<String>) -> DirListing {
+        let mut resolved = Vec::with_capacity(entries.len());
+
         for entry_name in entries {
-            let entry_path = if path == "/" {
-                format!("/{}", entry_name)
+            let entry_path = join_path(path, &entry_name);
+            if is_hidden(&entry_path, &self.config.hide_prefixes) {
+                continue;
+            }
+
+            let file_type = if let Some(file_type) = self.get_cached_type(&entry_path) {
+                file_type
+            } else if let Some(cached_attrs) = self.get_cached_attrs(&entry_path) {
+                file_type_from_mode(cached_attrs.mode)
+            } else if !self.api_health.lock().unwrap().allow_request() {
+                FileType::RegularFile
             } else {
-                format!("{}/{}", path, entry_name)
+                match self.fetch_attrs(&entry_path) {
+                    Ok(attrs) => {
+                        self.api_health.lock().unwrap().record_success();
+                        file_type_from_mode(attrs.mode)
+                    }
+                    // Listed but gone by the time we stat it
+                    Err(ApiError::Status(StatusCode::NOT_FOUND)) => {
+                        self.api_health.lock().unwrap().record_success();
+                        warn!("Skipping {} in {}: not found", entry_name, path);
+                        continue;
+                    }
+                    Err(e) => {
+                        self.record_api_error(
+                            &e,
+                            format!("getattr failed for {}: {}", entry_path, e),
+                        );
+                        FileType::RegularFile
+                    }
+                }
             };
 
+            // Only once the entry is known to be listed, so skipped ones
+            // don't take up inodes
             let entry_ino = {
                 let mut mapper = self.inode_mapper.lock().unwrap();
                 mapper.get_or_create_ino(&entry_path)
             };
+            resolved.push((entry_ino, file_type, entry_name));
+        }
 
-            let file_type = if let Some(cached_attrs) = self.get_cached_attrs(&entry_path) {
-                if cached_attrs.mode & 0o040000 != 0 {
-                    FileType::Directory
-                } else {
-                    FileType::RegularFile
-                }
-            } else {
-                match self.api.getattr(&entry_path) {
-                    Ok(attrs) => {
-                        self.cache_attrs(&entry_path, attrs.clone());
-                        if attrs.mode & 0o040000 != 0 {
-                            FileType::Directory
-                        } else {
-                            FileType::RegularFile
-                        }
-                    }
-                    Err(_) => FileType::RegularFile,
+        resolved
+    }
+
+    /// Reply with a directory handle's entries from `offset`, fetching
+    /// further pages as the reply buffer has room for them
+    fn reply_dir_entries(&self, handle: &mut DirHandle, offset: i64, mut reply: ReplyDirectory) {
+        // "." and ".." always occupy offsets 0 and 1 of the stored listing,
+        // so a nonzero offset resumes exactly where the last reply stopped
+        let mut i = offset as usize;
+        loop {
+            while let Some(entry) = handle.listing.get(i) {
+                if reply.add(entry.0, (i + 1) as i64, entry.1, &entry.2) {
+                    reply.ok();
+                    return;
                 }
+                i += 1;
+            }
+
+            let cursor = match handle.next_cursor.take() {
+                Some(cursor) => cursor,
+                None => break,
             };
 
-            full_entries.push((entry_ino, file_type, entry_name));
+            match self.fetch_dir_page(&handle.path, Some(&cursor)) {
+                Ok((entries, next_cursor)) => {
+                    handle.next_cursor = next_cursor;
+                    self.append_dir_page(handle, entries);
+                }
+                Err(errno) => {
+                    handle.next_cursor = Some(cursor);
+                    reply.error(errno);
+                    return;
+                }
+            }
+        }
+        reply.ok();
+    }
+
+    /// Open a directory handle, fetching only the first page when the
+    /// listing isn't cached; later pages are pulled in by readdir.
+    fn open_dir_handle(&self, ino: u64, path: &str) -> Result<DirHandle, libc::c_int> {
+        if self.config.flatten && ino == ROOT_INO {
+            return Ok(DirHandle {
+                path: path.to_string(),
+                listing: self.flat_root_listing()?,
+                next_cursor: None,
+                names: None,
+                seen: 0,
+            });
         }
 
-        for (i, entry) in full_entries.into_iter().enumerate().skip(offset as usize) {
-            if reply.add(entry.0, (i + 1) as i64, entry.1, entry.2) {
-                break;
+        let listing = self.dir_listing_prefix(ino, path);
+
+        if let Some(cached_entries) = self.get_cached_readdir(path) {
+            let mut handle = DirHandle {
+                path: path.to_string(),
+                listing,
+                next_cursor: None,
+                names: None,
+                seen: 0,
+            };
+            self.append_dir_page(&mut handle, cached_entries);
+            return Ok(handle);
+        }
+
+        let (entries, next_cursor) = match self.fetch_dir_page(path, None) {
+            Ok(page) => page,
+            // Only 401 and 403 map to EACCES. A refusal on a later page
+            // still fails, since the listing would silently end early.
+            Err(libc::EACCES) if self.config.readdir_empty_on_error => {
+                warn!("Listing {} was refused, showing it as empty", path);
+                // No names are gathered, so nothing reaches dir_cache
+                return Ok(DirHandle {
+                    path: path.to_string(),
+                    listing,
+                    next_cursor: None,
+                    names: None,
+                    seen: 0,
+                });
+            }
+            Err(errno) => return Err(errno),
+        };
+        let mut handle = DirHandle {
+            path: path.to_string(),
+            listing,
+            next_cursor,
+            names: Some(Vec::new()),
+            seen: 0,
+        };
+        self.append_dir_page(&mut handle, entries);
+        Ok(handle)
+    }
+
+    /// Every non-directory in the tree under its flat name, for the root in
+    /// flatten mode. Subdirectories that fail to list are skipped.
+    fn flat_root_listing(&self) -> Result<DirListing, libc::c_int> {
+        let mut listing = self.dir_listing_prefix(ROOT_INO, "/");
+        let mut pending = vec!["/".to_string()];
+
+        while let Some(dir) = pending.pop() {
+            let names = match self.dir_names(&dir) {
+                Ok(names) => names,
+                Err(errno) if dir == "/" => return Err(errno),
+                Err(errno) => {
+                    warn!("Leaving {} out of the flat listing: errno {}", dir, errno);
+                    continue;
+                }
+            };
+            for (entry_ino, file_type, name) in self.resolve_dir_entries(&dir, names) {
+                let entry_path = join_path(&dir, &name);
+                if file_type == FileType::Directory {
+                    pending.push(entry_path);
+                    continue;
+                }
+                let flat_name = flatten::encode(&entry_path);
+                if flat_name.len() > FLAT_NAME_MAX {
+                    warn!(
+                        "Leaving {} out of the flat listing: name too long",
+                        entry_path
+                    );
+                    continue;
+                }
+                listing.push((entry_ino, file_type, flat_name));
             }
         }
-        reply.ok();
+
+        Ok(listing)
     }
-}
 
-fn main() {
-    env_logger::init();
+    /// All names in a directory, from the cache or every page of the API
+    fn dir_names(&self, path: &str) -> Result<Vec<String>, libc::c_int> {
+        if let Some(cached_entries) = self.get_cached_readdir(path) {
+            return Ok(cached_entries);
+        }
 
-    let args: Vec<String> = std::env::args().collect();
+        let (mut names, mut cursor) = self.fetch_dir_page(path, None)?;
+        while let Some(next) = cursor {
+            let (entries, next_cursor) = self.fetch_dir_page(path, Some(&next))?;
+            names.extend(entries);
+            cursor = next_cursor;
+        }
+        if names.len() <= DIR_CACHE_MAX_ENTRIES {
+            self.cache_readdir(path, names.clone());
+        }
+        Ok(names)
+    }
 
-    if args.len() < 2 || args.len() > 5 {
-        eprintln!("Usage: {} <mountpoint> [api-url] [uid] [gid]", args[0]);
-        eprintln!("\nExample: {} /mnt/virtual http://localhost:3000 1000 1000", args[0]);
-        eprintln!("         {} /mnt/virtual (defaults: http://localhost:3000, uid=1000, gid=1000)", args[0]);
-        eprintln!("\nEnvironment variables:");
-        eprintln!("  PUID            - User ID for file ownership (default: 1000)");
-        eprintln!("  PGID            - Group ID for file ownership (default: 1000)");
-        eprintln!("  FUSE_FILE_PERM  - File permissions in octal (default: 755)");
-        eprintln!("  FUSE_DIR_PERM   - Directory permissions in octal (default: 755)");
-        eprintln!("  FUSE_API_URL    - API URL (default: http://localhost:3000)");
-        std::process::exit(1);
-    }
-
-    let mountpoint = &args[1];
-    let api_url = if args.len() >= 3 {
-        args[2].clone()
-    } else {
-        std::env::var("FUSE_API_URL").unwrap_or_else(|_| "http://localhost:3000".to_string())
-    };
+    /// Look up a flat name in the root by decoding it back to its real path.
+    /// Directories are not part of the flat view.
+    fn lookup_flattened(&self, name: &str, reply: ReplyEntry) {
+        let Some(path) = flatten::decode(name) else {
+            reply.error(ENOENT);
+            return;
+        };
+        debug!("lookup: flat name {} -> {}", name, path);
 
-    let uid: u32 = if args.len() >= 4 {
-        args[3].parse().unwrap_or_else(|_| {
-            eprintln!("Error: Invalid UID value");
-            std::process::exit(1);
-        })
-    } else {
-        std::env::var("PUID")
-            .ok()
-            .and_then(|v| v.parse().ok())
-            .unwrap_or(1000)
-    };
+        if is_hidden(&path, &self.config.hide_prefixes) || self.is_known_missing(&path) {
+            reply.error(ENOENT);
+            return;
+        }
+        match self.resolve_attrs(&path) {
+            Ok(api_attrs) if file_type_from_mode(api_attrs.mode) == FileType::Directory => {
+                reply.error(ENOENT);
+            }
+            Ok(api_attrs) => {
+                let attr = self.convert_attrs(&path, api_attrs);
+                self.reply_entry(&attr, reply);
+            }
+            Err(errno) => reply.error(errno),
+        }
+    }
 
-    let gid: u32 = if args.len() >= 5 {
-        args[4].parse().unwrap_or_else(|_| {
-            eprintln!("Error: Invalid GID value");
-            std::process::exit(1);
+    /// Add a fetched page to a handle, caching the assembled listing once
+    /// the last page arrives if it is small enough.
+    ///
+    /// Past FUSE_MAX_DIR_ENTRIES the listing stops, with the truncation
+    /// marker as its last entry.
+    fn append_dir_page(&self, handle: &mut DirHandle, mut entries: Vec<String>) {
+        let mut truncated = false;
+        if let Some(max) = self.config.max_dir_entries {
+            let room = max.saturating_sub(handle.seen);
+            if entries.len() > room {
+                warn!(
+                    "{} has more than {} entries, listing only the first {} (FUSE_MAX_DIR_ENTRIES)",
+                    handle.path, max, max
+                );
+                entries.truncate(room);
+                handle.next_cursor = None;
+                handle.names = None;
+                truncated = true;
+            }
+        }
+        handle.seen += entries.len();
+
+        if let Some(names) = handle.names.as_mut() {
+            names.extend(entries.iter().cloned());
+            if names.len() > DIR_CACHE_MAX_ENTRIES {
+                handle.names = None;
+            }
+        }
+
+        // A real file in the root hides the synthetic one of the same name
+        if handle.path == "/" {
+            handle.listing.retain(|(ino, _, name)| {
+                !matches!(*ino, INFO_FILE_INO | CONTROL_FILE_INO) || !entries.contains(name)
+            });
+        }
+        handle
+            .listing
+            .extend(self.resolve_dir_entries(&handle.path, entries));
+        if truncated {
+            handle.listing.push((
+                TRUNCATED_FILE_INO,
+                FileType::RegularFile,
+                TRUNCATED_FILE_NAME.to_string(),
+            ));
+            self.truncated_dirs
+                .lock()
+                .unwrap()
+                .insert(handle.path.clone());
+        } else if handle.next_cursor.is_none() {
+            self.truncated_dirs.lock().unwrap().remove(&handle.path);
+        }
+
+        if handle.next_cursor.is_none() {
+            if let Some(names) = handle.names.take() {
+                self.cache_readdir(&handle.path, names);
+            }
+        }
+    }
+
+    /// Tell core a file was accessed, at most once per ATIME_NOTIFY_INTERVAL
+    /// per file. Sent from a background thread; failures are only logged.
+    fn notify_access(&self, path: &str) {
+        if !self.config.notify_atime || self.health_status.is_unhealthy() {
+            return;
+        }
+
+        let key = cache_key(path);
+        let now = Instant::now();
+        {
+            let mut notified = self.atime_notified.lock().unwrap();
+            if notified
+                .get(&key)
+                .is_some_and(|&at| now.duration_since(at) < ATIME_NOTIFY_INTERVAL)
+            {
+                return;
+            }
+            if notified.len() >= ATIME_NOTIFY_MAX_TRACKED {
+                notified.retain(|_, at| now.duration_since(*at) < ATIME_NOTIFY_INTERVAL);
+            }
+            notified.insert(key.clone(), now);
+        }
+
+        let Some(queue) = &self.atime_queue else {
+            return;
+        };
+        let identity = api_client::current_identity();
+        if queue.try_send((identity, path.to_string())).is_err() {
+            // Core is falling behind; let a later read try again
+            debug!("Access notification queue full, dropping {}", path);
+            self.atime_notified.lock().unwrap().remove(&key);
+        }
+    }
+
+    /// An ACL xattr value for a path, or None if it has no such ACL.
+    ///
+    /// Without the getacl endpoint every path is reported as having none.
+    fn acl_xattr(&self, path: &str, default: bool) -> Result<Option<Vec<u8>>, libc::c_int> {
+        if !self.acl_supported.load(Ordering::Relaxed) {
+            return Ok(None);
+        }
+        if !self.api_health.lock().unwrap().allow_request() {
+            debug!("Circuit open, failing ACL fetch for {} fast", path);
+            return Err(libc::EIO);
+        }
+
+        match self.api.get_acl(path, default) {
+            Ok(entries) => {
+                self.api_health.lock().unwrap().record_success();
+                if entries.is_empty() {
+                    return Ok(None);
+                }
+                acl::encode(&entries).map(Some).map_err(|e| {
+                    warn!("Bad ACL for {} from the API: {}", path, e);
+                    libc::EIO
+                })
+            }
+            Err(ApiError::Status(StatusCode::NOT_FOUND)) => {
+                if !self.endpoint_missing(path) {
+                    return Err(ENOENT);
+                }
+                info!("API does not support getacl, reporting no ACLs");
+                self.acl_supported.store(false, Ordering::Relaxed);
+                Ok(None)
+            }
+            Err(e) => {
+                self.record_api_error(&e, format!("getacl failed for {}: {}", path, e));
+                debug!("getacl failed for {}: {}", path, e);
+                Err(e.errno())
+            }
+        }
+    }
+
+    /// Where SEEK_DATA or SEEK_HOLE from `offset` lands in a file of
+    /// `size`, which `offset` lies inside. Files core can't be asked about
+    /// have no holes.
+    fn seek_offset(
+        &self,
+        path: Option<&str>,
+        offset: u64,
+        size: u64,
+        hole: bool,
+    ) -> Result<u64, libc::c_int> {
+        if let Some(path) = path.filter(|_| self.seek_supported.load(Ordering::Relaxed)) {
+            match self.api.seek_hole_data(path, offset, hole) {
+                Ok(found) => {
+                    self.api_health.lock().unwrap().record_success();
+                    return match found {
+                        Some(found) => Ok(found.min(size)),
+                        // Every file has an implicit hole at EOF
+                        None if hole => Ok(size),
+                        None => Err(libc::ENXIO),
+                    };
+                }
+                // A file gone since it was opened still gets the no-holes
+                // answer below, without switching seek off
+                Err(ApiError::Status(StatusCode::NOT_FOUND)) => {
+                    if self.endpoint_missing(path) {
+                        info!("API does not support seek, treating files as fully allocated");
+                        self.seek_supported.store(false, Ordering::Relaxed);
+                    }
+                }
+                Err(e) => {
+                    self.record_api_error(&e, format!("seek failed for {}: {}", path, e));
+                    debug!("seek failed for {}: {}", path, e);
+                    return Err(e.errno());
+                }
+            }
+        }
+
+        // No holes: data starts where asked and the only hole is at EOF
+        Ok(if hole { size } else { offset })
+    }
+
+    /// Whether a 404 from an optional endpoint means core lacks the
+    /// endpoint rather than the path, asked of getattr, which every core
+    /// serves. A path that turns out to be gone is remembered as missing;
+    /// when getattr fails otherwise the endpoint is given the benefit of
+    /// the doubt.
+    fn endpoint_missing(&self, path: &str) -> bool {
+        match self.fetch_attrs(path) {
+            Ok(_) => true,
+            Err(ApiError::Status(StatusCode::NOT_FOUND)) => {
+                self.shared_caches().store_missing(path);
+                false
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Record a failed API call against the health tracker
+    fn record_api_error(&self, e: &ApiError, message: String) {
+        self.api_health.lock().unwrap().record_api_error(e, message);
+    }
+
+    /// Ask core for a file's read result again after the one in hand went
+    /// stale, recording the outcome like any other API call
+    fn reread(&self, path: &str) -> Result<ReadResponse, ApiError> {
+        let result = self.api.read(path, None);
+        match &result {
+            Ok(_) => self.api_health.lock().unwrap().record_success(),
+            Err(e) => self.record_api_error(e, format!("read failed for {}: {}", path, e)),
+        }
+        result
+    }
+
+    /// ERROR.txt content: the snapshot taken when the API became unhealthy,
+    /// or a fresh rendering while it is healthy
+    fn error_content(&self) -> Arc<str> {
+        self.health_status.error_content().unwrap_or_else(|| {
+            self.api_health
+                .lock()
+                .unwrap()
+                .render_error_content()
+                .into()
         })
-    } else {
-        std::env::var("PGID")
-            .ok()
-            .and_then(|v| v.parse().ok())
-            .unwrap_or(1000)
-    };
+    }
 
-    let file_perm: u16 = std::env::var("FUSE_FILE_PERM")
-        .ok()
-        .and_then(|v| u16::from_str_radix(&v, 8).ok())
-        .unwrap_or(0o755);
+    fn get_error_file_attrs(&self) -> FileAttr {
+        self.synthetic_file_attrs(ERROR_FILE_INO, self.error_content().len() as u64)
+    }
 
-    let dir_perm: u16 = std::env::var("FUSE_DIR_PERM")
-        .ok()
-        .and_then(|v| u16::from_str_radix(&v, 8).ok())
-        .unwrap_or(0o755);
+    fn get_stats_file_attrs(&self) -> FileAttr {
+        self.synthetic_file_attrs(STATS_FILE_INO, self.render_stats().len() as u64)
+    }
 
-    info!("Connecting to API at: {}", api_url);
-    info!("File ownership: uid={}, gid={}", uid, gid);
-    info!("File permissions: {:o} (files), {:o} (directories)", file_perm, dir_perm);
+    /// Whether the info file is enabled and no real file of that name exists
+    /// in the root, which it would otherwise hide
+    fn info_file_visible(&self) -> bool {
+        self.info_content.is_some() && !self.real_root_file_known(INFO_FILE_NAME)
+    }
 
-    let fs = match ApiFS::new(api_url.clone(), uid, gid, file_perm, dir_perm) {
-        Ok(fs) => {
-            info!("Successfully connected to meta-fuse API");
-            fs
+    fn info_content(&self) -> &str {
+        self.info_content.as_deref().unwrap_or_default()
+    }
+
+    fn get_info_file_attrs(&self) -> FileAttr {
+        self.synthetic_file_attrs(INFO_FILE_INO, self.info_content().len() as u64)
+    }
+
+    /// Whether the control file is enabled and not hidden by a real file of
+    /// that name in the root
+    fn control_file_visible(&self) -> bool {
+        self.config.control_file && !self.real_root_file_known(CONTROL_FILE_NAME)
+    }
+
+    /// Whether the caches know of a real file with this name in the root.
+    ///
+    /// Only the caches are consulted, expired entries included, so checking
+    /// a synthetic file never costs a round trip; a root listing fills them.
+    fn real_root_file_known(&self, name: &str) -> bool {
+        let path = join_path("/", name);
+        let in_attrs = self
+            .attr_cache
+            .lock()
+            .unwrap()
+            .keys()
+            .any(|(_, cached)| *cached == path);
+        in_attrs
+            || self
+                .dir_cache
+                .lock()
+                .unwrap()
+                .get(&cache_key("/"))
+                .is_some_and(|listing| listing.entries.iter().any(|entry| entry == name))
+    }
+
+    /// What reading the control file shows: the last command's outcome and
+    /// how full each cache is
+    fn render_control(&self) -> String {
+        format!(
+            "Last action: {}\n\
+            Directory cache: {} entries\n\
+            Attribute cache: {} entries\n\
+            Negative cache: {} entries\n\
+            Content cache: {} entries\n\
+            Pretty-printed cache: {} entries\n\
+            Sniff cache: {} entries\n\n\
+            Write \"flush\" to this file to empty every cache.\n",
+            self.last_control_action.lock().unwrap(),
+            self.dir_cache.lock().unwrap().len(),
+            self.attr_cache.lock().unwrap().len(),
+            self.negative_cache.lock().unwrap().len(),
+            self.content_cache.lock().unwrap().len(),
+            self.pretty_cache.lock().unwrap().len(),
+            self.sniff_cache.lock().unwrap().len(),
+        )
+    }
+
+    fn get_control_file_attrs(&self) -> FileAttr {
+        FileAttr {
+            // Only the mount's owner may flush; see control_writer_allowed
+            perm: 0o600,
+            ..self.synthetic_file_attrs(CONTROL_FILE_INO, self.render_control().len() as u64)
         }
-        Err(e) => {
-            error!("Failed to connect to API at {}: {}", api_url, e);
-            eprintln!("Error: Failed to connect to API at {}: {}", api_url, e);
-            eprintln!("\nMake sure meta-fuse-core is running.");
-            std::process::exit(1);
+    }
+
+    /// Whether `uid` may write commands to the control file: the owner the
+    /// mount reports for its files, or root
+    fn control_writer_allowed(&self, uid: u32) -> bool {
+        uid == self.config.uid || uid == 0
+    }
+
+    /// The error a write to `ino` by `uid` fails with, if any. With the
+    /// control file the mount is not mounted read-only, so every other
+    /// inode answers as a read-only mount would.
+    fn write_refusal(&self, ino: u64, uid: u32) -> Option<libc::c_int> {
+        if ino != CONTROL_FILE_INO || !self.config.control_file {
+            Some(EROFS)
+        } else if !self.control_writer_allowed(uid) {
+            Some(libc::EACCES)
+        } else {
+            None
         }
-    };
+    }
 
-    info!("Mounting filesystem at: {}", mountpoint);
+    /// Drop the kernel's cached pages of every known inode, which
+    /// FUSE_KERNEL_CACHE otherwise keeps across opens.
+    ///
+    /// Runs on its own thread: the kernel may need a page lock held by a
+    /// read waiting on this one, which would deadlock the write handler.
+    fn invalidate_kernel_pages(&self) {
+        let Some(notifier) = self.notifier.lock().unwrap().clone() else {
+            return;
+        };
+        let inodes: Vec<u64> = self
+            .inode_mapper
+            .lock()
+            .unwrap()
+            .ino_to_path
+            .keys()
+            .copied()
+            .filter(|&ino| !is_synthetic_ino(ino))
+            .collect();
+        let spawned = std::thread::Builder::new()
+            .name("kernel-inval".to_string())
+            .spawn(move || {
+                for ino in inodes {
+                    if let Err(e) = notifier.inval_inode(ino, 0, 0) {
+                        debug!("Kernel cache invalidation of inode {} failed: {}", ino, e);
+                    }
+                }
+            });
+        if let Err(e) = spawned {
+            warn!("Could not start kernel cache invalidation: {}", e);
+        }
+    }
 
-    let options = vec![
-        MountOption::RO,
-        MountOption::FSName("meta-fuse".to_string()),
-        MountOption::AutoUnmount,
-        MountOption::AllowOther,
-    ];
+    /// Carry out a command written to the control file before the write
+    /// returns, so the next access is already served by the new state
+    fn run_control(&self, command: &str) -> Result<(), libc::c_int> {
+        match command {
+            "flush" => {
+                let dropped = [
+                    take_len(&self.dir_cache),
+                    take_len(&self.attr_cache),
+                    take_len(&self.negative_cache),
+                    take_len(&self.content_cache),
+                    take_len(&self.pretty_cache),
+                    take_len(&self.sniff_cache),
+                ]
+                .iter()
+                .sum::<usize>();
+                self.type_cache.lock().unwrap().clear();
+                if self.config.kernel_cache {
+                    self.invalidate_kernel_pages();
+                }
+                info!("Control file: flushed {} cache entries", dropped);
+                *self.last_control_action.lock().unwrap() = format!(
+                    "flush at {}s uptime, {} entries dropped",
+                    self.started_at.elapsed().as_secs(),
+                    dropped
+                );
+                Ok(())
+            }
+            other => {
+                warn!("Control file: unknown command {:?}", other);
+                Err(libc::EINVAL)
+            }
+        }
+    }
 
-    match fuser::mount2(fs, mountpoint, &options) {
-        Ok(()) => {
-            info!("Filesystem unmounted successfully");
+    /// Explanation served by the truncation marker
+    /// Whether the last listing of a directory was cut short
+    fn is_truncated_dir(&self, ino: u64) -> bool {
+        if self.config.max_dir_entries.is_none() {
+            return false;
         }
-        Err(e) => {
-            error!("Mount failed: {}", e);
-            eprintln!("Error: Failed to mount filesystem: {}", e);
-            eprintln!("\nPossible causes:");
-            eprintln!("1. Mount point does not exist or is not accessible");
-            eprintln!("2. FUSE module is not loaded (try: modprobe fuse)");
-            eprintln!("3. /etc/fuse.conf missing 'user_allow_other' option");
-            eprintln!("   To fix: echo 'user_allow_other' | sudo tee -a /etc/fuse.conf");
-            std::process::exit(1);
+        let path = self.inode_mapper.lock().unwrap().get_path(ino).cloned();
+        path.is_some_and(|path| self.truncated_dirs.lock().unwrap().contains(&path))
+    }
+
+    fn truncated_notice(&self) -> String {
+        let max = self.config.max_dir_entries.unwrap_or_default();
+        format!(
+            "This directory has more than {} entries, so only the first {} are listed.\n\
+            Raise FUSE_MAX_DIR_ENTRIES to list more.\n",
+            max, max
+        )
+    }
+
+    fn get_truncated_file_attrs(&self) -> FileAttr {
+        self.synthetic_file_attrs(TRUNCATED_FILE_INO, self.truncated_notice().len() as u64)
+    }
+
+    /// Attributes for a read-only file generated by the driver itself
+    fn synthetic_file_attrs(&self, ino: u64, size: u64) -> FileAttr {
+        FileAttr {
+            ino,
+            size,
+            blocks: self.blocks(size),
+            atime: UNIX_EPOCH + Duration::from_secs(0),
+            mtime: UNIX_EPOCH + Duration::from_secs(0),
+            ctime: UNIX_EPOCH + Duration::from_secs(0),
+            crtime: UNIX_EPOCH,
+            kind: FileType::RegularFile,
+            perm: 0o444,
+            nlink: 1,
+            uid: self.config.uid,
+            gid: self.config.gid,
+            rdev: 0,
+            blksize: self.config.blksize,
+            flags: 0,
+        }
+    }
+
+    /// st_blocks for a file: whole blksize blocks, counted in the 512-byte
+    /// units stat expects so du stays correct whatever blksize is
+    fn blocks(&self, size: u64) -> u64 {
+        let blksize = u64::from(self.config.blksize);
+        size.div_ceil(blksize) * (blksize / 512)
+    }
+
+    /// Render the report served by the stats file
+    fn render_stats(&self) -> String {
+        let dir_entries = self.dir_cache.lock().unwrap().len();
+        let attr_entries = self.attr_cache.lock().unwrap().len();
+        let content_entries = self.content_cache.lock().unwrap().len();
+        let consecutive_errors = self.api_health.lock().unwrap().consecutive_errors;
+        let (in_flight, limit) = self.api.in_flight();
+        let limit = limit.map_or("unlimited".to_string(), |l| l.to_string());
+        let stat = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+
+        let mut latency = String::new();
+        for entry in self.api.latency() {
+            latency.push_str(&format!(
+                "  {}: {}ms average over {} requests, timeout {}ms\n",
+                entry.endpoint,
+                entry.average.as_millis(),
+                entry.samples,
+                self.api.timeout_for(entry.endpoint).as_millis()
+            ));
+        }
+
+        let mut report = format!(
+            "Meta-Fuse FUSE Driver - Cache Statistics\n\
+            ========================================\n\n\
+            Uptime: {}s\n\n\
+            Directory cache: {} entries, {} hits, {} misses\n\
+            Attribute cache: {} entries, {} hits, {} misses\n\
+            Content cache: {} entries, {} hits ({} without an API call), {} misses\n\
+            Sniff cache: {} entries, {} hits, {} misses\n\n\
+            Consecutive API errors: {}\n\
+            API requests in flight: {} (limit {})\n",
+            self.started_at.elapsed().as_secs(),
+            dir_entries,
+            stat(&self.stats.dir_hits),
+            stat(&self.stats.dir_misses),
+            attr_entries,
+            stat(&self.stats.attr_hits),
+            stat(&self.stats.attr_misses),
+            content_entries,
+            stat(&self.stats.content_hits),
+            stat(&self.stats.small_file_hits),
+            stat(&self.stats.content_misses),
+            self.sniff_cache.lock().unwrap().len(),
+            stat(&self.stats.sniff_hits),
+            stat(&self.stats.sniff_misses),
+            consecutive_errors,
+            in_flight,
+            limit
+        );
+        if !latency.is_empty() {
+            report.push_str("\nAPI latency (moving average):\n");
+            report.push_str(&latency);
+        }
+        report
+    }
+
+    fn convert_attrs(&self, path: &str, api_attrs: api_client::FileAttributes) -> FileAttr {
+        let ino = {
+            let mut mapper = self.inode_mapper.lock().unwrap();
+            mapper.get_or_create_ino(path)
+        };
+
+        let kind = file_type_from_mode(api_attrs.mode);
+
+        let perm = if api_attrs.display_mode.is_some() || self.config.use_api_perms {
+            api_attrs.perm_bits() as u16
+        } else if kind == FileType::Directory {
+            self.config.dir_perm
+        } else {
+            self.config.file_perm
+        };
+
+        let (uid, gid) = self.presented_owner(&api_attrs);
+
+        let nlink = if kind == FileType::Directory {
+            self.dir_nlink(path).unwrap_or(api_attrs.nlink)
+        } else {
+            api_attrs.nlink
+        };
+
+        // Directories report one block, as local filesystems do, whatever
+        // core says: some tools take a zero-size directory for an empty or
+        // broken one. Empty files have size and blocks 0.
+        let size = match kind {
+            FileType::RegularFile => self
+                .pretty_json_len(path, &api_attrs)
+                .unwrap_or(api_attrs.size),
+            FileType::Directory => u64::from(self.config.blksize),
+            _ => api_attrs.size,
+        };
+
+        FileAttr {
+            ino,
+            size,
+            // Core knows what the backend really allocates; without that
+            // the apparent size is all there is to go on
+            blocks: api_attrs.blocks.unwrap_or_else(|| self.blocks(size)),
+            atime: UNIX_EPOCH + Duration::from_secs_f64(api_attrs.atime()),
+            mtime: UNIX_EPOCH + Duration::from_secs_f64(api_attrs.mtime),
+            ctime: UNIX_EPOCH + Duration::from_secs_f64(api_attrs.ctime()),
+            // Unlike atime and ctime there is nothing to stand in for a
+            // missing birth time, so it stays at the epoch
+            crtime: api_attrs.crtime.map_or(UNIX_EPOCH, |secs| {
+                UNIX_EPOCH + Duration::from_secs_f64(secs)
+            }),
+            kind,
+            perm,
+            nlink,
+            uid,
+            gid,
+            rdev: api_attrs.rdev,
+            blksize: self.config.blksize,
+            flags: 0,
+        }
+    }
+
+    /// Owner uid and gid shown for an entry: core's with
+    /// FUSE_USE_API_OWNERSHIP, otherwise the configured ones
+    fn presented_owner(&self, api_attrs: &api_client::FileAttributes) -> (u32, u32) {
+        if self.config.use_api_ownership {
+            api_attrs.owner(self.config.uid, self.config.gid)
+        } else {
+            (self.config.uid, self.config.gid)
+        }
+    }
+
+    /// Size of the pretty-printed rendering of a JSON file, if one is cached.
+    ///
+    /// getattr never renders one itself, which would download every JSON
+    /// file a listing stats; until a file is opened its stored size is
+    /// reported.
+    fn pretty_json_len(&self, path: &str, attrs: &api_client::FileAttributes) -> Option<u64> {
+        if !self.config.pretty_json {
+            return None;
+        }
+        let cache = self.pretty_cache.lock().unwrap();
+        cache
+            .get(&cache_key(path))
+            .filter(|cached| cached.mtime == attrs.mtime)
+            .map(|cached| cached.data.len() as u64)
+    }
+
+    /// Run `f` on the pretty-printed rendering of a JSON file.
+    ///
+    /// Returns None when pretty-printing is off, the file isn't JSON, is too
+    /// large to reformat, or doesn't parse; the raw bytes are served then.
+    fn with_pretty_json<R>(
+        &self,
+        path: &str,
+        attrs: &api_client::FileAttributes,
+        f: impl FnOnce(&[u8]) -> R,
+    ) -> Option<R> {
+        if !self.config.pretty_json
+            || !attrs.content_type.as_deref().is_some_and(is_json_type)
+            || attrs.size as usize > CONTENT_CACHE_MAX_FILE_SIZE
+        {
+            return None;
+        }
+
+        let key = cache_key(path);
+        {
+            let cache = self.pretty_cache.lock().unwrap();
+            if let Some(cached) = cache.get(&key).filter(|c| c.mtime == attrs.mtime) {
+                return Some(f(&cached.data));
+            }
         }
+
+        let raw = self.read_range(path, 0, attrs.size as usize, None).ok()?;
+        let pretty = match serde_json::from_slice::<serde_json::Value>(&raw)
+            .and_then(|value| serde_json::to_vec_pretty(&value))
+        {
+            Ok(mut pretty) => {
+                pretty.push(b'\n');
+                pretty
+            }
+            Err(e) => {
+                debug!("Serving {} unformatted, not valid JSON: {}", path, e);
+                return None;
+            }
+        };
+        let result = f(&pretty);
+
+        let mut cache = self.pretty_cache.lock().unwrap();
+        if cache.len() >= CONTENT_CACHE_MAX_ENTRIES && !cache.contains_key(&key) {
+            let oldest = cache
+                .iter()
+                .min_by_key(|(_, c)| c.stored_at)
+                .map(|(p, _)| p.clone());
+            if let Some(oldest) = oldest {
+                cache.remove(&oldest);
+            }
+        }
+        cache.insert(
+            key,
+            CachedContent {
+                data: pretty,
+                mtime: attrs.mtime,
+                stored_at: Instant::now(),
+                fresh_until: None,
+            },
+        );
+        Some(result)
+    }
+
+    /// Conventional directory link count, 2 plus the number of
+    /// subdirectories, if the listing and every child's attributes are cached
+    fn dir_nlink(&self, path: &str) -> Option<u32> {
+        let names = {
+            let cache = self.dir_cache.lock().unwrap();
+            let cached = cache.get(&cache_key(path))?;
+            if !is_fresh(cached.timestamp, cached.ttl) {
+                return None;
+            }
+            cached.entries.clone()
+        };
+
+        let attr_cache = self.attr_cache.lock().unwrap();
+        let mut subdirs = 0u32;
+        for name in &names {
+            let child = attr_cache.get(&cache_key(&join_path(path, name)))?;
+            if file_type_from_mode(child.attrs.mode) == FileType::Directory {
+                subdirs += 1;
+            }
+        }
+        Some(2 + subdirs)
+    }
+
+    /// Read a byte range of a file, reusing cached content when core
+    /// confirms it hasn't changed since the cached mtime.
+    ///
+    /// `fh` is the open file handle, if any, whose WebDAV stream to continue.
+    fn read_range(
+        &self,
+        path: &str,
+        offset: usize,
+        size: usize,
+        fh: Option<u64>,
+    ) -> Result<Vec<u8>, libc::c_int> {
+        if let Some(data) = self.small_file_hit(path, offset, size) {
+            return Ok(data);
+        }
+        if let Some(data) = self.sniff_hit(path, offset, size) {
+            return Ok(data);
+        }
+
+        let mut known_mtime = self
+            .content_cache
+            .lock()
+            .unwrap()
+            .get(&cache_key(path))
+            .map(|c| c.mtime);
+
+        // A read within the head fetches all of it, for the next sniffer
+        let sniffing = self
+            .config
+            .sniff_bytes
+            .filter(|&limit| offset + size <= limit);
+        // Aligned blocks are kept on the file handle; without one, aligning
+        // would only fetch bytes nobody reads
+        let align = self
+            .config
+            .read_align
+            .zip(fh)
+            .filter(|_| sniffing.is_none());
+        // Large inline content is decoded only as far as this read may use
+        let (start, len) = match (sniffing, align) {
+            (Some(limit), _) => (0, limit),
+            (None, Some((align, _))) => aligned_range(offset, size, align, u64::MAX),
+            (None, None) => (offset, size),
+        };
+        let window = InlineWindow {
+            offset: start as u64,
+            len: len as u64,
+            keep_whole: self.config.small_file_cache_size as u64,
+        };
+        // Only files known to be too big to cache are fetched by range
+        let ranged = self.ranged_read_supported
+            && self
+                .attr_cache
+                .lock()
+                .unwrap()
+                .get(&cache_key(path))
+                .is_some_and(|cached| cached.attrs.size > window.keep_whole);
+
+        let read_result = loop {
+            match self.api.read_window(path, known_mtime, window, ranged) {
+                Ok(ReadResponse::Fresh(read_result)) => {
+                    self.api_health.lock().unwrap().record_success();
+                    self.stats.content_misses.fetch_add(1, Ordering::Relaxed);
+                    break read_result;
+                }
+                Ok(ReadResponse::NotModified) if known_mtime.is_some() => {
+                    self.api_health.lock().unwrap().record_success();
+                    if let Some(data) = self.cached_content_range(path, offset, size) {
+                        debug!("Content cache revalidated for {}", path);
+                        self.stats.content_hits.fetch_add(1, Ordering::Relaxed);
+                        return Ok(data);
+                    }
+                    // Evicted while we were asking; fetch unconditionally
+                    known_mtime = None;
+                }
+                Ok(ReadResponse::NotModified) => {
+                    error!("read for {} returned 304 to an unconditional request", path);
+                    return Err(libc::EIO);
+                }
+                Err(e) => {
+                    self.record_api_error(&e, format!("read API call failed for {}: {}", path, e));
+                    error!("read API call failed for {}: {}", path, e);
+                    return Err(e.errno());
+                }
+            }
+        };
+
+        if let Some((_, fh)) = align {
+            let handles = self.file_handles.lock().unwrap();
+            let block = handles.get(&fh).and_then(|handle| handle.aligned.as_ref());
+            if let Some(data) = block.and_then(|block| block.slice(&read_result, offset, size)) {
+                return Ok(data);
+            }
+        }
+        let (start, len) = match (sniffing, align) {
+            (Some(limit), _) => (0, limit),
+            (None, Some((align, _))) => aligned_range(offset, size, align, read_result.size),
+            (None, None) => (offset, size),
+        };
+        let data = self
+            .read_file_content(path, &read_result, start, len, fh, true)
+            .map_err(|e| {
+                error!("Failed to read file content for {}: {}", path, e);
+                libc::EIO
+            })?;
+        if sniffing.is_some() {
+            self.stats.sniff_misses.fetch_add(1, Ordering::Relaxed);
+            self.cache_head(path, &read_result, &data);
+        }
+        if let Some((_, fh)) = align {
+            if let Some(handle) = self.file_handles.lock().unwrap().get_mut(&fh) {
+                handle.aligned = Some(AlignedBlock {
+                    start,
+                    data: data.clone(),
+                    file_size: read_result.size,
+                    mtime: read_result.mtime,
+                });
+            }
+        }
+        if start == offset && data.len() <= size {
+            return Ok(data);
+        }
+        let skip = std::cmp::min(offset - start, data.len());
+        let end = std::cmp::min(skip + size, data.len());
+        Ok(data[skip..end].to_vec())
+    }
+
+    /// Serve a cached small file straight from memory while its attributes
+    /// are fresh and still carry the mtime the content was read at, or
+    /// while core's Cache-Control for the content allows
+    fn small_file_hit(&self, path: &str, offset: usize, size: usize) -> Option<Vec<u8>> {
+        let key = cache_key(path);
+        let fresh_until = self
+            .content_cache
+            .lock()
+            .unwrap()
+            .get(&key)
+            .map(|c| c.fresh_until)?;
+        let mtime = match fresh_until {
+            Some(until) if Instant::now() < until => None,
+            Some(_) => return None,
+            None => {
+                let attrs = self.attr_cache.lock().unwrap();
+                let cached = attrs.get(&key).filter(|c| is_fresh(c.timestamp, c.ttl))?;
+                Some(cached.attrs.mtime)
+            }
+        };
+
+        let cache = self.content_cache.lock().unwrap();
+        let data = &cache
+            .get(&key)
+            .filter(|c| mtime.is_none_or(|mtime| c.mtime == mtime))?
+            .data;
+        debug!("Small file cache hit for {}", path);
+        self.stats.content_hits.fetch_add(1, Ordering::Relaxed);
+        self.stats.small_file_hits.fetch_add(1, Ordering::Relaxed);
+        let start = std::cmp::min(offset, data.len());
+        let end = std::cmp::min(start + size, data.len());
+        Some(data[start..end].to_vec())
+    }
+
+    /// Serve a read within the first FUSE_SNIFF_BYTES from the cached head,
+    /// while fresh attributes still carry the mtime it was read at
+    fn sniff_hit(&self, path: &str, offset: usize, size: usize) -> Option<Vec<u8>> {
+        let limit = self.config.sniff_bytes?;
+        if offset + size > limit {
+            return None;
+        }
+        let key = cache_key(path);
+        let mtime = {
+            let attrs = self.attr_cache.lock().unwrap();
+            let cached = attrs.get(&key).filter(|c| is_fresh(c.timestamp, c.ttl))?;
+            cached.attrs.mtime
+        };
+
+        let cache = self.sniff_cache.lock().unwrap();
+        let data = &cache.get(&key).filter(|head| head.mtime == mtime)?.data;
+        debug!("Sniff cache hit for {}", path);
+        self.stats.sniff_hits.fetch_add(1, Ordering::Relaxed);
+        // A head shorter than the limit is the whole file
+        let start = std::cmp::min(offset, data.len());
+        let end = std::cmp::min(start + size, data.len());
+        Some(data[start..end].to_vec())
+    }
+
+    /// Read a file's head into the sniff cache on open, so the magic-byte
+    /// read that usually follows is served from memory.
+    ///
+    /// This is a single read of at most FUSE_SNIFF_BYTES, and is skipped
+    /// when the head is already cached, the file is small enough to sit in
+    /// the content cache, or the circuit is open.
+    fn prefetch_head(&self, path: &str, limit: usize) {
+        let Some(attrs) = self.get_cached_attrs(path) else {
+            return;
+        };
+        if file_type_from_mode(attrs.mode) != FileType::RegularFile || attrs.size == 0 {
+            return;
+        }
+        let key = cache_key(path);
+        let cached = self
+            .sniff_cache
+            .lock()
+            .unwrap()
+            .get(&key)
+            .is_some_and(|head| head.mtime == attrs.mtime)
+            || self
+                .content_cache
+                .lock()
+                .unwrap()
+                .get(&key)
+                .is_some_and(|content| content.mtime == attrs.mtime);
+        if cached {
+            return;
+        }
+        if !self.api_health.lock().unwrap().allow_request() {
+            return;
+        }
+        let len = std::cmp::min(limit as u64, attrs.size) as usize;
+        if let Err(errno) = self.read_range(path, 0, len, None) {
+            debug!("Prefetching the head of {} failed: errno {}", path, errno);
+        }
+    }
+
+    /// Remember the head of a file along with the mtime it was read at
+    fn cache_head(&self, path: &str, read_result: &api_client::ReadResult, data: &[u8]) {
+        let Some(mtime) = read_result.mtime.or_else(|| {
+            self.attr_cache
+                .lock()
+                .unwrap()
+                .get(&cache_key(path))
+                .map(|c| c.attrs.mtime)
+        }) else {
+            return;
+        };
+
+        let key = cache_key(path);
+        let mut cache = self.sniff_cache.lock().unwrap();
+        if cache.len() >= SNIFF_CACHE_MAX_ENTRIES && !cache.contains_key(&key) {
+            let oldest = cache
+                .iter()
+                .min_by_key(|(_, head)| head.stored_at)
+                .map(|(p, _)| p.clone());
+            if let Some(oldest) = oldest {
+                cache.remove(&oldest);
+            }
+        }
+        cache.insert(
+            key,
+            CachedHead {
+                data: data.to_vec(),
+                mtime,
+                stored_at: Instant::now(),
+            },
+        );
+    }
+
+    fn cached_content_range(&self, path: &str, offset: usize, size: usize) -> Option<Vec<u8>> {
+        let cache = self.content_cache.lock().unwrap();
+        let data = &cache.get(&cache_key(path))?.data;
+        if offset >= data.len() {
+            return Some(vec![]);
+        }
+        let end = std::cmp::min(offset + size, data.len());
+        Some(data[offset..end].to_vec())
+    }
+
+    /// Remember a file's complete content along with the mtime it was read at
+    /// Bring cached attributes in line with a read result fetched again
+    /// mid-read, which may carry a newer size and mtime than getattr saw
+    fn adopt_read_result(&self, path: &str, fresh: &api_client::ReadResult) {
+        let mut cache = self.attr_cache.lock().unwrap();
+        let Some(cached) = cache.get_mut(&cache_key(path)) else {
+            return;
+        };
+        let mut attrs = cached.attrs.clone();
+        attrs.size = fresh.size;
+        attrs.mtime = fresh.mtime.unwrap_or(attrs.mtime);
+        purge_changed_content(
+            &self.content_cache,
+            &self.pretty_cache,
+            path,
+            Some(&cached.attrs),
+            &attrs,
+        );
+        cached.attrs = attrs;
+    }
+
+    fn cache_content(&self, path: &str, read_result: &api_client::ReadResult, data: &[u8]) {
+        if data.len() > self.config.small_file_cache_size {
+            return;
+        }
+        let cache_control = &read_result.cache_control;
+        let Some(lifetime) = self.config.cache_ttl.for_response(cache_control) else {
+            debug!("Not caching content of {}: no-store", path);
+            self.content_cache.lock().unwrap().remove(&cache_key(path));
+            return;
+        };
+        let fresh_until = (cache_control.immutable || cache_control.max_age.is_some())
+            .then(|| Instant::now() + lifetime);
+        let mtime = match read_result.mtime.or_else(|| {
+            self.attr_cache
+                .lock()
+                .unwrap()
+                .get(&cache_key(path))
+                .map(|c| c.attrs.mtime)
+        }) {
+            Some(mtime) => mtime,
+            None => return,
+        };
+
+        let key = cache_key(path);
+        let mut cache = self.content_cache.lock().unwrap();
+        if cache.len() >= CONTENT_CACHE_MAX_ENTRIES && !cache.contains_key(&key) {
+            let oldest = cache
+                .iter()
+                .min_by_key(|(_, c)| c.stored_at)
+                .map(|(p, _)| p.clone());
+            if let Some(oldest) = oldest {
+                cache.remove(&oldest);
+            }
+        }
+        cache.insert(
+            key,
+            CachedContent {
+                data: data.to_vec(),
+                mtime,
+                stored_at: Instant::now(),
+                fresh_until,
+            },
+        );
+    }
+
+    /// Serve a range from the sources a read result offers, in the order of
+    /// FUSE_READ_SOURCE_ORDER. A source that fails hands over to the next
+    /// one offered; the last failure is returned if none succeeds.
+    ///
+    /// With `refetch_stale`, a source_path that has vanished since core
+    /// handed it out (the file was moved) costs one fresh read from core,
+    /// which may name a new source; without it that source just fails.
+    fn read_file_content(
+        &self,
+        path: &str,
+        read_result: &api_client::ReadResult,
+        offset: usize,
+        size: usize,
+        fh: Option<u64>,
+        refetch_stale: bool,
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let offered: Vec<ReadSource> = self
+            .config
+            .read_source_order
+            .iter()
+            .copied()
+            .filter(|&source| self.read_source_offered(read_result, source))
+            .collect();
+
+        // Inline content past the limit is never decoded; another source
+        // must serve it, or the read fails
+        let oversized_inline = read_result
+            .content
+            .as_ref()
+            .map(|content| content.len)
+            .filter(|&len| len > self.config.max_inline_size)
+            .filter(|_| self.config.read_source_order.contains(&ReadSource::Inline));
+        if let Some(decoded_len) = oversized_inline {
+            if offered.is_empty() {
+                error!(
+                    "Refusing {} bytes of inline content for {} (FUSE_MAX_INLINE_SIZE={})",
+                    decoded_len, path, self.config.max_inline_size
+                );
+                return Err("inline content exceeds FUSE_MAX_INLINE_SIZE".into());
+            }
+            // Every read of such a file lands here, so say it once
+            if !self.inline_size_warned.swap(true, Ordering::Relaxed) {
+                warn!(
+                    "Ignoring {} bytes of inline content for {}, reading it from {} (FUSE_MAX_INLINE_SIZE={}); later cases are logged at debug level",
+                    decoded_len, path, offered[0], self.config.max_inline_size
+                );
+            } else {
+                debug!(
+                    "Ignoring {} bytes of inline content for {}, reading it from {}",
+                    decoded_len, path, offered[0]
+                );
+            }
+        }
+
+        let mut last_error = None;
+        for (i, &source) in offered.iter().enumerate() {
+            let result = match source {
+                ReadSource::Inline => self.read_inline(path, read_result, offset, size),
+                ReadSource::Source => {
+                    self.read_source_path(path, read_result, offset, size, fh, refetch_stale)
+                }
+                ReadSource::Webdav => self.read_webdav_source(path, read_result, offset, size, fh),
+                ReadSource::FetchUrl => self.read_fetch_source(path, read_result, offset, size, fh),
+            };
+            match result {
+                Ok(data) => {
+                    debug!("Read of {} served from {}", path, source);
+                    return Ok(data);
+                }
+                Err(e) => {
+                    if let Some(next) = offered.get(i + 1) {
+                        warn!(
+                            "Reading {} from {} failed, trying {}: {}",
+                            path, source, next, e
+                        );
+                    }
+                    last_error = Some(e);
+                }
+            }
+        }
+        if let Some(e) = last_error {
+            return Err(e);
+        }
+
+        // A legitimately empty file needs no data source
+        if read_result.size == 0 {
+            return Ok(vec![]);
+        }
+
+        Err(read_source::no_source_error(&self.config.read_source_order))
+    }
+
+    /// Whether a read result offers `source`. Inline content past
+    /// FUSE_MAX_INLINE_SIZE is never decoded and so doesn't count.
+    fn read_source_offered(
+        &self,
+        read_result: &api_client::ReadResult,
+        source: ReadSource,
+    ) -> bool {
+        match (source, read_result.content.as_ref()) {
+            (ReadSource::Inline, Some(content)) => content.len <= self.config.max_inline_size,
+            _ => source.offered_by(read_result),
+        }
+    }
+
+    /// Inline base64 content, as core sends for virtual files
+    fn read_inline(
+        &self,
+        path: &str,
+        read_result: &api_client::ReadResult,
+        offset: usize,
+        size: usize,
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let content = read_result.content.as_ref().ok_or("no inline content")?;
+        // Large payloads are decoded only around the requested range; they
+        // are too big for content_cache and a partial read can't be checked
+        // against the whole-file checksum anyway
+        if !content.is_whole() {
+            return content
+                .range(offset as u64, size as u64)
+                .ok_or_else(|| "read lies outside the inline content kept".into());
+        }
+
+        self.verify_checksum(path, read_result, &content.data)?;
+        self.cache_content(path, read_result, &content.data);
+        Ok(content
+            .range(offset as u64, size as u64)
+            .unwrap_or_default())
+    }
+
+    /// WebDAV URL, read with HTTP Range requests
+    fn read_webdav_source(
+        &self,
+        path: &str,
+        read_result: &api_client::ReadResult,
+        offset: usize,
+        size: usize,
+        fh: Option<u64>,
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let webdav_url = read_result.webdav_url.as_deref().ok_or("no WebDAV URL")?;
+        let data = match fh {
+            Some(fh) => self.read_webdav_stream(fh, webdav_url, offset, size, read_result.size)?,
+            None => self.read_from_webdav(webdav_url, offset, size, read_result.size)?,
+        };
+        if offset == 0 && data.len() as u64 == read_result.size {
+            self.verify_checksum(path, read_result, &data)?;
+            self.cache_content(path, read_result, &data);
+        }
+        Ok(data)
+    }
+
+    /// Presigned or other plain HTTP URL
+    fn read_fetch_source(
+        &self,
+        path: &str,
+        read_result: &api_client::ReadResult,
+        offset: usize,
+        size: usize,
+        fh: Option<u64>,
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let fetch_url = read_result.fetch_url.as_deref().ok_or("no fetch URL")?;
+        let fresh;
+        let (data, read_result) =
+            match self.read_from_fetch_url(fh, fetch_url, offset, size, read_result.size)? {
+                Some(data) => (data, read_result),
+                None => {
+                    // Presigned URLs expire; ask core for a fresh one, once
+                    debug!("fetch URL for {} was refused, requesting a new one", path);
+                    fresh = match self.reread(path)? {
+                        ReadResponse::Fresh(fresh) => fresh,
+                        ReadResponse::NotModified => return Err("no fetch URL on re-read".into()),
+                    };
+                    let fresh_url = fresh
+                        .fetch_url
+                        .as_deref()
+                        .ok_or("no fetch URL on re-read")?;
+                    self.adopt_read_result(path, &fresh);
+                    let data = self
+                        .read_from_fetch_url(fh, fresh_url, offset, size, fresh.size)?
+                        .ok_or_else(|| format!("fetch URL for {} refused twice", path))?;
+                    (data, &fresh)
+                }
+            };
+        if offset == 0 && data.len() as u64 == read_result.size {
+            self.verify_checksum(path, read_result, &data)?;
+            self.cache_content(path, read_result, &data);
+        }
+        Ok(data)
+    }
+
+    /// Local file core shares with the driver
+    fn read_source_path(
+        &self,
+        path: &str,
+        read_result: &api_client::ReadResult,
+        offset: usize,
+        size: usize,
+        fh: Option<u64>,
+        refetch_stale: bool,
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let source_path = read_result.source_path.as_deref().ok_or("no source path")?;
+        let mut file = match retry_interrupted(|| File::open(source_path)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && refetch_stale => {
+                debug!(
+                    "source_path {} for {} is gone, asking core again",
+                    source_path, path
+                );
+                let ReadResponse::Fresh(fresh) = self.reread(path)? else {
+                    return Err(e.into());
+                };
+                self.adopt_read_result(path, &fresh);
+                return self.read_file_content(path, &fresh, offset, size, fh, false);
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        // Size the buffer by what is actually left, not by the request,
+        // so a tail read near EOF doesn't allocate a full max_read
+        let remaining = retry_interrupted(|| file.metadata())?
+            .len()
+            .saturating_sub(offset as u64);
+        let wanted = std::cmp::min(size as u64, remaining);
+        if wanted == 0 {
+            return Ok(vec![]);
+        }
+
+        if offset > 0 {
+            use std::io::Seek;
+            retry_interrupted(|| file.seek(std::io::SeekFrom::Start(offset as u64)))?;
+        }
+
+        // A single read() may return less than asked for; keep going
+        // until `wanted` bytes or EOF, should the file shrink meanwhile.
+        // read_to_end already retries reads interrupted by a signal.
+        let mut buffer = Vec::with_capacity(wanted as usize);
+        let bytes_read = file.take(wanted).read_to_end(&mut buffer)?;
+
+        if offset == 0 && bytes_read as u64 == read_result.size {
+            self.verify_checksum(path, read_result, &buffer)?;
+            self.cache_content(path, read_result, &buffer);
+        }
+
+        Ok(buffer)
+    }
+
+    /// Verify data against the API-provided sha256 checksum.
+    ///
+    /// The checksum covers the whole file, so callers must only pass data
+    /// that represents the complete content.
+    fn verify_checksum(
+        &self,
+        path: &str,
+        read_result: &api_client::ReadResult,
+        data: &[u8],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.config.verify_checksums {
+            return Ok(());
+        }
+        let expected = match read_result.checksum {
+            Some(ref checksum) => checksum,
+            None => return Ok(()),
+        };
+
+        let digest = ring::digest::digest(&ring::digest::SHA256, data);
+        let actual: String = digest
+            .as_ref()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+
+        if !actual.eq_ignore_ascii_case(expected) {
+            error!(
+                "Checksum mismatch for {}: expected {}, got {}",
+                path, expected, actual
+            );
+            return Err(format!("checksum mismatch for {}", path).into());
+        }
+        Ok(())
+    }
+
+    /// Read file content from WebDAV URL using HTTP Range requests
+    fn read_from_webdav(
+        &self,
+        webdav_url: &str,
+        offset: usize,
+        size: usize,
+        file_size: u64,
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        debug!(
+            "WebDAV read: {} offset={} size={}",
+            webdav_url, offset, size
+        );
+        self.ranged_get(webdav_url, offset, size, file_size)?
+            .map_err(|status| format!("WebDAV request failed: {} - {}", status, webdav_url).into())
+    }
+
+    /// Read a range from a fetch URL, or None if it was refused with 403,
+    /// as an expired presigned URL is. Reads through an open file go by
+    /// blocks when core says its URLs take multi-range requests.
+    fn read_from_fetch_url(
+        &self,
+        fh: Option<u64>,
+        fetch_url: &str,
+        offset: usize,
+        size: usize,
+        file_size: u64,
+    ) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+        // Presigned URLs carry credentials in the query; keep them out of logs
+        let loggable = fetch_url.split('?').next().unwrap_or(fetch_url);
+        debug!(
+            "Fetch URL read: {} offset={} size={}",
+            loggable, offset, size
+        );
+        // Reads through an open file go through its block cache, so small
+        // sequential reads share one ranged request and its readahead
+        let fetched = match fh {
+            Some(fh) => self.read_blocks(fh, fetch_url, offset, size, file_size)?,
+            None => self.ranged_get(fetch_url, offset, size, file_size)?,
+        };
+        match fetched {
+            Ok(data) => Ok(Some(data)),
+            Err(reqwest::StatusCode::FORBIDDEN) => Ok(None),
+            Err(status) => {
+                Err(format!("fetch URL request failed: {} - {}", status, loggable).into())
+            }
+        }
+    }
+
+    /// GET a byte range of a file over HTTP, returning the failing status if
+    /// the server answers with neither the range nor the whole file
+    fn ranged_get(
+        &self,
+        url: &str,
+        offset: usize,
+        size: usize,
+        file_size: u64,
+    ) -> Result<Result<Vec<u8>, reqwest::StatusCode>, reqwest::Error> {
+        // Don't request beyond the end of the file, or an empty range,
+        // which has no inclusive end to name
+        if size == 0 || offset as u64 >= file_size {
+            return Ok(Ok(vec![]));
+        }
+
+        // Calculate the actual range to request
+        let end = std::cmp::min(
+            (offset as u64).saturating_add(size as u64) - 1,
+            file_size - 1,
+        );
+        let range_header = format!("bytes={}-{}", offset, end);
+
+        let response = self
+            .webdav_client
+            .get(url)
+            .header("Range", range_header)
+            .send()?;
+
+        if response.status() == reqwest::StatusCode::PARTIAL_CONTENT {
+            let bytes = response.bytes()?;
+            Ok(Ok(bytes.to_vec()))
+        } else if response.status().is_success() {
+            // The server ignored the Range header and sent the whole file
+            let bytes = response.bytes()?;
+            let start = std::cmp::min(offset, bytes.len());
+            let end = std::cmp::min(offset.saturating_add(size), bytes.len());
+            Ok(Ok(bytes[start..end].to_vec()))
+        } else {
+            Ok(Err(response.status()))
+        }
+    }
+
+    /// Serve a range out of the open file's fetched blocks, first fetching
+    /// the missing ones along with a readahead window. Missing blocks close
+    /// together share a range; disjoint ranges go out as one multi-range
+    /// request where core advertises byteranges and the answer is usable,
+    /// else one request each.
+    fn read_blocks(
+        &self,
+        fh: u64,
+        url: &str,
+        offset: usize,
+        size: usize,
+        file_size: u64,
+    ) -> Result<Result<Vec<u8>, reqwest::StatusCode>, reqwest::Error> {
+        let offset = offset as u64;
+        if offset >= file_size || size == 0 {
+            return Ok(Ok(vec![]));
+        }
+        let block_size = self.config.read_align.unwrap_or(FETCH_BLOCK_SIZE) as u64;
+        let end = std::cmp::min(offset + size as u64, file_size);
+        let first = offset / block_size;
+        let last = (end - 1) / block_size;
+        let window_end = std::cmp::min(last + FETCH_READAHEAD_BLOCKS, (file_size - 1) / block_size);
+
+        // Blocks another thread is already fetching are waited for instead
+        let missing: Vec<u64> = {
+            let mut handles = self.file_handles.lock().unwrap();
+            let Some(handle) = handles.get_mut(&fh) else {
+                return self.ranged_get(url, offset as usize, size, file_size);
+            };
+            if handle.blocks.file_size != file_size {
+                handle.blocks = FetchedBlocks {
+                    file_size,
+                    ..FetchedBlocks::default()
+                };
+            }
+            let cached = &handle.blocks.blocks;
+            if (first..=last).all(|i| cached.contains_key(&i)) {
+                Vec::new()
+            } else {
+                let mut in_flight = self.blocks_in_flight.lock().unwrap();
+                let missing: Vec<u64> = (first..=window_end)
+                    .filter(|i| !cached.contains_key(i) && !in_flight.contains(&(fh, *i)))
+                    .collect();
+                in_flight.extend(missing.iter().map(|&i| (fh, i)));
+                missing
+            }
+        };
+        let claimed = InFlightBlocks {
+            fs: self,
+            fh,
+            indices: missing.clone(),
+        };
+
+        if !missing.is_empty() {
+            let mut ranges: Vec<(u64, u64)> = Vec::new();
+            for &index in &missing {
+                match ranges.last_mut() {
+                    Some((_, last_index)) if index - *last_index <= FETCH_MERGE_GAP_BLOCKS + 1 => {
+                        *last_index = index
+                    }
+                    _ => ranges.push((index, index)),
+                }
+            }
+            let byte_ranges: Vec<(u64, u64)> = ranges
+                .iter()
+                .map(|&(from, to)| {
+                    (
+                        from * block_size,
+                        std::cmp::min((to + 1) * block_size, file_size) - 1,
+                    )
+                })
+                .collect();
+
+            let parts = match self.fetch_ranges(url, &byte_ranges, file_size)? {
+                Ok(parts) => parts,
+                Err(status) => return Ok(Err(status)),
+            };
+            let mut handles = self.file_handles.lock().unwrap();
+            if let Some(handle) = handles.get_mut(&fh) {
+                for (start, data) in &parts {
+                    handle.blocks.store(block_size, *start, data);
+                }
+            }
+        }
+        drop(claimed);
+
+        // A block whose fetch failed elsewhere is simply absent afterwards,
+        // and served by the fallback below
+        let mut in_flight = self.blocks_in_flight.lock().unwrap();
+        while (first..=last).any(|i| in_flight.contains(&(fh, i))) {
+            in_flight = self.blocks_landed.wait(in_flight).unwrap();
+        }
+        drop(in_flight);
+
+        let mut data = Vec::with_capacity((end - offset) as usize);
+        {
+            let handles = self.file_handles.lock().unwrap();
+            let blocks = handles.get(&fh).map(|handle| &handle.blocks.blocks);
+            for index in first..=last {
+                let Some(block) = blocks.and_then(|blocks| blocks.get(&index)) else {
+                    // Evicted already, or the server sent less than asked
+                    drop(handles);
+                    return self.ranged_get(url, offset as usize, size, file_size);
+                };
+                let block_start = index * block_size;
+                let from = offset.saturating_sub(block_start) as usize;
+                let to = std::cmp::min(end - block_start, block.len() as u64) as usize;
+                data.extend_from_slice(&block[from.min(to)..to]);
+            }
+        }
+        Ok(Ok(data))
+    }
+
+    /// GET several inclusive byte ranges, as (start, data) pieces in
+    /// whatever shape the server answered with
+    fn fetch_ranges(
+        &self,
+        url: &str,
+        ranges: &[(u64, u64)],
+        file_size: u64,
+    ) -> Result<Result<Vec<RangePart>, reqwest::StatusCode>, reqwest::Error> {
+        let individually = |ranges: &[(u64, u64)]| {
+            let mut parts = Vec::with_capacity(ranges.len());
+            for &(start, end) in ranges {
+                let len = (end - start + 1) as usize;
+                match self.ranged_get(url, start as usize, len, file_size)? {
+                    Ok(data) => parts.push((start, data)),
+                    Err(status) => return Ok(Err(status)),
+                }
+            }
+            Ok(Ok(parts))
+        };
+        if ranges.len() == 1 || !self.multi_range_supported.load(Ordering::Relaxed) {
+            return individually(ranges);
+        }
+
+        debug!("Multi-range read: {} ranges", ranges.len());
+        let response = self
+            .webdav_client
+            .get(url)
+            .header("Range", byteranges::header(ranges))
+            .send()?;
+        let status = response.status();
+        if status == reqwest::StatusCode::OK {
+            // The whole file; keep the parts that were asked for
+            let body = response.bytes()?;
+            return Ok(Ok(ranges
+                .iter()
+                .filter(|&&(_, end)| (end as usize) < body.len())
+                .map(|&(start, end)| (start, body[start as usize..=end as usize].to_vec()))
+                .collect()));
+        }
+        if status != reqwest::StatusCode::PARTIAL_CONTENT {
+            return Ok(Err(status));
+        }
+
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        let content_type = header(reqwest::header::CONTENT_TYPE).unwrap_or_default();
+        let content_range = header(reqwest::header::CONTENT_RANGE);
+        let body = response.bytes()?;
+        let parts = match byteranges::boundary(&content_type) {
+            Some(boundary) => byteranges::parse(boundary, &body),
+            // The server merged everything into a single range
+            None => content_range
+                .as_deref()
+                .and_then(byteranges::parse_content_range)
+                .map(|(start, _)| vec![(start, body.to_vec())])
+                .ok_or_else(|| "206 without a Content-Range".to_string()),
+        };
+        match parts {
+            Ok(parts) => Ok(Ok(parts)),
+            Err(e) => {
+                warn!(
+                    "Unusable multi-range response ({}), fetching ranges one by one",
+                    e
+                );
+                individually(ranges)
+            }
+        }
+    }
+
+    /// Read from WebDAV through the open file's persistent stream.
+    ///
+    /// A read starting where the previous one ended continues the same
+    /// response body; any other offset, or a broken stream, starts a new
+    /// open-ended ranged GET.
+    fn read_webdav_stream(
+        &self,
+        fh: u64,
+        webdav_url: &str,
+        offset: usize,
+        size: usize,
+        file_size: u64,
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let offset = offset as u64;
+        if offset >= file_size {
+            return Ok(vec![]);
+        }
+        let wanted = std::cmp::min(size as u64, file_size - offset);
+
+        let reusable = self
+            .file_handles
+            .lock()
+            .unwrap()
+            .get_mut(&fh)
+            .and_then(|handle| handle.stream.take())
+            .filter(|stream| stream.url == webdav_url && stream.offset == offset);
+
+        let mut data = Vec::with_capacity(wanted as usize);
+        let mut stream = match reusable {
+            Some(mut stream) => {
+                debug!("WebDAV stream continues at {} for {}", offset, webdav_url);
+                match (&mut stream.body).take(wanted).read_to_end(&mut data) {
+                    Ok(_) => stream,
+                    Err(e) => {
+                        debug!("WebDAV stream broke ({}), reopening at {}", e, offset);
+                        data.clear();
+                        self.open_webdav_stream(webdav_url, offset, &mut data, wanted)?
+                    }
+                }
+            }
+            None => self.open_webdav_stream(webdav_url, offset, &mut data, wanted)?,
+        };
+        stream.offset += data.len() as u64;
+
+        // Keep the stream only if it is still positioned inside the file
+        if data.len() as u64 == wanted && stream.offset < file_size {
+            if let Some(handle) = self.file_handles.lock().unwrap().get_mut(&fh) {
+                handle.stream = Some(stream);
+            }
+        }
+        Ok(data)
+    }
+
+    /// Start a GET for everything from `offset` on and read the first
+    /// `wanted` bytes of it into `data`
+    fn open_webdav_stream(
+        &self,
+        webdav_url: &str,
+        offset: u64,
+        data: &mut Vec<u8>,
+        wanted: u64,
+    ) -> Result<WebdavStream, Box<dyn std::error::Error>> {
+        debug!("WebDAV stream: {} Range: bytes={}-", webdav_url, offset);
+
+        let mut body = self
+            .webdav_client
+            .get(webdav_url)
+            .header("Range", format!("bytes={}-", offset))
+            .send()?;
+
+        if body.status() == reqwest::StatusCode::OK {
+            // The server ignored the Range header; skip up to the offset
+            std::io::copy(&mut (&mut body).take(offset), &mut std::io::sink())?;
+        } else if body.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+            return Err(
+                format!("WebDAV request failed: {} - {}", body.status(), webdav_url).into(),
+            );
+        }
+
+        (&mut body).take(wanted).read_to_end(data)?;
+        Ok(WebdavStream {
+            url: webdav_url.to_string(),
+            offset,
+            body,
+        })
+    }
+}
+
+/// Interrupts: fuser 0.14 answers FUSE_INTERRUPT itself and never passes it
+/// on, so an operation whose caller was signalled runs to completion and is
+/// replied to normally, and the kernel only abandons the wait for a fatal
+/// signal. Every operation is safe to abandon that way, since none changes
+/// state on core apart from the advisory atime notification. Local
+/// source_path I/O retries EINTR rather than failing the read with it.
+impl Filesystem for ApiFS {
+    fn init(&mut self, _req: &Request, config: &mut KernelConfig) -> Result<(), libc::c_int> {
+        if let Some(max_read) = self.config.max_read {
+            let readahead = match config.set_max_readahead(max_read) {
+                Ok(_) => max_read,
+                Err(nearest) => {
+                    let _ = config.set_max_readahead(nearest);
+                    nearest
+                }
+            };
+            info!(
+                "Requested max_read={} KiB (a mount option the kernel may lower), \
+                 negotiated max_readahead={} KiB",
+                max_read / 1024,
+                readahead / 1024
+            );
+        }
+
+        if self.config.kernel_cache {
+            match config.add_capabilities(fuser::consts::FUSE_AUTO_INVAL_DATA) {
+                Ok(()) => info!("Kernel page cache kept across opens, dropped on mtime change"),
+                Err(_) => warn!(
+                    "Kernel lacks FUSE_AUTO_INVAL_DATA; cached pages only drop on change events"
+                ),
+            }
+        }
+
+        if self.config.preload_depth > 0 && !self.config.forward_identity {
+            preload::spawn_preload(
+                self.api.clone(),
+                self.shared_caches(),
+                Arc::clone(&self.api_health),
+                self.config.preload_depth,
+                Arc::clone(&self.shutdown),
+            );
+        }
+        Ok(())
+    }
+
+    fn open(&mut self, req: &Request, ino: u64, flags: i32, reply: ReplyOpen) {
+        let control = ino == CONTROL_FILE_INO && self.config.control_file;
+        if opens_for_writing(flags) {
+            if let Some(errno) = self.write_refusal(ino, req.uid()) {
+                debug!("open: ino={} for writing refused", ino);
+                reply.error(errno);
+                return;
+            }
+        }
+        if control && !self.control_writer_allowed(req.uid()) {
+            reply.error(libc::EACCES);
+            return;
+        }
+
+        if self.config.notify_atime && ino > LAST_SYNTHETIC_INO {
+            let path = self.inode_mapper.lock().unwrap().get_path(ino).cloned();
+            if let Some(path) = path {
+                api_client::begin_request(self.identity(req));
+                self.notify_access(&path);
+            }
+        }
+
+        if let Some(limit) = self.config.sniff_bytes.filter(|_| ino > LAST_SYNTHETIC_INO) {
+            let path = self.inode_mapper.lock().unwrap().get_path(ino).cloned();
+            if let Some(path) = path {
+                self.prefetch_head(&path, limit);
+            }
+        }
+
+        // Render a JSON file now, so later getattrs report its pretty size.
+        // The kernel may already hold the stored size and would cut reads
+        // short at it, so bypass the page cache when the two differ.
+        let reformatted = self.config.pretty_json && ino > LAST_SYNTHETIC_INO && {
+            let path = self.inode_mapper.lock().unwrap().get_path(ino).cloned();
+            path.and_then(|path| {
+                let attrs = self.resolve_attrs(&path).ok()?;
+                self.with_pretty_json(&path, &attrs, |data| data.len() as u64 != attrs.size)
+            })
+            .unwrap_or(false)
+        };
+
+        // Bypass the page cache for the stats and control files so every
+        // read sees a fresh report
+        let flags = if ino == STATS_FILE_INO || control || reformatted {
+            fuser::consts::FOPEN_DIRECT_IO
+        } else if self.config.kernel_cache && !is_synthetic_ino(ino) {
+            fuser::consts::FOPEN_KEEP_CACHE
+        } else {
+            0
+        };
+
+        let fh = self.next_fh.fetch_add(1, Ordering::Relaxed);
+        self.file_handles
+            .lock()
+            .unwrap()
+            .insert(fh, FileHandle::default());
+        reply.opened(fh, flags);
+    }
+
+    fn release(
+        &mut self,
+        _req: &Request,
+        _ino: u64,
+        fh: u64,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        _flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        self.file_handles.lock().unwrap().remove(&fh);
+        reply.ok();
+    }
+
+    fn destroy(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+    }
+
+    fn forget(&mut self, _req: &Request, ino: u64, nlookup: u64) {
+        self.forget_inodes(&[(ino, nlookup)]);
+    }
+
+    fn batch_forget(&mut self, _req: &Request, nodes: &[fuser::fuse_forget_one]) {
+        let nodes: Vec<_> = nodes.iter().map(|n| (n.nodeid, n.nlookup)).collect();
+        self.forget_inodes(&nodes);
+    }
+
+    fn lookup(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let name_str = match name.to_str() {
+            Some(s) => s,
+            None => {
+                // Names are UTF-8 end to end, since the API speaks JSON
+                debug!("lookup: non-UTF-8 name {:?} cannot exist", name);
+                reply.error(ENOENT);
+                return;
+            }
+        };
+
+        if parent == ROOT_INO && name_str == "ERROR.txt" {
+            let is_unhealthy = self.health_status.is_unhealthy();
+            if is_unhealthy {
+                let attr = self.get_error_file_attrs();
+                reply.entry(&self.config.attr_ttl, &attr, 0);
+                return;
+            }
+        }
+
+        if name_str == TRUNCATED_FILE_NAME && self.is_truncated_dir(parent) {
+            reply.entry(&self.config.attr_ttl, &self.get_truncated_file_attrs(), 0);
+            return;
+        }
+
+        if parent == ROOT_INO && name_str == INFO_FILE_NAME && self.info_file_visible() {
+            reply.entry(&self.config.attr_ttl, &self.get_info_file_attrs(), 0);
+            return;
+        }
+
+        if parent == ROOT_INO && name_str == CONTROL_FILE_NAME && self.control_file_visible() {
+            reply.entry(&Duration::ZERO, &self.get_control_file_attrs(), 0);
+            return;
+        }
+
+        if parent == ROOT_INO && self.config.stats_file.as_deref() == Some(name_str) {
+            // The report changes constantly, so never let the kernel cache its size
+            reply.entry(&Duration::ZERO, &self.get_stats_file_attrs(), 0);
+            return;
+        }
+
+        if self.config.flatten && parent == ROOT_INO {
+            api_client::begin_request(self.identity(req));
+            self.lookup_flattened(name_str, reply);
+            return;
+        }
+
+        let parent_path = {
+            let mut mapper = self.inode_mapper.lock().unwrap();
+            match mapper.get_path(parent) {
+                Some(p) => p.clone(),
+                None => {
+                    error!("Parent inode {} not found", parent);
+                    reply.error(ENOENT);
+                    return;
+                }
+            }
+        };
+
+        let child_path = join_path(&parent_path, name_str);
+        if is_hidden(&child_path, &self.config.hide_prefixes) {
+            reply.error(ENOENT);
+            return;
+        }
+
+        let request_id = api_client::begin_request(self.identity(req));
+        debug!(
+            "lookup: parent={} name={} -> {} request_id={}",
+            parent, name_str, child_path, request_id
+        );
+
+        if let Some(cached_attrs) = self.get_cached_attrs(&child_path) {
+            let attr = self.convert_attrs(&child_path, cached_attrs);
+            self.reply_entry(&attr, reply);
+            return;
+        }
+
+        if self.is_known_missing(&child_path) {
+            debug!("Negative cache hit for lookup: {}", child_path);
+            reply.error(ENOENT);
+            return;
+        }
+
+        if !self.api_health.lock().unwrap().allow_request() {
+            debug!("Circuit open, failing lookup for {} fast", child_path);
+            reply.error(libc::EIO);
+            return;
+        }
+
+        // The kernel only ever asks for one component, so a lookup below a
+        // directory we hold no fresh attributes for (its dentry outlived our
+        // cache) means the ancestors are stale too; refresh the whole chain
+        // in one call instead of one round trip per level
+        if parent != ROOT_INO
+            && self.path_chain_supported.load(Ordering::Relaxed)
+            && !self.has_fresh_attrs(&parent_path)
+        {
+            match self.prime_path_chain(&child_path) {
+                Some(Ok(api_attrs)) => {
+                    let attr = self.convert_attrs(&child_path, api_attrs);
+                    self.reply_entry(&attr, reply);
+                    return;
+                }
+                Some(Err(errno)) => {
+                    reply.error(errno);
+                    return;
+                }
+                None => {}
+            }
+        }
+
+        // Probing with the cheaper exists() first saves a full attribute
+        // fetch on misses, at the cost of an extra round trip on hits
+        if self.config.lookup_exists_first {
+            match self.api.exists(&child_path) {
+                Ok(true) => {}
+                Ok(false) => {
+                    self.api_health.lock().unwrap().record_success();
+                    self.cache_missing(&child_path);
+                    reply.error(ENOENT);
+                    return;
+                }
+                Err(e) => {
+                    self.record_api_error(&e, format!("exists failed for {}: {}", child_path, e));
+                    debug!("exists failed for {}: {}", child_path, e);
+                    reply.error(e.errno());
+                    return;
+                }
+            }
+        }
+
+        match self.fetch_attrs(&child_path) {
+            Ok(api_attrs) => {
+                self.api_health.lock().unwrap().record_success();
+                let attr = self.convert_attrs(&child_path, api_attrs);
+                self.reply_entry(&attr, reply);
+            }
+            Err(e) => {
+                self.record_api_error(&e, format!("lookup failed for {}: {}", child_path, e));
+                debug!("lookup failed for {}: {}", child_path, e);
+                reply.error(e.errno());
+            }
+        }
+    }
+
+    fn getattr(&mut self, req: &Request, ino: u64, reply: ReplyAttr) {
+        if ino == ERROR_FILE_INO {
+            let is_unhealthy = self.health_status.is_unhealthy();
+            if is_unhealthy {
+                let attr = self.get_error_file_attrs();
+                reply.attr(&self.config.attr_ttl, &attr);
+                return;
+            } else {
+                reply.error(ENOENT);
+                return;
+            }
+        }
+
+        if ino == STATS_FILE_INO {
+            if self.config.stats_file.is_some() {
+                reply.attr(&Duration::ZERO, &self.get_stats_file_attrs());
+            } else {
+                reply.error(ENOENT);
+            }
+            return;
+        }
+
+        if ino == TRUNCATED_FILE_INO {
+            if self.config.max_dir_entries.is_some() {
+                reply.attr(&self.config.attr_ttl, &self.get_truncated_file_attrs());
+            } else {
+                reply.error(ENOENT);
+            }
+            return;
+        }
+
+        if ino == INFO_FILE_INO {
+            if self.info_content.is_some() {
+                reply.attr(&self.config.attr_ttl, &self.get_info_file_attrs());
+            } else {
+                reply.error(ENOENT);
+            }
+            return;
+        }
+
+        if ino == CONTROL_FILE_INO {
+            if self.config.control_file {
+                reply.attr(&Duration::ZERO, &self.get_control_file_attrs());
+            } else {
+                reply.error(ENOENT);
+            }
+            return;
+        }
+
+        let path = {
+            let mut mapper = self.inode_mapper.lock().unwrap();
+            match mapper.get_path(ino) {
+                Some(p) => p.clone(),
+                None => {
+                    error!("Inode {} not found", ino);
+                    reply.error(ENOENT);
+                    return;
+                }
+            }
+        };
+        if is_hidden(&path, &self.config.hide_prefixes) {
+            reply.error(ENOENT);
+            return;
+        }
+
+        let request_id = api_client::begin_request(self.identity(req));
+        debug!(
+            "getattr: ino={} path={} request_id={}",
+            ino, path, request_id
+        );
+
+        if let Some(cached_attrs) = self.get_cached_attrs(&path) {
+            let attr = self.convert_attrs(&path, cached_attrs);
+            reply.attr(&self.config.attr_ttl, &attr);
+            return;
+        }
+
+        if !self.api_health.lock().unwrap().allow_request() {
+            debug!("Circuit open, failing getattr for {} fast", path);
+            reply.error(libc::EIO);
+            return;
+        }
+
+        match self.fetch_attrs(&path) {
+            Ok(api_attrs) => {
+                self.api_health.lock().unwrap().record_success();
+                let attr = self.convert_attrs(&path, api_attrs);
+                reply.attr(&self.config.attr_ttl, &attr);
+            }
+            Err(e) => {
+                self.record_api_error(&e, format!("getattr failed for {}: {}", path, e));
+                error!("getattr failed for {}: {}", path, e);
+                reply.error(e.errno());
+            }
+        }
+    }
+
+    fn read(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock: Option<u64>,
+        reply: ReplyData,
+    ) {
+        if is_synthetic_ino(ino) {
+            let content = match ino {
+                ERROR_FILE_INO => self.error_content(),
+                STATS_FILE_INO => self.render_stats().into(),
+                INFO_FILE_INO => self.info_content().into(),
+                CONTROL_FILE_INO => self.render_control().into(),
+                _ => self.truncated_notice().into(),
+            };
+            let content_bytes = content.as_bytes();
+            let offset = offset as usize;
+            let size = size as usize;
+
+            if offset >= content_bytes.len() {
+                reply.data(&[]);
+                return;
+            }
+
+            let end = std::cmp::min(offset + size, content_bytes.len());
+            reply.data(&content_bytes[offset..end]);
+            return;
+        }
+
+        let path = {
+            let mut mapper = self.inode_mapper.lock().unwrap();
+            match mapper.get_path(ino) {
+                Some(p) => p.clone(),
+                None => {
+                    error!("Inode {} not found", ino);
+                    reply.error(ENOENT);
+                    return;
+                }
+            }
+        };
+
+        let request_id = api_client::begin_request(self.identity(req));
+        debug!(
+            "read: ino={} path={} offset={} size={} request_id={}",
+            ino, path, offset, size, request_id
+        );
+
+        if !self.api_health.lock().unwrap().allow_request() {
+            debug!("Circuit open, failing read for {} fast", path);
+            reply.error(libc::EIO);
+            return;
+        }
+
+        if self.config.pretty_json {
+            let pretty = self.resolve_attrs(&path).ok().and_then(|attrs| {
+                self.with_pretty_json(&path, &attrs, |data| {
+                    let start = std::cmp::min(offset as usize, data.len());
+                    let end = std::cmp::min(start + size as usize, data.len());
+                    data[start..end].to_vec()
+                })
+            });
+            if let Some(data) = pretty {
+                reply.data(&data);
+                self.notify_access(&path);
+                return;
+            }
+        }
+
+        match self.read_range(&path, offset as usize, size as usize, Some(fh)) {
+            Ok(data) => {
+                reply.data(&data);
+                self.notify_access(&path);
+            }
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    /// Targets are returned exactly as core stores them. With confinement
+    /// on, ones that would resolve outside the mount are refused; this is
+    /// advisory only, since the kernel resolves links and a target can
+    /// still be reached by other means.
+    fn readlink(&mut self, req: &Request, ino: u64, reply: ReplyData) {
+        let path = match self.inode_mapper.lock().unwrap().get_path(ino) {
+            Some(p) => p.clone(),
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+
+        let request_id = api_client::begin_request(self.identity(req));
+        debug!(
+            "readlink: ino={} path={} request_id={}",
+            ino, path, request_id
+        );
+
+        if !self.api_health.lock().unwrap().allow_request() {
+            debug!("Circuit open, failing readlink for {} fast", path);
+            reply.error(libc::EIO);
+            return;
+        }
+
+        match self.api.readlink(&path) {
+            Ok(target) => {
+                self.api_health.lock().unwrap().record_success();
+                if let Some(ref mountpoint) = self.config.confine_symlinks {
+                    if !symlink_is_confined(&path, &target, mountpoint) {
+                        warn!(
+                            "Refusing symlink {} -> {}, it points outside the mount",
+                            path, target
+                        );
+                        reply.error(libc::EACCES);
+                        return;
+                    }
+                }
+                reply.data(target.as_bytes());
+            }
+            Err(e) => {
+                self.record_api_error(&e, format!("readlink failed for {}: {}", path, e));
+                error!("readlink failed for {}: {}", path, e);
+                reply.error(e.errno());
+            }
+        }
+    }
+
+    /// SEEK_DATA and SEEK_HOLE for sparse files; the kernel handles the
+    /// other whence values itself. Without core support every file is
+    /// treated as data from start to end.
+    fn lseek(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        whence: i32,
+        reply: ReplyLseek,
+    ) {
+        if whence != libc::SEEK_DATA && whence != libc::SEEK_HOLE {
+            reply.error(libc::EINVAL);
+            return;
+        }
+        let Ok(offset) = u64::try_from(offset) else {
+            reply.error(libc::ENXIO);
+            return;
+        };
+        let hole = whence == libc::SEEK_HOLE;
+
+        let (path, size) = if is_synthetic_ino(ino) {
+            let attrs = match ino {
+                ERROR_FILE_INO => self.get_error_file_attrs(),
+                STATS_FILE_INO => self.get_stats_file_attrs(),
+                INFO_FILE_INO => self.get_info_file_attrs(),
+                CONTROL_FILE_INO => self.get_control_file_attrs(),
+                _ => self.get_truncated_file_attrs(),
+            };
+            (None, attrs.size)
+        } else {
+            let path = match self.inode_mapper.lock().unwrap().get_path(ino) {
+                Some(p) => p.clone(),
+                None => {
+                    reply.error(ENOENT);
+                    return;
+                }
+            };
+            let request_id = api_client::begin_request(self.identity(req));
+            debug!(
+                "lseek: ino={} path={} offset={} whence={} request_id={}",
+                ino, path, offset, whence, request_id
+            );
+            match self.resolve_attrs(&path) {
+                Ok(attrs) => (Some(path), attrs.size),
+                Err(errno) => {
+                    reply.error(errno);
+                    return;
+                }
+            }
+        };
+
+        if offset >= size {
+            reply.error(libc::ENXIO);
+            return;
+        }
+
+        match self.seek_offset(path.as_deref(), offset, size, hole) {
+            Ok(found) => reply.offset(found as i64),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn access(&mut self, req: &Request, ino: u64, mask: i32, reply: ReplyEmpty) {
+        if mask & libc::W_OK != 0 {
+            if let Some(errno) = self.write_refusal(ino, req.uid()) {
+                reply.error(errno);
+                return;
+            }
+        }
+        if !self.config.use_api_perms || mask == libc::F_OK {
+            reply.ok();
+            return;
+        }
+
+        if is_synthetic_ino(ino) {
+            let denied = if ino == CONTROL_FILE_INO {
+                if self.control_writer_allowed(req.uid()) {
+                    libc::X_OK
+                } else {
+                    libc::R_OK | libc::W_OK | libc::X_OK
+                }
+            } else {
+                libc::W_OK | libc::X_OK
+            };
+            if mask & denied != 0 {
+                reply.error(libc::EACCES);
+            } else {
+                reply.ok();
+            }
+            return;
+        }
+
+        let path = {
+            let mut mapper = self.inode_mapper.lock().unwrap();
+            match mapper.get_path(ino) {
+                Some(p) => p.clone(),
+                None => {
+                    error!("Inode {} not found", ino);
+                    reply.error(ENOENT);
+                    return;
+                }
+            }
+        };
+
+        let request_id = api_client::begin_request(self.identity(req));
+        debug!(
+            "access: ino={} path={} mask={:o} request_id={}",
+            ino, path, mask, request_id
+        );
+
+        match self.resolve_attrs(&path) {
+            Ok(attrs) => {
+                // Judged against the owner stat shows, so access agrees
+                // with what the caller sees
+                let owner = self.presented_owner(&attrs);
+                let groups = || supplementary_groups(req.pid());
+                if access_permitted(&attrs, owner, req.uid(), req.gid(), groups, mask) {
+                    reply.ok();
+                } else {
+                    reply.error(libc::EACCES);
+                }
+            }
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn opendir(&mut self, req: &Request, ino: u64, _flags: i32, reply: ReplyOpen) {
+        let path = {
+            let mut mapper = self.inode_mapper.lock().unwrap();
+            match mapper.get_path(ino) {
+                Some(p) => p.clone(),
+                None => {
+                    error!("Inode {} not found", ino);
+                    reply.error(ENOENT);
+                    return;
+                }
+            }
+        };
+        if is_hidden(&path, &self.config.hide_prefixes) {
+            reply.error(ENOENT);
+            return;
+        }
+
+        let request_id = api_client::begin_request(self.identity(req));
+        match self.open_dir_handle(ino, &path) {
+            Ok(handle) => {
+                let fh = self.next_fh.fetch_add(1, Ordering::Relaxed);
+                debug!(
+                    "opendir: ino={} path={} fh={} entries={} request_id={}",
+                    ino,
+                    path,
+                    fh,
+                    handle.listing.len(),
+                    request_id
+                );
+                self.dir_handles.lock().unwrap().insert(fh, handle);
+                reply.opened(fh, 0);
+            }
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn readdir(&mut self, req: &Request, ino: u64, fh: u64, offset: i64, reply: ReplyDirectory) {
+        let request_id = api_client::begin_request(self.identity(req));
+        debug!(
+            "readdir: ino={} fh={} offset={} request_id={}",
+            ino, fh, offset, request_id
+        );
+
+        // The handle is taken out while pages are fetched, so the lock is
+        // never held across a network call, and stored again afterwards
+        let taken = self.dir_handles.lock().unwrap().remove(&fh);
+        let mut handle = match taken {
+            Some(handle) => handle,
+            None => {
+                // No handle from opendir; open one now so later calls for
+                // this fh resume from it instead of re-resolving everything
+                let path = {
+                    let mut mapper = self.inode_mapper.lock().unwrap();
+                    match mapper.get_path(ino) {
+                        Some(p) => p.clone(),
+                        None => {
+                            error!("Inode {} not found", ino);
+                            reply.error(ENOENT);
+                            return;
+                        }
+                    }
+                };
+                match self.open_dir_handle(ino, &path) {
+                    Ok(handle) => handle,
+                    Err(errno) => {
+                        reply.error(errno);
+                        return;
+                    }
+                }
+            }
+        };
+        self.reply_dir_entries(&mut handle, offset, reply);
+        self.dir_handles.lock().unwrap().insert(fh, handle);
+    }
+
+    fn releasedir(&mut self, _req: &Request, _ino: u64, fh: u64, _flags: i32, reply: ReplyEmpty) {
+        self.dir_handles.lock().unwrap().remove(&fh);
+        reply.ok();
+    }
+
+    /// The mount is read-only, so there is never anything to flush; report
+    /// success rather than ENOSYS so careful writers don't fail on close
+    fn fsync(&mut self, _req: &Request, ino: u64, _fh: u64, _datasync: bool, reply: ReplyEmpty) {
+        debug!("fsync: ino={} (read-only, nothing to flush)", ino);
+        reply.ok();
+    }
+
+    fn fsyncdir(&mut self, _req: &Request, ino: u64, _fh: u64, _datasync: bool, reply: ReplyEmpty) {
+        debug!("fsyncdir: ino={} (read-only, nothing to flush)", ino);
+        reply.ok();
+    }
+
+    // The mount has no write support beyond the control file, so every
+    // other modifying operation fails with EROFS rather than fuser's default
+    // ENOSYS, which tools report as "not supported" instead of "read-only
+    // file system"
+
+    fn setattr(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        _mode: Option<u32>,
+        _uid: Option<u32>,
+        _gid: Option<u32>,
+        _size: Option<u64>,
+        _atime: Option<TimeOrNow>,
+        _mtime: Option<TimeOrNow>,
+        _ctime: Option<SystemTime>,
+        _fh: Option<u64>,
+        _crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
+        _flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        // Shells truncate before writing a command; there is nothing to cut
+        match self.write_refusal(ino, req.uid()) {
+            None => reply.attr(&Duration::ZERO, &self.get_control_file_attrs()),
+            Some(errno) => {
+                debug!("setattr: ino={} refused", ino);
+                reply.error(errno);
+            }
+        }
+    }
+
+    fn mknod(
+        &mut self,
+        _req: &Request,
+        parent: u64,
+        name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        _rdev: u32,
+        reply: ReplyEntry,
+    ) {
+        debug!("mknod: parent={}, name={:?} (read-only)", parent, name);
+        reply.error(EROFS);
+    }
+
+    fn mkdir(
+        &mut self,
+        _req: &Request,
+        parent: u64,
+        name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        reply: ReplyEntry,
+    ) {
+        debug!("mkdir: parent={}, name={:?} (read-only)", parent, name);
+        reply.error(EROFS);
+    }
+
+    fn unlink(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        debug!("unlink: parent={}, name={:?} (read-only)", parent, name);
+        reply.error(EROFS);
+    }
+
+    fn rmdir(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        debug!("rmdir: parent={}, name={:?} (read-only)", parent, name);
+        reply.error(EROFS);
+    }
+
+    fn symlink(
+        &mut self,
+        _req: &Request,
+        parent: u64,
+        link_name: &OsStr,
+        _target: &std::path::Path,
+        reply: ReplyEntry,
+    ) {
+        debug!(
+            "symlink: parent={}, name={:?} (read-only)",
+            parent, link_name
+        );
+        reply.error(EROFS);
+    }
+
+    fn rename(
+        &mut self,
+        _req: &Request,
+        parent: u64,
+        name: &OsStr,
+        _newparent: u64,
+        _newname: &OsStr,
+        _flags: u32,
+        reply: ReplyEmpty,
+    ) {
+        debug!("rename: parent={}, name={:?} (read-only)", parent, name);
+        reply.error(EROFS);
+    }
+
+    fn link(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _newparent: u64,
+        _newname: &OsStr,
+        reply: ReplyEntry,
+    ) {
+        debug!("link: ino={} (read-only)", ino);
+        reply.error(EROFS);
+    }
+
+    fn write(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        _fh: u64,
+        _offset: i64,
+        data: &[u8],
+        _write_flags: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        match self.write_refusal(ino, req.uid()) {
+            None => {
+                let command = String::from_utf8_lossy(data);
+                match self.run_control(command.trim()) {
+                    Ok(()) => reply.written(data.len() as u32),
+                    Err(errno) => reply.error(errno),
+                }
+            }
+            Some(libc::EACCES) => {
+                warn!("Control file: refusing a command from uid {}", req.uid());
+                reply.error(libc::EACCES);
+            }
+            Some(errno) => {
+                debug!("write: ino={} (read-only)", ino);
+                reply.error(errno);
+            }
+        }
+    }
+
+    fn create(
+        &mut self,
+        _req: &Request,
+        parent: u64,
+        name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        _flags: i32,
+        reply: ReplyCreate,
+    ) {
+        debug!("create: parent={}, name={:?} (read-only)", parent, name);
+        reply.error(EROFS);
+    }
+
+    /// Only the POSIX ACL xattrs exist; every other name has no value
+    fn getxattr(&mut self, req: &Request, ino: u64, name: &OsStr, size: u32, reply: ReplyXattr) {
+        let default = match name.to_str() {
+            Some(acl::ACCESS_XATTR) => false,
+            Some(acl::DEFAULT_XATTR) => true,
+            _ => {
+                reply.error(libc::ENODATA);
+                return;
+            }
+        };
+        let Some(path) = self.inode_mapper.lock().unwrap().get_path(ino).cloned() else {
+            // Synthetic files carry no ACLs
+            reply.error(if ino <= LAST_SYNTHETIC_INO {
+                libc::ENODATA
+            } else {
+                ENOENT
+            });
+            return;
+        };
+
+        let request_id = api_client::begin_request(self.identity(req));
+        debug!(
+            "getxattr: ino={} path={} name={:?} request_id={}",
+            ino, path, name, request_id
+        );
+
+        match self.acl_xattr(&path, default) {
+            Ok(Some(value)) => reply_xattr(reply, &value, size),
+            Ok(None) => reply.error(libc::ENODATA),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn listxattr(&mut self, req: &Request, ino: u64, size: u32, reply: ReplyXattr) {
+        let Some(path) = self.inode_mapper.lock().unwrap().get_path(ino).cloned() else {
+            if ino <= LAST_SYNTHETIC_INO {
+                reply_xattr(reply, &[], size);
+            } else {
+                reply.error(ENOENT);
+            }
+            return;
+        };
+
+        let request_id = api_client::begin_request(self.identity(req));
+        debug!(
+            "listxattr: ino={} path={} request_id={}",
+            ino, path, request_id
+        );
+
+        let mut names = Vec::new();
+        for (name, default) in [(acl::ACCESS_XATTR, false), (acl::DEFAULT_XATTR, true)] {
+            match self.acl_xattr(&path, default) {
+                Ok(Some(_)) => {
+                    names.extend_from_slice(name.as_bytes());
+                    names.push(0);
+                }
+                Ok(None) => {}
+                Err(errno) => {
+                    reply.error(errno);
+                    return;
+                }
+            }
+        }
+        reply_xattr(reply, &names, size);
+    }
+
+    /// ACLs included: the mount is read-only
+    fn setxattr(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _name: &OsStr,
+        _value: &[u8],
+        _flags: i32,
+        _position: u32,
+        reply: ReplyEmpty,
+    ) {
+        debug!("setxattr: ino={} (read-only)", ino);
+        reply.error(EROFS);
+    }
+
+    fn removexattr(&mut self, _req: &Request, ino: u64, _name: &OsStr, reply: ReplyEmpty) {
+        debug!("removexattr: ino={} (read-only)", ino);
+        reply.error(EROFS);
+    }
+
+    fn fallocate(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _fh: u64,
+        _offset: i64,
+        _length: i64,
+        _mode: i32,
+        reply: ReplyEmpty,
+    ) {
+        debug!("fallocate: ino={} (read-only)", ino);
+        reply.error(EROFS);
+    }
+
+    fn copy_file_range(
+        &mut self,
+        _req: &Request,
+        _ino_in: u64,
+        _fh_in: u64,
+        _offset_in: i64,
+        ino_out: u64,
+        _fh_out: u64,
+        _offset_out: i64,
+        _len: u64,
+        _flags: u32,
+        reply: ReplyWrite,
+    ) {
+        debug!("copy_file_range: ino_out={} (read-only)", ino_out);
+        reply.error(EROFS);
+    }
+}
+
+/// Start the thread that sends access notifications, one at a time, so a
+/// slow core costs a bounded queue rather than a thread per read
+fn spawn_atime_worker(api: ApiClient) -> Option<SyncSender<AtimeNotice>> {
+    let (sender, receiver) = mpsc::sync_channel::<AtimeNotice>(ATIME_NOTIFY_QUEUE);
+    let spawned = std::thread::Builder::new()
+        .name("fuse-atime".to_string())
+        .spawn(move || {
+            // Ends once the filesystem, and with it the sender, is dropped
+            for (identity, path) in receiver {
+                api_client::begin_request(identity);
+                if let Err(e) = api.notify_access(&path) {
+                    debug!("Access notification for {} failed: {}", path, e);
+                }
+            }
+        });
+    match spawned {
+        Ok(_) => Some(sender),
+        Err(e) => {
+            warn!(
+                "Failed to start access notifications, leaving them off: {}",
+                e
+            );
+            None
+        }
+    }
+}
+
+/// Run the startup health check, retrying with backoff until `limit` runs
+/// out so the driver can start before core does
+fn wait_for_api(
+    api: &ApiClient,
+    limit: Option<Duration>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let started = Instant::now();
+    let mut delay = WAIT_FOR_API_INITIAL_DELAY;
+    loop {
+        let failure: Box<dyn std::error::Error> = match api.health_check() {
+            Ok(true) => return Ok(()),
+            Ok(false) => "API health check failed".into(),
+            Err(e) => e.into(),
+        };
+
+        let remaining = limit
+            .and_then(|limit| limit.checked_sub(started.elapsed()))
+            .filter(|remaining| !remaining.is_zero());
+        let Some(remaining) = remaining else {
+            return Err(failure);
+        };
+
+        info!(
+            "Waiting for the API ({}), retrying in {:.1}s, giving up in {}s",
+            failure,
+            delay.min(remaining).as_secs_f64(),
+            remaining.as_secs()
+        );
+        std::thread::sleep(delay.min(remaining));
+        delay = (delay * 2).min(WAIT_FOR_API_MAX_DELAY);
+    }
+}
+
+/// Answer an xattr request: the value's size when `size` is 0, else the
+/// value itself if it fits
+fn reply_xattr(reply: ReplyXattr, value: &[u8], size: u32) {
+    if size == 0 {
+        reply.size(value.len() as u32);
+    } else if value.len() > size as usize {
+        reply.error(libc::ERANGE);
+    } else {
+        reply.data(value);
+    }
+}
+
+/// Text of the info file: what the mount is and where its data comes from
+fn render_info(api_url: &str, config: &FsConfig, mounted_at: SystemTime) -> String {
+    let mounted_at = mounted_at
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let root = config.root_prefix.as_deref().unwrap_or("/");
+
+    format!(
+        "Meta-Fuse FUSE Driver - Mount Information\n\
+        =========================================\n\n\
+        This is a read-only view of a meta-fuse-core virtual filesystem.\n\n\
+        Driver version: {}\n\
+        API URL: {}\n\
+        API directory shown as the root: {}\n\
+        File ownership: uid={}, gid={}\n\
+        Mounted at: {} (Unix time)\n\n\
+        Check API health: curl {}/api/fuse/health\n",
+        env!("CARGO_PKG_VERSION"),
+        api_url,
+        root,
+        config.uid,
+        config.gid,
+        mounted_at,
+        api_url.trim_end_matches('/')
+    )
+}
+
+/// Whether an inode is one of the files the driver generates itself
+fn is_synthetic_ino(ino: u64) -> bool {
+    ino != ROOT_INO && ino <= LAST_SYNTHETIC_INO
+}
+
+/// Whether a MIME type denotes JSON, e.g. application/json or
+/// application/ld+json; parameters such as charset are ignored
+fn is_json_type(content_type: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    essence == "application/json" || essence.ends_with("+json")
+}
+
+/// Classify the S_IFMT bits of an API mode into a FUSE file type
+pub fn file_type_from_mode(mode: u32) -> FileType {
+    match mode & libc::S_IFMT {
+        libc::S_IFDIR => FileType::Directory,
+        libc::S_IFCHR => FileType::CharDevice,
+        libc::S_IFBLK => FileType::BlockDevice,
+        libc::S_IFIFO => FileType::NamedPipe,
+        libc::S_IFSOCK => FileType::Socket,
+        libc::S_IFLNK => FileType::Symlink,
+        _ => FileType::RegularFile,
+    }
+}
+
+/// Whether open(2) flags ask to modify the file. O_TRUNC truncates even
+/// with O_RDONLY, so it is a write intent too.
+fn opens_for_writing(flags: i32) -> bool {
+    flags & libc::O_ACCMODE != libc::O_RDONLY || flags & libc::O_TRUNC != 0
+}
+
+/// Empty a cache, returning how many entries it held
+fn take_len<K, V>(cache: &Mutex<HashMap<K, V>>) -> usize {
+    let mut cache = cache.lock().unwrap();
+    let len = cache.len();
+    cache.clear();
+    len
+}
+
+/// Run an I/O call again for as long as a signal interrupts it
+pub fn retry_interrupted<T>(mut op: impl FnMut() -> std::io::Result<T>) -> std::io::Result<T> {
+    loop {
+        match op() {
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            result => return result,
+        }
+    }
+}
+
+/// The range of whole `align`-sized blocks covering `size` bytes at
+/// `offset`, as (start, len). The end is not rounded past the file's size
+/// unless the request itself reaches beyond it.
+fn aligned_range(offset: usize, size: usize, align: usize, file_size: u64) -> (usize, usize) {
+    let start = offset / align * align;
+    let end = (offset + size).div_ceil(align) * align;
+    let end = std::cmp::min(end, std::cmp::max(file_size as usize, offset + size));
+    (start, end - start)
+}
+
+/// Canonical form of a virtual path, used as every cache and inode key.
+///
+/// Collapses repeated slashes and drops a trailing one, so "//a/b/" and
+/// "/a/b" name the same entry; anything empty becomes the root. "." and
+/// ".." are left alone: the kernel resolves them before asking, and
+/// entry_name refuses them from core.
+pub fn normalize_path(path: &str) -> String {
+    let mut normalized = String::with_capacity(path.len() + 1);
+    for component in path.split('/').filter(|c| !c.is_empty()) {
+        normalized.push('/');
+        normalized.push_str(component);
+    }
+    if normalized.is_empty() {
+        normalized.push('/');
+    }
+    normalized
+}
+
+/// Whether a symlink at `link_path` with target `target` resolves inside
+/// the mount at `mountpoint`: a relative target must not climb above the
+/// root, and an absolute one, being resolved against the host root, must
+/// lie under the mountpoint.
+fn symlink_is_confined(link_path: &str, target: &str, mountpoint: &std::path::Path) -> bool {
+    let (start_depth, rest) = if target.starts_with('/') {
+        match std::path::Path::new(target).strip_prefix(mountpoint) {
+            Ok(rest) => (0, rest.to_string_lossy().into_owned()),
+            Err(_) => return false,
+        }
+    } else {
+        // The link's own directory is where a relative target starts from
+        let parent_depth = normalize_path(link_path)
+            .split('/')
+            .filter(|c| !c.is_empty())
+            .count()
+            .saturating_sub(1);
+        (parent_depth, target.to_string())
+    };
+
+    let mut depth = start_depth;
+    for component in rest.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => match depth.checked_sub(1) {
+                Some(up) => depth = up,
+                None => return false,
+            },
+            _ => depth += 1,
+        }
+    }
+    true
+}
+
+/// Validate a directory entry name from the API, dropping the trailing slash
+/// some backends put on directories.
+///
+/// Names are UTF-8 only (the API speaks JSON) and must be a single path
+/// component: empty names, "." and "..", and any containing a slash or NUL
+/// are refused, since the kernel could never look them up.
+pub fn entry_name(raw: &str) -> Option<&str> {
+    let name = raw.trim_end_matches('/');
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\0']) {
+        return None;
+    }
+    Some(name)
+}
+
+/// Split a normalized path into its parent directory and final name
+fn split_parent(path: &str) -> (&str, &str) {
+    match path.rsplit_once('/') {
+        Some(("", name)) => ("/", name),
+        Some((parent, name)) => (parent, name),
+        None => ("/", path),
+    }
+}
+
+/// Parse a comma-separated FUSE_HIDE_PREFIXES list into normalized paths.
+/// A bare "/" would hide the whole mount, so it is ignored.
+fn parse_hide_prefixes(spec: &str) -> Vec<String> {
+    let mut prefixes = Vec::new();
+    for token in spec.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+        let prefix = normalize_path(token);
+        if prefix == "/" {
+            warn!(
+                "Ignoring hide prefix {:?}: it would hide the whole mount",
+                token
+            );
+        } else if !prefixes.contains(&prefix) {
+            prefixes.push(prefix);
+        }
+    }
+    prefixes
+}
+
+/// Whether a normalized path is, or lies under, one of the hidden prefixes.
+/// Matching is by whole components, so "/secret" leaves "/secretsauce" alone.
+fn is_hidden(path: &str, prefixes: &[String]) -> bool {
+    prefixes.iter().any(|prefix| {
+        path.strip_prefix(prefix.as_str())
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    })
+}
+
+/// Build the normalized virtual path of a child entry, so a directory core
+/// lists as "name/" is keyed the same as the bare name lookup sees
+pub fn join_path(parent: &str, name: &str) -> String {
+    normalize_path(&format!("{}/{}", parent, name))
+}
+
+/// Check an access(2) mask against ownership and API-reported permission
+/// bits. `groups` yields the caller's supplementary groups, and is only
+/// called when the group class might apply.
+fn access_permitted(
+    attrs: &api_client::FileAttributes,
+    (owner_uid, owner_gid): (u32, u32),
+    uid: u32,
+    gid: u32,
+    groups: impl FnOnce() -> Vec<u32>,
+    mask: i32,
+) -> bool {
+    let wanted = (mask & (libc::R_OK | libc::W_OK | libc::X_OK)) as u32;
+    let perm = attrs.perm_bits();
+
+    // root bypasses read/write checks, and execute checks on directories,
+    // but a file still needs some execute bit
+    if uid == 0 {
+        return mask & libc::X_OK == 0
+            || file_type_from_mode(attrs.mode) == FileType::Directory
+            || perm & 0o111 != 0;
+    }
+
+    let granted = if uid == owner_uid {
+        (perm >> 6) & 0o7
+    } else if gid == owner_gid || groups().contains(&owner_gid) {
+        (perm >> 3) & 0o7
+    } else {
+        perm & 0o7
+    };
+
+    granted & wanted == wanted
+}
+
+/// Supplementary groups of a process, from /proc/<pid>/status; none if it
+/// can't be read, e.g. once the process has exited
+fn supplementary_groups(pid: u32) -> Vec<u32> {
+    std::fs::read_to_string(format!("/proc/{}/status", pid))
+        .map(|status| status_groups(&status))
+        .unwrap_or_default()
+}
+
+/// The ids on the Groups: line of a /proc/<pid>/status
+fn status_groups(status: &str) -> Vec<u32> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("Groups:"))
+        .map(|groups| {
+            groups
+                .split_whitespace()
+                .filter_map(|id| id.parse().ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Mount options every mount starts with, before FUSE_MOUNT_OPTIONS.
+///
+/// fuser adds allow_other on its own whenever auto_unmount is set without
+/// allow_other or allow_root, which unprivileged users without
+/// user_allow_other in /etc/fuse.conf cannot mount with. So auto_unmount
+/// comes only with allow_other; without it, a driver that dies leaves the
+/// mountpoint stale until `fusermount -u` clears it.
+fn base_mount_options(allow_other: bool, control_file: bool) -> Vec<MountOption> {
+    let mut options = vec![MountOption::FSName("meta-fuse".to_string())];
+    // A read-only mount would stop writes to the control file in the
+    // kernel; every other inode still refuses them with EROFS, through
+    // ApiFS::write_refusal
+    if !control_file {
+        options.push(MountOption::RO);
+    }
+    if allow_other {
+        options.push(MountOption::AllowOther);
+        options.push(MountOption::AutoUnmount);
+    }
+    options
+}
+
+/// Translate a comma-separated mount option list into fuser options.
+///
+/// Unknown tokens are logged and skipped. The mount is always read-only, so
+/// "rw" is rejected the same way. With the control file enabled "ro" is
+/// dropped too, since the kernel would then refuse writes to it.
+fn parse_mount_options(spec: &str, control_file: bool) -> Vec<MountOption> {
+    let mut options = Vec::new();
+
+    for token in spec.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+        let option = match token {
+            "allow_other" => MountOption::AllowOther,
+            "allow_root" => MountOption::AllowRoot,
+            "auto_unmount" => MountOption::AutoUnmount,
+            "default_permissions" => MountOption::DefaultPermissions,
+            "dev" => MountOption::Dev,
+            "nodev" => MountOption::NoDev,
+            "suid" => MountOption::Suid,
+            "nosuid" => MountOption::NoSuid,
+            "exec" => MountOption::Exec,
+            "noexec" => MountOption::NoExec,
+            "atime" => MountOption::Atime,
+            "noatime" => MountOption::NoAtime,
+            "dirsync" => MountOption::DirSync,
+            "sync" => MountOption::Sync,
+            "async" => MountOption::Async,
+            "ro" if control_file => {
+                warn!("Ignoring ro in FUSE_MOUNT_OPTIONS: it would block the control file, and the driver refuses every other write anyway");
+                continue;
+            }
+            "ro" => MountOption::RO,
+            _ => {
+                if let Some(name) = token.strip_prefix("fsname=") {
+                    MountOption::FSName(name.to_string())
+                } else if let Some(name) = token.strip_prefix("subtype=") {
+                    MountOption::Subtype(name.to_string())
+                } else {
+                    warn!("Ignoring unsupported mount option: {}", token);
+                    continue;
+                }
+            }
+        };
+        options.push(option);
+    }
+
+    options
+}
+
+/// Create the mountpoint and its parents if missing, then make sure it is an
+/// empty directory so the mount does not hide existing files.
+fn prepare_mountpoint(path: &std::path::Path, mode: u16) -> Result<(), String> {
+    use std::io::ErrorKind;
+    use std::os::unix::fs::DirBuilderExt;
+
+    if let Err(e) = std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(mode as u32)
+        .create(path)
+    {
+        return Err(match e.kind() {
+            ErrorKind::PermissionDenied => {
+                format!("permission denied creating mountpoint {}", path.display())
+            }
+            ErrorKind::NotFound => format!(
+                "cannot create mountpoint {}: a parent directory is missing",
+                path.display()
+            ),
+            _ => format!("cannot create mountpoint {}: {}", path.display(), e),
+        });
+    }
+
+    let mut entries = std::fs::read_dir(path).map_err(|e| match e.kind() {
+        ErrorKind::PermissionDenied => {
+            format!("permission denied reading mountpoint {}", path.display())
+        }
+        _ => format!("cannot read mountpoint {}: {}", path.display(), e),
+    })?;
+    if entries.next().is_some() {
+        return Err(format!("mountpoint {} is not empty", path.display()));
+    }
+    Ok(())
+}
+
+fn env_parse<T: std::str::FromStr>(name: &str) -> Option<T> {
+    std::env::var(name).ok().and_then(|v| v.parse().ok())
+}
+
+fn env_flag(name: &str) -> Option<bool> {
+    std::env::var(name).ok().map(|v| v == "1")
+}
+
+fn env_octal(name: &str) -> Option<u16> {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| u16::from_str_radix(&v, 8).ok())
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let program = args
+        .first()
+        .map(String::as_str)
+        .unwrap_or("meta-fuse-driver");
+
+    let (cli, check, export, benchmark) = match cli::parse(args.get(1..).unwrap_or_default()) {
+        Ok(cli::Command::Mount(cli)) => (cli, false, None, None),
+        Ok(cli::Command::Check(cli)) => (cli, true, None, None),
+        Ok(cli::Command::Export(cli, archive)) => (cli, false, Some(archive), None),
+        Ok(cli::Command::Benchmark(cli, path, json)) => (cli, false, None, Some((path, json))),
+        Ok(cli::Command::Help) => {
+            print!("{}", cli::usage(program));
+            return;
+        }
+        Ok(cli::Command::Version) => {
+            println!("meta-fuse-driver {}", env!("CARGO_PKG_VERSION"));
+            return;
+        }
+        Err(e) => {
+            eprintln!("Error: {}\n", e);
+            eprint!("{}", cli::usage(program));
+            std::process::exit(1);
+        }
+    };
+
+    let expand = |raw: &str| {
+        cli::expand_path(raw).unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        })
+    };
+
+    // A file named on the command line must exist; one from FUSE_CONFIG,
+    // often set for a whole fleet, may be missing
+    let config_path = match cli.config_path.clone() {
+        Some(path) => Some((path, true)),
+        None => std::env::var("FUSE_CONFIG").ok().map(|path| (path, false)),
+    }
+    .map(|(path, required)| (expand(&path), required));
+
+    let file_config = match config_path {
+        Some((ref path, required)) => {
+            config::FileConfig::load(std::path::Path::new(path), required).unwrap_or_else(|e| {
+                eprintln!("Error: Invalid config file {}", e);
+                std::process::exit(1);
+            })
+        }
+        None => config::FileConfig::default(),
+    };
+
+    let log_file = std::env::var("FUSE_LOG_FILE")
+        .ok()
+        .or(file_config.log_file.clone())
+        .filter(|path| !path.is_empty())
+        .map(|path| std::path::PathBuf::from(expand(&path)));
+
+    let mut logger = env_logger::Builder::from_default_env();
+    if let Some(ref path) = log_file {
+        match std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+        {
+            Ok(file) => {
+                logger.target(env_logger::Target::Pipe(Box::new(file)));
+            }
+            Err(e) => {
+                eprintln!("Error: Cannot open log file {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        }
+    }
+    logger.init();
+
+    let mountpoint = &expand(&cli.mountpoint);
+    let api_url = cli
+        .api_url
+        .clone()
+        .or_else(|| std::env::var("FUSE_API_URL").ok())
+        .or(file_config.api_url)
+        .unwrap_or_else(|| "http://localhost:3000".to_string());
+
+    let uid: u32 = cli
+        .uid
+        .or_else(|| env_parse("PUID"))
+        .or(file_config.uid)
+        .unwrap_or(1000);
+
+    let gid: u32 = cli
+        .gid
+        .or_else(|| env_parse("PGID"))
+        .or(file_config.gid)
+        .unwrap_or(1000);
+
+    let file_perm: u16 = cli
+        .file_perm
+        .or_else(|| env_octal("FUSE_FILE_PERM"))
+        .or(file_config.file_perm)
+        .unwrap_or(0o755);
+
+    let dir_perm: u16 = cli
+        .dir_perm
+        .or_else(|| env_octal("FUSE_DIR_PERM"))
+        .or(file_config.dir_perm)
+        .unwrap_or(0o755);
+
+    let attr_ttl = env_parse("FUSE_ATTR_TTL_SECS")
+        .or(file_config.attr_ttl_secs)
+        .map(Duration::from_secs)
+        .unwrap_or(TTL);
+
+    let cache_ttl = env_parse("FUSE_CACHE_TTL_SECS")
+        .or(file_config.cache_ttl_secs)
+        .map(Duration::from_secs)
+        .unwrap_or(CACHE_TTL);
+
+    let cache_ttl = CacheTtl {
+        base: cache_ttl,
+        jitter_pct: env_parse("FUSE_CACHE_TTL_JITTER_PCT")
+            .or(file_config.cache_ttl_jitter_pct)
+            .map(|pct: u32| pct.min(100))
+            .unwrap_or(DEFAULT_CACHE_TTL_JITTER_PCT),
+        adaptive_max: env_parse("FUSE_ADAPTIVE_TTL_MAX_SECS")
+            .or(file_config.adaptive_ttl_max_secs)
+            .filter(|&secs| secs > 0)
+            .map(Duration::from_secs),
+    };
+
+    let request_timeout = env_parse("FUSE_REQUEST_TIMEOUT_SECS")
+        .or(file_config.request_timeout_secs)
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_REQUEST_TIMEOUT);
+
+    let request_timeouts = RequestTimeouts {
+        global: request_timeout,
+        getattr: env_parse("FUSE_GETATTR_TIMEOUT_SECS")
+            .or(file_config.getattr_timeout_secs)
+            .map(Duration::from_secs),
+        readdir: env_parse("FUSE_READDIR_TIMEOUT_SECS")
+            .or(file_config.readdir_timeout_secs)
+            .map(Duration::from_secs),
+        read: env_parse("FUSE_READ_TIMEOUT_SECS")
+            .or(file_config.read_timeout_secs)
+            .map(Duration::from_secs),
+        adaptive: env_parse("FUSE_TIMEOUT_MULTIPLIER")
+            .or(file_config.timeout_multiplier)
+            .filter(|&multiplier: &f64| multiplier > 0.0)
+            .map(|multiplier| latency::AdaptiveTimeout {
+                multiplier,
+                floor: env_parse("FUSE_TIMEOUT_FLOOR_MS")
+                    .or(file_config.timeout_floor_ms)
+                    .map(Duration::from_millis)
+                    .unwrap_or(DEFAULT_TIMEOUT_FLOOR),
+                ceiling: env_parse("FUSE_TIMEOUT_CEILING_SECS")
+                    .or(file_config.timeout_ceiling_secs)
+                    .map(Duration::from_secs)
+                    .unwrap_or(DEFAULT_TIMEOUT_CEILING),
+            }),
+    };
+
+    let webdav_timeout = env_parse("FUSE_WEBDAV_TIMEOUT_SECS")
+        .or(file_config.webdav_timeout_secs)
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_WEBDAV_TIMEOUT);
+
+    let error_threshold = env_parse("FUSE_ERROR_THRESHOLD")
+        .or(file_config.error_threshold)
+        .filter(|&n: &usize| n > 0)
+        .unwrap_or(DEFAULT_ERROR_THRESHOLD);
+
+    let error_window = env_parse("FUSE_ERROR_WINDOW_SECS")
+        .or(file_config.error_window_secs)
+        .filter(|&secs| secs > 0)
+        .map(Duration::from_secs);
+
+    let circuit_cooldown = env_parse("FUSE_CIRCUIT_COOLDOWN_SECS")
+        .or(file_config.circuit_cooldown_secs)
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_CIRCUIT_COOLDOWN);
+
+    let verify_checksums = env_flag("FUSE_VERIFY_CHECKSUMS")
+        .or(file_config.verify_checksums)
+        .unwrap_or(false);
+
+    let use_api_perms = env_flag("FUSE_USE_API_PERMS")
+        .or(file_config.use_api_perms)
+        .unwrap_or(false);
+
+    let use_api_ownership = env_flag("FUSE_USE_API_OWNERSHIP")
+        .or(file_config.use_api_ownership)
+        .unwrap_or(false);
+
+    let default_permissions = env_flag("FUSE_DEFAULT_PERMISSIONS")
+        .or(file_config.default_permissions)
+        .unwrap_or(false);
+
+    if default_permissions && !(use_api_perms && use_api_ownership) {
+        warn!("default_permissions is enabled without FUSE_USE_API_PERMS and FUSE_USE_API_OWNERSHIP; \
+               the kernel will enforce the configured uid/gid and perms, which may deny access unexpectedly");
+    }
+
+    // Unprivileged users may only pass allow_other when /etc/fuse.conf has
+    // user_allow_other, which rootless containers usually lack
+    let is_root = unsafe { libc::geteuid() } == 0;
+    let allow_other = env_flag("FUSE_ALLOW_OTHER")
+        .or(file_config.allow_other)
+        .unwrap_or(is_root);
+
+    let preload_depth = env_parse("FUSE_PRELOAD_DEPTH")
+        .or(file_config.preload_depth)
+        .unwrap_or(0);
+
+    let stats_file = env_flag("FUSE_STATS_FILE")
+        .or(file_config.stats_file)
+        .unwrap_or(false);
+
+    let stats_file = stats_file.then(|| {
+        let name = std::env::var("FUSE_STATS_FILE_NAME")
+            .ok()
+            .or(file_config.stats_file_name)
+            .unwrap_or_else(|| DEFAULT_STATS_FILE_NAME.to_string());
+        if name.is_empty() || name.contains('/') || name == "ERROR.txt" {
+            warn!(
+                "Invalid stats file name {:?}, using {}",
+                name, DEFAULT_STATS_FILE_NAME
+            );
+            DEFAULT_STATS_FILE_NAME.to_string()
+        } else {
+            name
+        }
+    });
+
+    let info_file = env_flag("FUSE_INFO_FILE")
+        .or(file_config.info_file)
+        .unwrap_or(false);
+
+    let control_file = env_flag("FUSE_CONTROL_FILE")
+        .or(file_config.control_file)
+        .unwrap_or(false);
+
+    let stable_inodes = env_flag("FUSE_STABLE_INODES")
+        .or(file_config.stable_inodes)
+        .unwrap_or(false);
+
+    let forward_identity = env_flag("FUSE_FORWARD_IDENTITY")
+        .or(file_config.forward_identity)
+        .unwrap_or(false);
+
+    let slow_request = match env_parse("FUSE_SLOW_REQUEST_MS").or(file_config.slow_request_ms) {
+        Some(0) => None,
+        Some(ms) => Some(Duration::from_millis(ms)),
+        None => Some(DEFAULT_SLOW_REQUEST),
+    };
+
+    let api_user_agent = std::env::var("FUSE_API_USER_AGENT")
+        .ok()
+        .or(file_config.api_user_agent)
+        .filter(|ua| !ua.is_empty());
+
+    let api_body_format = std::env::var("FUSE_API_BODY_FORMAT")
+        .ok()
+        .or(file_config.api_body_format)
+        .map(|format| {
+            format.parse().unwrap_or_else(|e| {
+                warn!("Invalid FUSE_API_BODY_FORMAT: {}, using json", e);
+                BodyFormat::Json
+            })
+        })
+        .unwrap_or_default();
+
+    let api_compression = Compression {
+        max_decoded_size: env_parse("FUSE_API_MAX_DECODED_SIZE")
+            .or(file_config.api_max_decoded_size)
+            .unwrap_or(api_client::DEFAULT_MAX_DECODED_SIZE),
+        request_min_size: env_parse("FUSE_API_COMPRESS_MIN_SIZE")
+            .or(file_config.api_compress_min_size)
+            .filter(|&size: &usize| size > 0),
+    };
+
+    let api_client_cert = std::env::var("FUSE_API_CLIENT_CERT")
+        .ok()
+        .or(file_config.api_client_cert)
+        .filter(|path| !path.is_empty());
+    let api_client_key = std::env::var("FUSE_API_CLIENT_KEY")
+        .ok()
+        .or(file_config.api_client_key)
+        .filter(|path| !path.is_empty());
+    let api_client_cert = api_client_cert.map(|path| std::path::PathBuf::from(expand(&path)));
+    let api_client_key = api_client_key.map(|path| std::path::PathBuf::from(expand(&path)));
+    let api_tls_identity =
+        api_client::load_identity_pair(api_client_cert.as_deref(), api_client_key.as_deref())
+            .unwrap_or_else(|e| {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            });
+    let api_ca_cert = std::env::var("FUSE_API_CA_CERT")
+        .ok()
+        .or(file_config.api_ca_cert)
+        .filter(|path| !path.is_empty())
+        .map(|path| {
+            api_client::load_ca_cert(std::path::Path::new(&expand(&path))).unwrap_or_else(|e| {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            })
+        });
+    let api_tls = TlsSettings {
+        identity: api_tls_identity,
+        ca_cert: api_ca_cert,
+    };
+
+    let root_prefix = std::env::var("FUSE_ROOT_PREFIX")
+        .ok()
+        .or(file_config.root_prefix)
+        .filter(|prefix| !prefix.is_empty());
+
+    let api_max_concurrency = env_parse("FUSE_API_MAX_CONCURRENCY")
+        .or(file_config.api_max_concurrency)
+        .filter(|&n: &usize| n > 0);
+
+    let max_read = env_parse("FUSE_MAX_READ_KB")
+        .or(file_config.max_read_kb)
+        .filter(|&kb: &u32| kb > 0)
+        .map(|kb| kb.saturating_mul(1024));
+
+    let blksize = match env_parse("FUSE_BLKSIZE").or(file_config.blksize) {
+        Some(size) if size >= 512 && u32::is_power_of_two(size) => size,
+        Some(size) => {
+            warn!(
+                "FUSE_BLKSIZE must be a power of two of at least 512, ignoring {}",
+                size
+            );
+            DEFAULT_BLKSIZE
+        }
+        None => DEFAULT_BLKSIZE,
+    };
+
+    let lookup_exists_first = env_flag("FUSE_LOOKUP_EXISTS_FIRST")
+        .or(file_config.lookup_exists_first)
+        .unwrap_or(false);
+
+    let dir_mtime_validation = env_flag("FUSE_DIR_MTIME_VALIDATION")
+        .or(file_config.dir_mtime_validation)
+        .unwrap_or(false);
+
+    let max_stale = env_flag("FUSE_STALE_WHILE_REVALIDATE")
+        .or(file_config.stale_while_revalidate)
+        .unwrap_or(false)
+        .then(|| {
+            env_parse("FUSE_MAX_STALE_SECS")
+                .or(file_config.max_stale_secs)
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_MAX_STALE)
+        });
+
+    let small_file_cache_size = env_parse("FUSE_SMALL_FILE_CACHE_SIZE")
+        .or(file_config.small_file_cache_size)
+        .unwrap_or(CONTENT_CACHE_MAX_FILE_SIZE);
+
+    let max_inline_size = env_parse("FUSE_MAX_INLINE_SIZE")
+        .or(file_config.max_inline_size)
+        .unwrap_or(DEFAULT_MAX_INLINE_SIZE);
+
+    let read_align = env_parse("FUSE_READ_ALIGN")
+        .or(file_config.read_align)
+        .filter(|&n: &usize| n > 0);
+
+    let sniff_bytes = env_parse("FUSE_SNIFF_BYTES")
+        .or(file_config.sniff_bytes)
+        .filter(|&n: &usize| n > 0);
+
+    let hide_prefixes = std::env::var("FUSE_HIDE_PREFIXES")
+        .ok()
+        .or(file_config.hide_prefixes)
+        .map(|spec| parse_hide_prefixes(&spec))
+        .unwrap_or_default();
+
+    let read_source_order = std::env::var("FUSE_READ_SOURCE_ORDER")
+        .ok()
+        .or(file_config.read_source_order)
+        .map(|spec| {
+            read_source::parse_order(&spec).unwrap_or_else(|e| {
+                warn!("Invalid FUSE_READ_SOURCE_ORDER: {}, using the default", e);
+                read_source::DEFAULT_ORDER.to_vec()
+            })
+        })
+        .unwrap_or_else(|| read_source::DEFAULT_ORDER.to_vec());
+
+    let max_dir_entries = env_parse("FUSE_MAX_DIR_ENTRIES")
+        .or(file_config.max_dir_entries)
+        .filter(|&n: &usize| n > 0);
+
+    let readdir_empty_on_error = env_flag("FUSE_READDIR_EMPTY_ON_ERROR")
+        .or(file_config.readdir_empty_on_error)
+        .unwrap_or(false);
+
+    let flatten = env_flag("FUSE_FLATTEN")
+        .or(file_config.flatten)
+        .unwrap_or(false);
+
+    let kernel_cache = env_flag("FUSE_KERNEL_CACHE")
+        .or(file_config.kernel_cache)
+        .unwrap_or(false);
+
+    let notify_atime = env_flag("FUSE_NOTIFY_ATIME")
+        .or(file_config.notify_atime)
+        .unwrap_or(false);
+
+    let confine_symlinks = env_flag("FUSE_CONFINE_SYMLINKS")
+        .or(file_config.confine_symlinks)
+        .unwrap_or(false)
+        .then(|| {
+            std::path::absolute(mountpoint).unwrap_or_else(|_| std::path::PathBuf::from(mountpoint))
+        });
+
+    let pretty_json = env_flag("FUSE_PRETTY_JSON")
+        .or(file_config.pretty_json)
+        .unwrap_or(false);
+
+    let create_mountpoint = env_flag("FUSE_CREATE_MOUNTPOINT")
+        .or(file_config.create_mountpoint)
+        .unwrap_or(false);
+
+    let wait_for_api = env_parse("FUSE_WAIT_FOR_API_SECS")
+        .or(file_config.wait_for_api_secs)
+        .filter(|&secs| secs > 0)
+        .map(Duration::from_secs);
+
+    let unhealthy_exit = env_parse("FUSE_UNHEALTHY_EXIT_SECS")
+        .or(file_config.unhealthy_exit_secs)
+        .filter(|&secs| secs > 0)
+        .map(Duration::from_secs);
+
+    if forward_identity && preload_depth > 0 {
+        warn!("FUSE_PRELOAD_DEPTH has no effect with FUSE_FORWARD_IDENTITY, since caches are per user");
+    }
+
+    let mount_options = std::env::var("FUSE_MOUNT_OPTIONS")
+        .ok()
+        .or(file_config.mount_options);
+
+    if check {
+        std::process::exit(
+            if check::run(
+                &api_url,
+                request_timeouts,
+                api_user_agent,
+                root_prefix,
+                api_body_format,
+                api_compression,
+                api_tls.clone(),
+            ) {
+                0
+            } else {
+                1
+            },
+        );
+    }
+    if let Some(archive) = export {
+        std::process::exit(
+            if export::run(
+                &api_url,
+                request_timeouts,
+                api_user_agent,
+                root_prefix,
+                api_body_format,
+                api_compression,
+                api_tls.clone(),
+                &read_source_order,
+                std::path::Path::new(&expand(&archive)),
+            ) {
+                0
+            } else {
+                1
+            },
+        );
+    }
+    if let Some((path, json)) = benchmark {
+        std::process::exit(
+            if benchmark::run(
+                &api_url,
+                request_timeouts,
+                api_user_agent,
+                root_prefix,
+                api_body_format,
+                api_compression,
+                api_tls.clone(),
+                &read_source_order,
+                &normalize_path(&path),
+                json,
+            ) {
+                0
+            } else {
+                1
+            },
+        );
+    }
+
+    info!("Connecting to API at: {}", api_url);
+    if let Some(ref prefix) = root_prefix {
+        info!("Mount root maps to {} on the API", prefix);
+    }
+    info!("File ownership: uid={}, gid={}", uid, gid);
+    info!(
+        "File permissions: {:o} (files), {:o} (directories)",
+        file_perm, dir_perm
+    );
+
+    let config = FsConfig {
+        uid,
+        gid,
+        file_perm,
+        dir_perm,
+        attr_ttl,
+        cache_ttl,
+        request_timeouts,
+        webdav_timeout,
+        circuit_cooldown,
+        error_threshold,
+        error_window,
+        verify_checksums,
+        use_api_perms,
+        use_api_ownership,
+        preload_depth,
+        stats_file,
+        info_file,
+        control_file,
+        stable_inodes,
+        forward_identity,
+        slow_request,
+        api_user_agent,
+        root_prefix,
+        api_body_format,
+        api_compression,
+        api_tls,
+        api_max_concurrency,
+        wait_for_api,
+        unhealthy_exit,
+        max_read,
+        blksize,
+        lookup_exists_first,
+        dir_mtime_validation,
+        pretty_json,
+        confine_symlinks,
+        notify_atime,
+        kernel_cache,
+        small_file_cache_size,
+        max_inline_size,
+        read_align,
+        sniff_bytes,
+        max_stale,
+        flatten,
+        max_dir_entries,
+        readdir_empty_on_error,
+        hide_prefixes,
+        read_source_order,
+    };
+
+    // Fork before ApiFS::new, whose HTTP client starts threads
+    let mut detached = cli.daemon.then(|| {
+        daemon::detach().unwrap_or_else(|e| {
+            eprintln!("Error: Failed to daemonize: {}", e);
+            std::process::exit(1);
+        })
+    });
+
+    let fs = match ApiFS::new(api_url.clone(), config) {
+        Ok(fs) => {
+            info!("Successfully connected to meta-fuse API");
+            fs
+        }
+        Err(e) => {
+            error!("Failed to connect to API at {}: {}", api_url, e);
+            eprintln!("Error: Failed to connect to API at {}: {}", api_url, e);
+            eprintln!("\nMake sure meta-fuse-core is running.");
+            std::process::exit(1);
+        }
+    };
+
+    if create_mountpoint {
+        if let Err(e) = prepare_mountpoint(std::path::Path::new(mountpoint), dir_perm) {
+            error!("{}", e);
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    info!("Mounting filesystem at: {}", mountpoint);
+
+    let mut options = base_mount_options(allow_other, control_file);
+    if !allow_other {
+        info!("allow_other is off, so only this user can access the mount (set FUSE_ALLOW_OTHER=1 to share it)");
+        info!("auto_unmount is off too, so a crashed driver leaves the mountpoint to be cleared with fusermount -u");
+    }
+
+    if default_permissions {
+        options.push(MountOption::DefaultPermissions);
+    }
+
+    if let Some(max_read) = max_read {
+        options.push(MountOption::CUSTOM(format!("max_read={}", max_read)));
+    }
+
+    if let Some(spec) = mount_options {
+        for option in parse_mount_options(&spec, control_file) {
+            if !options.contains(&option) {
+                options.push(option);
+            }
+        }
+    }
+    info!("Mount options: {:?}", options);
+
+    let api = fs.api.clone();
+    let caches = fs.shared_caches();
+    let notifier_slot = Arc::clone(&fs.notifier);
+    let health = Arc::clone(&fs.api_health);
+    let unhealthy_exit = fs.config.unhealthy_exit;
+    let watchdog_tripped = Arc::new(AtomicBool::new(false));
+
+    let result = fuser::Session::new(fs, std::path::Path::new(&mountpoint), &options).and_then(
+        |mut session| {
+            if let Some(detached) = detached.take() {
+                info!("Mounted, continuing in the background");
+                if let Err(e) = detached.mounted(log_file.as_deref()) {
+                    warn!("Failed to redirect standard streams: {}", e);
+                }
+            }
+            if let Some(limit) = unhealthy_exit {
+                watchdog::spawn(
+                    api.clone(),
+                    health,
+                    limit,
+                    session.unmount_callable(),
+                    Arc::clone(&watchdog_tripped),
+                );
+            }
+            for backend in api.backend_clients() {
+                events::spawn_listener(backend, caches.clone(), session.notifier());
+            }
+            *notifier_slot.lock().unwrap() = Some(Arc::new(session.notifier()));
+            session.run()
+        },
+    );
+
+    match result {
+        Ok(()) if watchdog_tripped.load(Ordering::SeqCst) => {
+            error!("Exiting because the API stayed unreachable");
+            std::process::exit(2);
+        }
+        Ok(()) => {
+            info!("Filesystem unmounted successfully");
+        }
+        Err(e) => {
+            error!("Mount failed: {}", e);
+            eprintln!("Error: Failed to mount filesystem: {}", e);
+            eprintln!("\nPossible causes:");
+            eprintln!("1. Mount point does not exist or is not accessible");
+            eprintln!("2. FUSE module is not loaded (try: modprobe fuse)");
+            if options.contains(&MountOption::AllowOther) && !is_root {
+                eprintln!(
+                    "3. allow_other was requested but /etc/fuse.conf lacks 'user_allow_other'"
+                );
+                eprintln!("   To fix: echo 'user_allow_other' | sudo tee -a /etc/fuse.conf");
+                eprintln!("   or set FUSE_ALLOW_OTHER=0 to mount for this user only");
+            }
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{Response, TestServer};
+    use base64::Engine;
+
+    pub(crate) fn test_config() -> FsConfig {
+        FsConfig {
+            uid: 1000,
+            gid: 1000,
+            file_perm: 0o644,
+            dir_perm: 0o755,
+            attr_ttl: TTL,
+            cache_ttl: CacheTtl {
+                base: CACHE_TTL,
+                jitter_pct: 0,
+                adaptive_max: None,
+            },
+            request_timeouts: RequestTimeouts {
+                global: Duration::from_secs(5),
+                getattr: None,
+                readdir: None,
+                read: None,
+                adaptive: None,
+            },
+            webdav_timeout: Duration::from_secs(5),
+            circuit_cooldown: Duration::from_secs(30),
+            error_threshold: DEFAULT_ERROR_THRESHOLD,
+            error_window: None,
+            verify_checksums: false,
+            use_api_perms: false,
+            use_api_ownership: false,
+            preload_depth: 0,
+            stats_file: None,
+            info_file: false,
+            control_file: false,
+            stable_inodes: false,
+            forward_identity: false,
+            slow_request: None,
+            api_user_agent: None,
+            root_prefix: None,
+            api_body_format: BodyFormat::Json,
+            api_compression: Compression::default(),
+            api_tls: TlsSettings::default(),
+            api_max_concurrency: None,
+            wait_for_api: None,
+            unhealthy_exit: None,
+            max_read: None,
+            blksize: DEFAULT_BLKSIZE,
+            lookup_exists_first: false,
+            dir_mtime_validation: false,
+            pretty_json: false,
+            confine_symlinks: None,
+            notify_atime: false,
+            kernel_cache: false,
+            small_file_cache_size: CONTENT_CACHE_MAX_FILE_SIZE,
+            max_inline_size: DEFAULT_MAX_INLINE_SIZE,
+            read_align: None,
+            sniff_bytes: None,
+            max_stale: None,
+            flatten: false,
+            max_dir_entries: None,
+            readdir_empty_on_error: false,
+            hide_prefixes: Vec::new(),
+            read_source_order: read_source::DEFAULT_ORDER.to_vec(),
+        }
+    }
+
+    /// Answers health and capability probes, then defers to `handler`
+    pub(crate) fn start_core(
+        handler: impl Fn(&test_server::Request) -> Response + Send + Sync + 'static,
+    ) -> TestServer {
+        TestServer::start(move |request| match request.target.as_str() {
+            "/api/fuse/health" => Response::json(serde_json::json!({ "status": "ok" })),
+            "/api/fuse/capabilities" => Response::new(404, ""),
+            _ => handler(request),
+        })
+    }
+
+    pub(crate) fn test_fs(server: &TestServer, config: FsConfig) -> ApiFS {
+        ApiFS::new(server.url.clone(), config).unwrap()
+    }
+
+    /// Core refusing to list /private
+    fn refusing_core(status: u16) -> TestServer {
+        start_core(move |request| match request.json_path().as_deref() {
+            Some("/private") => Response::new(status, ""),
+            _ => Response::json(serde_json::json!({ "entries": [] })),
+        })
+    }
+
+    #[test]
+    fn refused_listing_is_empty_with_readdir_empty_on_error() {
+        let server = refusing_core(403);
+        let fs = test_fs(
+            &server,
+            FsConfig {
+                readdir_empty_on_error: true,
+                ..test_config()
+            },
+        );
+        let ino = fs
+            .inode_mapper
+            .lock()
+            .unwrap()
+            .get_or_create_ino("/private");
+
+        let handle = fs.open_dir_handle(ino, "/private").unwrap();
+        let names: Vec<&str> = handle.listing.iter().map(|e| e.2.as_str()).collect();
+        assert_eq!(names, [".", ".."]);
+        assert!(handle.next_cursor.is_none());
+        assert!(fs.get_cached_readdir("/private").is_none());
+
+        // Not cached, so the next open asks core again
+        fs.open_dir_handle(ino, "/private").unwrap();
+        let listings = server.count("/api/fuse/readdirplus") + server.count("/api/fuse/readdir");
+        assert_eq!(listings, 2);
+    }
+
+    #[test]
+    fn refused_listing_fails_by_default() {
+        let server = refusing_core(403);
+        let fs = test_fs(&server, test_config());
+        let ino = fs
+            .inode_mapper
+            .lock()
+            .unwrap()
+            .get_or_create_ino("/private");
+        assert_eq!(
+            fs.open_dir_handle(ino, "/private").err(),
+            Some(libc::EACCES)
+        );
+    }
+
+    #[test]
+    fn readdir_empty_on_error_keeps_other_errors() {
+        for (status, errno) in [(404, libc::ENOENT), (429, libc::EAGAIN)] {
+            let server = refusing_core(status);
+            let fs = test_fs(
+                &server,
+                FsConfig {
+                    readdir_empty_on_error: true,
+                    ..test_config()
+                },
+            );
+            let ino = fs
+                .inode_mapper
+                .lock()
+                .unwrap()
+                .get_or_create_ino("/private");
+            assert_eq!(fs.open_dir_handle(ino, "/private").err(), Some(errno));
+        }
+    }
+
+    #[test]
+    fn refused_continuation_page_still_fails() {
+        let server = refusing_core(403);
+        let fs = test_fs(
+            &server,
+            FsConfig {
+                readdir_empty_on_error: true,
+                ..test_config()
+            },
+        );
+        assert_eq!(
+            fs.fetch_dir_page("/private", Some("page2")).err(),
+            Some(libc::EACCES)
+        );
+    }
+
+    #[test]
+    fn control_file_is_writable_by_its_owner_only() {
+        let server = start_core(|_| Response::new(404, ""));
+        let fs = test_fs(
+            &server,
+            FsConfig {
+                control_file: true,
+                ..test_config()
+            },
+        );
+        assert_eq!(fs.get_control_file_attrs().perm, 0o600);
+        assert!(fs.control_writer_allowed(1000));
+        assert!(fs.control_writer_allowed(0));
+        assert!(!fs.control_writer_allowed(1001));
+    }
+
+    #[test]
+    fn writing_anything_but_the_control_file_is_read_only() {
+        let server = start_core(|_| Response::new(404, ""));
+        let fs = test_fs(
+            &server,
+            FsConfig {
+                control_file: true,
+                ..test_config()
+            },
+        );
+        let file = fs.inode_mapper.lock().unwrap().get_or_create_ino("/f");
+        for ino in [file, ROOT_INO, STATS_FILE_INO, INFO_FILE_INO] {
+            assert_eq!(fs.write_refusal(ino, 1000), Some(EROFS), "{}", ino);
+            assert_eq!(fs.write_refusal(ino, 0), Some(EROFS), "{}", ino);
+        }
+        assert_eq!(fs.write_refusal(CONTROL_FILE_INO, 1000), None);
+        assert_eq!(fs.write_refusal(CONTROL_FILE_INO, 0), None);
+        assert_eq!(fs.write_refusal(CONTROL_FILE_INO, 1001), Some(libc::EACCES));
+
+        let fs = test_fs(&server, test_config());
+        assert_eq!(fs.write_refusal(CONTROL_FILE_INO, 1000), Some(EROFS));
+        assert_eq!(fs.write_refusal(file, 1000), Some(EROFS));
+    }
+
+    #[test]
+    fn ro_mount_option_is_dropped_with_the_control_file() {
+        assert_eq!(
+            parse_mount_options("ro,noatime", true),
+            [MountOption::NoAtime]
+        );
+        assert_eq!(
+            parse_mount_options("ro,noatime", false),
+            [MountOption::RO, MountOption::NoAtime]
+        );
+    }
+
+    #[test]
+    fn auto_unmount_comes_only_with_allow_other() {
+        let fsname = MountOption::FSName("meta-fuse".to_string());
+        assert_eq!(
+            base_mount_options(false, false),
+            [fsname.clone(), MountOption::RO]
+        );
+        assert_eq!(
+            base_mount_options(true, false),
+            [
+                fsname,
+                MountOption::RO,
+                MountOption::AllowOther,
+                MountOption::AutoUnmount
+            ]
+        );
+    }
+
+    #[test]
+    fn pretty_json_size_is_reported_once_rendered() {
+        let raw = br#"{"a":1}"#;
+        let server = start_core(move |request| match request.target.as_str() {
+            "/api/fuse/read" => Response::json(serde_json::json!({
+                "content": base64::prelude::BASE64_STANDARD.encode(raw),
+                "size": raw.len(),
+            })),
+            _ => Response::new(404, ""),
+        });
+        let fs = test_fs(
+            &server,
+            FsConfig {
+                pretty_json: true,
+                ..test_config()
+            },
+        );
+        let attrs: api_client::FileAttributes = serde_json::from_value(serde_json::json!({
+            "size": raw.len(), "mode": 0o100644, "mtime": 1.0, "contentType": "application/json",
+        }))
+        .unwrap();
+
+        // A stat alone must not download the file
+        assert_eq!(
+            fs.convert_attrs("/a.json", attrs.clone()).size,
+            raw.len() as u64
+        );
+        assert_eq!(server.count("/api/fuse/read"), 0);
+
+        let pretty = fs
+            .with_pretty_json("/a.json", &attrs, |data| data.to_vec())
+            .unwrap();
+        assert_eq!(pretty, b"{\n  \"a\": 1\n}\n");
+        assert_eq!(fs.convert_attrs("/a.json", attrs).size, pretty.len() as u64);
+    }
+
+    #[test]
+    fn normalize_path_collapses_slashes() {
+        for (raw, normalized) in [
+            ("", "/"),
+            ("/", "/"),
+            ("//", "/"),
+            ("//a", "/a"),
+            ("a/b/", "/a/b"),
+            ("/a//b", "/a/b"),
+            ("a/../b", "/a/../b"),
+        ] {
+            assert_eq!(normalize_path(raw), normalized, "{:?}", raw);
+        }
+    }
+
+    #[test]
+    fn join_path_builds_normalized_children() {
+        assert_eq!(join_path("/", "a"), "/a");
+        assert_eq!(join_path("/a", "b"), "/a/b");
+        assert_eq!(join_path("/a/", "b/"), "/a/b");
+        assert_eq!(join_path("//a", "b"), "/a/b");
+        assert_eq!(join_path("/", ""), "/");
+    }
+
+    #[test]
+    fn split_parent_splits_the_last_component() {
+        assert_eq!(split_parent("/a"), ("/", "a"));
+        assert_eq!(split_parent("/a/b"), ("/a", "b"));
+        assert_eq!(split_parent("/"), ("/", ""));
+    }
+
+    /// Map a path and evict it again, as a lookup followed by forget does
+    fn evict(mapper: &mut InodeMapper, path: &str) -> u64 {
+        let ino = mapper.get_or_create_ino(path);
+        mapper.add_lookup(ino);
+        assert_eq!(mapper.forget(ino, 1).as_deref(), Some(path));
+        ino
+    }
+
+    #[test]
+    fn lookup_counts_skip_reserved_inodes_and_never_go_negative() {
+        let mut mapper = InodeMapper::new(true);
+        for ino in ROOT_INO..=LAST_SYNTHETIC_INO {
+            mapper.add_lookup(ino);
+            assert_eq!(mapper.forget(ino, 5), None);
+            assert!(!mapper.lookups.contains_key(&ino));
+        }
+        assert_eq!(mapper.get_path(ROOT_INO).map(String::as_str), Some("/"));
+
+        let ino = mapper.get_or_create_ino("/f");
+        for _ in 0..3 {
+            mapper.add_lookup(ino);
+        }
+        assert_eq!(mapper.forget(ino, 1), None);
+        assert_eq!(mapper.lookups[&ino], 2);
+        // Forgetting more than was looked up evicts once, without wrapping
+        assert_eq!(mapper.forget(ino, 10).as_deref(), Some("/f"));
+        assert!(!mapper.lookups.contains_key(&ino));
+        assert_eq!(mapper.forget(ino, 1), None);
+        assert_eq!(mapper.forget(ino, u64::MAX), None);
+        assert!(!mapper.lookups.contains_key(&ino));
+
+        // Inodes never handed out are neither counted nor forgotten
+        mapper.add_lookup(0xdead_beef);
+        assert!(mapper.lookups.is_empty());
+        assert_eq!(mapper.forget(0xdead_beef, 1), None);
+    }
+
+    #[test]
+    fn batch_forget_evicts_only_fully_forgotten_inodes() {
+        let server = start_core(|_| {
+            Response::json(serde_json::json!({ "size": 3, "mode": 0o100644, "mtime": 1.0 }))
+        });
+        let fs = test_fs(&server, test_config());
+        let mut inos = Vec::new();
+        for path in ["/a", "/b"] {
+            fs.resolve_attrs(path).unwrap();
+            let mut mapper = fs.inode_mapper.lock().unwrap();
+            let ino = mapper.get_or_create_ino(path);
+            mapper.add_lookup(ino);
+            mapper.add_lookup(ino);
+            inos.push(ino);
+        }
+
+        // The same inode may appear twice in one batch, with reserved ones
+        fs.forget_inodes(&[
+            (inos[0], 1),
+            (inos[0], 1),
+            (inos[1], 1),
+            (ROOT_INO, 1),
+            (STATS_FILE_INO, 3),
+        ]);
+        let cached = |path: &str| fs.attr_cache.lock().unwrap().contains_key(&cache_key(path));
+        assert!(!cached("/a"));
+        assert!(cached("/b"));
+        let mapper = fs.inode_mapper.lock().unwrap();
+        assert!(!mapper.lookups.contains_key(&inos[0]));
+        assert_eq!(mapper.lookups[&inos[1]], 1);
+        assert_eq!(mapper.path_to_ino.get("/b"), Some(&inos[1]));
+        assert_eq!(mapper.path_to_ino.get("/"), Some(&ROOT_INO));
+    }
+
+    #[test]
+    fn evicted_inode_is_recovered_from_its_parent() {
+        let mut mapper = InodeMapper::new(true);
+        let parent = mapper.get_or_create_ino("/a");
+        let ino = evict(&mut mapper, "/a/b");
+        assert_ne!(ino, parent);
+        assert_eq!(mapper.get_path(ino).map(String::as_str), Some("/a/b"));
+        assert_eq!(mapper.get_or_create_ino("/a/b"), ino);
+        assert_eq!(mapper.generation(ino), 0);
+    }
+
+    #[test]
+    fn remapping_an_evicted_inode_bumps_its_generation() {
+        let mut mapper = InodeMapper::new(true);
+        let ino = evict(&mut mapper, "/f");
+        assert_eq!(mapper.generation(ino), 0);
+        assert_eq!(mapper.get_or_create_ino("/f"), ino);
+        assert_eq!(mapper.generation(ino), 1);
+
+        // Each recycling bumps it again, and a live inode keeps its own
+        assert_eq!(evict(&mut mapper, "/f"), ino);
+        assert_eq!(mapper.get_or_create_ino("/f"), ino);
+        assert_eq!(mapper.generation(ino), 2);
+        assert_eq!(mapper.get_or_create_ino("/f"), ino);
+        assert_eq!(mapper.generation(ino), 2);
+
+        // Recovering it for a late request is not a new lookup
+        let parent = mapper.get_or_create_ino("/d");
+        let child = evict(&mut mapper, "/d/c");
+        assert_eq!(mapper.get_or_create_ino("/d/c"), child);
+        assert_eq!(evict(&mut mapper, "/d/c"), child);
+        assert_ne!(child, parent);
+        assert_eq!(mapper.get_path(child).map(String::as_str), Some("/d/c"));
+        assert_eq!(mapper.generation(child), 1);
+
+        // Sequential inodes are never reused, so never need a generation
+        let mut mapper = InodeMapper::new(false);
+        let first = evict(&mut mapper, "/f");
+        let second = mapper.get_or_create_ino("/f");
+        assert_ne!(first, second);
+        assert_eq!(mapper.generation(second), 0);
+    }
+
+    #[test]
+    fn retired_inodes_are_capped() {
+        let mut mapper = InodeMapper::new(true);
+        let first = evict(&mut mapper, "/f0");
+        for i in 1..RETIRED_INODES_MAX + 10 {
+            evict(&mut mapper, &format!("/f{}", i));
+        }
+        assert!(mapper.retired.len() <= RETIRED_INODES_MAX);
+        // The oldest evictions went first
+        assert!(!mapper.retired.contains_key(&first));
+    }
+
+    #[test]
+    fn negative_cache_is_capped() {
+        let ttl = Duration::from_secs(30);
+        let cache = Mutex::new(HashMap::new());
+        let expired = SystemTime::now() - Duration::from_secs(60);
+        for i in 0..NEGATIVE_CACHE_MAX_ENTRIES {
+            cache
+                .lock()
+                .unwrap()
+                .insert(cache_key(&format!("/old{}", i)), expired);
+        }
+        cache_negative(&cache, ttl, "/new");
+        assert_eq!(cache.lock().unwrap().len(), 1);
+
+        for i in 0..NEGATIVE_CACHE_MAX_ENTRIES {
+            cache_negative(&cache, ttl, &format!("/fresh{}", i));
+        }
+        let cache = cache.lock().unwrap();
+        assert!(cache.len() <= NEGATIVE_CACHE_MAX_ENTRIES);
+        assert!(cache.contains_key(&cache_key(&format!(
+            "/fresh{}",
+            NEGATIVE_CACHE_MAX_ENTRIES - 1
+        ))));
+        assert!(!cache.contains_key(&cache_key("/new")));
+    }
+
+    #[test]
+    fn access_notifications_queue_on_one_worker() {
+        let server = start_core(|request| {
+            if request.target == "/api/fuse/atime" {
+                std::thread::sleep(Duration::from_millis(50));
+            }
+            Response::json(serde_json::json!({}))
+        });
+        let fs = test_fs(
+            &server,
+            FsConfig {
+                notify_atime: true,
+                ..test_config()
+            },
+        );
+
+        for i in 0..ATIME_NOTIFY_QUEUE + 50 {
+            fs.notify_access(&format!("/f{}", i));
+        }
+        // What did not fit was dropped, and forgotten so a later read retries
+        let queued = fs.atime_notified.lock().unwrap().len();
+        assert!((ATIME_NOTIFY_QUEUE..=ATIME_NOTIFY_QUEUE + 1).contains(&queued));
+
+        std::thread::sleep(Duration::from_millis(300));
+        let sent = server.count("/api/fuse/atime");
+        assert!((1..=7).contains(&sent), "{} sent", sent);
+    }
+
+    #[test]
+    fn truncation_marker_resolves_only_where_a_listing_was_cut() {
+        let server = start_core(|request| match request.json_path().as_deref() {
+            Some("/big") => Response::json(serde_json::json!({ "entries": ["a", "b", "c", "d"] })),
+            _ => Response::json(serde_json::json!({ "entries": ["a"] })),
+        });
+        let fs = test_fs(
+            &server,
+            FsConfig {
+                max_dir_entries: Some(2),
+                ..test_config()
+            },
+        );
+        let (big, small) = {
+            let mut mapper = fs.inode_mapper.lock().unwrap();
+            (
+                mapper.get_or_create_ino("/big"),
+                mapper.get_or_create_ino("/small"),
+            )
+        };
+        assert!(!fs.is_truncated_dir(big));
+
+        let handle = fs.open_dir_handle(big, "/big").unwrap();
+        assert_eq!(handle.listing.last().unwrap().2, TRUNCATED_FILE_NAME);
+        fs.open_dir_handle(small, "/small").unwrap();
+        assert!(fs.is_truncated_dir(big));
+        assert!(!fs.is_truncated_dir(small));
+    }
+
+    #[test]
+    fn real_root_file_hides_the_control_file_without_asking_core() {
+        let server = start_core(|_| {
+            Response::json(serde_json::json!({ "entries": [CONTROL_FILE_NAME, "a"] }))
+        });
+        let fs = test_fs(
+            &server,
+            FsConfig {
+                control_file: true,
+                ..test_config()
+            },
+        );
+        let before = server.requests().len();
+        assert!(fs.control_file_visible());
+        assert_eq!(server.requests().len(), before);
+
+        let handle = fs.open_dir_handle(ROOT_INO, "/").unwrap();
+        let controls = handle
+            .listing
+            .iter()
+            .filter(|entry| entry.2 == CONTROL_FILE_NAME)
+            .count();
+        assert_eq!(controls, 1);
+        assert!(handle
+            .listing
+            .iter()
+            .all(|entry| entry.0 != CONTROL_FILE_INO));
+        assert!(!fs.control_file_visible());
+    }
+
+    #[test]
+    fn open_prefetches_the_sniff_head_once() {
+        let server = start_core(|request| match request.target.as_str() {
+            "/api/fuse/read" => Response::json(serde_json::json!({
+                "content": base64::prelude::BASE64_STANDARD.encode(b"\x89PNG rest of file"),
+                "size": 18,
+                "mtime": 1.0,
+            })),
+            _ => Response::new(404, ""),
+        });
+        let fs = test_fs(
+            &server,
+            FsConfig {
+                sniff_bytes: Some(4),
+                ..test_config()
+            },
+        );
+        let attrs = serde_json::from_value(serde_json::json!({
+            "size": 18, "mode": 0o100644, "mtime": 1.0,
+        }))
+        .unwrap();
+        fs.cache_attrs("/image", attrs, None, CacheControl::default());
+
+        fs.prefetch_head("/image", 4);
+        assert_eq!(server.count("/api/fuse/read"), 1);
+        assert_eq!(fs.sniff_hit("/image", 0, 4).unwrap(), b"\x89PNG");
+        fs.prefetch_head("/image", 4);
+        assert_eq!(server.count("/api/fuse/read"), 1);
+    }
+
+    #[test]
+    fn aligned_range_covers_whole_blocks() {
+        assert_eq!(aligned_range(50, 100, 4096, 10_000), (0, 4096));
+        assert_eq!(aligned_range(4000, 200, 4096, 10_000), (0, 8192));
+        assert_eq!(aligned_range(4096, 4096, 4096, 10_000), (4096, 4096));
+        // Not past the end of the file, unless the request already is
+        assert_eq!(aligned_range(9000, 500, 4096, 10_000), (8192, 1808));
+        assert_eq!(aligned_range(9990, 100, 4096, 10_000), (8192, 1898));
+    }
+
+    #[test]
+    fn aligned_reads_are_served_from_the_kept_block() {
+        let blob: Vec<u8> = (0..10_000u32).map(|i| i as u8).collect();
+        let served = blob.clone();
+        let url = Arc::new(std::sync::OnceLock::<String>::new());
+        let blob_url = Arc::clone(&url);
+        let server = TestServer::start(move |request| match request.target.as_str() {
+            "/api/fuse/health" => Response::json(serde_json::json!({ "status": "ok" })),
+            "/api/fuse/read" => Response::json(serde_json::json!({
+                "fetchUrl": format!("{}/blob", blob_url.get().unwrap()),
+                "size": 10_000,
+                "mtime": 1.0,
+            })),
+            "/blob" => Response::new(200, served.clone()),
+            _ => Response::new(404, ""),
+        });
+        url.set(server.url.clone()).unwrap();
+        let fs = test_fs(
+            &server,
+            FsConfig {
+                read_align: Some(4096),
+                ..test_config()
+            },
+        );
+        fs.file_handles
+            .lock()
+            .unwrap()
+            .insert(7, FileHandle::default());
+
+        assert_eq!(
+            fs.read_range("/f", 50, 100, Some(7)).unwrap(),
+            &blob[50..150]
+        );
+        assert_eq!(server.count("/blob"), 1);
+        assert_eq!(
+            fs.read_range("/f", 150, 3000, Some(7)).unwrap(),
+            &blob[150..3150]
+        );
+        assert_eq!(server.count("/blob"), 1);
+        // The tail block came with the first block's readahead
+        assert_eq!(
+            fs.read_range("/f", 9000, 4096, Some(7)).unwrap(),
+            &blob[9000..]
+        );
+        assert_eq!(server.count("/blob"), 1);
+    }
+
+    #[test]
+    fn sequential_small_reads_share_one_range_request() {
+        let blob: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+        let served = blob.clone();
+        let url = Arc::new(std::sync::OnceLock::<String>::new());
+        let blob_url = Arc::clone(&url);
+        let server = start_core(move |request| match request.target.as_str() {
+            "/api/fuse/read" => Response::json(serde_json::json!({
+                "fetchUrl": format!("{}/blob", blob_url.get().unwrap()),
+                "size": 200_000,
+                "mtime": 1.0,
+            })),
+            "/blob" => {
+                let range = request.header("range").unwrap();
+                let (start, end) = range.trim_start_matches("bytes=").split_once('-').unwrap();
+                let (start, end): (usize, usize) = (start.parse().unwrap(), end.parse().unwrap());
+                Response::new(206, served[start..=end].to_vec())
+            }
+            _ => Response::new(404, ""),
+        });
+        url.set(server.url.clone()).unwrap();
+        let fs = test_fs(&server, test_config());
+        fs.file_handles
+            .lock()
+            .unwrap()
+            .insert(7, FileHandle::default());
+
+        for offset in (0..128 * 1024).step_by(4096) {
+            assert_eq!(
+                fs.read_range("/f", offset, 4096, Some(7)).unwrap(),
+                &blob[offset..offset + 4096]
+            );
+        }
+        let ranges: Vec<_> = server
+            .requests()
+            .iter()
+            .filter(|request| request.target == "/blob")
+            .map(|request| request.header("range").unwrap().to_string())
+            .collect();
+        assert_eq!(ranges, ["bytes=0-199999"]);
+    }
+
+    #[test]
+    fn ranged_get_sends_no_request_for_an_empty_range() {
+        let server = start_core(|request| {
+            let range = request.header("range").unwrap_or_default().to_string();
+            Response::new(206, range)
+        });
+        let fs = test_fs(&server, test_config());
+        let url = format!("{}/blob", server.url);
+
+        assert_eq!(fs.ranged_get(&url, 0, 0, 10).unwrap().unwrap(), b"");
+        assert_eq!(fs.ranged_get(&url, 5, 0, 10).unwrap().unwrap(), b"");
+        assert_eq!(fs.ranged_get(&url, 10, 4, 10).unwrap().unwrap(), b"");
+        assert_eq!(fs.ranged_get(&url, 0, 4, 0).unwrap().unwrap(), b"");
+        assert_eq!(server.count("/blob"), 0);
+
+        // A range reaching past either limit is cut at the end of the file
+        let range = fs.ranged_get(&url, 3, usize::MAX, 10).unwrap().unwrap();
+        assert_eq!(range, b"bytes=3-9");
+        let range = fs.ranged_get(&url, 3, 4, 10).unwrap().unwrap();
+        assert_eq!(range, b"bytes=3-6");
+    }
+
+    /// A core whose read results offer every source, each holding
+    /// different bytes, with source_path pointing at `source_path`
+    fn all_sources(source_path: &std::path::Path) -> (TestServer, api_client::ReadResult) {
+        let server = start_core(|request| match request.target.as_str() {
+            "/dav" => Response::new(200, "webdv"),
+            "/fetch" => Response::new(200, "fetch"),
+            _ => Response::new(404, ""),
+        });
+        let result = serde_json::from_value(serde_json::json!({
+            "content": base64::prelude::BASE64_STANDARD.encode(b"inlin"),
+            "sourcePath": source_path,
+            "webdavUrl": format!("{}/dav", server.url),
+            "fetchUrl": format!("{}/fetch", server.url),
+            "size": 5,
+        }))
+        .unwrap();
+        (server, result)
+    }
+
+    #[test]
+    fn each_read_source_order_serves_from_its_first_source() {
+        let local = std::env::temp_dir().join(format!("meta-fuse-order-{}", std::process::id()));
+        std::fs::write(&local, "local").unwrap();
+        let (server, result) = all_sources(&local);
+        for (order, expected) in [
+            ("inline,source,webdav,fetchurl", "inlin"),
+            ("source,inline", "local"),
+            ("webdav,inline", "webdv"),
+            ("fetchurl,inline", "fetch"),
+        ] {
+            let fs = test_fs(
+                &server,
+                FsConfig {
+                    read_source_order: read_source::parse_order(order).unwrap(),
+                    ..test_config()
+                },
+            );
+            let data = fs
+                .read_file_content("/f", &result, 0, 5, None, false)
+                .unwrap();
+            assert_eq!(data, expected.as_bytes(), "{}", order);
+        }
+        std::fs::remove_file(&local).unwrap();
+    }
+
+    #[test]
+    fn failed_read_source_falls_back_to_the_next() {
+        let (server, result) = all_sources(std::path::Path::new("/nonexistent/meta-fuse"));
+        let fs = test_fs(
+            &server,
+            FsConfig {
+                read_source_order: read_source::parse_order("source,webdav").unwrap(),
+                ..test_config()
+            },
+        );
+        let data = fs
+            .read_file_content("/f", &result, 0, 5, None, false)
+            .unwrap();
+        assert_eq!(data, b"webdv");
+    }
+
+    #[test]
+    fn oversized_inline_content_is_refused_without_another_source() {
+        let (server, result) = all_sources(std::path::Path::new("/unused"));
+        let fs = test_fs(
+            &server,
+            FsConfig {
+                max_inline_size: 2,
+                read_source_order: vec![ReadSource::Inline],
+                ..test_config()
+            },
+        );
+        let e = fs
+            .read_file_content("/f", &result, 0, 5, None, false)
+            .unwrap_err();
+        assert_eq!(e.to_string(), "inline content exceeds FUSE_MAX_INLINE_SIZE");
+
+        let fs = test_fs(
+            &server,
+            FsConfig {
+                max_inline_size: 2,
+                read_source_order: read_source::parse_order("inline,fetchurl").unwrap(),
+                ..test_config()
+            },
+        );
+        let data = fs
+            .read_file_content("/f", &result, 0, 5, None, false)
+            .unwrap();
+        assert_eq!(data, b"fetch");
+    }
+
+    fn file_of(size: u64, mtime: f64) -> api_client::FileAttributes {
+        serde_json::from_value(
+            serde_json::json!({ "size": size, "mode": 0o100644, "mtime": mtime }),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn expired_fetch_url_is_replaced_along_with_the_read_result() {
+        let url = Arc::new(std::sync::OnceLock::<String>::new());
+        let base = Arc::clone(&url);
+        let server = start_core(move |request| match request.target.as_str() {
+            "/api/fuse/read" => Response::json(serde_json::json!({
+                "fetchUrl": format!("{}/signed?v=2", base.get().unwrap()),
+                "size": 7,
+                "mtime": 2.0,
+            })),
+            "/signed?v=1" => Response::new(403, "expired"),
+            "/signed?v=2" => Response::new(200, "longer!"),
+            _ => Response::new(404, ""),
+        });
+        url.set(server.url.clone()).unwrap();
+        let fs = test_fs(&server, test_config());
+        fs.cache_attrs("/big", file_of(5, 1.0), None, CacheControl::default());
+        let stale: api_client::ReadResult = serde_json::from_value(serde_json::json!({
+            "fetchUrl": format!("{}/signed?v=1", server.url),
+            "size": 5,
+            "mtime": 1.0,
+        }))
+        .unwrap();
+
+        let data = fs
+            .read_file_content("/big", &stale, 0, 4096, None, true)
+            .unwrap();
+        assert_eq!(data, b"longer!");
+        assert_eq!(server.count("/api/fuse/read"), 1);
+        let attrs = fs.get_cached_attrs("/big").unwrap();
+        assert_eq!((attrs.size, attrs.mtime), (7, 2.0));
+        assert_eq!(fs.cached_content_range("/big", 0, 7).unwrap(), b"longer!");
+    }
+
+    #[test]
+    fn stale_source_path_recovery_updates_the_cache() {
+        let server = start_core(|request| match request.target.as_str() {
+            "/api/fuse/read" => Response::json(serde_json::json!({
+                "content": base64::prelude::BASE64_STANDARD.encode(b"moved"),
+                "size": 5,
+                "mtime": 2.0,
+            })),
+            _ => Response::new(404, ""),
+        });
+        let fs = test_fs(&server, test_config());
+        fs.cache_attrs("/f", file_of(3, 1.0), None, CacheControl::default());
+        let stale: api_client::ReadResult = serde_json::from_value(serde_json::json!({
+            "sourcePath": "/nonexistent/meta-fuse/f",
+            "size": 3,
+            "mtime": 1.0,
+        }))
+        .unwrap();
+
+        let data = fs
+            .read_file_content("/f", &stale, 0, 4096, None, true)
+            .unwrap();
+        assert_eq!(data, b"moved");
+        let attrs = fs.get_cached_attrs("/f").unwrap();
+        assert_eq!((attrs.size, attrs.mtime), (5, 2.0));
+
+        // Only once: a result that is stale again fails
+        let e = fs.read_file_content("/f", &stale, 0, 4096, None, false);
+        assert!(e.is_err());
+    }
+
+    #[test]
+    fn large_inline_files_are_read_by_range_without_caching() {
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        let body = serde_json::json!({
+            "content": base64::prelude::BASE64_STANDARD.encode(&data),
+            "size": data.len(),
+        });
+        let server = start_core(move |request| match request.target.as_str() {
+            "/api/fuse/read" => Response::json(body.clone()),
+            _ => Response::new(404, ""),
+        });
+        let fs = test_fs(
+            &server,
+            FsConfig {
+                small_file_cache_size: 64 * 1024,
+                ..test_config()
+            },
+        );
+
+        assert_eq!(
+            fs.read_range("/big", 150_000, 1000, None).unwrap(),
+            &data[150_000..151_000]
+        );
+        assert_eq!(
+            fs.read_range("/big", 199_990, 1000, None).unwrap(),
+            &data[199_990..]
+        );
+        assert!(fs.read_range("/big", 300_000, 10, None).unwrap().is_empty());
+        assert!(fs.content_cache.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn windowed_errors_trip_despite_successes_between_them() {
+        let mut health = ApiHealth::new(Duration::from_secs(30), 3, Some(Duration::from_secs(60)));
+        for _ in 0..2 {
+            health.record_error("down".to_string());
+            health.record_success();
+        }
+        assert!(!health.status().is_unhealthy());
+        health.record_error("down".to_string());
+        assert!(health.status().is_unhealthy());
+        assert!(!health.allow_request());
+    }
+
+    #[test]
+    fn windowed_errors_expire_by_age() {
+        let mut health =
+            ApiHealth::new(Duration::from_secs(30), 2, Some(Duration::from_millis(50)));
+        health.record_error("down".to_string());
+        std::thread::sleep(Duration::from_millis(100));
+        health.record_error("down".to_string());
+        assert!(!health.status().is_unhealthy());
+        assert_eq!(health.recent_errors.len(), 1);
+        health.record_error("down".to_string());
+        assert!(health.status().is_unhealthy());
+    }
+
+    #[test]
+    fn unrecorded_probe_does_not_hold_the_circuit() {
+        let mut health = ApiHealth::new(Duration::from_millis(50), 1, None);
+        health.record_error("down".to_string());
+        assert!(!health.allow_request());
+        std::thread::sleep(Duration::from_millis(60));
+        // The probe is let through but its outcome never recorded
+        assert!(health.allow_request());
+        assert!(!health.allow_request());
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(health.allow_request());
+        health.record_success();
+        assert_eq!(health.circuit, CircuitState::Closed);
+        assert!(!health.status().is_unhealthy());
+        assert!(health.allow_request());
+    }
+
+    #[test]
+    fn cached_types_are_kept_per_user() {
+        let server = start_core(|_| Response::new(404, ""));
+        let fs = test_fs(&server, test_config());
+        api_client::begin_request(Some((1001, 1001)));
+        cache_type(&fs.type_cache, "/d", libc::S_IFDIR | 0o755);
+        assert_eq!(fs.get_cached_type("/d"), Some(FileType::Directory));
+
+        api_client::begin_request(Some((1002, 1002)));
+        assert_eq!(fs.get_cached_type("/d"), None);
+        cache_type(&fs.type_cache, "/d", libc::S_IFREG | 0o644);
+        assert_eq!(fs.get_cached_type("/d"), Some(FileType::RegularFile));
+
+        // Invalidation drops every user's view
+        fs.shared_caches().invalidate("/d");
+        api_client::begin_request(Some((1001, 1001)));
+        assert_eq!(fs.get_cached_type("/d"), None);
+        api_client::begin_request(None);
+    }
+
+    #[test]
+    fn broken_and_vanished_entries_are_skipped_without_inodes() {
+        let server = TestServer::start(|request| match request.target.as_str() {
+            "/api/fuse/health" => Response::json(serde_json::json!({ "status": "ok" })),
+            "/api/fuse/capabilities" => {
+                Response::json(serde_json::json!({ "capabilities": ["readdirplus"] }))
+            }
+            "/api/fuse/readdirplus" => Response::json(serde_json::json!({
+                "entries": [
+                    { "name": "ok", "attrs": { "size": 1, "mode": 0o100644, "mtime": 1.0 } },
+                    { "name": "broken", "error": "EIO" },
+                    { "name": "gone" },
+                ]
+            })),
+            "/api/fuse/getattr" => Response::new(404, ""),
+            _ => Response::new(404, ""),
+        });
+        let fs = test_fs(&server, test_config());
+
+        let (names, _) = fs.fetch_dir_page("/d", None).unwrap();
+        assert_eq!(names, ["ok", "gone"]);
+        let resolved = fs.resolve_dir_entries("/d", names);
+        let resolved: Vec<&str> = resolved.iter().map(|(_, _, name)| name.as_str()).collect();
+        assert_eq!(resolved, ["ok"]);
+
+        let mapper = fs.inode_mapper.lock().unwrap();
+        assert!(mapper.get_ino("/d/ok").is_some());
+        assert_eq!(mapper.get_ino("/d/broken"), None);
+        assert_eq!(mapper.get_ino("/d/gone"), None);
+    }
+
+    /// Core advertising `capabilities`, answering getattr for /here only
+    /// and 404 to everything `handler` doesn't take
+    fn capable_core(
+        capabilities: &'static [&'static str],
+        handler: impl Fn(&test_server::Request) -> Option<Response> + Send + Sync + 'static,
+    ) -> TestServer {
+        TestServer::start(move |request| {
+            if let Some(response) = handler(request) {
+                return response;
+            }
+            match request.target.as_str() {
+                "/api/fuse/health" => Response::json(serde_json::json!({ "status": "ok" })),
+                "/api/fuse/capabilities" => {
+                    Response::json(serde_json::json!({ "capabilities": capabilities }))
+                }
+                "/api/fuse/getattr" if request.json_path().as_deref() == Some("/here") => {
+                    Response::json(serde_json::json!({ "size": 1, "mode": 0o100644, "mtime": 1.0 }))
+                }
+                _ => Response::new(404, ""),
+            }
+        })
+    }
+
+    #[test]
+    fn chain_404_for_a_missing_path_keeps_the_endpoint() {
+        let server = capable_core(&["getattrchain"], |_| None);
+        let fs = test_fs(&server, test_config());
+
+        assert!(matches!(fs.prime_path_chain("/gone"), Some(Err(ENOENT))));
+        assert!(fs.path_chain_supported.load(Ordering::Relaxed));
+        assert!(fs.is_known_missing("/gone"));
+
+        assert!(fs.prime_path_chain("/here").is_none());
+        assert!(!fs.path_chain_supported.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn seek_404_for_a_missing_path_keeps_the_endpoint() {
+        let server = capable_core(&["seek"], |_| None);
+        let fs = test_fs(&server, test_config());
+
+        assert_eq!(fs.seek_offset(Some("/gone"), 10, 100, true), Ok(100));
+        assert!(fs.seek_supported.load(Ordering::Relaxed));
+
+        assert_eq!(fs.seek_offset(Some("/here"), 10, 100, false), Ok(10));
+        assert!(!fs.seek_supported.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn acl_404_for_a_missing_path_keeps_the_endpoint() {
+        let server = capable_core(&["getacl"], |_| None);
+        let fs = test_fs(&server, test_config());
+        // Cached attributes for a path since deleted must not count
+        fs.cache_attrs("/gone", file_of(1, 1.0), None, CacheControl::default());
+
+        assert_eq!(fs.acl_xattr("/gone", false), Err(ENOENT));
+        assert!(fs.acl_supported.load(Ordering::Relaxed));
+
+        assert_eq!(fs.acl_xattr("/here", false), Ok(None));
+        assert!(!fs.acl_supported.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn big_files_are_read_by_range_when_core_supports_it() {
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        let server = capable_core(&["readrange"], move |request| {
+            let body: serde_json::Value = serde_json::from_slice(&request.body).ok()?;
+            match (request.target.as_str(), request.json_path()?.as_str()) {
+                ("/api/fuse/getattr", "/big") => Some(Response::json(
+                    serde_json::json!({ "size": data.len(), "mode": 0o100644, "mtime": 1.0 }),
+                )),
+                ("/api/fuse/read", _) => {
+                    let Some(offset) = body["offset"].as_u64() else {
+                        return Some(Response::json(serde_json::json!({
+                            "content": base64::prelude::BASE64_STANDARD.encode(&data[..10]),
+                            "size": 10,
+                            "mtime": 1.0,
+                        })));
+                    };
+                    let end = (offset + body["length"].as_u64()?).min(data.len() as u64);
+                    let range = &data[offset as usize..end as usize];
+                    let content_range = format!("bytes {}-{}/{}", offset, end - 1, data.len());
+                    Some(
+                        Response::json(serde_json::json!({
+                            "content": base64::prelude::BASE64_STANDARD.encode(range),
+                            "size": data.len(),
+                        }))
+                        .header("Content-Range", &content_range),
+                    )
+                }
+                _ => None,
+            }
+        });
+        let fs = test_fs(
+            &server,
+            FsConfig {
+                small_file_cache_size: 64 * 1024,
+                ..test_config()
+            },
+        );
+        let expected: Vec<u8> = (150_000..151_000u32).map(|i| (i % 251) as u8).collect();
+
+        fs.resolve_attrs("/big").unwrap();
+        assert_eq!(
+            fs.read_range("/big", 150_000, 1000, None).unwrap(),
+            expected
+        );
+        // A file of unknown size may be small enough to cache, so it is
+        // fetched whole
+        assert_eq!(fs.read_range("/small", 0, 4, None).unwrap().len(), 4);
+
+        let reads: Vec<serde_json::Value> = server
+            .requests()
+            .iter()
+            .filter(|request| request.target == "/api/fuse/read")
+            .map(|request| serde_json::from_slice(&request.body).unwrap())
+            .collect();
+        assert_eq!(reads[0]["offset"], 150_000);
+        assert_eq!(reads[0]["length"], 1000);
+        assert!(reads[1].get("offset").is_none());
+        assert!(fs
+            .content_cache
+            .lock()
+            .unwrap()
+            .contains_key(&cache_key("/small")));
+    }
+
+    #[test]
+    fn sourceless_read_result_is_empty_only_at_size_zero() {
+        let server = start_core(|request| match request.json_path().as_deref() {
+            Some("/empty") => Response::json(serde_json::json!({ "size": 0 })),
+            _ => Response::json(serde_json::json!({ "size": 5 })),
+        });
+        let fs = test_fs(&server, test_config());
+
+        assert!(fs.read_range("/empty", 0, 4096, None).unwrap().is_empty());
+        assert_eq!(fs.read_range("/full", 0, 4096, None), Err(libc::EIO));
+    }
+
+    #[test]
+    fn zero_byte_reads_of_an_empty_file_are_empty() {
+        let url = Arc::new(std::sync::OnceLock::<String>::new());
+        let blob_url = Arc::clone(&url);
+        let server = TestServer::start(move |request| match request.target.as_str() {
+            "/api/fuse/health" => Response::json(serde_json::json!({ "status": "ok" })),
+            "/api/fuse/read" => match request.json_path().as_deref() {
+                Some("/inline") => Response::json(serde_json::json!({
+                    "content": "",
+                    "size": 0,
+                    "mtime": 1.0,
+                })),
+                _ => Response::json(serde_json::json!({
+                    "fetchUrl": format!("{}/blob", blob_url.get().unwrap()),
+                    "size": 0,
+                    "mtime": 1.0,
+                })),
+            },
+            _ => Response::new(404, ""),
+        });
+        url.set(server.url.clone()).unwrap();
+
+        for read_align in [None, Some(4096)] {
+            let fs = test_fs(
+                &server,
+                FsConfig {
+                    read_align,
+                    ..test_config()
+                },
+            );
+            for path in ["/inline", "/fetched"] {
+                for (offset, fh) in [(0, None), (0, Some(7)), (10, Some(7))] {
+                    let data = fs.read_range(path, offset, 0, fh);
+                    assert_eq!(data, Ok(vec![]), "{} at {} with {:?}", path, offset, fh);
+                }
+                assert!(fs.read_range(path, 0, 4096, Some(7)).unwrap().is_empty());
+            }
+        }
+        assert_eq!(server.count("/blob"), 0);
+    }
+
+    #[test]
+    fn inline_content_is_checked_against_its_sha256() {
+        use base64::Engine;
+        // sha256("hello")
+        const HELLO: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+        let server = start_core(|request| {
+            let checksum = match request.json_path().as_deref() {
+                Some("/good") => Some(HELLO.to_string()),
+                Some("/upper") => Some(HELLO.to_ascii_uppercase()),
+                Some("/bad") => Some(HELLO.replace('2', "3")),
+                _ => None,
+            };
+            Response::json(serde_json::json!({
+                "content": base64::prelude::BASE64_STANDARD.encode(b"hello"),
+                "size": 5,
+                "checksum": checksum,
+            }))
+        });
+        let fs = test_fs(
+            &server,
+            FsConfig {
+                verify_checksums: true,
+                ..test_config()
+            },
+        );
+
+        assert_eq!(fs.read_range("/good", 0, 4096, None).unwrap(), b"hello");
+        assert_eq!(fs.read_range("/upper", 1, 3, None).unwrap(), b"ell");
+        assert_eq!(fs.read_range("/absent", 0, 4096, None).unwrap(), b"hello");
+        assert_eq!(fs.read_range("/bad", 0, 4096, None), Err(libc::EIO));
+        // Nothing that failed the check is served from the cache later
+        assert!(!fs
+            .content_cache
+            .lock()
+            .unwrap()
+            .contains_key(&cache_key("/bad")));
+
+        // With verification off the mismatch goes unnoticed
+        let fs = test_fs(&server, test_config());
+        assert_eq!(fs.read_range("/bad", 0, 4096, None).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn special_modes_map_to_their_node_types() {
+        assert_eq!(file_type_from_mode(0o040755), FileType::Directory);
+        assert_eq!(file_type_from_mode(0o020666), FileType::CharDevice);
+        assert_eq!(file_type_from_mode(0o060660), FileType::BlockDevice);
+        assert_eq!(file_type_from_mode(0o010644), FileType::NamedPipe);
+        assert_eq!(file_type_from_mode(0o140777), FileType::Socket);
+        assert_eq!(file_type_from_mode(0o120777), FileType::Symlink);
+        assert_eq!(file_type_from_mode(0o100644), FileType::RegularFile);
+        assert_eq!(file_type_from_mode(0o644), FileType::RegularFile);
+
+        let server = start_core(|_| Response::new(404, ""));
+        let fs = test_fs(&server, test_config());
+        let device: api_client::FileAttributes = serde_json::from_value(
+            serde_json::json!({ "size": 0, "mode": 0o020666, "mtime": 1.0, "rdev": 0x0103 }),
+        )
+        .unwrap();
+        let attr = fs.convert_attrs("/null", device);
+        assert_eq!(attr.kind, FileType::CharDevice);
+        assert_eq!(attr.rdev, 0x0103);
+        assert_eq!(fs.convert_attrs("/f", file_of(1, 1.0)).rdev, 0);
+    }
+
+    #[test]
+    fn dir_nlink_counts_cached_subdirectories() {
+        let server = start_core(|_| Response::new(404, ""));
+        let fs = test_fs(&server, test_config());
+        let dir: api_client::FileAttributes = serde_json::from_value(
+            serde_json::json!({ "size": 0, "mode": 0o040755, "mtime": 1.0 }),
+        )
+        .unwrap();
+
+        assert_eq!(fs.dir_nlink("/d"), None);
+        fs.cache_readdir(
+            "/d",
+            vec!["a".to_string(), "b".to_string(), "f".to_string()],
+        );
+        // Any child without cached attributes leaves the count unknown
+        assert_eq!(fs.dir_nlink("/d"), None);
+        for name in ["/d/a", "/d/b"] {
+            fs.cache_attrs(name, dir.clone(), None, CacheControl::default());
+        }
+        fs.cache_attrs("/d/f", file_of(1, 1.0), None, CacheControl::default());
+        assert_eq!(fs.dir_nlink("/d"), Some(4));
+
+        fs.cache_readdir("/e", Vec::new());
+        assert_eq!(fs.dir_nlink("/e"), Some(2));
+    }
+
+    #[test]
+    fn error_content_is_frozen_for_the_outage() {
+        let mut health = ApiHealth::new(Duration::from_secs(30), 2, None);
+        let status = health.status();
+        health.record_error("first".to_string());
+        assert!(status.error_content().is_none());
+        health.record_error("second".to_string());
+        let snapshot = status.error_content().unwrap();
+        assert!(snapshot.contains("second"));
+
+        health.record_error("third".to_string());
+        assert_eq!(status.error_content().unwrap(), snapshot);
+        assert!(health.render_error_content().contains("third"));
+
+        health.record_success();
+        assert!(status.error_content().is_none());
+        health.record_error("fourth".to_string());
+        health.record_error("fifth".to_string());
+        assert!(status.error_content().unwrap().contains("fifth"));
+    }
+
+    #[test]
+    fn listed_directory_slashes_are_dropped_from_names_and_cache_keys() {
+        assert_eq!(join_path("/", "sub/"), "/sub");
+        assert_eq!(join_path("/d/", "sub/"), "/d/sub");
+
+        let server = capable_core(&["readdirplus"], |request| {
+            (request.target == "/api/fuse/readdirplus").then(|| {
+                Response::json(serde_json::json!({ "entries": [
+                    { "name": "sub/", "attrs": { "size": 0, "mode": 0o040755, "mtime": 1.0 } },
+                    { "name": "f", "attrs": { "size": 3, "mode": 0o100644, "mtime": 1.0 } },
+                ] }))
+            })
+        });
+        let fs = test_fs(&server, test_config());
+        let (names, _) = fs.fetch_dir_page("/d", None).unwrap();
+        assert_eq!(names, ["sub", "f"]);
+        assert!(fs
+            .attr_cache
+            .lock()
+            .unwrap()
+            .contains_key(&cache_key("/d/sub")));
+
+        let server = start_core(|request| match request.target.as_str() {
+            "/api/fuse/readdir" => Response::json(serde_json::json!({ "entries": ["sub/", "f"] })),
+            _ => Response::new(404, ""),
+        });
+        let fs = test_fs(&server, test_config());
+        let (names, _) = fs.fetch_dir_page("/d", None).unwrap();
+        assert_eq!(names, ["sub", "f"]);
+    }
+
+    #[test]
+    fn differently_spelled_paths_share_inodes_and_cache_entries() {
+        let mut mapper = InodeMapper::new(false);
+        let ino = mapper.get_or_create_ino("/a/b");
+        assert_eq!(mapper.get_or_create_ino("//a//b/"), ino);
+        assert_eq!(mapper.get_ino("a/b"), Some(ino));
+
+        let server = start_core(|_| Response::new(404, ""));
+        let fs = test_fs(&server, test_config());
+        fs.cache_attrs("/a//b/", file_of(7, 1.0), None, CacheControl::default());
+        assert_eq!(fs.get_cached_attrs("/a/b").unwrap().size, 7);
+    }
+
+    #[test]
+    fn inline_content_over_the_limit_is_never_read() {
+        let server = start_core(|request| match request.target.as_str() {
+            "/plain" => Response::new(200, "from url"),
+            _ => Response::new(404, ""),
+        });
+        let fs = test_fs(
+            &server,
+            FsConfig {
+                max_inline_size: 4,
+                ..test_config()
+            },
+        );
+        let inline = |extra: serde_json::Value| {
+            let mut result = serde_json::json!({
+                "content": base64::prelude::BASE64_STANDARD.encode(b"from inline"),
+                "size": 11,
+            });
+            result
+                .as_object_mut()
+                .unwrap()
+                .extend(extra.as_object().unwrap().clone());
+            serde_json::from_value::<api_client::ReadResult>(result).unwrap()
+        };
+
+        let only_inline = inline(serde_json::json!({}));
+        assert!(fs
+            .read_file_content("/f", &only_inline, 0, 4096, None, false)
+            .is_err());
+        let with_url = inline(serde_json::json!({ "fetchUrl": format!("{}/plain", server.url) }));
+        let data = fs
+            .read_file_content("/f", &with_url, 0, 4096, None, false)
+            .unwrap();
+        assert_eq!(data, b"from url");
+    }
+
+    #[test]
+    fn jittered_ttls_stay_within_the_percentage() {
+        let ttl = CacheTtl {
+            base: Duration::from_secs(100),
+            jitter_pct: 10,
+            adaptive_max: None,
+        };
+        let samples: Vec<Duration> = (0..200).map(|_| ttl.sample()).collect();
+        assert!(samples
+            .iter()
+            .all(|&s| s >= Duration::from_secs(90) && s <= Duration::from_secs(110)));
+        assert!(samples.iter().any(|&s| s != samples[0]));
+
+        let fixed = CacheTtl {
+            jitter_pct: 0,
+            ..ttl
+        };
+        assert!((0..20).all(|_| fixed.sample() == Duration::from_secs(100)));
+    }
+
+    #[test]
+    fn confined_symlinks_stay_under_the_mount() {
+        let mount = std::path::Path::new("/mnt/meta");
+        for (link, target, confined) in [
+            ("/a/link", "b", true),
+            ("/a/link", "../b", true),
+            ("/a/link", "../../b", false),
+            ("/link", "..", false),
+            ("/a/link", "./b/../../c", true),
+            ("/a/link", "/mnt/meta/c", true),
+            ("/a/link", "/mnt/meta/../etc", false),
+            ("/a/link", "/etc/passwd", false),
+            ("/a/link", "/mnt/metadata", false),
+        ] {
+            assert_eq!(
+                symlink_is_confined(link, target, mount),
+                confined,
+                "{} -> {}",
+                link,
+                target
+            );
+        }
+    }
+
+    #[test]
+    fn source_path_tail_reads_allocate_only_what_remains() {
+        let local = std::env::temp_dir().join(format!("meta-fuse-tail-{}", std::process::id()));
+        std::fs::write(&local, b"0123456789").unwrap();
+        let server = start_core(|_| Response::new(404, ""));
+        let fs = test_fs(&server, test_config());
+        let result: api_client::ReadResult = serde_json::from_value(serde_json::json!({
+            "sourcePath": local.to_str().unwrap(),
+            "size": 10,
+        }))
+        .unwrap();
+
+        let (data, peak) = crate::test_server::peak_allocated(|| {
+            fs.read_source_path("/f", &result, 6, 16 << 20, None, false)
+        });
+        assert_eq!(data.unwrap(), b"6789");
+        assert!(peak < 64 * 1024, "peak {} bytes", peak);
+        assert!(fs
+            .read_source_path("/f", &result, 20, 4096, None, false)
+            .unwrap()
+            .is_empty());
+        std::fs::remove_file(&local).unwrap();
+    }
+
+    #[test]
+    fn entry_names_must_be_one_component() {
+        assert_eq!(entry_name("file"), Some("file"));
+        assert_eq!(entry_name("dir/"), Some("dir"));
+        assert_eq!(entry_name("dir//"), Some("dir"));
+        for raw in ["", "/", ".", "..", "../", "a/b", "a\0b", "nul\0"] {
+            assert_eq!(entry_name(raw), None, "{:?}", raw);
+        }
+
+        let server = start_core(|request| match request.target.as_str() {
+            "/api/fuse/readdir" => {
+                Response::json(serde_json::json!({ "entries": ["ok", "a/b", "c\0"] }))
+            }
+            _ => Response::new(404, ""),
+        });
+        let fs = test_fs(&server, test_config());
+        let (names, _) = fs.fetch_dir_page("/d", None).unwrap();
+        assert_eq!(names, ["ok"]);
+    }
+
+    #[test]
+    fn cache_control_sets_or_forbids_entry_lifetimes() {
+        let ttl = CacheTtl {
+            base: Duration::from_secs(5),
+            jitter_pct: 0,
+            adaptive_max: None,
+        };
+        let with = |max_age, no_store, immutable| CacheControl {
+            max_age,
+            no_store,
+            immutable,
+        };
+
+        assert_eq!(
+            ttl.for_response(&CacheControl::default()),
+            Some(Duration::from_secs(5))
+        );
+        let max_age = with(Some(Duration::from_secs(60)), false, false);
+        assert_eq!(ttl.for_response(&max_age), Some(Duration::from_secs(60)));
+        assert_eq!(
+            ttl.for_response(&with(None, false, true)),
+            Some(IMMUTABLE_TTL)
+        );
+        assert_eq!(
+            ttl.for_response(&with(Some(Duration::ZERO), true, true)),
+            None
+        );
+    }
+
+    #[test]
+    fn changed_attrs_purge_cached_content() {
+        let server = start_core(|request| match request.target.as_str() {
+            "/api/fuse/read" => Response::json(serde_json::json!({
+                "content": base64::prelude::BASE64_STANDARD.encode(b"abc"),
+                "size": 3,
+                "mtime": 1.0,
+            })),
+            _ => Response::new(404, ""),
+        });
+        let fs = test_fs(&server, test_config());
+        let cached = || {
+            fs.content_cache
+                .lock()
+                .unwrap()
+                .contains_key(&cache_key("/f"))
+        };
+        let refresh =
+            |size, mtime| fs.cache_attrs("/f", file_of(size, mtime), None, CacheControl::default());
+
+        refresh(3, 1.0);
+        fs.read_range("/f", 0, 4096, None).unwrap();
+        assert!(cached());
+        refresh(3, 1.0);
+        assert!(cached());
+        refresh(4, 1.0);
+        assert!(!cached());
+
+        fs.read_range("/f", 0, 4096, None).unwrap();
+        assert!(cached());
+        refresh(3, 2.0);
+        assert!(!cached());
+    }
+
+    #[test]
+    fn unchanged_revalidations_grow_attr_ttls_up_to_the_max() {
+        let ttl = CacheTtl {
+            base: Duration::from_secs(5),
+            jitter_pct: 0,
+            adaptive_max: Some(Duration::from_secs(30)),
+        };
+        let mut cache = HashMap::new();
+        let entry_ttl = |cache: &HashMap<CacheKey, CachedAttrs>| cache[&cache_key("/f")].ttl;
+        let store = |cache: &mut HashMap<_, _>, size| {
+            insert_attrs(
+                cache,
+                &ttl,
+                "/f",
+                file_of(size, 1.0),
+                None,
+                CacheControl::default(),
+            )
+        };
+
+        store(&mut cache, 1);
+        assert_eq!(entry_ttl(&cache), Duration::from_secs(5));
+        store(&mut cache, 1);
+        assert_eq!(entry_ttl(&cache), Duration::from_secs(10));
+        store(&mut cache, 1);
+        assert_eq!(entry_ttl(&cache), Duration::from_secs(20));
+        cache.get_mut(&cache_key("/f")).unwrap().renew(&ttl);
+        assert_eq!(entry_ttl(&cache), Duration::from_secs(30));
+        store(&mut cache, 2);
+        assert_eq!(entry_ttl(&cache), Duration::from_secs(5));
+
+        let fixed = CacheTtl {
+            adaptive_max: None,
+            ..ttl
+        };
+        assert_eq!(
+            fixed.extended(Duration::from_secs(5), 3),
+            Duration::from_secs(5)
+        );
+    }
+
+    #[test]
+    fn truncating_opens_count_as_writes() {
+        assert!(!opens_for_writing(libc::O_RDONLY));
+        assert!(!opens_for_writing(libc::O_RDONLY | libc::O_NOFOLLOW));
+        assert!(opens_for_writing(libc::O_WRONLY));
+        assert!(opens_for_writing(libc::O_RDWR));
+        assert!(opens_for_writing(libc::O_RDONLY | libc::O_TRUNC));
+    }
+
+    #[test]
+    fn hide_prefixes_match_whole_components() {
+        let prefixes = parse_hide_prefixes(" secret/, /a//b ,,/,/secret");
+        assert_eq!(prefixes, ["/secret", "/a/b"]);
+
+        assert!(is_hidden("/secret", &prefixes));
+        assert!(is_hidden("/secret/x", &prefixes));
+        assert!(is_hidden("/a/b/c", &prefixes));
+        assert!(!is_hidden("/secretsauce", &prefixes));
+        assert!(!is_hidden("/a", &prefixes));
+        assert!(!is_hidden("/", &prefixes));
+        assert!(!is_hidden("/secret", &[]));
+    }
+
+    #[test]
+    fn expired_listing_is_renewed_while_the_dir_mtime_holds() {
+        let mtime = Arc::new(Mutex::new(1.0));
+        let current = Arc::clone(&mtime);
+        let server = start_core(move |request| match request.target.as_str() {
+            "/api/fuse/getattr" => Response::json(serde_json::json!({
+                "size": 0,
+                "mode": 0o040755,
+                "mtime": *current.lock().unwrap(),
+            })),
+            _ => Response::new(404, ""),
+        });
+        let fs = test_fs(
+            &server,
+            FsConfig {
+                dir_mtime_validation: true,
+                ..test_config()
+            },
+        );
+        let dir = serde_json::from_value(
+            serde_json::json!({ "size": 0, "mode": 0o040755, "mtime": 1.0 }),
+        )
+        .unwrap();
+        fs.cache_attrs("/d", dir, None, CacheControl::default());
+        fs.cache_readdir("/d", vec!["a".to_string()]);
+        let expire = || {
+            let mut cache = fs.dir_cache.lock().unwrap();
+            let cached = cache.get_mut(&cache_key("/d")).unwrap();
+            cached.timestamp -= cached.ttl + Duration::from_secs(1);
+        };
+
+        expire();
+        assert_eq!(fs.get_cached_readdir("/d").unwrap(), ["a"]);
+        assert_eq!(server.count("/api/fuse/getattr"), 1);
+        // Renewed, so fresh again without asking
+        assert_eq!(fs.get_cached_readdir("/d").unwrap(), ["a"]);
+        assert_eq!(server.count("/api/fuse/getattr"), 1);
+
+        *mtime.lock().unwrap() = 2.0;
+        expire();
+        assert!(fs.get_cached_readdir("/d").is_none());
+    }
+
+    #[test]
+    fn interrupted_io_is_retried_and_other_errors_are_not() {
+        let mut calls = 0;
+        let result = retry_interrupted(|| {
+            calls += 1;
+            match calls {
+                1 | 2 => Err(std::io::Error::from(std::io::ErrorKind::Interrupted)),
+                _ => Ok(calls),
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        let mut calls = 0;
+        let result: std::io::Result<()> = retry_interrupted(|| {
+            calls += 1;
+            Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied))
+        });
+        assert_eq!(
+            result.unwrap_err().kind(),
+            std::io::ErrorKind::PermissionDenied
+        );
+        assert_eq!(calls, 1);
+    }
+
+    fn attrs_with(mode: u32, uid: u32, gid: u32) -> api_client::FileAttributes {
+        serde_json::from_value(serde_json::json!({
+            "size": 1, "mode": mode, "mtime": 1.0, "uid": uid, "gid": gid,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn access_checks_the_owner_group_and_other_bits() {
+        let none = Vec::new;
+        // rw- r-- --x, owned by 10:20
+        let attrs = attrs_with(0o100641, 10, 20);
+        let owner = (10, 20);
+        let (r, w, x) = (libc::R_OK, libc::W_OK, libc::X_OK);
+
+        assert!(access_permitted(&attrs, owner, 10, 99, none, r | w));
+        assert!(!access_permitted(&attrs, owner, 10, 20, none, x));
+
+        assert!(access_permitted(&attrs, owner, 11, 20, none, r));
+        assert!(!access_permitted(&attrs, owner, 11, 20, none, w));
+        // A supplementary group counts as the group class too
+        assert!(access_permitted(&attrs, owner, 11, 30, || vec![5, 20], r));
+        assert!(!access_permitted(&attrs, owner, 11, 30, || vec![5], r));
+
+        assert!(access_permitted(&attrs, owner, 11, 30, none, x));
+        assert!(!access_permitted(&attrs, owner, 11, 30, none, r));
+        // The owner class wins even where the others grant more
+        assert!(!access_permitted(&attrs, owner, 10, 30, none, x));
+    }
+
+    #[test]
+    fn root_needs_an_execute_bit_only_on_files() {
+        let none = Vec::new;
+        let file = attrs_with(0o100600, 10, 20);
+        assert!(access_permitted(
+            &file,
+            (10, 20),
+            0,
+            0,
+            none,
+            libc::R_OK | libc::W_OK
+        ));
+        assert!(!access_permitted(&file, (10, 20), 0, 0, none, libc::X_OK));
+        let script = attrs_with(0o100601, 10, 20);
+        assert!(access_permitted(&script, (10, 20), 0, 0, none, libc::X_OK));
+        let dir = attrs_with(0o40700, 10, 20);
+        assert!(access_permitted(&dir, (10, 20), 0, 0, none, libc::X_OK));
+    }
+
+    #[test]
+    fn access_uses_the_owner_stat_shows() {
+        let server = start_core(|_| Response::new(404, ""));
+        let attrs = attrs_with(0o100600, 10, 20);
+        let fs = test_fs(&server, test_config());
+        let owner = fs.presented_owner(&attrs);
+        assert_eq!(owner, (fs.config.uid, fs.config.gid));
+        let uid = fs.convert_attrs("/f", attrs.clone()).uid;
+        assert!(access_permitted(
+            &attrs,
+            owner,
+            uid,
+            0,
+            Vec::new,
+            libc::R_OK
+        ));
+        assert!(!access_permitted(
+            &attrs,
+            owner,
+            10,
+            20,
+            Vec::new,
+            libc::R_OK
+        ));
+
+        let fs = test_fs(
+            &server,
+            FsConfig {
+                use_api_ownership: true,
+                ..test_config()
+            },
+        );
+        assert_eq!(fs.presented_owner(&attrs), (10, 20));
+        assert_eq!(fs.convert_attrs("/f", attrs).uid, 10);
+    }
+
+    #[test]
+    fn supplementary_groups_come_from_the_status_groups_line() {
+        let status = "Name:\tsh\nUid:\t1000\t1000\t1000\t1000\nGroups:\t4 24 1000 \nNgid:\t0\n";
+        assert_eq!(status_groups(status), [4, 24, 1000]);
+        assert!(status_groups("Groups:\n").is_empty());
+        assert!(status_groups("Name:\tsh\n").is_empty());
+        assert_eq!(supplementary_groups(std::process::id()), unsafe {
+            let mut groups = vec![0; 64];
+            let n = libc::getgroups(groups.len() as i32, groups.as_mut_ptr());
+            groups.truncate(n.max(0) as usize);
+            groups
+        });
+    }
+
+    #[test]
+    fn display_mode_overrides_the_presented_permissions() {
+        let server = start_core(|_| Response::new(404, ""));
+        let fs = test_fs(&server, test_config());
+        let attrs: api_client::FileAttributes = serde_json::from_value(serde_json::json!({
+            "size": 1,
+            "mode": 0o100666,
+            "mtime": 1.0,
+            "displayMode": 0o104440,
+        }))
+        .unwrap();
+
+        let attr = fs.convert_attrs("/f", attrs);
+        assert_eq!(attr.perm, 0o4440);
+        assert_eq!(attr.kind, FileType::RegularFile);
+        assert_eq!(fs.convert_attrs("/g", file_of(1, 1.0)).perm, 0o644);
+    }
+
+    #[test]
+    fn several_ranges_are_fetched_in_one_multipart_request() {
+        let data: Vec<u8> = (b'a'..=b't').collect();
+        let server = start_core(move |request| {
+            let range = request.header("range").unwrap_or_default();
+            match (request.target.as_str(), range) {
+                ("/multi", "bytes=0-2,10-14") => {
+                    let mut body = Vec::new();
+                    for (start, end) in [(0, 2), (10, 14)] {
+                        body.extend_from_slice(
+                            format!("--XX\r\nContent-Range: bytes {}-{}/20\r\n\r\n", start, end)
+                                .as_bytes(),
+                        );
+                        body.extend_from_slice(&data[start..=end]);
+                        body.extend_from_slice(b"\r\n");
+                    }
+                    body.extend_from_slice(b"--XX--\r\n");
+                    Response::new(206, body)
+                        .header("Content-Type", "multipart/byteranges; boundary=XX")
+                }
+                ("/whole", _) => Response::new(200, data.clone()),
+                _ => Response::new(404, ""),
+            }
+        });
+        let fs = test_fs(&server, test_config());
+        fs.multi_range_supported.store(true, Ordering::Relaxed);
+        let ranges = [(0, 2), (10, 14)];
+        let expected = vec![(0, b"abc".to_vec()), (10, b"klmno".to_vec())];
+
+        let multi = fs.fetch_ranges(&format!("{}/multi", server.url), &ranges, 20);
+        assert_eq!(multi.unwrap().unwrap(), expected);
+        let whole = fs.fetch_ranges(&format!("{}/whole", server.url), &ranges, 20);
+        assert_eq!(whole.unwrap().unwrap(), expected);
+        assert_eq!(server.count("/multi") + server.count("/whole"), 2);
+    }
+
+    #[test]
+    fn overlapping_concurrent_reads_fetch_each_block_once() {
+        const SIZE: usize = 16 * FETCH_BLOCK_SIZE;
+        let data: Arc<Vec<u8>> = Arc::new((0..SIZE).map(|i| (i % 251) as u8).collect());
+        let body = Arc::clone(&data);
+        let server = start_core(move |request| {
+            let Some(range) = request.header("range") else {
+                return Response::new(404, "");
+            };
+            // Widen the window in which a second reader could miss the
+            // same block
+            std::thread::sleep(Duration::from_millis(20));
+            let spec = range.trim_start_matches("bytes=");
+            if spec.contains(',') {
+                // Several ranges: answer with the whole file
+                return Response::new(200, body.to_vec());
+            }
+            let (start, end) = spec.split_once('-').unwrap();
+            let (start, end): (usize, usize) = (start.parse().unwrap(), end.parse().unwrap());
+            Response::new(206, body[start..=end].to_vec())
+        });
+        let fs = test_fs(&server, test_config());
+        fs.file_handles
+            .lock()
+            .unwrap()
+            .insert(7, FileHandle::default());
+        let url = format!("{}/blob", server.url);
+
+        std::thread::scope(|scope| {
+            for i in 0..16 {
+                let (fs, url, data) = (&fs, &url, &data);
+                scope.spawn(move || {
+                    let offset = (i % 8) * 48 * 1024;
+                    let read = fs.read_blocks(7, url, offset, 128 * 1024, SIZE as u64);
+                    assert_eq!(read.unwrap().unwrap(), &data[offset..offset + 128 * 1024]);
+                });
+            }
+        });
+
+        let mut fetched = Vec::new();
+        for request in server.requests() {
+            let Some(range) = request.header("range") else {
+                continue;
+            };
+            for spec in range.trim_start_matches("bytes=").split(',') {
+                let (start, end) = spec.split_once('-').unwrap();
+                let (start, end): (usize, usize) = (start.parse().unwrap(), end.parse().unwrap());
+                fetched.extend(start / FETCH_BLOCK_SIZE..=end / FETCH_BLOCK_SIZE);
+            }
+        }
+        let distinct: HashSet<usize> = fetched.iter().copied().collect();
+        assert_eq!(
+            fetched.len(),
+            distinct.len(),
+            "blocks fetched: {:?}",
+            fetched
+        );
+        assert!(fs.blocks_in_flight.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn stats_report_counts_entries_hits_and_latency() {
+        let server = start_core(|_| {
+            Response::json(serde_json::json!({ "size": 3, "mode": 0o100644, "mtime": 1.0 }))
+        });
+        let fs = test_fs(&server, test_config());
+        assert!(!fs.render_stats().contains("API latency"));
+
+        fs.resolve_attrs("/f").unwrap();
+        fs.resolve_attrs("/f").unwrap();
+        fs.stats.dir_hits.fetch_add(4, Ordering::Relaxed);
+        fs.stats.small_file_hits.fetch_add(2, Ordering::Relaxed);
+        fs.stats.content_hits.fetch_add(3, Ordering::Relaxed);
+
+        let report = fs.render_stats();
+        assert!(report.lines().any(|l| l.starts_with("Uptime: ")));
+        for line in [
+            "Directory cache: 0 entries, 4 hits, 0 misses",
+            "Attribute cache: 1 entries, 1 hits, 1 misses",
+            "Content cache: 0 entries, 3 hits (2 without an API call), 0 misses",
+            "Sniff cache: 0 entries, 0 hits, 0 misses",
+            "Consecutive API errors: 0",
+            "API requests in flight: 0 (limit unlimited)",
+            "API latency (moving average):",
+        ] {
+            assert!(
+                report.lines().any(|l| l == line),
+                "{:?} in\n{}",
+                line,
+                report
+            );
+        }
+        assert!(report.contains("  getattr: "), "{}", report);
+        assert_eq!(
+            fs.get_stats_file_attrs().size,
+            fs.render_stats().len() as u64
+        );
+    }
+
+    #[test]
+    fn directories_report_one_block_of_size() {
+        let server = start_core(|_| Response::new(404, ""));
+        let fs = test_fs(&server, test_config());
+        let dir = |size: u64| {
+            serde_json::from_value(
+                serde_json::json!({ "size": size, "mode": 0o040755, "mtime": 1.0 }),
+            )
+            .unwrap()
+        };
+
+        for size in [0, 1, 1 << 20] {
+            assert_eq!(
+                fs.convert_attrs("/d", dir(size)).size,
+                u64::from(fs.config.blksize)
+            );
+        }
+        let empty = fs.convert_attrs("/f", file_of(0, 1.0));
+        assert_eq!((empty.size, empty.blocks), (0, 0));
+        assert_eq!(fs.convert_attrs("/f", file_of(3, 1.0)).size, 3);
+    }
+
+    #[test]
+    fn hashed_inodes_are_stable_and_never_reserved() {
+        for i in 0..10_000 {
+            let path = format!("/dir/file-{}", i);
+            let ino = path_ino_hash(&path);
+            assert!(ino > LAST_SYNTHETIC_INO, "{} hashed to {}", path, ino);
+            assert_eq!(ino, path_ino_hash(&path));
+        }
+        assert_eq!(path_ino_hash("/a"), fnv1a_64(b"/a"));
+
+        let mut mapper = InodeMapper::new(true);
+        assert_eq!(mapper.get_or_create_ino("/a"), path_ino_hash("/a"));
+        assert_eq!(
+            InodeMapper::new(true).get_or_create_ino("/a"),
+            path_ino_hash("/a")
+        );
+    }
+
+    #[test]
+    fn birth_time_comes_from_crtime_or_btime() {
+        let server = start_core(|_| Response::new(404, ""));
+        let fs = test_fs(&server, test_config());
+        let with = |extra: serde_json::Value| {
+            let mut attrs = serde_json::json!({ "size": 1, "mode": 0o100644, "mtime": 9.0 });
+            attrs
+                .as_object_mut()
+                .unwrap()
+                .extend(extra.as_object().unwrap().clone());
+            serde_json::from_value::<api_client::FileAttributes>(attrs).unwrap()
+        };
+
+        let crtime = fs
+            .convert_attrs("/f", with(serde_json::json!({ "crtime": 2.5 })))
+            .crtime;
+        assert_eq!(crtime, UNIX_EPOCH + Duration::from_millis(2500));
+        let btime = fs
+            .convert_attrs("/f", with(serde_json::json!({ "btime": 3.0 })))
+            .crtime;
+        assert_eq!(btime, UNIX_EPOCH + Duration::from_secs(3));
+        assert_eq!(
+            fs.convert_attrs("/f", with(serde_json::json!({}))).crtime,
+            UNIX_EPOCH
+        );
+    }
+
+    #[test]
+    fn blocks_come_from_core_when_given() {
+        let server = start_core(|_| Response::new(404, ""));
+        let fs = test_fs(&server, test_config());
+        let sparse: api_client::FileAttributes = serde_json::from_value(
+            serde_json::json!({ "size": 1 << 20, "mode": 0o100644, "mtime": 1.0, "blocks": 8 }),
+        )
+        .unwrap();
+
+        assert_eq!(fs.convert_attrs("/sparse", sparse).blocks, 8);
+        let dense = fs.convert_attrs("/dense", file_of(1 << 20, 1.0));
+        assert_eq!(dense.blocks, fs.blocks(1 << 20));
+        assert_eq!(dense.blocks, (1 << 20) / 512);
     }
 }
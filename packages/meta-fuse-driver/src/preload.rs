@@ -0,0 +1,229 @@
+use crate::api_client::{ApiClient, ApiError, CacheControl, DirEntryPlus};
+use crate::{
+    entry_name, file_type_from_mode, join_path, ApiHealth, SharedCaches, DIR_CACHE_MAX_ENTRIES,
+};
+use fuser::FileType;
+use log::{debug, info, warn};
+use reqwest::StatusCode;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How often the preload logs how far it has got
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Walk the tree breadth-first down to `max_depth` levels below the root in
+/// the background, filling the directory and attribute caches.
+///
+/// Each listing counts towards the API health like any other call. The walk
+/// stops early once `shutdown` is set, or once the circuit opens, since it
+/// only warms caches that callers fill on demand anyway.
+pub fn spawn_preload(
+    api: ApiClient,
+    caches: SharedCaches,
+    health: Arc<Mutex<ApiHealth>>,
+    max_depth: usize,
+    shutdown: Arc<AtomicBool>,
+) {
+    let spawned = std::thread::Builder::new()
+        .name("fuse-preload".to_string())
+        .spawn(move || preload(&api, &caches, &health, max_depth, &shutdown));
+
+    if let Err(e) = spawned {
+        warn!("Failed to start cache preload: {}", e);
+    }
+}
+
+fn preload(
+    api: &ApiClient,
+    caches: &SharedCaches,
+    health: &Mutex<ApiHealth>,
+    max_depth: usize,
+    shutdown: &AtomicBool,
+) {
+    let started = Instant::now();
+    let mut last_progress = started;
+    let mut queue = VecDeque::from([("/".to_string(), 1)]);
+    let mut dirs = 0usize;
+    let mut entries = 0usize;
+
+    info!("Preloading caches to depth {}", max_depth);
+
+    while let Some((path, depth)) = queue.pop_front() {
+        if shutdown.load(Ordering::Relaxed) {
+            info!("Preload interrupted after {} directories", dirs);
+            return;
+        }
+
+        if !health.lock().unwrap().allow_request() {
+            info!("Circuit open, stopping preload after {} directories", dirs);
+            return;
+        }
+
+        let request_id = crate::api_client::begin_request(None);
+        debug!("preload: path={} request_id={}", path, request_id);
+        let listing = list_dir(api, &path);
+        match &listing {
+            Ok(_) => health.lock().unwrap().record_success(),
+            Err(e) => health
+                .lock()
+                .unwrap()
+                .record_api_error(e, format!("preload failed to list {}: {}", path, e)),
+        }
+        let listing = match listing {
+            Ok(listing) => listing,
+            Err(ApiError::Status(StatusCode::NOT_FOUND)) if path == "/" => {
+                info!("API does not support readdirplus, skipping preload");
+                return;
+            }
+            Err(e) => {
+                debug!("Preload failed to list {}: {}", path, e);
+                continue;
+            }
+        };
+
+        let mut names = Vec::with_capacity(listing.len());
+        for entry in listing {
+            if entry.error.is_some() {
+                continue;
+            }
+            let Some(name) = entry_name(&entry.name) else {
+                debug!(
+                    "Preload skipping invalid entry name {:?} in {}",
+                    entry.name, path
+                );
+                continue;
+            };
+            let child = join_path(&path, name);
+            if let Some(attrs) = entry.attrs {
+                if depth < max_depth && file_type_from_mode(attrs.mode) == FileType::Directory {
+                    queue.push_back((child.clone(), depth + 1));
+                }
+                caches.store_attrs(&child, attrs, None, CacheControl::default());
+            }
+            names.push(name.to_string());
+        }
+
+        dirs += 1;
+        entries += names.len();
+        if names.len() <= DIR_CACHE_MAX_ENTRIES {
+            caches.store_listing(&path, names);
+        }
+
+        if last_progress.elapsed() >= PROGRESS_INTERVAL {
+            info!(
+                "Preload progress: {} directories, {} entries, {} queued",
+                dirs,
+                entries,
+                queue.len()
+            );
+            last_progress = Instant::now();
+        }
+    }
+
+    info!(
+        "Preload finished: {} directories, {} entries in {:.1}s",
+        dirs,
+        entries,
+        started.elapsed().as_secs_f64()
+    );
+}
+
+/// Fetch every page of a directory via readdirplus
+fn list_dir(api: &ApiClient, path: &str) -> Result<Vec<DirEntryPlus>, ApiError> {
+    let mut entries = Vec::new();
+    let mut cursor = None;
+
+    loop {
+        let page = api.readdir_plus_page(path, cursor.as_deref())?;
+        entries.extend(page.entries);
+        cursor = page.next_cursor;
+        if cursor.is_none() {
+            return Ok(entries);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::Response;
+    use crate::tests::{start_core, test_config, test_fs};
+    use crate::FsConfig;
+
+    fn dir() -> serde_json::Value {
+        serde_json::json!({ "size": 0, "mode": 0o040755, "mtime": 1.0 })
+    }
+
+    /// A core listing /down and then /ok under the root, and failing to
+    /// list /down with `status`
+    fn core_failing_below(status: u16) -> crate::test_server::TestServer {
+        start_core(move |request| match request.json_path().as_deref() {
+            Some("/") => Response::json(serde_json::json!({
+                "entries": [
+                    { "name": "down", "attrs": dir() },
+                    { "name": "ok", "attrs": dir() },
+                ],
+            })),
+            Some("/down") => Response::new(status, ""),
+            _ => Response::json(serde_json::json!({ "entries": [] })),
+        })
+    }
+
+    fn run(fs: &crate::ApiFS) {
+        preload(
+            &fs.api,
+            &fs.shared_caches(),
+            &fs.api_health,
+            2,
+            &AtomicBool::new(false),
+        );
+    }
+
+    #[test]
+    fn listing_outcomes_count_towards_api_health() {
+        let server = core_failing_below(500);
+        let fs = test_fs(&server, test_config());
+        run(&fs);
+        assert_eq!(server.count("/api/fuse/readdirplus"), 3);
+        let health = fs.api_health.lock().unwrap();
+        assert_eq!(health.recent_errors.len(), 1);
+        assert!(health.last_error_message.contains("/down"));
+        // The listing of /ok after it succeeded
+        assert_eq!(health.consecutive_errors, 0);
+
+        // An answer such as "not found" shows the API is alive
+        let server = core_failing_below(404);
+        let fs = test_fs(&server, test_config());
+        run(&fs);
+        assert!(fs.api_health.lock().unwrap().recent_errors.is_empty());
+    }
+
+    #[test]
+    fn open_circuit_stops_the_preload() {
+        let server = core_failing_below(500);
+        let fs = test_fs(
+            &server,
+            FsConfig {
+                error_threshold: 1,
+                ..test_config()
+            },
+        );
+        run(&fs);
+        // The failure opened the circuit, so /ok queued after it was left
+        assert_eq!(server.count("/api/fuse/readdirplus"), 2);
+        assert!(!fs.api_health.lock().unwrap().allow_request());
+
+        let server = core_failing_below(500);
+        let fs = test_fs(&server, test_config());
+        for _ in 0..crate::DEFAULT_ERROR_THRESHOLD {
+            fs.api_health
+                .lock()
+                .unwrap()
+                .record_error("down".to_string());
+        }
+        run(&fs);
+        assert_eq!(server.count("/api/fuse/readdirplus"), 0);
+    }
+}
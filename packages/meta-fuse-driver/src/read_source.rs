@@ -0,0 +1,112 @@
+//! The places a read result can offer file content from, and the order
+//! FUSE_READ_SOURCE_ORDER tries them in. The mounted driver, export and
+//! benchmark all pick sources the same way.
+
+use crate::api_client::ReadResult;
+use std::fmt;
+
+/// A place file content can be taken from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadSource {
+    /// Base64 content in the read response
+    Inline,
+    /// source_path on a filesystem shared with core
+    Source,
+    Webdav,
+    FetchUrl,
+}
+
+/// Order sources are tried in when FUSE_READ_SOURCE_ORDER isn't set
+pub const DEFAULT_ORDER: [ReadSource; 4] = [
+    ReadSource::Inline,
+    ReadSource::Webdav,
+    ReadSource::FetchUrl,
+    ReadSource::Source,
+];
+
+impl ReadSource {
+    /// Whether a read result names this source at all
+    pub fn offered_by(self, result: &ReadResult) -> bool {
+        match self {
+            ReadSource::Inline => result.content.is_some(),
+            ReadSource::Source => result.source_path.is_some(),
+            ReadSource::Webdav => result.webdav_url.is_some(),
+            ReadSource::FetchUrl => result.fetch_url.is_some(),
+        }
+    }
+}
+
+impl fmt::Display for ReadSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ReadSource::Inline => "inline",
+            ReadSource::Source => "source",
+            ReadSource::Webdav => "webdav",
+            ReadSource::FetchUrl => "fetchurl",
+        })
+    }
+}
+
+/// Parse a comma-separated FUSE_READ_SOURCE_ORDER list. Sources left out
+/// are never read from; an unknown name rejects the whole list.
+pub fn parse_order(spec: &str) -> Result<Vec<ReadSource>, String> {
+    let mut order = Vec::new();
+    for token in spec.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+        let source = match token.to_ascii_lowercase().as_str() {
+            "inline" => ReadSource::Inline,
+            "source" => ReadSource::Source,
+            "webdav" => ReadSource::Webdav,
+            "fetchurl" => ReadSource::FetchUrl,
+            _ => {
+                return Err(format!(
+                    "unknown read source {:?} (expected inline, source, webdav or fetchurl)",
+                    token
+                ))
+            }
+        };
+        if !order.contains(&source) {
+            order.push(source);
+        }
+    }
+    if order.is_empty() {
+        return Err("no read sources listed".to_string());
+    }
+    Ok(order)
+}
+
+/// The error for a non-empty file none of whose sources can be read
+pub fn no_source_error(order: &[ReadSource]) -> Box<dyn std::error::Error> {
+    let listed: Vec<String> = order.iter().map(ReadSource::to_string).collect();
+    format!(
+        "No content source available (tried in order: {})",
+        listed.join(", ")
+    )
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_order_keeps_the_listed_order() {
+        assert_eq!(
+            parse_order("source, WebDAV,inline").unwrap(),
+            [ReadSource::Source, ReadSource::Webdav, ReadSource::Inline]
+        );
+        assert_eq!(
+            parse_order("fetchurl,fetchurl,").unwrap(),
+            [ReadSource::FetchUrl]
+        );
+        assert!(parse_order("inline,ftp").is_err());
+        assert!(parse_order(" , ").is_err());
+    }
+
+    #[test]
+    fn no_source_error_names_the_order() {
+        assert_eq!(
+            no_source_error(&DEFAULT_ORDER).to_string(),
+            "No content source available (tried in order: inline, webdav, fetchurl, source)"
+        );
+    }
+}
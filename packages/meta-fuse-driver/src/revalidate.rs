@@ -0,0 +1,220 @@
+use crate::api_client::{self, ApiClient, ApiError, AttrResponse};
+use crate::{cache_key, ApiHealth, CacheKey, SharedCaches};
+use log::{debug, warn};
+use reqwest::StatusCode;
+use std::collections::HashSet;
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::{Arc, Mutex};
+
+/// A queued refresh: the identity it was asked for under, and the path
+type Refresh = (Option<(u32, u32)>, String);
+
+/// Refreshes expired attribute entries in the background while callers are
+/// served the stale copy.
+///
+/// One worker takes refreshes from a bounded queue, so a slow core costs
+/// queued paths rather than a thread each. A cache key is queued at most
+/// once; `pending` holds the keys queued or being refreshed.
+pub struct AttrRefresher {
+    queue: SyncSender<Refresh>,
+    pending: Arc<Mutex<HashSet<CacheKey>>>,
+}
+
+impl AttrRefresher {
+    /// Start the worker, holding up to `capacity` waiting refreshes; None
+    /// if its thread can't be started
+    pub fn spawn(
+        api: ApiClient,
+        caches: SharedCaches,
+        health: Arc<Mutex<ApiHealth>>,
+        capacity: usize,
+    ) -> Option<Self> {
+        let (queue, receiver) = mpsc::sync_channel::<Refresh>(capacity);
+        let pending = Arc::new(Mutex::new(HashSet::new()));
+        let worker = {
+            let pending = Arc::clone(&pending);
+            move || {
+                // Ends once the filesystem, and with it the sender, is dropped
+                for (identity, path) in receiver {
+                    let request_id = api_client::begin_request(identity);
+                    debug!("Revalidating attrs for {} request_id={}", path, request_id);
+                    refresh(&api, &caches, &health, &path);
+                    pending.lock().unwrap().remove(&cache_key(&path));
+                }
+            }
+        };
+        match std::thread::Builder::new()
+            .name("fuse-revalidate".to_string())
+            .spawn(worker)
+        {
+            Ok(_) => Some(AttrRefresher { queue, pending }),
+            Err(e) => {
+                warn!(
+                    "Failed to start attribute refresh, serving expired attrs until refetched: {}",
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    /// Queue a refresh of `path` for the current identity, unless its key
+    /// is already queued or the queue is full
+    pub fn request(&self, path: &str) {
+        let key = cache_key(path);
+        if !self.pending.lock().unwrap().insert(key.clone()) {
+            return;
+        }
+        let identity = api_client::current_identity();
+        if self.queue.try_send((identity, path.to_string())).is_err() {
+            // Core is falling behind; a later stale hit will ask again
+            debug!("Attribute refresh queue full, dropping {}", path);
+            self.pending.lock().unwrap().remove(&key);
+        }
+    }
+}
+
+fn refresh(api: &ApiClient, caches: &SharedCaches, health: &Mutex<ApiHealth>, path: &str) {
+    if !health.lock().unwrap().allow_request() {
+        debug!("Circuit open, skipping refresh of {}", path);
+        return;
+    }
+
+    let etag = caches.attr_etag(path);
+    match api.getattr(path, etag.as_deref()) {
+        Ok(AttrResponse::Fresh {
+            attrs,
+            etag,
+            cache_control,
+        }) => {
+            health.lock().unwrap().record_success();
+            caches.store_attrs(path, attrs, etag, cache_control);
+        }
+        Ok(AttrResponse::NotModified) => {
+            health.lock().unwrap().record_success();
+            caches.touch_attrs(path);
+        }
+        Err(e) => {
+            health
+                .lock()
+                .unwrap()
+                .record_api_error(&e, format!("getattr refresh failed for {}: {}", path, e));
+            debug!("getattr refresh failed for {}: {}", path, e);
+            // The path is gone, or this user may no longer see it: stop
+            // serving the stale copy
+            match e {
+                ApiError::Status(StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) => {
+                    caches.invalidate(path);
+                }
+                ApiError::Status(StatusCode::TOO_MANY_REQUESTS) => {}
+                ApiError::Status(status) if status.is_client_error() => caches.store_missing(path),
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::Response;
+    use crate::tests::{start_core, test_config, test_fs};
+    use crate::CacheControl;
+    use std::time::{Duration, Instant};
+
+    fn cached_file() -> api_client::FileAttributes {
+        serde_json::from_value(serde_json::json!({ "size": 3, "mode": 0o100644, "mtime": 1.0 }))
+            .unwrap()
+    }
+
+    #[test]
+    fn vanished_path_is_evicted_and_cached_missing() {
+        let server = start_core(|_| Response::new(404, ""));
+        let fs = test_fs(&server, test_config());
+        fs.cache_attrs("/gone", cached_file(), None, CacheControl::default());
+
+        refresh(&fs.api, &fs.shared_caches(), &fs.api_health, "/gone");
+        assert!(fs.get_cached_attrs("/gone").is_none());
+        assert!(fs.is_known_missing("/gone"));
+    }
+
+    #[test]
+    fn refused_path_is_evicted_but_not_cached_missing() {
+        let server = start_core(|_| Response::new(403, ""));
+        let fs = test_fs(&server, test_config());
+        fs.cache_attrs("/private", cached_file(), None, CacheControl::default());
+
+        refresh(&fs.api, &fs.shared_caches(), &fs.api_health, "/private");
+        assert!(fs.get_cached_attrs("/private").is_none());
+        assert!(!fs.is_known_missing("/private"));
+    }
+
+    fn wait_for(what: &str, done: impl Fn() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !done() {
+            assert!(Instant::now() < deadline, "timed out waiting for {}", what);
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn refreshes_are_deduplicated_and_bounded() {
+        // Hold the first refresh in core until the rest are queued
+        let gate = Arc::new(Mutex::new(()));
+        let held = gate.lock().unwrap();
+        let server = start_core({
+            let gate = Arc::clone(&gate);
+            move |request| {
+                if request.json_path().as_deref() == Some("/first") {
+                    drop(gate.lock().unwrap());
+                }
+                Response::json(serde_json::json!({ "size": 3, "mode": 0o100644, "mtime": 2.0 }))
+            }
+        });
+        let fs = test_fs(&server, test_config());
+        let refresher =
+            AttrRefresher::spawn(fs.api.clone(), fs.shared_caches(), fs.api_health.clone(), 2)
+                .unwrap();
+        let getattrs = |path: &str| {
+            server
+                .requests()
+                .iter()
+                .filter(|request| request.json_path().as_deref() == Some(path))
+                .count()
+        };
+
+        refresher.request("/first");
+        wait_for("the first refresh", || getattrs("/first") == 1);
+        for path in ["/a", "/a", "/b", "/a", "/c"] {
+            refresher.request(path);
+        }
+        // /a and /b fill the queue, so /c is dropped
+        assert!(!refresher.pending.lock().unwrap().contains(&cache_key("/c")));
+
+        drop(held);
+        wait_for("the queue to drain", || {
+            refresher.pending.lock().unwrap().is_empty()
+        });
+        assert_eq!(["/first", "/a", "/b", "/c"].map(getattrs), [1, 1, 1, 0]);
+
+        // A dropped path can be asked for again
+        refresher.request("/c");
+        wait_for("the retried refresh", || {
+            refresher.pending.lock().unwrap().is_empty()
+        });
+        assert_eq!(getattrs("/c"), 1);
+        assert!(fs
+            .get_cached_attrs("/c")
+            .is_some_and(|attrs| attrs.mtime == 2.0));
+    }
+
+    #[test]
+    fn backend_failure_keeps_the_stale_copy() {
+        let server = start_core(|_| Response::new(503, ""));
+        let fs = test_fs(&server, test_config());
+        fs.cache_attrs("/file", cached_file(), None, CacheControl::default());
+
+        refresh(&fs.api, &fs.shared_caches(), &fs.api_health, "/file");
+        assert!(fs.get_cached_attrs("/file").is_some());
+    }
+}
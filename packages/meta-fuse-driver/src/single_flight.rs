@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::sync::{Arc, Condvar, Mutex};
+
+/// Coalesces concurrent identical calls so only one reaches the backend.
+///
+/// The first caller for a key runs the call; anyone asking for the same key
+/// while it is in flight waits and receives a clone of its result.
+pub struct SingleFlight<K, V> {
+    calls: Mutex<HashMap<K, Arc<Call<V>>>>,
+}
+
+struct Call<V> {
+    state: Mutex<CallState<V>>,
+    done: Condvar,
+}
+
+enum CallState<V> {
+    Pending,
+    Done(V),
+    /// The leader unwound without producing a result
+    Abandoned,
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> SingleFlight<K, V> {
+    pub fn new() -> Self {
+        SingleFlight {
+            calls: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn run<F: FnOnce() -> V>(&self, key: K, f: F) -> V {
+        let (call, leader) = {
+            let mut calls = self.calls.lock().unwrap();
+            match calls.get(&key) {
+                Some(call) => (Arc::clone(call), false),
+                None => {
+                    let call = Arc::new(Call {
+                        state: Mutex::new(CallState::Pending),
+                        done: Condvar::new(),
+                    });
+                    calls.insert(key.clone(), Arc::clone(&call));
+                    (call, true)
+                }
+            }
+        };
+
+        if !leader {
+            let mut state = call.state.lock().unwrap();
+            loop {
+                match &*state {
+                    CallState::Pending => state = call.done.wait(state).unwrap(),
+                    CallState::Done(value) => return value.clone(),
+                    CallState::Abandoned => break,
+                }
+            }
+            drop(state);
+            return f();
+        }
+
+        let guard = LeaderGuard {
+            flight: self,
+            key,
+            call: &call,
+        };
+        let value = f();
+        *call.state.lock().unwrap() = CallState::Done(value.clone());
+        drop(guard);
+        value
+    }
+}
+
+impl<K, V> fmt::Debug for SingleFlight<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SingleFlight").finish_non_exhaustive()
+    }
+}
+
+/// Unregisters the call and wakes followers, even if the leader panics
+struct LeaderGuard<'a, K: Eq + Hash, V> {
+    flight: &'a SingleFlight<K, V>,
+    key: K,
+    call: &'a Call<V>,
+}
+
+impl<K: Eq + Hash, V> Drop for LeaderGuard<'_, K, V> {
+    fn drop(&mut self) {
+        self.flight.calls.lock().unwrap().remove(&self.key);
+        if let Ok(mut state) = self.call.state.lock() {
+            if matches!(*state, CallState::Pending) {
+                *state = CallState::Abandoned;
+            }
+        }
+        self.call.done.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Barrier;
+    use std::time::Duration;
+
+    #[test]
+    fn concurrent_callers_share_one_call() {
+        let flight = Arc::new(SingleFlight::new());
+        let calls = Arc::new(AtomicUsize::new(0));
+        let barrier = Arc::new(Barrier::new(8));
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let (flight, calls, barrier) = (
+                    Arc::clone(&flight),
+                    Arc::clone(&calls),
+                    Arc::clone(&barrier),
+                );
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    flight.run("key", || {
+                        calls.fetch_add(1, Ordering::SeqCst);
+                        std::thread::sleep(Duration::from_millis(200));
+                        42
+                    })
+                })
+            })
+            .collect();
+        for thread in threads {
+            assert_eq!(thread.join().unwrap(), 42);
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn distinct_keys_run_separately() {
+        let flight = SingleFlight::new();
+        assert_eq!(flight.run(1, || "one"), "one");
+        assert_eq!(flight.run(2, || "two"), "two");
+    }
+
+    #[test]
+    fn followers_retry_after_the_leader_panics() {
+        let flight = Arc::new(SingleFlight::new());
+        let leader = {
+            let flight = Arc::clone(&flight);
+            std::thread::spawn(move || {
+                flight.run("key", || -> u32 {
+                    std::thread::sleep(Duration::from_millis(100));
+                    panic!("leader failed")
+                })
+            })
+        };
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(flight.run("key", || 7), 7);
+        assert!(leader.join().is_err());
+    }
+}
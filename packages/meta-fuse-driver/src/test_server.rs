@@ -0,0 +1,219 @@
+//! A minimal HTTP/1.1 server for tests, answering each request from a
+//! handler closure and recording what it was asked.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+
+/// One request as the server received it
+#[derive(Debug, Clone)]
+pub struct Request {
+    /// Path and query, e.g. "/api/fuse/getattr"
+    pub target: String,
+    /// Header names lowercased, in the order sent
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// The "path" field of a JSON request body
+    pub fn json_path(&self) -> Option<String> {
+        let body: serde_json::Value = serde_json::from_slice(&self.body).ok()?;
+        body.get("path")?.as_str().map(str::to_string)
+    }
+
+    /// The value of a header, looked up by its lowercased name
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16, body: impl Into<Vec<u8>>) -> Self {
+        Response {
+            status,
+            headers: Vec::new(),
+            body: body.into(),
+        }
+    }
+
+    pub fn json(value: serde_json::Value) -> Self {
+        Response::new(200, value.to_string()).header("Content-Type", "application/json")
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+type Handler = dyn Fn(&Request) -> Response + Send + Sync;
+
+pub struct TestServer {
+    pub url: String,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl TestServer {
+    /// Serve on an ephemeral port until the test process exits
+    pub fn start(handler: impl Fn(&Request) -> Response + Send + Sync + 'static) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let handler: Arc<Handler> = Arc::new(handler);
+        let log = Arc::clone(&requests);
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let handler = Arc::clone(&handler);
+                let log = Arc::clone(&log);
+                std::thread::spawn(move || serve(stream, &*handler, &log));
+            }
+        });
+        TestServer { url, requests }
+    }
+
+    /// Every request received so far
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// How many requests went to `target`
+    pub fn count(&self, target: &str) -> usize {
+        self.requests()
+            .iter()
+            .filter(|request| request.target == target)
+            .count()
+    }
+}
+
+fn serve(stream: TcpStream, handler: &Handler, log: &Mutex<Vec<Request>>) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut stream = stream;
+    while let Some(request) = read_request(&mut reader) {
+        log.lock().unwrap().push(request.clone());
+        let response = handler(&request);
+        let mut head = format!("HTTP/1.1 {} X\r\n", response.status);
+        for (name, value) in &response.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str(&format!("Content-Length: {}\r\n\r\n", response.body.len()));
+        if stream.write_all(head.as_bytes()).is_err() || stream.write_all(&response.body).is_err() {
+            return;
+        }
+    }
+}
+
+fn read_request(reader: &mut BufReader<TcpStream>) -> Option<Request> {
+    let mut line = String::new();
+    if reader.read_line(&mut line).ok()? == 0 {
+        return None;
+    }
+    let target = line.split_whitespace().nth(1)?.to_string();
+
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line.split_once(':')?;
+        headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+    }
+    let length = headers
+        .iter()
+        .find(|(name, _)| name == "content-length")
+        .and_then(|(_, value)| value.parse().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).ok()?;
+    Some(Request {
+        target,
+        headers,
+        body,
+    })
+}
+
+/// The system allocator, counting what each thread has live so a test can
+/// bound the memory a call needs. Each block records the thread that
+/// allocated it, so freeing another thread's memory, such as response
+/// chunks from reqwest's runtime, doesn't offset a thread's own count.
+struct CountingAlloc;
+
+thread_local! {
+    static LIVE: Cell<isize> = const { Cell::new(0) };
+    static PEAK: Cell<isize> = const { Cell::new(0) };
+}
+
+fn count_alloc(delta: isize) {
+    let _ = LIVE.try_with(|live| {
+        live.set(live.get() + delta);
+        let _ = PEAK.try_with(|peak| peak.set(peak.get().max(live.get())));
+    });
+}
+
+/// Identifies the current thread while it runs; 0 once its locals are gone
+fn thread_token() -> usize {
+    LIVE.try_with(|live| live as *const Cell<isize> as usize)
+        .unwrap_or(0)
+}
+
+/// Room in front of each block for its owner, and the layout asked of the
+/// system allocator for a block of `layout`
+fn with_header(layout: Layout) -> (usize, Layout) {
+    let header = layout.align().max(16);
+    let full = Layout::from_size_align(layout.size() + header, header).unwrap();
+    (header, full)
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let (header, full) = with_header(layout);
+        let base = System.alloc(full);
+        if base.is_null() {
+            return base;
+        }
+        let ptr = base.add(header);
+        let owner = thread_token();
+        ptr.cast::<usize>().sub(1).write(owner);
+        if owner != 0 {
+            count_alloc(layout.size() as isize);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let (header, full) = with_header(layout);
+        let owner = ptr.cast::<usize>().sub(1).read();
+        if owner != 0 && owner == thread_token() {
+            count_alloc(-(layout.size() as isize));
+        }
+        System.dealloc(ptr.sub(header), full)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAlloc = CountingAlloc;
+
+/// Run `f`, returning its result and the most memory this thread had
+/// allocated at once while it ran, beyond what it held before. Memory
+/// other threads allocate, such as reqwest's runtime, is not counted.
+pub fn peak_allocated<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = LIVE.with(Cell::get);
+    PEAK.with(|peak| peak.set(before));
+    let result = f();
+    let peak = PEAK.with(Cell::get);
+    (result, (peak - before).max(0) as usize)
+}
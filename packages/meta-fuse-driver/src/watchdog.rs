@@ -0,0 +1,57 @@
+use crate::api_client::ApiClient;
+use crate::ApiHealth;
+use fuser::SessionUnmounter;
+use log::{error, info, warn};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Spawn a thread that unmounts the filesystem once the API has been
+/// continuously unhealthy for `limit`, setting `tripped` so main can exit
+/// with a failure code.
+///
+/// Before giving up it probes the health endpoint itself, so an idle mount
+/// whose API has quietly recovered is not torn down.
+pub fn spawn(
+    api: ApiClient,
+    health: Arc<Mutex<ApiHealth>>,
+    limit: Duration,
+    mut unmounter: SessionUnmounter,
+    tripped: Arc<AtomicBool>,
+) {
+    let spawned = std::thread::Builder::new()
+        .name("fuse-watchdog".to_string())
+        .spawn(move || loop {
+            std::thread::sleep(POLL_INTERVAL);
+
+            let unhealthy_for = health.lock().unwrap().unhealthy_for();
+            match unhealthy_for {
+                Some(elapsed) if elapsed >= limit => {}
+                _ => continue,
+            }
+
+            if matches!(api.health_check(), Ok(true)) {
+                info!("Watchdog probe found the API healthy again");
+                health.lock().unwrap().record_success();
+                continue;
+            }
+
+            error!(
+                "API has been unreachable for {}s (FUSE_UNHEALTHY_EXIT_SECS={}), unmounting and exiting",
+                unhealthy_for.unwrap_or_default().as_secs(),
+                limit.as_secs()
+            );
+            tripped.store(true, Ordering::SeqCst);
+            if let Err(e) = unmounter.unmount() {
+                warn!("Watchdog unmount failed: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        });
+
+    if let Err(e) = spawned {
+        warn!("Failed to start unhealthy-exit watchdog: {}", e);
+    }
+}